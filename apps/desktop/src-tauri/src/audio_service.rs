@@ -3,15 +3,293 @@
 //! This module provides utility functions and constants for audio capture management.
 //! The actual stream handling is done in the commands module using async tasks.
 
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+
+use heronote_audio_core::{
+    AgcConfig, AudioError, BleedConfig, BleedStatus, BleedStream, FailoverEvent, HealthConfig, HealthEvent,
+    MeterConfig, MeterReading, PipelineConfig, PreRollConfig, ProcessorConfig, TapStream, VadEvent,
+};
+
+#[cfg(target_os = "linux")]
+use heronote_audio_linux::watch_devices;
+#[cfg(target_os = "macos")]
+use heronote_audio_macos::watch_devices;
+#[cfg(target_os = "windows")]
+use heronote_audio_windows::watch_devices;
+
+use crate::audio_state::AudioState;
+
+/// Application identifier for directory paths
+pub const APP_QUALIFIER: &str = "com";
+pub const APP_ORGANIZATION: &str = "heronote";
+pub const APP_NAME: &str = "app";
+
+/// Directory recordings are saved to in release builds, under the app's
+/// data directory
+#[cfg(not(debug_assertions))]
+pub const RECORDINGS_DIR: &str = "recordings";
+
 /// Poll interval for checking the stop signal in capture tasks
-#[allow(dead_code)]
 pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
 
+/// Interval at which the levels of a capture are sent to the frontend and
+/// the capture is checked for stalls
+pub const LEVELS_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Event names emitted to the frontend
+pub mod events {
+    pub const BLEED: &str = "audio:bleed";
+    pub const CAPTURE_ERROR: &str = "audio:capture-error";
+    pub const DEVICE_CHANGED: &str = "audio:device-changed";
+    pub const FAILOVER: &str = "audio:failover";
+    pub const HEALTH: &str = "audio:health";
+    pub const LEVELS: &str = "audio:levels";
+    pub const VOICE_ACTIVITY: &str = "audio:voice-activity";
+}
+
+/// Capture that reported an event
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureSource {
+    Mic,
+    Speaker,
+}
+
+/// Processing applied to a capture unless settings say otherwise
+///
+/// Captures are watched for problems, metered and searched for speech, and
+/// their drift and the gain they would need are measured, which leaves
+/// their audio untouched. Noise suppression, gain control and drift
+/// correction change the audio, so settings have to enable them.
+pub fn default_pipeline() -> PipelineConfig {
+    PipelineConfig::new()
+        .with_stage(ProcessorConfig::Health(HealthConfig::default()))
+        .with_stage(ProcessorConfig::DriftMeasurement)
+        .with_stage(ProcessorConfig::Agc(AgcConfig::default().with_bypass(true)))
+        .with_stage(ProcessorConfig::Meter(MeterConfig::default()))
+        .with_stage(ProcessorConfig::Vad {
+            config: Default::default(),
+            detector: Default::default(),
+        })
+}
+
+/// How the captures keep audio from before a recording starts
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct PreRollSettings {
+    #[serde(flatten)]
+    pub buffer: PreRollConfig,
+    /// Whether a recording begins with the audio kept before it
+    pub prepend_to_recordings: bool,
+}
+
+impl Default for PreRollSettings {
+    fn default() -> Self {
+        Self {
+            buffer: PreRollConfig::default(),
+            prepend_to_recordings: true,
+        }
+    }
+}
+
+/// Category of a capture error, for the frontend to pick a message
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureErrorKind {
+    DeviceDisconnected,
+    FormatChanged,
+    StreamError,
+}
+
+/// Payload of [`events::CAPTURE_ERROR`]
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureErrorEvent {
+    pub source: CaptureSource,
+    pub kind: CaptureErrorKind,
+    pub message: String,
+    /// Whether the capture stopped because of this error
+    pub fatal: bool,
+}
+
+impl CaptureErrorEvent {
+    pub fn new(source: CaptureSource, error: &AudioError) -> Self {
+        let kind = match error {
+            AudioError::DeviceDisconnected(_) => CaptureErrorKind::DeviceDisconnected,
+            AudioError::FormatChanged { .. } => CaptureErrorKind::FormatChanged,
+            _ => CaptureErrorKind::StreamError,
+        };
+
+        Self {
+            source,
+            kind,
+            message: error.to_string(),
+            fatal: !error.is_recoverable(),
+        }
+    }
+}
+
+/// Log an error delivered by a capture stream and forward it to the frontend
+pub fn report_stream_error(app: &AppHandle, source: CaptureSource, error: &AudioError) {
+    if error.is_recoverable() {
+        tracing::warn!(?source, "{}", error);
+    } else {
+        tracing::error!(?source, "Capture stopped: {}", error);
+    }
+
+    if let Err(e) = app.emit(events::CAPTURE_ERROR, CaptureErrorEvent::new(source, error)) {
+        tracing::warn!("Failed to emit capture error: {}", e);
+    }
+}
+
+/// Payload of [`events::FAILOVER`]
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureFailoverEvent {
+    pub source: CaptureSource,
+    #[serde(flatten)]
+    pub event: FailoverEvent,
+}
+
+/// Log a device switch during capture and forward it to the frontend
+pub fn report_failover(app: &AppHandle, source: CaptureSource, event: FailoverEvent) {
+    tracing::info!(?source, ?event, "Capture failover");
+
+    if let Err(e) = app.emit(events::FAILOVER, CaptureFailoverEvent { source, event }) {
+        tracing::warn!("Failed to emit failover event: {}", e);
+    }
+}
+
+/// Payload of [`events::VOICE_ACTIVITY`]
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureVoiceActivityEvent {
+    pub source: CaptureSource,
+    #[serde(flatten)]
+    pub event: VadEvent,
+}
+
+/// Forward a speech start or end during capture to the frontend
+pub fn report_voice_activity(app: &AppHandle, source: CaptureSource, event: VadEvent) {
+    tracing::debug!(?source, ?event, "Voice activity");
+
+    if let Err(e) = app.emit(events::VOICE_ACTIVITY, CaptureVoiceActivityEvent { source, event }) {
+        tracing::warn!("Failed to emit voice activity event: {}", e);
+    }
+}
+
+/// Payload of [`events::HEALTH`]
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureHealthEvent {
+    pub source: CaptureSource,
+    #[serde(flatten)]
+    pub event: HealthEvent,
+}
+
+/// Forward a capture health warning, or its end, to the frontend
+pub fn report_health(app: &AppHandle, source: CaptureSource, event: HealthEvent) {
+    tracing::debug!(?source, ?event, "Capture health");
+
+    if let Err(e) = app.emit(events::HEALTH, CaptureHealthEvent { source, event }) {
+        tracing::warn!("Failed to emit health event: {}", e);
+    }
+}
+
+/// Payload of [`events::LEVELS`]
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureLevelsEvent {
+    pub source: CaptureSource,
+    #[serde(flatten)]
+    pub reading: MeterReading,
+}
+
+/// Forward the levels of a capture to the frontend
+pub fn report_levels(app: &AppHandle, source: CaptureSource, reading: MeterReading) {
+    if let Err(e) = app.emit(events::LEVELS, CaptureLevelsEvent { source, reading }) {
+        tracing::warn!("Failed to emit levels event: {}", e);
+    }
+}
+
+/// Record the speaker bleed status and forward it to the frontend
+fn report_bleed(app: &AppHandle, status: BleedStatus) {
+    app.state::<AudioState>().set_bleed_status(status);
+
+    if let Err(e) = app.emit(events::BLEED, status) {
+        tracing::warn!("Failed to emit bleed event: {}", e);
+    }
+}
+
+/// Look for the speaker capture in the microphone until `stop_signal` is set
+///
+/// Runs alongside a microphone capture; while the speaker is not captured
+/// there is nothing to find. The status returns to "not detected" when the
+/// detection stops.
+pub fn spawn_bleed_detection(
+    app: AppHandle,
+    mic: TapStream,
+    speaker: TapStream,
+    stop_signal: Arc<AtomicBool>,
+) {
+    tauri::async_runtime::spawn(async move {
+        let mut bleed = BleedStream::new(mic, speaker, BleedConfig::default());
+
+        loop {
+            if stop_signal.load(Ordering::SeqCst) {
+                break;
+            }
+
+            tokio::select! {
+                status = bleed.next() => match status {
+                    Some(Ok(status)) => report_bleed(&app, status),
+                    Some(Err(e)) => {
+                        tracing::warn!("Bleed detection stopped: {}", e);
+                        break;
+                    }
+                    None => break,
+                },
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+
+        if bleed.detector().is_detected() {
+            report_bleed(&app, BleedStatus::default());
+        }
+    });
+}
+
+/// Forward device hot-plug events to the frontend for the app's lifetime
+///
+/// Platforms without device notifications only log a warning; the device
+/// list can still be refreshed manually.
+pub fn spawn_device_watcher(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut watcher = match watch_devices() {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                tracing::warn!("Device change notifications unavailable: {}", e);
+                return;
+            }
+        };
+
+        tracing::info!("Watching for audio device changes");
+
+        while let Some(event) = watcher.next().await {
+            if let Err(e) = app.emit(events::DEVICE_CHANGED, &event) {
+                tracing::warn!("Failed to emit device event: {}", e);
+            }
+        }
+
+        tracing::info!("Device watcher stopped");
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use heronote_audio_core::{BleedEstimate, HealthIssue, HealthWarning};
 
     #[test]
     fn test_poll_interval_is_reasonable() {
@@ -19,4 +297,108 @@ mod tests {
         assert!(POLL_INTERVAL >= Duration::from_millis(50));
         assert!(POLL_INTERVAL <= Duration::from_millis(500));
     }
+
+    #[test]
+    fn test_capture_error_event_kind() {
+        let event = CaptureErrorEvent::new(
+            CaptureSource::Mic,
+            &AudioError::DeviceDisconnected("USB Microphone".to_string()),
+        );
+        assert_eq!(event.kind, CaptureErrorKind::DeviceDisconnected);
+        assert!(event.fatal);
+        assert_eq!(event.message, "Device disconnected: USB Microphone");
+    }
+
+    #[test]
+    fn test_failover_event_payload() {
+        let event = CaptureFailoverEvent {
+            source: CaptureSource::Mic,
+            event: FailoverEvent::DeviceLost {
+                device_id: Some("usb".to_string()),
+                reason: "Device disconnected: usb".to_string(),
+            },
+        };
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["source"], "mic");
+        assert_eq!(json["type"], "device_lost");
+        assert_eq!(json["device_id"], "usb");
+    }
+
+    #[test]
+    fn test_voice_activity_event_payload() {
+        let event = CaptureVoiceActivityEvent {
+            source: CaptureSource::Speaker,
+            event: VadEvent::SpeechStart {
+                frame: 48000,
+                timestamp: Duration::from_secs(2),
+            },
+        };
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["source"], "speaker");
+        assert_eq!(json["type"], "speech_start");
+        assert_eq!(json["frame"], 48000);
+    }
+
+    #[test]
+    fn test_health_event_payload() {
+        let event = CaptureHealthEvent {
+            source: CaptureSource::Speaker,
+            event: HealthEvent::Raised(HealthWarning::DigitalSilence {
+                duration: Duration::from_secs(5),
+            }),
+        };
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["source"], "speaker");
+        assert_eq!(json["state"], "raised");
+        assert_eq!(json["type"], "digital_silence");
+        assert_eq!(json["duration"]["secs"], 5);
+
+        let event = CaptureHealthEvent {
+            source: CaptureSource::Mic,
+            event: HealthEvent::Cleared {
+                issue: HealthIssue::Stalled,
+            },
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["state"], "cleared");
+        assert_eq!(json["issue"], "stalled");
+    }
+
+    #[test]
+    fn test_bleed_event_payload() {
+        let status = BleedStatus {
+            detected: true,
+            estimate: Some(BleedEstimate {
+                level_db: -18.0,
+                delay: Duration::from_millis(120),
+                correlation: 0.8,
+            }),
+        };
+
+        let json = serde_json::to_value(status).unwrap();
+        assert_eq!(json["detected"], true);
+        assert_eq!(json["estimate"]["level_db"], -18.0);
+        assert_eq!(json["estimate"]["delay"]["nanos"], 120_000_000);
+    }
+
+    #[test]
+    fn test_levels_event_payload() {
+        let event = CaptureLevelsEvent {
+            source: CaptureSource::Mic,
+            reading: MeterReading {
+                peak_db: -6.0,
+                clip_count: 3,
+                ..Default::default()
+            },
+        };
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["source"], "mic");
+        assert_eq!(json["peak_db"], -6.0);
+        assert_eq!(json["clip_count"], 3);
+        assert_eq!(json["momentary_lufs"], -100.0);
+    }
 }
@@ -23,7 +23,14 @@
 //! provides simpler reasoning about correctness with negligible performance impact.
 
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+
+use heronote_audio_core::{BleedStatus, FailoverConfig, PipelineConfig, PreRollBuffer, TapHandle};
+
+#[cfg(debug_assertions)]
+use heronote_audio_core::PreRollHistory;
+
+use crate::audio_service::{default_pipeline, CaptureSource, PreRollSettings};
 
 /// Thread-safe audio capture state
 ///
@@ -49,12 +56,28 @@ pub struct AudioState {
     mic_running: Arc<AtomicBool>,
     /// Signal to stop the microphone capture thread
     mic_stop_signal: Arc<AtomicBool>,
+    /// How the microphone capture recovers from losing its device
+    mic_failover: RwLock<FailoverConfig>,
+    /// Processing applied to the microphone audio
+    mic_pipeline: RwLock<PipelineConfig>,
+    /// Processing applied to the speaker audio
+    speaker_pipeline: RwLock<PipelineConfig>,
+    /// Raw audio of each capture, for analyses across both
+    mic_tap: TapHandle,
+    speaker_tap: TapHandle,
+    /// Whether speaker audio reaches the microphone
+    bleed: RwLock<BleedStatus>,
+    /// Last minutes of processed audio of each capture
+    mic_preroll: PreRollBuffer,
+    speaker_preroll: PreRollBuffer,
+    /// Whether recordings begin with the pre-roll
+    prepend_preroll: AtomicBool,
 
-    /// Whether the speaker capture thread is currently running (macOS only)
-    #[cfg(target_os = "macos")]
+    /// Whether the speaker capture thread is currently running (macOS and Linux)
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
     speaker_running: Arc<AtomicBool>,
-    /// Signal to stop the speaker capture thread (macOS only)
-    #[cfg(target_os = "macos")]
+    /// Signal to stop the speaker capture thread (macOS and Linux)
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
     speaker_stop_signal: Arc<AtomicBool>,
 }
 
@@ -63,9 +86,18 @@ impl Default for AudioState {
         Self {
             mic_running: Arc::new(AtomicBool::new(false)),
             mic_stop_signal: Arc::new(AtomicBool::new(false)),
-            #[cfg(target_os = "macos")]
+            mic_failover: RwLock::new(FailoverConfig::default()),
+            mic_pipeline: RwLock::new(default_pipeline()),
+            speaker_pipeline: RwLock::new(default_pipeline()),
+            mic_tap: TapHandle::new(),
+            speaker_tap: TapHandle::new(),
+            bleed: RwLock::new(BleedStatus::default()),
+            mic_preroll: PreRollBuffer::default(),
+            speaker_preroll: PreRollBuffer::default(),
+            prepend_preroll: AtomicBool::new(PreRollSettings::default().prepend_to_recordings),
+            #[cfg(any(target_os = "macos", target_os = "linux"))]
             speaker_running: Arc::new(AtomicBool::new(false)),
-            #[cfg(target_os = "macos")]
+            #[cfg(any(target_os = "macos", target_os = "linux"))]
             speaker_stop_signal: Arc::new(AtomicBool::new(false)),
         }
     }
@@ -112,49 +144,145 @@ impl AudioState {
         self.mic_stop_signal.clone()
     }
 
+    /// Get the microphone failover configuration
+    ///
+    /// Read when a capture starts; changes apply to the next capture.
+    pub fn mic_failover(&self) -> FailoverConfig {
+        self.mic_failover.read().unwrap().clone()
+    }
+
+    /// Set the microphone failover configuration
+    pub fn set_mic_failover(&self, config: FailoverConfig) {
+        *self.mic_failover.write().unwrap() = config;
+    }
+
+    /// Get the processing pipeline of the microphone capture
+    ///
+    /// Read when a capture starts; changes apply to the next capture.
+    pub fn mic_pipeline(&self) -> PipelineConfig {
+        self.mic_pipeline.read().unwrap().clone()
+    }
+
+    /// Get the processing pipeline of the speaker capture
+    ///
+    /// Read when a capture starts; changes apply to the next capture.
+    pub fn speaker_pipeline(&self) -> PipelineConfig {
+        self.speaker_pipeline.read().unwrap().clone()
+    }
+
+    /// Set the processing pipeline of a capture
+    pub fn set_pipeline(&self, source: CaptureSource, config: PipelineConfig) {
+        let pipeline = match source {
+            CaptureSource::Mic => &self.mic_pipeline,
+            CaptureSource::Speaker => &self.speaker_pipeline,
+        };
+        *pipeline.write().unwrap() = config;
+    }
+
+    /// Get the tap receiving the raw audio of a capture
+    ///
+    /// Every capture of `source` feeds the subscribers of the same tap.
+    pub fn tap(&self, source: CaptureSource) -> &TapHandle {
+        match source {
+            CaptureSource::Mic => &self.mic_tap,
+            CaptureSource::Speaker => &self.speaker_tap,
+        }
+    }
+
+    /// Get whether speaker audio reaches the microphone, as last measured
+    pub fn bleed_status(&self) -> BleedStatus {
+        *self.bleed.read().unwrap()
+    }
+
+    /// Update the speaker bleed status
+    pub fn set_bleed_status(&self, status: BleedStatus) {
+        *self.bleed.write().unwrap() = status;
+    }
+
+    /// Get the pre-roll buffer keeping the last minutes of a capture
+    ///
+    /// Every capture of `source` adds to the same buffer.
+    pub fn preroll(&self, source: CaptureSource) -> &PreRollBuffer {
+        match source {
+            CaptureSource::Mic => &self.mic_preroll,
+            CaptureSource::Speaker => &self.speaker_preroll,
+        }
+    }
+
+    /// Get the pre-roll settings, shared by both captures
+    pub fn preroll_settings(&self) -> PreRollSettings {
+        PreRollSettings {
+            buffer: self.mic_preroll.config(),
+            prepend_to_recordings: self.prepend_preroll.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Set the pre-roll settings of both captures
+    ///
+    /// A new buffer configuration applies at once and discards the audio
+    /// kept so far.
+    pub fn set_preroll_settings(&self, settings: PreRollSettings) {
+        if settings.buffer != self.mic_preroll.config() {
+            self.mic_preroll.set_config(settings.buffer.clone());
+            self.speaker_preroll.set_config(settings.buffer);
+        }
+        self.prepend_preroll
+            .store(settings.prepend_to_recordings, Ordering::SeqCst);
+    }
+
+    /// Take the pre-roll of a capture for a recording starting now, if
+    /// recordings begin with it
+    #[cfg(debug_assertions)]
+    pub fn take_preroll_for_recording(&self, source: CaptureSource) -> Option<PreRollHistory> {
+        if !self.prepend_preroll.load(Ordering::SeqCst) {
+            return None;
+        }
+        self.preroll(source).take_history()
+    }
+
     // ========================================================================
-    // Speaker state management (macOS only)
+    // Speaker state management (macOS and Linux)
     // ========================================================================
 
     /// Check if speaker capture is currently running
-    #[cfg(target_os = "macos")]
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
     pub fn is_speaker_running(&self) -> bool {
         self.speaker_running.load(Ordering::SeqCst)
     }
 
     /// Set the speaker running state
-    #[cfg(target_os = "macos")]
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
     pub fn set_speaker_running(&self, running: bool) {
         self.speaker_running.store(running, Ordering::SeqCst);
     }
 
     /// Get a clone of the speaker running flag for use in a capture thread
-    #[cfg(target_os = "macos")]
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
     pub fn speaker_running_handle(&self) -> Arc<AtomicBool> {
         self.speaker_running.clone()
     }
 
     /// Check if a stop signal has been sent to the speaker capture
-    #[cfg(target_os = "macos")]
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
     #[allow(dead_code)]
     pub fn is_speaker_stop_signaled(&self) -> bool {
         self.speaker_stop_signal.load(Ordering::SeqCst)
     }
 
     /// Signal the speaker capture thread to stop
-    #[cfg(target_os = "macos")]
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
     pub fn signal_speaker_stop(&self) {
         self.speaker_stop_signal.store(true, Ordering::SeqCst);
     }
 
     /// Reset the speaker stop signal (call before starting capture)
-    #[cfg(target_os = "macos")]
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
     pub fn reset_speaker_stop_signal(&self) {
         self.speaker_stop_signal.store(false, Ordering::SeqCst);
     }
 
     /// Get a clone of the speaker stop signal for use in a capture thread
-    #[cfg(target_os = "macos")]
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
     pub fn speaker_stop_signal_handle(&self) -> Arc<AtomicBool> {
         self.speaker_stop_signal.clone()
     }
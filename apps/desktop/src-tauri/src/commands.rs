@@ -3,53 +3,374 @@
 //! This module contains all the Tauri-exposed commands for controlling
 //! audio capture.
 
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use futures::channel::mpsc;
 use futures::StreamExt;
-use tauri::State;
+use tauri::{AppHandle, Manager, State};
 
-use heronote_audio_core::{AudioDevice, AudioInput};
+use heronote_audio_core::{
+    open_input, AudioDevice, AudioInput, AudioInputConfig, AudioProcessor, AudioStream, BleedStatus,
+    FailoverConfig, FailoverEvent, FailoverStream, HealthMonitor, Meter, Pipeline, PipelineConfig,
+    ProcessedStream, VoiceActivity,
+};
 
+#[cfg(debug_assertions)]
+use heronote_audio_core::{
+    Agc, AudioChunk, DriftCorrection, DriftMeasurement, GapLog, MeterReading, PreRollHistory, ProcessorConfig,
+    WavSink,
+};
+
+use crate::audio_service::{
+    report_failover, report_health, report_levels, report_stream_error, report_voice_activity,
+    spawn_bleed_detection, CaptureSource, PreRollSettings, LEVELS_INTERVAL, POLL_INTERVAL,
+};
+
+#[cfg(not(debug_assertions))]
+use crate::audio_service::{APP_NAME, APP_ORGANIZATION, APP_QUALIFIER, RECORDINGS_DIR};
 use crate::audio_state::AudioState;
 
 #[cfg(debug_assertions)]
-use crate::debug_state::{DebugAudioFile, DebugConfig, DebugState, FlatAudioMetrics};
+use crate::debug_state::{AudioSource, DebugAudioFile, DebugConfig, DebugState, FlatAudioMetrics};
 
 #[cfg(debug_assertions)]
-use std::fs::{self, File};
+use std::cell::RefCell;
 #[cfg(debug_assertions)]
-use std::io::BufWriter;
+use std::fs;
+
+/// Files a debug capture is recorded to
 #[cfg(debug_assertions)]
-use std::path::PathBuf;
+struct Recording {
+    /// Audio as delivered by the pipeline
+    path: PathBuf,
+    /// Audio as captured, before any processing
+    raw_path: PathBuf,
+    /// Gaps of the recording at `path`, beginning with those of its pre-roll
+    gaps: RefCell<GapLog>,
+}
 
-/// Create a WAV writer for audio capture
 #[cfg(debug_assertions)]
-fn create_wav_writer(
-    output_dir: &PathBuf,
-    source: &str,
-    sample_rate: u32,
-) -> Result<(hound::WavWriter<BufWriter<File>>, PathBuf), String> {
-    // Create output directory
-    fs::create_dir_all(output_dir)
-        .map_err(|e| format!("Failed to create audio directory: {}", e))?;
-
-    // Generate filename with timestamp
-    let timestamp = chrono::Utc::now().format("%Y%m%d_%H%M%S");
-    let filename = format!("{}_{}.wav", source, timestamp);
-    let path = output_dir.join(&filename);
-
-    // WAV spec: mono, 32-bit float
-    let spec = hound::WavSpec {
-        channels: 1,
-        sample_rate,
-        bits_per_sample: 32,
-        sample_format: hound::SampleFormat::Float,
-    };
+impl Recording {
+    /// Name the recordings of a capture, if debug mode saves audio files
+    fn new(config: &DebugConfig, source: AudioSource) -> Option<Self> {
+        if !(config.enabled && config.save_audio_files) {
+            return None;
+        }
+
+        // Generate filenames with timestamp
+        let timestamp = chrono::Utc::now().format("%Y%m%d_%H%M%S");
+        let dir = &config.audio_output_dir;
+        Some(Self {
+            path: dir.join(format!("{}_{}.wav", source.as_str(), timestamp)),
+            raw_path: dir.join(format!("{}_raw_{}.wav", source.as_str(), timestamp)),
+            gaps: RefCell::new(GapLog::new()),
+        })
+    }
+
+    /// Record the audio before the stages of `config`
+    ///
+    /// The audio after them is recorded by [`Recording::sink`].
+    fn wrap(&self, config: PipelineConfig) -> PipelineConfig {
+        let mut stages = vec![ProcessorConfig::Record {
+            path: self.raw_path.clone(),
+        }];
+        stages.extend(config.stages);
+
+        PipelineConfig { stages }
+    }
+
+    /// Processor recording the audio delivered by the pipeline, beginning
+    /// with `preroll`
+    ///
+    /// The pre-roll is processed audio kept before the capture started, so
+    /// only this recording begins with it; the raw one starts with the
+    /// capture. Its gaps go to the gap log ahead of those of the capture,
+    /// so the offsets logged count from the start of the file.
+    fn sink(&self, preroll: Option<PreRollHistory>) -> WavSink {
+        let mut sink = WavSink::new(&self.path);
+        let Some(preroll) = preroll else {
+            return sink;
+        };
+
+        tracing::info!(
+            path = %self.path.display(),
+            duration = ?preroll.duration(),
+            "Recording begins with the pre-roll"
+        );
+        let mut gaps = self.gaps.borrow_mut();
+        for chunk in preroll {
+            let result = chunk.and_then(|chunk| {
+                gaps.record(&chunk);
+                sink.process(chunk)
+            });
+            if let Err(e) = result {
+                tracing::warn!("Recording left out the rest of the pre-roll: {}", e);
+                break;
+            }
+        }
+        sink
+    }
+}
+
+/// Directory recordings are saved to: the debug audio directory
+#[cfg(debug_assertions)]
+fn recordings_dir(app: &AppHandle) -> PathBuf {
+    app.state::<DebugState>().config().audio_output_dir
+}
+
+/// Directory recordings are saved to
+#[cfg(not(debug_assertions))]
+fn recordings_dir(_app: &AppHandle) -> PathBuf {
+    directories::ProjectDirs::from(APP_QUALIFIER, APP_ORGANIZATION, APP_NAME)
+        .map(|dirs| dirs.data_local_dir().join(RECORDINGS_DIR))
+        .unwrap_or_else(|| PathBuf::from(format!("./{}", RECORDINGS_DIR)))
+}
+
+/// Write the gaps of a recording next to its WAV file
+///
+/// Recordings without gaps get no gap file.
+#[cfg(debug_assertions)]
+fn write_gap_log(wav_path: &Path, log: &GapLog) -> Result<(), String> {
+    if log.gaps.is_empty() {
+        return Ok(());
+    }
+
+    let path = wav_path.with_extension("gaps.json");
+    let json = serde_json::to_string_pretty(log)
+        .map_err(|e| format!("Failed to serialize gap log: {}", e))?;
+    fs::write(&path, json).map_err(|e| format!("Failed to write gap log: {}", e))?;
+
+    tracing::info!(
+        path = %path.display(),
+        gaps = log.gaps.len(),
+        silence = ?log.total(),
+        "Recording gaps saved"
+    );
+    Ok(())
+}
+
+/// A capture running on its own task until stopped
+struct Capture {
+    app: AppHandle,
+    source: CaptureSource,
+    running: Arc<AtomicBool>,
+    stop_signal: Arc<AtomicBool>,
+    #[cfg(debug_assertions)]
+    recording: Option<Recording>,
+}
+
+impl Capture {
+    /// Build the pipeline described by `config`, recorded before and after
+    /// in debug builds
+    ///
+    /// The raw audio also goes to the tap of the capture, and the processed
+    /// audio to its pre-roll buffer. A recording of the processed audio
+    /// begins with the pre-roll kept so far, if the settings ask for it, so
+    /// the pipeline is best built before the capture starts.
+    fn pipeline(&self, config: PipelineConfig) -> Pipeline {
+        #[cfg(debug_assertions)]
+        let config = match &self.recording {
+            Some(recording) => {
+                tracing::info!(
+                    source = ?self.source,
+                    path = %recording.path.display(),
+                    "Recording audio to file"
+                );
+                recording.wrap(config)
+            }
+            None => config,
+        };
+
+        let state = self.app.state::<AudioState>();
+        let mut pipeline = config.build();
+        pipeline.insert(0, Box::new(state.tap(self.source).tap()));
+        #[cfg(debug_assertions)]
+        if let Some(recording) = &self.recording {
+            let preroll = state.take_preroll_for_recording(self.source);
+            pipeline.push(Box::new(recording.sink(preroll)));
+        }
+        pipeline.push(Box::new(state.preroll(self.source).recorder()));
+        pipeline
+    }
+
+    /// Mark the capture as stopped after it failed to start
+    fn abort(self, message: &str, error: impl std::fmt::Display) {
+        tracing::error!("{}: {}", message, error);
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    /// Run `stream` through `pipeline` until the capture is stopped or the
+    /// stream ends
+    ///
+    /// The processing itself happens in the pipeline; the loop forwards
+    /// errors, events, the levels of a [`Meter`] stage and the warnings of a
+    /// [`HealthMonitor`] stage to the frontend and, in debug builds, keeps
+    /// the debug metrics and the gap log of the recording.
+    async fn run<S: AudioStream>(
+        self,
+        stream: S,
+        mut pipeline: Pipeline,
+        mut failover_events: mpsc::UnboundedReceiver<FailoverEvent>,
+    ) {
+        let source = self.source;
+        let mut voice_events = match pipeline.find_mut::<VoiceActivity>() {
+            Some(vad) => vad.subscribe(),
+            None => mpsc::unbounded().1,
+        };
+        let mut health_events = match pipeline.find_mut::<HealthMonitor>() {
+            Some(health) => health.subscribe(),
+            None => mpsc::unbounded().1,
+        };
+
+        let mut stream = ProcessedStream::new(stream, pipeline);
+        let mut levels_interval = tokio::time::interval(LEVELS_INTERVAL);
+        levels_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        #[cfg(debug_assertions)]
+        let mut gap_log = self.recording.as_ref().map(|r| r.gaps.take()).unwrap_or_default();
+
+        tracing::info!(?source, "Capture started");
+
+        // Consume the stream until stop signal
+        loop {
+            if self.stop_signal.load(Ordering::SeqCst) {
+                break;
+            }
+
+            tokio::select! {
+                biased;
+
+                audio = stream.next() => {
+                    match audio {
+                        Some(Ok(chunk)) => {
+                            #[cfg(debug_assertions)]
+                            self.update_debug_metrics(stream.processor(), &mut gap_log, &chunk);
+
+                            tracing::trace!(
+                                ?source,
+                                samples = chunk.samples.len(),
+                                sequence = chunk.sequence,
+                                timestamp = ?chunk.timestamp,
+                                gap_fill = chunk.gap_fill,
+                                "Audio chunk received"
+                            );
+                        }
+                        Some(Err(e)) => {
+                            report_stream_error(&self.app, source, &e);
+                            if !e.is_recoverable() {
+                                break;
+                            }
+                        }
+                        None => {
+                            tracing::warn!(?source, "Capture stream ended unexpectedly");
+                            break;
+                        }
+                    }
+                }
+
+                Some(event) = failover_events.next() => {
+                    report_failover(&self.app, source, event);
+                }
+
+                Some(event) = voice_events.next() => {
+                    report_voice_activity(&self.app, source, event);
+                }
+
+                Some(event) = health_events.next() => {
+                    report_health(&self.app, source, event);
+                }
+
+                _ = levels_interval.tick() => {
+                    let pipeline = stream.processor_mut();
+                    self.report_levels(pipeline);
+                    // A stalled stream delivers no chunks for the monitor to notice
+                    if let Some(health) = pipeline.find_mut::<HealthMonitor>() {
+                        health.check_stall(Instant::now());
+                    }
+                }
+
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+
+        // Push the audio still buffered through the pipeline, which also
+        // completes its recordings
+        if let Err(e) = stream.processor_mut().flush() {
+            tracing::warn!(?source, "Failed to flush audio pipeline: {}", e);
+        }
 
-    let writer = hound::WavWriter::create(&path, spec)
-        .map_err(|e| format!("Failed to create WAV file: {}", e))?;
+        #[cfg(debug_assertions)]
+        if let Some(recording) = &self.recording {
+            if let Err(e) = write_gap_log(&recording.path, &gap_log) {
+                tracing::warn!("{}", e);
+            }
+        }
+
+        self.running.store(false, Ordering::SeqCst);
+        tracing::info!(?source, "Capture stopped");
+    }
+
+    /// Send the levels measured since the last report, if the pipeline meters
+    fn report_levels(&self, pipeline: &mut Pipeline) {
+        let Some(meter) = pipeline.find_mut::<Meter>() else {
+            return;
+        };
+
+        let reading = meter.take_reading();
+        #[cfg(debug_assertions)]
+        self.update_debug_levels(&reading);
+        report_levels(&self.app, self.source, reading);
+    }
+
+    /// Count a delivered chunk and refresh the pipeline figures of the debug panel
+    #[cfg(debug_assertions)]
+    fn update_debug_metrics(&self, pipeline: &Pipeline, gap_log: &mut GapLog, chunk: &AudioChunk) {
+        let debug_state = self.app.state::<DebugState>();
+        let source = AudioSource::from(self.source);
+
+        debug_state.add_samples(source, chunk.samples.len() as u64);
+        if gap_log.record(chunk).is_some() {
+            debug_state.add_dropped(source, chunk.samples.len() as u64);
+        }
+
+        // Correction, when enabled, measures the drift it corrects
+        let drift_ppm = match pipeline.find::<DriftCorrection>() {
+            Some(correction) => correction.drift_ppm(),
+            None => pipeline.find::<DriftMeasurement>().map_or(0.0, DriftMeasurement::drift_ppm),
+        } as f32;
+        let gain_db = pipeline.find::<Agc>().map_or(0.0, Agc::gain_db);
+        let latency_ms = pipeline.latency().as_secs_f32() * 1000.0;
+        debug_state.update_metrics(|metrics| {
+            let metrics = match source {
+                AudioSource::Mic => &mut metrics.mic,
+                AudioSource::Speaker => &mut metrics.speaker,
+            };
+            metrics.drift_ppm = drift_ppm;
+            metrics.gain_db = gain_db;
+            metrics.latency_ms = latency_ms;
+        });
+    }
 
-    Ok((writer, path))
+    /// Show the latest levels in the debug panel
+    #[cfg(debug_assertions)]
+    fn update_debug_levels(&self, reading: &MeterReading) {
+        let source = AudioSource::from(self.source);
+        self.app.state::<DebugState>().update_metrics(|metrics| {
+            let metrics = match source {
+                AudioSource::Mic => &mut metrics.mic,
+                AudioSource::Speaker => &mut metrics.speaker,
+            };
+            metrics.rms_db = reading.rms_db;
+            metrics.peak_db = reading.peak_db;
+            metrics.true_peak_db = reading.true_peak_db;
+            metrics.clip_count = reading.clip_count;
+            metrics.momentary_lufs = reading.momentary_lufs;
+            metrics.short_term_lufs = reading.short_term_lufs;
+        });
+    }
 }
 
 #[cfg(target_os = "macos")]
@@ -59,7 +380,7 @@ use heronote_audio_macos::{list_devices, MicInput, SpeakerInput};
 use heronote_audio_windows::{list_devices, MicInput};
 
 #[cfg(target_os = "linux")]
-use heronote_audio_linux::{list_devices, MicInput};
+use heronote_audio_linux::{list_devices, MicInput, SpeakerInput};
 
 // ============================================================================
 // Device listing
@@ -77,9 +398,6 @@ pub fn list_audio_devices() -> Result<Vec<AudioDevice>, String> {
 
 /// Start capturing audio from the default microphone (debug builds)
 ///
-/// Note: MicStream contains cpal::Stream which is not Send, so we must use
-/// a blocking thread with spawn_blocking for the async runtime integration.
-///
 /// # Errors
 ///
 /// Returns an error if:
@@ -88,213 +406,89 @@ pub fn list_audio_devices() -> Result<Vec<AudioDevice>, String> {
 #[cfg(debug_assertions)]
 #[tauri::command]
 pub fn start_mic_capture(
+    app: AppHandle,
     audio_state: State<AudioState>,
     debug_state: State<DebugState>,
 ) -> Result<(), String> {
-    use std::thread;
-
-    if audio_state.is_mic_running() {
-        return Err("Microphone capture is already running".to_string());
-    }
-
-    // Verify device exists before spawning thread
-    let mic = MicInput::new().map_err(|e| e.to_string())?;
-    let sample_rate = mic.sample_rate();
-
-    // Get debug config for the thread
-    let debug_config = debug_state.config();
-
-    // Update state
-    audio_state.set_mic_running(true);
-    audio_state.reset_mic_stop_signal();
-
-    // Get handles for the capture thread
-    let running = audio_state.mic_running_handle();
-    let stop_signal = audio_state.mic_stop_signal_handle();
-
-    // Use blocking thread because cpal::Stream (inside MicStream) is not Send
-    thread::spawn(move || {
-        // Create a local tokio runtime for this thread
-        let rt = match tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-        {
-            Ok(rt) => rt,
-            Err(e) => {
-                tracing::error!("Failed to create tokio runtime: {}", e);
-                running.store(false, Ordering::SeqCst);
-                return;
-            }
-        };
-
-        rt.block_on(async {
-            let mic = match MicInput::new() {
-                Ok(m) => m,
-                Err(e) => {
-                    tracing::error!("Failed to create Microphone input: {}", e);
-                    running.store(false, Ordering::SeqCst);
-                    return;
-                }
-            };
-
-            let stream = match mic.stream() {
-                Ok(s) => s,
-                Err(e) => {
-                    tracing::error!("Failed to start Microphone stream: {}", e);
-                    running.store(false, Ordering::SeqCst);
-                    return;
-                }
-            };
-
-            // Create WAV writer if debug save is enabled
-            let mut wav_writer = if debug_config.enabled && debug_config.save_audio_files {
-                match create_wav_writer(&debug_config.audio_output_dir, "mic", sample_rate) {
-                    Ok((writer, path)) => {
-                        tracing::info!(path = %path.display(), "Recording microphone audio to file");
-                        Some((writer, path))
-                    }
-                    Err(e) => {
-                        tracing::warn!("Failed to create WAV writer: {}", e);
-                        None
-                    }
-                }
-            } else {
-                None
-            };
-
-            tracing::info!("Microphone capture started");
-            tokio::pin!(stream);
-
-            // Consume the stream until stop signal
-            loop {
-                if stop_signal.load(Ordering::SeqCst) {
-                    break;
-                }
-
-                tokio::select! {
-                    biased;
-
-                    audio = stream.next() => {
-                        match audio {
-                            Some(samples) => {
-                                if let Some((ref mut writer, _)) = wav_writer {
-                                    for &sample in &samples {
-                                        if let Err(e) = writer.write_sample(sample) {
-                                            tracing::warn!("Failed to write sample: {}", e);
-                                            break;
-                                        }
-                                    }
-                                }
-                                tracing::trace!(samples = samples.len(), "Microphone audio chunk received");
-                            }
-                            None => {
-                                tracing::warn!("Microphone stream ended unexpectedly");
-                                break;
-                            }
-                        }
-                    }
-
-                    _ = tokio::time::sleep(tokio::time::Duration::from_millis(100)) => {}
-                }
-            }
-
-            // Finalize WAV file
-            if let Some((writer, path)) = wav_writer {
-                if let Err(e) = writer.finalize() {
-                    tracing::error!("Failed to finalize WAV file: {}", e);
-                } else {
-                    tracing::info!(path = %path.display(), "Microphone audio file saved");
-                }
-            }
-
-            running.store(false, Ordering::SeqCst);
-            tracing::info!("Microphone capture stopped");
-        });
-    });
-
-    Ok(())
+    let recording = Recording::new(&debug_state.config(), AudioSource::Mic);
+    spawn_mic_capture(&audio_state, |running, stop_signal| Capture {
+        app,
+        source: CaptureSource::Mic,
+        running,
+        stop_signal,
+        recording,
+    })
 }
 
 /// Start capturing audio from the default microphone (release builds)
 #[cfg(not(debug_assertions))]
 #[tauri::command]
-pub fn start_mic_capture(state: State<AudioState>) -> Result<(), String> {
+pub fn start_mic_capture(app: AppHandle, state: State<AudioState>) -> Result<(), String> {
+    spawn_mic_capture(&state, |running, stop_signal| Capture {
+        app,
+        source: CaptureSource::Mic,
+        running,
+        stop_signal,
+    })
+}
+
+/// Spawn the microphone capture thread
+///
+/// Note: MicStream contains cpal::Stream which is not Send, so we must use
+/// a blocking thread with its own runtime for the async integration.
+fn spawn_mic_capture(
+    state: &AudioState,
+    capture: impl FnOnce(Arc<AtomicBool>, Arc<AtomicBool>) -> Capture,
+) -> Result<(), String> {
     use std::thread;
 
     if state.is_mic_running() {
         return Err("Microphone capture is already running".to_string());
     }
 
+    // Verify device exists before spawning thread
     let _ = MicInput::new().map_err(|e| e.to_string())?;
+    let failover = state.mic_failover();
+    let pipeline = state.mic_pipeline();
 
     state.set_mic_running(true);
     state.reset_mic_stop_signal();
 
-    let running = state.mic_running_handle();
-    let stop_signal = state.mic_stop_signal_handle();
+    let capture = capture(state.mic_running_handle(), state.mic_stop_signal_handle());
+
+    spawn_bleed_detection(
+        capture.app.clone(),
+        state.tap(CaptureSource::Mic).subscribe(),
+        state.tap(CaptureSource::Speaker).subscribe(),
+        state.mic_stop_signal_handle(),
+    );
 
     // Use blocking thread because cpal::Stream (inside MicStream) is not Send
     thread::spawn(move || {
+        // Create a local tokio runtime for this thread
         let rt = match tokio::runtime::Builder::new_current_thread()
             .enable_all()
             .build()
         {
             Ok(rt) => rt,
-            Err(e) => {
-                tracing::error!("Failed to create tokio runtime: {}", e);
-                running.store(false, Ordering::SeqCst);
-                return;
-            }
+            Err(e) => return capture.abort("Failed to create tokio runtime", e),
         };
 
         rt.block_on(async {
-            let mic = match MicInput::new() {
-                Ok(m) => m,
-                Err(e) => {
-                    tracing::error!("Failed to create Microphone input: {}", e);
-                    running.store(false, Ordering::SeqCst);
-                    return;
-                }
-            };
-
-            let stream = match mic.stream() {
+            let pipeline = capture.pipeline(pipeline);
+
+            // Reopens the new default or a fallback microphone if the device goes away
+            let mut stream = match FailoverStream::new(
+                AudioInputConfig::default(),
+                failover,
+                open_input::<MicInput>,
+            ) {
                 Ok(s) => s,
-                Err(e) => {
-                    tracing::error!("Failed to start Microphone stream: {}", e);
-                    running.store(false, Ordering::SeqCst);
-                    return;
-                }
+                Err(e) => return capture.abort("Failed to start Microphone stream", e),
             };
+            let failover_events = stream.subscribe();
 
-            tracing::info!("Microphone capture started");
-            tokio::pin!(stream);
-
-            loop {
-                if stop_signal.load(Ordering::SeqCst) {
-                    break;
-                }
-
-                tokio::select! {
-                    biased;
-
-                    audio = stream.next() => {
-                        match audio {
-                            Some(samples) => {
-                                tracing::trace!(samples = samples.len(), "Microphone audio chunk received");
-                            }
-                            None => {
-                                tracing::warn!("Microphone stream ended unexpectedly");
-                                break;
-                            }
-                        }
-                    }
-
-                    _ = tokio::time::sleep(tokio::time::Duration::from_millis(100)) => {}
-                }
-            }
-
-            running.store(false, Ordering::SeqCst);
-            tracing::info!("Microphone capture stopped");
+            capture.run(stream, pipeline, failover_events).await;
         });
     });
 
@@ -322,11 +516,94 @@ pub fn is_mic_capturing(state: State<AudioState>) -> bool {
     state.is_mic_running()
 }
 
+/// Get how microphone capture recovers when its device disconnects
+#[tauri::command]
+pub fn get_mic_failover_config(state: State<AudioState>) -> FailoverConfig {
+    state.mic_failover()
+}
+
+/// Set how microphone capture recovers when its device disconnects
+///
+/// Takes effect on the next capture.
+#[tauri::command]
+pub fn set_mic_failover_config(state: State<AudioState>, config: FailoverConfig) {
+    state.set_mic_failover(config);
+}
+
+/// Get whether speaker audio reaches the microphone, with its level and
+/// delay as last measured
+#[tauri::command]
+pub fn get_bleed_status(state: State<AudioState>) -> BleedStatus {
+    state.bleed_status()
+}
+
+/// Get how much of the captures is kept before a recording starts
+#[tauri::command]
+pub fn get_preroll_config(state: State<AudioState>) -> PreRollSettings {
+    state.preroll_settings()
+}
+
+/// Set how much of the captures is kept before a recording starts
+///
+/// Takes effect at once; a new buffer configuration discards the audio
+/// kept so far.
+#[tauri::command]
+pub fn set_preroll_config(state: State<AudioState>, config: PreRollSettings) {
+    state.set_preroll_settings(config);
+}
+
+/// Save the audio a capture kept before now to a WAV file named `name` in
+/// the recordings directory, returning its length
+///
+/// The saved audio is taken out of the pre-roll.
+///
+/// # Errors
+///
+/// Returns an error if `name` is not a plain file name, no audio was kept
+/// or the file cannot be written
+#[tauri::command]
+pub async fn save_preroll(app: AppHandle, source: CaptureSource, name: String) -> Result<Duration, String> {
+    // Only a plain name, so the file cannot land outside the directory
+    if Path::new(&name).file_name() != Some(OsStr::new(&name)) {
+        return Err(format!("Invalid pre-roll file name: {}", name));
+    }
+    let path = recordings_dir(&app).join(&name).with_extension("wav");
+
+    let history = app
+        .state::<AudioState>()
+        .preroll(source)
+        .take_history()
+        .ok_or_else(|| "No pre-roll audio has been kept".to_string())?;
+
+    // Minutes of audio take a while to write
+    tauri::async_runtime::spawn_blocking(move || history.save(&path))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+/// Get the processing pipeline of a capture
+#[tauri::command]
+pub fn get_pipeline_config(state: State<AudioState>, source: CaptureSource) -> PipelineConfig {
+    match source {
+        CaptureSource::Mic => state.mic_pipeline(),
+        CaptureSource::Speaker => state.speaker_pipeline(),
+    }
+}
+
+/// Set the processing pipeline of a capture
+///
+/// Takes effect on the next capture.
+#[tauri::command]
+pub fn set_pipeline_config(state: State<AudioState>, source: CaptureSource, config: PipelineConfig) {
+    state.set_pipeline(source, config);
+}
+
 // ============================================================================
-// Speaker capture commands (macOS only)
+// Speaker capture commands (macOS and Linux)
 // ============================================================================
 
-/// Start capturing system audio output (macOS only)
+/// Start capturing system audio output (macOS and Linux)
 ///
 /// # Errors
 ///
@@ -334,200 +611,89 @@ pub fn is_mic_capturing(state: State<AudioState>) -> bool {
 /// - Speaker capture is already running
 /// - System audio capture is not available
 /// - Required permissions are not granted
-#[cfg(all(target_os = "macos", debug_assertions))]
+#[cfg(all(any(target_os = "macos", target_os = "linux"), debug_assertions))]
 #[tauri::command]
 pub fn start_speaker_capture(
+    app: AppHandle,
     audio_state: State<AudioState>,
     debug_state: State<DebugState>,
 ) -> Result<(), String> {
-    if audio_state.is_speaker_running() {
-        return Err("Speaker capture is already running".to_string());
-    }
-
-    // Verify we can create speaker input before spawning task
-    let speaker = SpeakerInput::new().map_err(|e| e.to_string())?;
-    let sample_rate = speaker.sample_rate();
-
-    // Get debug config for the async task
-    let debug_config = debug_state.config();
-
-    // Update state
-    audio_state.set_speaker_running(true);
-    audio_state.reset_speaker_stop_signal();
-
-    // Get handles for the capture task
-    let running = audio_state.speaker_running_handle();
-    let stop_signal = audio_state.speaker_stop_signal_handle();
-
-    // Spawn async task to consume the stream
-    tauri::async_runtime::spawn(async move {
-        let speaker = match SpeakerInput::new() {
-            Ok(s) => s,
-            Err(e) => {
-                tracing::error!("Failed to create Speaker input: {}", e);
-                running.store(false, Ordering::SeqCst);
-                return;
-            }
-        };
-
-        let stream = match speaker.stream() {
-            Ok(s) => s,
-            Err(e) => {
-                tracing::error!("Failed to start Speaker stream: {}", e);
-                running.store(false, Ordering::SeqCst);
-                return;
-            }
-        };
-
-        // Create WAV writer if debug save is enabled
-        let mut wav_writer = if debug_config.enabled && debug_config.save_audio_files {
-            match create_wav_writer(&debug_config.audio_output_dir, "speaker", sample_rate) {
-                Ok((writer, path)) => {
-                    tracing::info!(path = %path.display(), "Recording speaker audio to file");
-                    Some((writer, path))
-                }
-                Err(e) => {
-                    tracing::warn!("Failed to create WAV writer: {}", e);
-                    None
-                }
-            }
-        } else {
-            None
-        };
-
-        tracing::info!("Speaker capture started");
-        tokio::pin!(stream);
-
-        // Consume the stream until stop signal
-        loop {
-            if stop_signal.load(Ordering::SeqCst) {
-                break;
-            }
-
-            tokio::select! {
-                biased;
-
-                audio = stream.next() => {
-                    match audio {
-                        Some(samples) => {
-                            if let Some((ref mut writer, _)) = wav_writer {
-                                for &sample in &samples {
-                                    if let Err(e) = writer.write_sample(sample) {
-                                        tracing::warn!("Failed to write sample: {}", e);
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                        None => {
-                            tracing::warn!("Speaker stream ended unexpectedly");
-                            break;
-                        }
-                    }
-                }
-
-                _ = tokio::time::sleep(tokio::time::Duration::from_millis(100)) => {}
-            }
-        }
-
-        // Finalize WAV file
-        if let Some((writer, path)) = wav_writer {
-            if let Err(e) = writer.finalize() {
-                tracing::error!("Failed to finalize WAV file: {}", e);
-            } else {
-                tracing::info!(path = %path.display(), "Speaker audio file saved");
-            }
-        }
-
-        running.store(false, Ordering::SeqCst);
-        tracing::info!("Speaker capture stopped");
-    });
-
-    Ok(())
+    let recording = Recording::new(&debug_state.config(), AudioSource::Speaker);
+    spawn_speaker_capture(&audio_state, |running, stop_signal| Capture {
+        app,
+        source: CaptureSource::Speaker,
+        running,
+        stop_signal,
+        recording,
+    })
 }
 
-/// Start capturing system audio output (macOS release builds)
-#[cfg(all(target_os = "macos", not(debug_assertions)))]
+/// Start capturing system audio output (macOS and Linux release builds)
+#[cfg(all(any(target_os = "macos", target_os = "linux"), not(debug_assertions)))]
 #[tauri::command]
-pub fn start_speaker_capture(state: State<AudioState>) -> Result<(), String> {
+pub fn start_speaker_capture(app: AppHandle, state: State<AudioState>) -> Result<(), String> {
+    spawn_speaker_capture(&state, |running, stop_signal| Capture {
+        app,
+        source: CaptureSource::Speaker,
+        running,
+        stop_signal,
+    })
+}
+
+/// Spawn the task consuming the speaker capture
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn spawn_speaker_capture(
+    state: &AudioState,
+    capture: impl FnOnce(Arc<AtomicBool>, Arc<AtomicBool>) -> Capture,
+) -> Result<(), String> {
     if state.is_speaker_running() {
         return Err("Speaker capture is already running".to_string());
     }
 
+    // Verify we can create speaker input before spawning task
     let _ = SpeakerInput::new().map_err(|e| e.to_string())?;
+    let pipeline = state.speaker_pipeline();
 
     state.set_speaker_running(true);
     state.reset_speaker_stop_signal();
 
-    let running = state.speaker_running_handle();
-    let stop_signal = state.speaker_stop_signal_handle();
+    let capture = capture(state.speaker_running_handle(), state.speaker_stop_signal_handle());
 
     tauri::async_runtime::spawn(async move {
+        let pipeline = capture.pipeline(pipeline);
+
         let speaker = match SpeakerInput::new() {
             Ok(s) => s,
-            Err(e) => {
-                tracing::error!("Failed to create Speaker input: {}", e);
-                running.store(false, Ordering::SeqCst);
-                return;
-            }
+            Err(e) => return capture.abort("Failed to create Speaker input", e),
         };
 
         let stream = match speaker.stream() {
             Ok(s) => s,
-            Err(e) => {
-                tracing::error!("Failed to start Speaker stream: {}", e);
-                running.store(false, Ordering::SeqCst);
-                return;
-            }
+            Err(e) => return capture.abort("Failed to start Speaker stream", e),
         };
 
-        tracing::info!("Speaker capture started");
-        tokio::pin!(stream);
-
-        loop {
-            if stop_signal.load(Ordering::SeqCst) {
-                break;
-            }
-
-            tokio::select! {
-                biased;
-
-                audio = stream.next() => {
-                    match audio {
-                        Some(samples) => {
-                            tracing::trace!(samples = samples.len(), "Speaker audio chunk received");
-                        }
-                        None => {
-                            tracing::warn!("Speaker stream ended unexpectedly");
-                            break;
-                        }
-                    }
-                }
+        // Speaker capture has no failover; the sender is dropped right away
+        let (_, failover_events) = mpsc::unbounded();
 
-                _ = tokio::time::sleep(tokio::time::Duration::from_millis(100)) => {}
-            }
-        }
-
-        running.store(false, Ordering::SeqCst);
-        tracing::info!("Speaker capture stopped");
+        capture.run(stream, pipeline, failover_events).await;
     });
 
     Ok(())
 }
 
-/// Start speaker capture stub for non-macOS platforms
-#[cfg(not(target_os = "macos"))]
+/// Start speaker capture stub for unsupported platforms
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
 #[tauri::command]
 pub fn start_speaker_capture() -> Result<(), String> {
-    Err("Speaker capture is only supported on macOS".to_string())
+    Err("Speaker capture is only supported on macOS and Linux".to_string())
 }
 
-/// Stop the current speaker capture (macOS only)
+/// Stop the current speaker capture (macOS and Linux)
 ///
 /// # Errors
 ///
 /// Returns an error if speaker capture is not running
-#[cfg(target_os = "macos")]
+#[cfg(any(target_os = "macos", target_os = "linux"))]
 #[tauri::command]
 pub fn stop_speaker_capture(state: State<AudioState>) -> Result<(), String> {
     if !state.is_speaker_running() {
@@ -538,22 +704,22 @@ pub fn stop_speaker_capture(state: State<AudioState>) -> Result<(), String> {
     Ok(())
 }
 
-/// Stop speaker capture stub for non-macOS platforms
-#[cfg(not(target_os = "macos"))]
+/// Stop speaker capture stub for unsupported platforms
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
 #[tauri::command]
 pub fn stop_speaker_capture() -> Result<(), String> {
-    Err("Speaker capture is only supported on macOS".to_string())
+    Err("Speaker capture is only supported on macOS and Linux".to_string())
 }
 
-/// Check if speaker capture is currently active (macOS only)
-#[cfg(target_os = "macos")]
+/// Check if speaker capture is currently active (macOS and Linux)
+#[cfg(any(target_os = "macos", target_os = "linux"))]
 #[tauri::command]
 pub fn is_speaker_capturing(state: State<AudioState>) -> bool {
     state.is_speaker_running()
 }
 
-/// Speaker capture status stub for non-macOS platforms
-#[cfg(not(target_os = "macos"))]
+/// Speaker capture status stub for unsupported platforms
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
 #[tauri::command]
 pub fn is_speaker_capturing() -> bool {
     false
@@ -672,7 +838,7 @@ pub fn get_debug_metrics(
     debug_state.update_metrics(|metrics| {
         metrics.mic.capturing = audio_state.is_mic_running();
 
-        #[cfg(target_os = "macos")]
+        #[cfg(any(target_os = "macos", target_os = "linux"))]
         {
             metrics.speaker.capturing = audio_state.is_speaker_running();
         }
@@ -708,7 +874,7 @@ pub fn list_debug_files(state: State<DebugState>) -> Vec<DebugAudioFile> {
             let path = entry.path();
 
             // Only process .wav files
-            if path.extension().map_or(false, |ext| ext == "wav") {
+            if path.extension().is_some_and(|ext| ext == "wav") {
                 if let Some(file_info) = parse_wav_file_info(&path) {
                     files.push(file_info);
                 }
@@ -717,16 +883,14 @@ pub fn list_debug_files(state: State<DebugState>) -> Vec<DebugAudioFile> {
     }
 
     // Sort by creation time (newest first)
-    files.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    files.sort_by_key(|f| std::cmp::Reverse(f.created_at));
 
     files
 }
 
 /// Parse WAV file metadata
 #[cfg(debug_assertions)]
-fn parse_wav_file_info(path: &std::path::Path) -> Option<DebugAudioFile> {
-    use crate::debug_state::AudioSource;
-
+fn parse_wav_file_info(path: &Path) -> Option<DebugAudioFile> {
     let filename = path.file_name()?.to_str()?;
 
     // Parse source from filename (mic_*.wav or speaker_*.wav)
@@ -747,8 +911,7 @@ fn parse_wav_file_info(path: &std::path::Path) -> Option<DebugAudioFile> {
         .created()
         .ok()
         .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-        .map(|d| chrono::DateTime::from_timestamp(d.as_secs() as i64, 0))
-        .flatten()
+        .and_then(|d| chrono::DateTime::from_timestamp(d.as_secs() as i64, 0))
         .unwrap_or_else(chrono::Utc::now);
 
     // Read WAV header for sample rate and duration
@@ -7,7 +7,7 @@
 
 use std::fs::{self, File};
 use std::io::BufWriter;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use chrono::Utc;
 use hound::{SampleFormat, WavSpec, WavWriter};
@@ -96,7 +96,7 @@ impl DebugAudioWriter {
     }
 
     /// Generate a unique file path for the audio file
-    fn generate_file_path(output_dir: &PathBuf, source: AudioSource) -> PathBuf {
+    fn generate_file_path(output_dir: &Path, source: AudioSource) -> PathBuf {
         let timestamp = Utc::now().format("%Y%m%d_%H%M%S%.3f");
         let filename = format!("{}_{}.wav", source.as_str(), timestamp);
         output_dir.join(filename)
@@ -10,6 +10,10 @@ use std::sync::RwLock;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use heronote_audio_core::SILENCE_DB;
+
+use crate::audio_service::{CaptureSource, APP_NAME, APP_ORGANIZATION, APP_QUALIFIER};
+
 // ============================================================================
 // Constants
 // ============================================================================
@@ -18,10 +22,7 @@ use serde::{Deserialize, Serialize};
 #[allow(dead_code)]
 pub const MAX_LOG_ENTRIES: usize = 100;
 
-/// Default application identifier for directory paths
-const APP_QUALIFIER: &str = "com";
-const APP_ORGANIZATION: &str = "heronote";
-const APP_NAME: &str = "app";
+/// Directory debug recordings are saved to, under the app's data directory
 const DEBUG_AUDIO_DIR: &str = "debug_audio";
 
 // ============================================================================
@@ -36,6 +37,15 @@ pub enum AudioSource {
     Speaker,
 }
 
+impl From<CaptureSource> for AudioSource {
+    fn from(source: CaptureSource) -> Self {
+        match source {
+            CaptureSource::Mic => AudioSource::Mic,
+            CaptureSource::Speaker => AudioSource::Speaker,
+        }
+    }
+}
+
 impl AudioSource {
     pub fn as_str(&self) -> &'static str {
         match self {
@@ -109,7 +119,7 @@ impl Default for DebugConfig {
 // ============================================================================
 
 /// Metrics for a single audio source
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SourceMetrics {
     pub sample_rate: u32,
     pub buffer_usage_percent: f32,
@@ -118,6 +128,40 @@ pub struct SourceMetrics {
     pub latency_ms: f32,
     pub device_name: Option<String>,
     pub capturing: bool,
+    pub drift_ppm: f32,
+    /// Gain applied by automatic gain control, in dB
+    pub gain_db: f32,
+    /// Levels of the capture, in dBFS
+    pub rms_db: f32,
+    pub peak_db: f32,
+    pub true_peak_db: f32,
+    /// Clipped samples since the capture started
+    pub clip_count: u64,
+    /// EBU R128 loudness of the capture, in LUFS
+    pub momentary_lufs: f32,
+    pub short_term_lufs: f32,
+}
+
+impl Default for SourceMetrics {
+    fn default() -> Self {
+        Self {
+            sample_rate: 0,
+            buffer_usage_percent: 0.0,
+            samples_processed: 0,
+            samples_dropped: 0,
+            latency_ms: 0.0,
+            device_name: None,
+            capturing: false,
+            drift_ppm: 0.0,
+            gain_db: 0.0,
+            rms_db: SILENCE_DB,
+            peak_db: SILENCE_DB,
+            true_peak_db: SILENCE_DB,
+            clip_count: 0,
+            momentary_lufs: SILENCE_DB,
+            short_term_lufs: SILENCE_DB,
+        }
+    }
 }
 
 /// Real-time audio metrics for all sources
@@ -157,6 +201,22 @@ impl AudioMetrics {
             speaker_device_name: self.speaker.device_name.clone(),
             mic_capturing: self.mic.capturing,
             speaker_capturing: self.speaker.capturing,
+            mic_drift_ppm: self.mic.drift_ppm,
+            speaker_drift_ppm: self.speaker.drift_ppm,
+            mic_gain_db: self.mic.gain_db,
+            speaker_gain_db: self.speaker.gain_db,
+            mic_rms_db: self.mic.rms_db,
+            speaker_rms_db: self.speaker.rms_db,
+            mic_peak_db: self.mic.peak_db,
+            speaker_peak_db: self.speaker.peak_db,
+            mic_true_peak_db: self.mic.true_peak_db,
+            speaker_true_peak_db: self.speaker.true_peak_db,
+            mic_clip_count: self.mic.clip_count,
+            speaker_clip_count: self.speaker.clip_count,
+            mic_momentary_lufs: self.mic.momentary_lufs,
+            speaker_momentary_lufs: self.speaker.momentary_lufs,
+            mic_short_term_lufs: self.mic.short_term_lufs,
+            speaker_short_term_lufs: self.speaker.short_term_lufs,
             last_update: self.last_update,
         }
     }
@@ -179,6 +239,22 @@ pub struct FlatAudioMetrics {
     pub speaker_device_name: Option<String>,
     pub mic_capturing: bool,
     pub speaker_capturing: bool,
+    pub mic_drift_ppm: f32,
+    pub speaker_drift_ppm: f32,
+    pub mic_gain_db: f32,
+    pub speaker_gain_db: f32,
+    pub mic_rms_db: f32,
+    pub speaker_rms_db: f32,
+    pub mic_peak_db: f32,
+    pub speaker_peak_db: f32,
+    pub mic_true_peak_db: f32,
+    pub speaker_true_peak_db: f32,
+    pub mic_clip_count: u64,
+    pub speaker_clip_count: u64,
+    pub mic_momentary_lufs: f32,
+    pub speaker_momentary_lufs: f32,
+    pub mic_short_term_lufs: f32,
+    pub speaker_short_term_lufs: f32,
     pub last_update: DateTime<Utc>,
 }
 
@@ -8,7 +8,7 @@
 //! The application is organized into the following modules:
 //!
 //! - [`audio_state`]: Thread-safe state management for audio capture
-//! - [`audio_service`]: Service layer for audio capture operations
+//! - [`audio_service`]: Service layer for audio capture operations and device events
 //! - [`commands`]: Tauri command handlers exposed to the frontend
 //! - [`debug_state`]: Debug mode state management (debug builds only)
 //! - [`debug_service`]: Debug services for metrics and file writing (debug builds only)
@@ -17,7 +17,7 @@
 //!
 //! - **macOS**: Full support for microphone and system audio capture
 //! - **Windows**: Microphone capture (system audio coming soon)
-//! - **Linux**: Microphone capture (system audio coming soon)
+//! - **Linux**: Microphone and system audio capture (PulseAudio/PipeWire)
 
 mod audio_service;
 mod audio_state;
@@ -31,8 +31,9 @@ mod debug_state;
 use audio_state::AudioState;
 use commands::{
     // Audio commands
-    is_mic_capturing, is_speaker_capturing, list_audio_devices, start_mic_capture,
-    start_speaker_capture, stop_mic_capture, stop_speaker_capture,
+    get_bleed_status, get_mic_failover_config, get_pipeline_config, get_preroll_config, is_mic_capturing,
+    is_speaker_capturing, list_audio_devices, save_preroll, set_mic_failover_config, set_pipeline_config,
+    set_preroll_config, start_mic_capture, start_speaker_capture, stop_mic_capture, stop_speaker_capture,
     // Permission commands
     check_screen_recording_permission, open_screen_recording_settings,
     request_screen_recording_permission,
@@ -87,12 +88,24 @@ pub fn run() {
     }
 
     builder
+        .setup(|app| {
+            audio_service::spawn_device_watcher(app.handle().clone());
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             // Audio commands
             list_audio_devices,
             start_mic_capture,
             stop_mic_capture,
             is_mic_capturing,
+            get_mic_failover_config,
+            set_mic_failover_config,
+            get_pipeline_config,
+            set_pipeline_config,
+            get_bleed_status,
+            get_preroll_config,
+            set_preroll_config,
+            save_preroll,
             start_speaker_capture,
             stop_speaker_capture,
             is_speaker_capturing,
@@ -1,23 +1,186 @@
 import { useState, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import { DebugPanel } from "./components/DebugPanel";
 
+interface SampleRateRange {
+  min: number;
+  max: number;
+}
+
 interface AudioDevice {
+  id: string;
   name: string;
-  device_type: "Input" | "Output";
+  device_type: "Input" | "Output" | "Monitor";
   is_default: boolean;
+  kind: "Builtin" | "Usb" | "Bluetooth" | "Virtual" | "Monitor" | "Unknown";
+  channels: number;
+  sample_rates: SampleRateRange[];
+  sample_formats: string[];
+}
+
+interface CaptureErrorEvent {
+  source: "mic" | "speaker";
+  kind: "device_disconnected" | "format_changed" | "stream_error";
+  message: string;
+  fatal: boolean;
+}
+
+type FailoverEvent =
+  | { source: "mic" | "speaker"; type: "device_lost"; device_id: string | null; reason: string }
+  | {
+      source: "mic" | "speaker";
+      type: "switched";
+      device_id: string | null;
+      sample_rate: number;
+      channels: number;
+      gap: { secs: number; nanos: number };
+    };
+
+interface VoiceActivityEvent {
+  source: "mic" | "speaker";
+  type: "speech_start" | "speech_end";
+  frame: number;
+  timestamp: { secs: number; nanos: number };
+}
+
+type HealthWarning =
+  | { type: "stalled"; duration: { secs: number; nanos: number } }
+  | { type: "digital_silence"; duration: { secs: number; nanos: number } }
+  | { type: "dc_offset"; offset_db: number }
+  | { type: "clipping"; duration: { secs: number; nanos: number }; clip_ratio: number }
+  | { type: "drops"; drop_rate: number };
+
+type HealthEvent = { source: "mic" | "speaker" } & (
+  | ({ state: "raised" } & HealthWarning)
+  | { state: "cleared"; issue: HealthWarning["type"] }
+);
+
+interface BleedStatus {
+  detected: boolean;
+  estimate: {
+    level_db: number;
+    delay: { secs: number; nanos: number };
+    correlation: number;
+  } | null;
+}
+
+function healthMessage(warning: HealthWarning): string {
+  switch (warning.type) {
+    case "stalled":
+      return `no audio received for ${warning.duration.secs} s, check that the device is still connected`;
+    case "digital_silence":
+      return "recording pure silence, check that the device is not muted and the app has permission to record";
+    case "dc_offset":
+      return `signal stuck at ${warning.offset_db.toFixed(0)} dBFS, the device may be faulty`;
+    case "clipping":
+      return "input is clipping, lower the input volume";
+    case "drops":
+      return `${(warning.drop_rate * 100).toFixed(1)}% of the audio is being lost`;
+  }
 }
 
 function App() {
   const [devices, setDevices] = useState<AudioDevice[]>([]);
   const [isMicCapturing, setIsMicCapturing] = useState(false);
   const [isSpeakerCapturing, setIsSpeakerCapturing] = useState(false);
+  const [talking, setTalking] = useState({ mic: false, speaker: false });
+  const [bleedDetected, setBleedDetected] = useState(false);
   const [error, setError] = useState<string | null>(null);
 
   useEffect(() => {
     loadDevices();
   }, []);
 
+  useEffect(() => {
+    const unlisten = listen<CaptureErrorEvent>(
+      "audio:capture-error",
+      ({ payload }) => {
+        const label = payload.source === "mic" ? "Mic" : "Speaker";
+        setError(`${label} capture error: ${payload.message}`);
+
+        if (payload.fatal) {
+          if (payload.source === "mic") {
+            setIsMicCapturing(false);
+          } else {
+            setIsSpeakerCapturing(false);
+          }
+        }
+      }
+    );
+
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  useEffect(() => {
+    const unlisten = listen<FailoverEvent>("audio:failover", ({ payload }) => {
+      const label = payload.source === "mic" ? "Mic" : "Speaker";
+      if (payload.type === "device_lost") {
+        setError(`${label} device lost, looking for another one: ${payload.reason}`);
+      } else {
+        const device = payload.device_id ?? "default device";
+        const gapMs = Math.round(payload.gap.secs * 1000 + payload.gap.nanos / 1e6);
+        setError(`${label} switched to ${device} (${gapMs} ms of silence inserted)`);
+      }
+    });
+
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  useEffect(() => {
+    const unlisten = listen<HealthEvent>("audio:health", ({ payload }) => {
+      if (payload.state === "raised") {
+        const label = payload.source === "mic" ? "Mic" : "Speaker";
+        setError(`${label}: ${healthMessage(payload)}`);
+      }
+    });
+
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  useEffect(() => {
+    const unlisten = listen<BleedStatus>("audio:bleed", ({ payload }) => {
+      setBleedDetected(payload.detected);
+    });
+
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  useEffect(() => {
+    const unlisten = listen<VoiceActivityEvent>(
+      "audio:voice-activity",
+      ({ payload }) => {
+        setTalking((current) => ({
+          ...current,
+          [payload.source]: payload.type === "speech_start",
+        }));
+      }
+    );
+
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  useEffect(() => {
+    // Refresh whenever a device is plugged in, removed or made the default
+    const unlisten = listen("audio:device-changed", () => {
+      loadDevices();
+    });
+
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
   async function loadDevices() {
     try {
       const deviceList = await invoke<AudioDevice[]>("list_audio_devices");
@@ -35,6 +198,7 @@ function App() {
         setIsMicCapturing(false);
       } else {
         await invoke("start_mic_capture");
+        setTalking((current) => ({ ...current, mic: false }));
         setIsMicCapturing(true);
       }
       setError(null);
@@ -50,6 +214,7 @@ function App() {
         setIsSpeakerCapturing(false);
       } else {
         await invoke("start_speaker_capture");
+        setTalking((current) => ({ ...current, speaker: false }));
         setIsSpeakerCapturing(true);
       }
       setError(null);
@@ -126,12 +291,20 @@ function App() {
             <p>
               Recording:{" "}
               {[
-                isMicCapturing && "Microphone",
-                isSpeakerCapturing && "System Audio",
+                isMicCapturing &&
+                  `Microphone${talking.mic ? " (talking)" : ""}`,
+                isSpeakerCapturing &&
+                  `System Audio${talking.speaker ? " (talking)" : ""}`,
               ]
                 .filter(Boolean)
                 .join(", ")}
             </p>
+            {isMicCapturing && bleedDetected && (
+              <p style={{ marginTop: "0.5rem", color: "#ff9800" }}>
+                Speaker bleed detected: the microphone picks up the speakers.
+                Consider using headphones.
+              </p>
+            )}
           </div>
         )}
       </section>
@@ -151,7 +324,7 @@ function App() {
             <ul style={{ listStyle: "none" }}>
               {inputDevices.map((device) => (
                 <li
-                  key={device.name}
+                  key={device.id}
                   style={{
                     padding: "0.5rem",
                     background: device.is_default ? "#3a3a5e" : "transparent",
@@ -176,7 +349,7 @@ function App() {
             <ul style={{ listStyle: "none" }}>
               {outputDevices.map((device) => (
                 <li
-                  key={device.name}
+                  key={device.id}
                   style={{
                     padding: "0.5rem",
                     background: device.is_default ? "#3a3a5e" : "transparent",
@@ -16,6 +16,10 @@ import {
 // ============================================================================
 
 const BUFFER_WARNING_THRESHOLD = 80;
+// Gain close to the AGC maximum (30 dB) means the mic is barely above its noise floor
+const GAIN_WARNING_THRESHOLD = 27;
+// Peaks this close to full scale leave no headroom before clipping
+const PEAK_WARNING_THRESHOLD = -1;
 
 type TabType = "metrics" | "files" | "logs";
 
@@ -253,6 +257,33 @@ function SourceMetricsCard({ title, metrics, color }: SourceMetricsCardProps) {
         label="Latency"
         value={`${metrics.latencyMs.toFixed(2)} ms`}
       />
+      <MetricRow
+        label="Clock Drift"
+        value={`${metrics.driftPpm.toFixed(1)} ppm`}
+      />
+      <MetricRow
+        label="Gain"
+        value={`${metrics.gainDb.toFixed(1)} dB`}
+        status={metrics.gainDb > GAIN_WARNING_THRESHOLD ? "warning" : "normal"}
+      />
+      <MetricRow
+        label="RMS / Peak"
+        value={`${metrics.rmsDb.toFixed(1)} / ${metrics.peakDb.toFixed(1)} dBFS`}
+      />
+      <MetricRow
+        label="True Peak"
+        value={`${metrics.truePeakDb.toFixed(1)} dBTP`}
+        status={metrics.truePeakDb > PEAK_WARNING_THRESHOLD ? "warning" : "normal"}
+      />
+      <MetricRow
+        label="Clipped Samples"
+        value={formatNumber(metrics.clipCount)}
+        status={metrics.clipCount > 0 ? "error" : "normal"}
+      />
+      <MetricRow
+        label="Loudness (M / S)"
+        value={`${metrics.momentaryLufs.toFixed(1)} / ${metrics.shortTermLufs.toFixed(1)} LUFS`}
+      />
     </>
   );
 }
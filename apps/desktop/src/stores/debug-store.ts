@@ -59,6 +59,22 @@ export interface AudioMetrics {
   speaker_device_name: string | null;
   mic_capturing: boolean;
   speaker_capturing: boolean;
+  mic_drift_ppm: number;
+  speaker_drift_ppm: number;
+  mic_gain_db: number;
+  speaker_gain_db: number;
+  mic_rms_db: number;
+  speaker_rms_db: number;
+  mic_peak_db: number;
+  speaker_peak_db: number;
+  mic_true_peak_db: number;
+  speaker_true_peak_db: number;
+  mic_clip_count: number;
+  speaker_clip_count: number;
+  mic_momentary_lufs: number;
+  speaker_momentary_lufs: number;
+  mic_short_term_lufs: number;
+  speaker_short_term_lufs: number;
   last_update: string;
 }
 
@@ -71,6 +87,25 @@ export interface SourceMetrics {
   latencyMs: number;
   deviceName: string | null;
   capturing: boolean;
+  driftPpm: number;
+  gainDb: number;
+  rmsDb: number;
+  peakDb: number;
+  truePeakDb: number;
+  clipCount: number;
+  momentaryLufs: number;
+  shortTermLufs: number;
+}
+
+/** Payload of the `audio:levels` event, sent for each capture at a fixed rate */
+export interface LevelsEvent {
+  source: "mic" | "speaker";
+  rms_db: number;
+  peak_db: number;
+  true_peak_db: number;
+  clip_count: number;
+  momentary_lufs: number;
+  short_term_lufs: number;
 }
 
 export interface DebugAudioFile {
@@ -104,6 +139,14 @@ export function extractMicMetrics(metrics: AudioMetrics): SourceMetrics {
     latencyMs: metrics.mic_latency_ms,
     deviceName: metrics.mic_device_name,
     capturing: metrics.mic_capturing,
+    driftPpm: metrics.mic_drift_ppm,
+    gainDb: metrics.mic_gain_db,
+    rmsDb: metrics.mic_rms_db,
+    peakDb: metrics.mic_peak_db,
+    truePeakDb: metrics.mic_true_peak_db,
+    clipCount: metrics.mic_clip_count,
+    momentaryLufs: metrics.mic_momentary_lufs,
+    shortTermLufs: metrics.mic_short_term_lufs,
   };
 }
 
@@ -117,6 +160,14 @@ export function extractSpeakerMetrics(metrics: AudioMetrics): SourceMetrics {
     latencyMs: metrics.speaker_latency_ms,
     deviceName: metrics.speaker_device_name,
     capturing: metrics.speaker_capturing,
+    driftPpm: metrics.speaker_drift_ppm,
+    gainDb: metrics.speaker_gain_db,
+    rmsDb: metrics.speaker_rms_db,
+    peakDb: metrics.speaker_peak_db,
+    truePeakDb: metrics.speaker_true_peak_db,
+    clipCount: metrics.speaker_clip_count,
+    momentaryLufs: metrics.speaker_momentary_lufs,
+    shortTermLufs: metrics.speaker_short_term_lufs,
   };
 }
 
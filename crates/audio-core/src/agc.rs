@@ -0,0 +1,378 @@
+//! Automatic gain control
+//!
+//! Microphones arrive at very different levels: a whisper into a laptop
+//! microphone sits near the noise floor, while a close headset clips. An
+//! [`Agc`] measures the level of the signal in short frames and moves a
+//! gain towards the one that brings it to a target level, quickly when the
+//! signal gets louder and slowly when it gets quieter. A peak limiter after
+//! the gain keeps sudden loud sounds from clipping before the gain has
+//! caught up.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::Stream as FuturesStream;
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::{duration_to_frames, AudioChunk};
+use crate::error::AudioError;
+use crate::meter::{db_to_linear, smoothing};
+use crate::processor::{AudioProcessor, ProcessedStream};
+use crate::traits::AudioStream;
+
+/// Length of the frames whose level is measured
+const LEVEL_FRAME: Duration = Duration::from_millis(10);
+
+/// Time in which the limiter recovers from a peak
+const LIMITER_RELEASE: Duration = Duration::from_millis(50);
+
+/// Configuration of an [`Agc`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct AgcConfig {
+    /// RMS level the gain aims for, in dBFS
+    pub target_level_db: f32,
+    /// Largest amplification, in dB
+    pub max_gain_db: f32,
+    /// Largest attenuation, in dB; zero or below
+    pub min_gain_db: f32,
+    /// Time constant with which the gain drops when the level rises
+    pub attack: Duration,
+    /// Time constant with which the gain rises when the level falls
+    pub release: Duration,
+    /// Level below which the signal counts as background noise, in dBFS
+    ///
+    /// The gain is held while the signal stays below it, so pauses do not
+    /// pull the noise floor up to the target level.
+    pub noise_gate_db: f32,
+    /// Peak level the output never exceeds, in dBFS
+    pub limiter_threshold_db: f32,
+    /// Follow the level and report the gain without applying it
+    pub bypass: bool,
+}
+
+impl Default for AgcConfig {
+    fn default() -> Self {
+        Self {
+            target_level_db: -20.0,
+            max_gain_db: 30.0,
+            min_gain_db: -20.0,
+            attack: Duration::from_millis(20),
+            release: Duration::from_secs(1),
+            noise_gate_db: -60.0,
+            limiter_threshold_db: -1.0,
+            bypass: false,
+        }
+    }
+}
+
+impl AgcConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_target_level_db(mut self, target_level_db: f32) -> Self {
+        self.target_level_db = target_level_db;
+        self
+    }
+
+    pub fn with_max_gain_db(mut self, max_gain_db: f32) -> Self {
+        self.max_gain_db = max_gain_db;
+        self
+    }
+
+    pub fn with_min_gain_db(mut self, min_gain_db: f32) -> Self {
+        self.min_gain_db = min_gain_db;
+        self
+    }
+
+    pub fn with_attack(mut self, attack: Duration) -> Self {
+        self.attack = attack;
+        self
+    }
+
+    pub fn with_release(mut self, release: Duration) -> Self {
+        self.release = release;
+        self
+    }
+
+    pub fn with_noise_gate_db(mut self, noise_gate_db: f32) -> Self {
+        self.noise_gate_db = noise_gate_db;
+        self
+    }
+
+    pub fn with_limiter_threshold_db(mut self, limiter_threshold_db: f32) -> Self {
+        self.limiter_threshold_db = limiter_threshold_db;
+        self
+    }
+
+    pub fn with_bypass(mut self, bypass: bool) -> Self {
+        self.bypass = bypass;
+        self
+    }
+}
+
+/// Automatic gain control with a peak limiter
+///
+/// Chunks of any channel count are processed with one gain for all
+/// channels; the level is measured across them.
+pub struct Agc {
+    config: AgcConfig,
+    sample_rate: u32,
+    frame_len: usize,
+    /// Energy and length of the level frame being filled
+    energy: f64,
+    filled: usize,
+    /// Gain the current level frame ramps towards, in dB
+    target_gain_db: f32,
+    /// Linear gain applied to the previous frame
+    current_gain: f32,
+    /// Per-frame change of the linear gain within the level frame
+    gain_step: f32,
+    limiter_gain: f32,
+    limiter_release: f32,
+    limiter_threshold: f32,
+}
+
+impl Agc {
+    pub fn new(config: AgcConfig) -> Self {
+        let limiter_threshold = db_to_linear(config.limiter_threshold_db.min(0.0));
+        Self {
+            config,
+            sample_rate: 0,
+            frame_len: 1,
+            energy: 0.0,
+            filled: 0,
+            target_gain_db: 0.0,
+            current_gain: 1.0,
+            gain_step: 0.0,
+            limiter_gain: 1.0,
+            limiter_release: 1.0,
+            limiter_threshold,
+        }
+    }
+
+    pub fn config(&self) -> &AgcConfig {
+        &self.config
+    }
+
+    /// Gain currently applied before the limiter, in dB
+    ///
+    /// With [`AgcConfig::bypass`], the gain that would be applied.
+    pub fn gain_db(&self) -> f32 {
+        20.0 * self.current_gain.max(f32::MIN_POSITIVE).log10()
+    }
+
+    /// Attenuation currently applied by the limiter, in dB; zero or below
+    pub fn limiter_db(&self) -> f32 {
+        20.0 * self.limiter_gain.max(f32::MIN_POSITIVE).log10()
+    }
+
+    /// Whether the gain has reached the configured maximum
+    pub fn at_max_gain(&self) -> bool {
+        self.gain_db() >= self.config.max_gain_db - 0.5
+    }
+
+    /// Return to unity gain, forgetting the measured level
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config.clone());
+    }
+
+    /// Apply the gain to `chunk`
+    pub fn process(&mut self, mut chunk: AudioChunk) -> AudioChunk {
+        if chunk.sample_rate != self.sample_rate {
+            self.sample_rate = chunk.sample_rate;
+            self.frame_len = duration_to_frames(LEVEL_FRAME, chunk.sample_rate).max(1) as usize;
+            self.limiter_release = 1.0 / (LIMITER_RELEASE.as_secs_f32() * chunk.sample_rate as f32).max(1.0);
+            self.energy = 0.0;
+            self.filled = 0;
+        }
+
+        let channels = chunk.channels.max(1) as usize;
+        for frame in chunk.samples.chunks_exact_mut(channels) {
+            self.energy += frame.iter().map(|&s| (s * s) as f64).sum::<f64>() / channels as f64;
+            self.filled += 1;
+            if self.filled == self.frame_len {
+                self.update_gain();
+            }
+
+            self.current_gain += self.gain_step;
+            if self.config.bypass {
+                continue;
+            }
+            let gain = self.current_gain;
+            let peak = frame.iter().fold(0.0f32, |peak, s| peak.max((s * gain).abs()));
+
+            // Recover smoothly, clamp peaks instantly
+            self.limiter_gain += (1.0 - self.limiter_gain) * self.limiter_release;
+            if peak * self.limiter_gain > self.limiter_threshold {
+                self.limiter_gain = self.limiter_threshold / peak;
+            }
+
+            for sample in frame.iter_mut() {
+                *sample *= gain * self.limiter_gain;
+            }
+        }
+
+        chunk
+    }
+
+    /// Move the gain towards the level of the frame just completed
+    fn update_gain(&mut self) {
+        let mean = self.energy / self.filled as f64;
+        self.energy = 0.0;
+        self.filled = 0;
+
+        let level_db = if mean > 0.0 { 10.0 * mean.log10() as f32 } else { f32::NEG_INFINITY };
+        if level_db >= self.config.noise_gate_db {
+            let desired = (self.config.target_level_db - level_db)
+                .clamp(self.config.min_gain_db.min(0.0), self.config.max_gain_db.max(0.0));
+
+            let time_constant = if desired < self.target_gain_db {
+                self.config.attack
+            } else {
+                self.config.release
+            };
+            self.target_gain_db += (desired - self.target_gain_db) * smoothing(LEVEL_FRAME, time_constant);
+        }
+
+        // Ramp across the next level frame to avoid steps in the gain
+        let target = db_to_linear(self.target_gain_db);
+        self.gain_step = (target - self.current_gain) / self.frame_len as f32;
+    }
+}
+
+impl AudioProcessor for Agc {
+    fn process(&mut self, chunk: AudioChunk) -> Result<Vec<AudioChunk>, AudioError> {
+        Ok(vec![Agc::process(self, chunk)])
+    }
+
+    fn reset(&mut self) {
+        Agc::reset(self);
+    }
+}
+
+/// Adapter applying an [`Agc`] to a stream
+///
+/// Errors pass through unchanged; the gain carries over rate changes.
+pub struct AgcStream<S> {
+    stream: ProcessedStream<S, Agc>,
+}
+
+impl<S: AudioStream> AgcStream<S> {
+    pub fn new(inner: S, config: AgcConfig) -> Self {
+        Self {
+            stream: ProcessedStream::new(inner, Agc::new(config)),
+        }
+    }
+
+    /// The wrapped stream
+    pub fn get_ref(&self) -> &S {
+        self.stream.get_ref()
+    }
+
+    pub fn agc(&self) -> &Agc {
+        self.stream.processor()
+    }
+
+    /// Gain currently applied before the limiter, in dB
+    pub fn gain_db(&self) -> f32 {
+        self.agc().gain_db()
+    }
+}
+
+impl<S: AudioStream> AudioStream for AgcStream<S> {
+    fn sample_rate(&self) -> u32 {
+        self.stream.sample_rate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.stream.channels()
+    }
+}
+
+impl<S: AudioStream> FuturesStream for AgcStream<S> {
+    type Item = Result<AudioChunk, AudioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.stream).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ChunkStream;
+    use futures::StreamExt;
+    use std::f64::consts::PI;
+
+    fn sine(amplitude: f32) -> impl Fn(f64) -> f32 {
+        move |t| amplitude * (2.0 * PI * 440.0 * t).sin() as f32
+    }
+
+    /// RMS level in dBFS
+    fn level_db(samples: &[f32]) -> f32 {
+        let power = samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32;
+        10.0 * power.log10()
+    }
+
+    fn run(config: AgcConfig, seconds: f64, signal: impl Fn(f64) -> f32) -> (Vec<f32>, Agc) {
+        let mut agc = Agc::new(config);
+        let samples = futures::executor::block_on_stream(ChunkStream::new(16000, seconds, 0.0, signal))
+            .flat_map(|chunk| agc.process(chunk.unwrap()).samples)
+            .collect();
+        (samples, agc)
+    }
+
+    #[test]
+    fn test_bypass_reports_gain_without_applying_it() {
+        let (samples, agc) = run(AgcConfig::default().with_bypass(true), 8.0, sine(0.0316));
+        assert!((agc.gain_db() - 13.0).abs() < 0.5, "gain {} dB", agc.gain_db());
+        assert!((level_db(&samples[112000..]) + 33.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_quiet_input_is_raised_up_to_max_gain() {
+        // A sine at -33 dBFS needs 13 dB
+        let (samples, agc) = run(AgcConfig::default(), 8.0, sine(0.0316));
+        assert!((agc.gain_db() - 13.0).abs() < 0.5, "gain {} dB", agc.gain_db());
+        assert!((level_db(&samples[112000..]) + 20.0).abs() < 0.5);
+
+        // At -63 dBFS the maximum gain is reached before the target
+        let config = AgcConfig::default().with_noise_gate_db(-70.0);
+        let (samples, agc) = run(config, 8.0, sine(0.001));
+        assert!(agc.at_max_gain(), "gain {} dB", agc.gain_db());
+        assert!((level_db(&samples[112000..]) + 33.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_noise_below_gate_holds_gain() {
+        let (_, agc) = run(AgcConfig::default(), 4.0, sine(0.0005));
+        assert_eq!(agc.gain_db(), 0.0);
+    }
+
+    #[test]
+    fn test_limiter_catches_sudden_peaks() {
+        // Amplified whisper followed by a shout the gain has not reacted to
+        let signal = |t: f64| if t < 4.0 { sine(0.01)(t) } else { sine(0.9)(t) };
+        let (samples, _) = run(AgcConfig::default(), 5.0, signal);
+
+        let threshold = db_to_linear(-1.0);
+        let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak <= threshold + 1e-6, "peak {}", peak);
+
+        // The loud part ends up at the target level again
+        assert!((level_db(&samples[76000..]) + 20.0).abs() < 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_stream_reports_gain() {
+        let input = ChunkStream::new(16000, 1.0, 0.0, sine(0.5));
+        let mut stream = AgcStream::new(input, AgcConfig::default());
+
+        let chunks = stream.by_ref().count().await;
+        assert_eq!(chunks, 100);
+        assert!(stream.gain_db() < -5.0, "gain {} dB", stream.gain_db());
+    }
+}
@@ -0,0 +1,340 @@
+//! Detection of speaker audio in the microphone
+//!
+//! Without headphones, what the speakers play reaches the microphone a
+//! moment later, and the remote side of a call ends up in the transcript
+//! twice. A [`BleedDetector`] correlates the microphone with the speaker
+//! capture to find that copy, measures how loud it arrives and how late,
+//! and decides whether the bleed is there. [`BleedStream`] runs it on a
+//! microphone and a speaker stream. The measured delay is the one an
+//! [`EchoCanceller`](crate::EchoCanceller) looks for.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::Stream as FuturesStream;
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::{duration_to_frames, frames_to_duration, AudioChunk};
+use crate::echo::{DelayEstimator, EnvelopeMatch, ACTIVITY_FLOOR};
+use crate::error::AudioError;
+use crate::meter::SILENCE_DB;
+use crate::mixer::{MixMode, MixStream, MixerConfig};
+use crate::traits::AudioStream;
+
+/// Estimates in a row that must find bleed before it counts as detected
+const CONFIRMATIONS: usize = 2;
+
+/// Configuration of a [`BleedDetector`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct BleedConfig {
+    /// Analysis rate in Hz
+    pub sample_rate: u32,
+    /// Largest delay between playback and its bleed that is searched for
+    pub max_delay: Duration,
+    /// Correlation of the signal envelopes from which the speaker counts
+    /// as present in the microphone, from 0 to 1
+    pub min_correlation: f32,
+    /// Level of the bleed relative to the speaker from which it is
+    /// reported, in dB
+    pub threshold_db: f32,
+    /// Playback without bleed after which a detected bleed counts as gone
+    pub hold: Duration,
+}
+
+impl Default for BleedConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 16000,
+            max_delay: Duration::from_millis(500),
+            min_correlation: 0.5,
+            threshold_db: -40.0,
+            hold: Duration::from_secs(10),
+        }
+    }
+}
+
+impl BleedConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn with_min_correlation(mut self, min_correlation: f32) -> Self {
+        self.min_correlation = min_correlation;
+        self
+    }
+
+    pub fn with_threshold_db(mut self, threshold_db: f32) -> Self {
+        self.threshold_db = threshold_db;
+        self
+    }
+
+    pub fn with_hold(mut self, hold: Duration) -> Self {
+        self.hold = hold;
+        self
+    }
+}
+
+/// Measurement of the speaker audio found in the microphone
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct BleedEstimate {
+    /// Level of the bleed relative to the speaker capture, in dB
+    pub level_db: f32,
+    /// Time the played audio takes to reach the microphone
+    pub delay: Duration,
+    /// Correlation of the signal envelopes at that delay
+    pub correlation: f32,
+}
+
+/// Whether speaker audio reaches the microphone, with the latest
+/// measurement of it
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct BleedStatus {
+    pub detected: bool,
+    pub estimate: Option<BleedEstimate>,
+}
+
+/// Finds the speaker capture in the microphone
+///
+/// Both signals are mono at the configured rate and aligned on the capture
+/// clock, as for an [`EchoCanceller`](crate::EchoCanceller). Bleed is
+/// detected after two estimates in a row find it, half a second apart, and
+/// counts as gone after the hold time of playback without it; while the
+/// speaker is silent the status is kept.
+pub struct BleedDetector {
+    config: BleedConfig,
+    estimator: DelayEstimator,
+    hold: u64,
+    /// Frames processed so far
+    frames: u64,
+    /// Speaker energy and frames since the last estimate
+    far_energy: f64,
+    far_frames: u64,
+    /// Frame of the last estimate that found bleed
+    last_bleed: u64,
+    confirmations: usize,
+    status: BleedStatus,
+}
+
+impl BleedDetector {
+    pub fn new(config: BleedConfig) -> Self {
+        let rate = config.sample_rate.max(1);
+        Self {
+            estimator: DelayEstimator::new(rate, config.max_delay),
+            hold: duration_to_frames(config.hold, rate),
+            frames: 0,
+            far_energy: 0.0,
+            far_frames: 0,
+            last_bleed: 0,
+            confirmations: 0,
+            status: BleedStatus::default(),
+            config: BleedConfig {
+                sample_rate: rate,
+                ..config
+            },
+        }
+    }
+
+    pub fn config(&self) -> &BleedConfig {
+        &self.config
+    }
+
+    pub fn status(&self) -> BleedStatus {
+        self.status
+    }
+
+    pub fn is_detected(&self) -> bool {
+        self.status.detected
+    }
+
+    /// Latest measured delay between playback and its bleed
+    pub fn delay(&self) -> Option<Duration> {
+        self.status.estimate.map(|estimate| estimate.delay)
+    }
+
+    /// Forget all measurements
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config.clone());
+    }
+
+    /// Look for `far` in `near`, returning the status when it changed or a
+    /// new measurement was made
+    ///
+    /// Missing speaker frames are treated as silence.
+    pub fn process(&mut self, near: &[f32], far: &[f32]) -> Option<BleedStatus> {
+        let mut changed = false;
+        for (i, &near) in near.iter().enumerate() {
+            let far = far.get(i).copied().unwrap_or(0.0);
+            self.frames += 1;
+            self.far_energy += (far * far) as f64;
+            self.far_frames += 1;
+
+            if let Some(estimate) = self.estimator.push(near, far) {
+                changed |= self.update(estimate);
+            }
+        }
+        changed.then_some(self.status)
+    }
+
+    fn update(&mut self, estimate: EnvelopeMatch) -> bool {
+        let far_power = self.far_energy / self.far_frames.max(1) as f64;
+        self.far_energy = 0.0;
+        self.far_frames = 0;
+
+        // Nothing played, nothing to learn
+        if far_power <= ACTIVITY_FLOOR as f64 {
+            return false;
+        }
+
+        let level_db = if estimate.gain > 0.0 {
+            (20.0 * estimate.gain.log10() as f32).max(SILENCE_DB)
+        } else {
+            SILENCE_DB
+        };
+
+        let correlation = estimate.correlation as f32;
+        if correlation >= self.config.min_correlation && level_db >= self.config.threshold_db {
+            self.status.estimate = Some(BleedEstimate {
+                level_db,
+                delay: frames_to_duration(estimate.delay as u64, self.config.sample_rate),
+                correlation,
+            });
+            self.last_bleed = self.frames;
+            self.confirmations += 1;
+
+            if !self.status.detected && self.confirmations >= CONFIRMATIONS {
+                tracing::info!(estimate = ?self.status.estimate, "Speaker bleed detected");
+                self.status.detected = true;
+            }
+            return true;
+        }
+
+        self.confirmations = 0;
+        if self.status.detected && self.frames - self.last_bleed >= self.hold {
+            tracing::info!("Speaker bleed gone");
+            self.status.detected = false;
+            return true;
+        }
+        false
+    }
+}
+
+/// Bleed measurements of a microphone stream against a speaker stream
+///
+/// Both sources are resampled to the analysis rate and aligned on their
+/// capture timestamps, as in a [`MixStream`]. Yields the status of the
+/// detector whenever it changes or a new measurement is made. Errors of
+/// either source are passed through.
+pub struct BleedStream<M, S> {
+    inner: MixStream<M, S>,
+    detector: BleedDetector,
+}
+
+impl<M: AudioStream, S: AudioStream> BleedStream<M, S> {
+    pub fn new(mic: M, speaker: S, config: BleedConfig) -> Self {
+        let detector = BleedDetector::new(config);
+        let mixer = MixerConfig::new()
+            .with_sample_rate(detector.config().sample_rate)
+            .with_mode(MixMode::Separate);
+
+        Self {
+            inner: MixStream::new(mic, speaker, mixer),
+            detector,
+        }
+    }
+
+    pub fn detector(&self) -> &BleedDetector {
+        &self.detector
+    }
+}
+
+impl<M: AudioStream, S: AudioStream> FuturesStream for BleedStream<M, S> {
+    type Item = Result<BleedStatus, AudioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.as_mut().get_mut();
+
+        loop {
+            let chunk: AudioChunk = match futures::ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(chunk)) => chunk,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => return Poll::Ready(None),
+            };
+
+            let (near, far): (Vec<f32>, Vec<f32>) = chunk
+                .samples
+                .chunks_exact(2)
+                .map(|frame| (frame[0], frame[1]))
+                .unzip();
+            if let Some(status) = this.detector.process(&near, &far) {
+                return Poll::Ready(Some(Ok(status)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic white noise in [-0.5, 0.5)
+    fn noise(n: u64, seed: u64) -> f32 {
+        let x = n.wrapping_add(seed << 32).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        (x >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+    }
+
+    /// Speech-like speaker audio: noise under a slow syllable envelope
+    fn speaker(n: u64) -> f32 {
+        let t = n as f64 / 16000.0;
+        noise(n, 1) * (0.6 + 0.4 * (2.0 * std::f64::consts::PI * 3.0 * t).sin() as f32)
+    }
+
+    #[test]
+    fn test_detects_bleed_level_and_delay_then_clears() {
+        let mut detector = BleedDetector::new(BleedConfig::default().with_hold(Duration::from_secs(2)));
+        let delay = 1920; // 120 ms
+
+        // The microphone hears the speaker 20 dB down, over some room noise
+        let mut statuses = Vec::new();
+        for block in 0..500u64 {
+            let frames = block * 160..(block + 1) * 160;
+            let far: Vec<f32> = frames.clone().map(speaker).collect();
+            let near: Vec<f32> = frames
+                .map(|n| 0.1 * n.checked_sub(delay).map_or(0.0, speaker) + 0.002 * noise(n, 2))
+                .collect();
+            statuses.extend(detector.process(&near, &far));
+        }
+
+        assert!(detector.is_detected());
+        assert!(statuses.iter().any(|status| !status.detected && status.estimate.is_some()));
+        let estimate = detector.status().estimate.unwrap();
+        assert!(
+            estimate.delay.abs_diff(Duration::from_millis(120)) <= Duration::from_millis(4),
+            "{:?}",
+            estimate
+        );
+        assert!((estimate.level_db + 20.0).abs() < 2.0, "{:?}", estimate);
+
+        // With headphones on, the speaker keeps playing but the microphone
+        // only hears the room
+        for block in 500..1000u64 {
+            let frames = block * 160..(block + 1) * 160;
+            let far: Vec<f32> = frames.clone().map(speaker).collect();
+            let near: Vec<f32> = frames.map(|n| 0.05 * noise(n, 2)).collect();
+            detector.process(&near, &far);
+        }
+        assert!(!detector.is_detected());
+        assert_eq!(detector.delay(), Some(estimate.delay));
+    }
+}
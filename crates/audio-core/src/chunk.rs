@@ -0,0 +1,161 @@
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Process-wide reference point of the capture clock
+static CLOCK_EPOCH: OnceLock<Instant> = OnceLock::new();
+
+/// Current time on the monotonic capture clock
+///
+/// All backends stamp chunks against this clock, so timestamps from
+/// different inputs in the same process can be compared directly.
+pub fn capture_clock_now() -> Duration {
+    CLOCK_EPOCH.get_or_init(Instant::now).elapsed()
+}
+
+/// Block of captured audio with its timing
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioChunk {
+    /// Interleaved samples
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Capture time of the first frame on the [`capture_clock_now`] clock
+    pub timestamp: Duration,
+    /// Position of this chunk in its stream, starting at 0
+    pub sequence: u64,
+    /// Silence standing in for audio that was never captured
+    pub gap_fill: bool,
+}
+
+impl AudioChunk {
+    pub fn new(
+        samples: Vec<f32>,
+        sample_rate: u32,
+        channels: u16,
+        timestamp: Duration,
+        sequence: u64,
+    ) -> Self {
+        Self {
+            samples,
+            sample_rate,
+            channels,
+            timestamp,
+            sequence,
+            gap_fill: false,
+        }
+    }
+
+    /// Create a chunk of `frames` silent frames marked as [`AudioChunk::gap_fill`]
+    pub fn silence(
+        frames: usize,
+        sample_rate: u32,
+        channels: u16,
+        timestamp: Duration,
+        sequence: u64,
+    ) -> Self {
+        Self {
+            gap_fill: true,
+            ..Self::new(
+                vec![0.0; frames * channels.max(1) as usize],
+                sample_rate,
+                channels,
+                timestamp,
+                sequence,
+            )
+        }
+    }
+
+    /// Number of frames (samples per channel)
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Duration of audio in this chunk
+    pub fn duration(&self) -> Duration {
+        frames_to_duration(self.frames() as u64, self.sample_rate)
+    }
+
+    /// Capture time just after the last frame
+    pub fn end_timestamp(&self) -> Duration {
+        self.timestamp + self.duration()
+    }
+}
+
+/// Duration of `frames` at `sample_rate`
+pub fn frames_to_duration(frames: u64, sample_rate: u32) -> Duration {
+    if sample_rate == 0 {
+        return Duration::ZERO;
+    }
+
+    let secs = frames / sample_rate as u64;
+    let rem = frames % sample_rate as u64;
+    Duration::new(secs, (rem * 1_000_000_000 / sample_rate as u64) as u32)
+}
+
+/// Number of whole frames at `sample_rate` that fit in `duration`
+pub fn duration_to_frames(duration: Duration, sample_rate: u32) -> u64 {
+    (duration.as_nanos() * sample_rate as u128 / 1_000_000_000) as u64
+}
+
+/// Maps frame positions in a buffered stream to capture timestamps
+///
+/// Ring-buffered backends lose chunk boundaries between the audio callback
+/// and the consumer. The producer records the timestamp of every block it
+/// writes via [`FrameClock::anchor`], and the consumer derives the
+/// timestamp of any later read position from the most recent anchor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameClock {
+    anchor_frame: u64,
+    anchor_timestamp: Duration,
+}
+
+impl FrameClock {
+    /// Record that frame `frame` was captured at `timestamp`
+    pub fn anchor(&mut self, frame: u64, timestamp: Duration) {
+        self.anchor_frame = frame;
+        self.anchor_timestamp = timestamp;
+    }
+
+    /// Capture timestamp of frame `frame`
+    pub fn timestamp_of(&self, frame: u64, sample_rate: u32) -> Duration {
+        if frame >= self.anchor_frame {
+            self.anchor_timestamp + frames_to_duration(frame - self.anchor_frame, sample_rate)
+        } else {
+            self.anchor_timestamp
+                .saturating_sub(frames_to_duration(self.anchor_frame - frame, sample_rate))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_duration() {
+        let chunk = AudioChunk::new(vec![0.0; 960], 48000, 2, Duration::from_millis(5), 0);
+        assert_eq!(chunk.frames(), 480);
+        assert_eq!(chunk.duration(), Duration::from_millis(10));
+        assert_eq!(chunk.end_timestamp(), Duration::from_millis(15));
+    }
+
+    #[test]
+    fn test_frame_clock_interpolates_from_anchor() {
+        let mut clock = FrameClock::default();
+        clock.anchor(16000, Duration::from_secs(10));
+
+        assert_eq!(clock.timestamp_of(24000, 16000), Duration::from_millis(10500));
+        assert_eq!(clock.timestamp_of(8000, 16000), Duration::from_millis(9500));
+    }
+
+    #[test]
+    fn test_capture_clock_is_monotonic() {
+        let a = capture_clock_now();
+        let b = capture_clock_now();
+        assert!(b >= a);
+    }
+}
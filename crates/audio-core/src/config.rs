@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+
+use crate::device::SampleRateRange;
+
+/// Channel layout requested from an input
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ChannelMode {
+    /// Downmix every device channel into one
+    #[default]
+    Mono,
+    /// Two interleaved channels; mono devices are duplicated
+    Stereo,
+    /// Every channel the device offers, interleaved
+    Native,
+}
+
+impl ChannelMode {
+    /// Number of channels delivered for a device with `device_channels`
+    pub fn output_channels(self, device_channels: u16) -> u16 {
+        match self {
+            ChannelMode::Mono => 1,
+            ChannelMode::Stereo => 2,
+            ChannelMode::Native => device_channels.max(1),
+        }
+    }
+
+    /// Preference score of a device channel count (lower is better)
+    fn channel_penalty(self, device_channels: u16) -> u32 {
+        let wanted = match self {
+            ChannelMode::Mono => 1,
+            ChannelMode::Stereo => 2,
+            // More channels are better; the exact count does not matter
+            ChannelMode::Native => return u32::from(u16::MAX - device_channels),
+        };
+
+        // Prefer the exact count, then more channels over fewer
+        match device_channels.cmp(&wanted) {
+            std::cmp::Ordering::Equal => 0,
+            std::cmp::Ordering::Greater => u32::from(device_channels - wanted),
+            std::cmp::Ordering::Less => u32::from(u16::MAX) + u32::from(wanted - device_channels),
+        }
+    }
+}
+
+/// Requested configuration for an [`crate::AudioInput`]
+///
+/// Every field is a preference. Backends pick the closest configuration the
+/// device supports and report it as a [`StreamFormat`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AudioInputConfig {
+    /// Device id from [`crate::AudioDevice::id`]; `None` opens the default device
+    pub device_id: Option<String>,
+    /// Preferred sample rate in Hz; `None` keeps the device's default rate
+    pub sample_rate: Option<u32>,
+    pub channels: ChannelMode,
+    /// Preferred hardware buffer size in frames; `None` lets the backend decide
+    pub buffer_frames: Option<u32>,
+}
+
+impl AudioInputConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_device_id(mut self, device_id: impl Into<String>) -> Self {
+        self.device_id = Some(device_id.into());
+        self
+    }
+
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    pub fn with_channels(mut self, channels: ChannelMode) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    pub fn with_buffer_frames(mut self, buffer_frames: u32) -> Self {
+        self.buffer_frames = Some(buffer_frames);
+        self
+    }
+}
+
+/// Configuration an input actually negotiated with the device
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StreamFormat {
+    pub sample_rate: u32,
+    /// Number of interleaved channels in every chunk
+    pub channels: u16,
+    /// Hardware buffer size in frames, if the backend controls it
+    pub buffer_frames: Option<u32>,
+}
+
+/// One configuration range offered by a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfigCandidate {
+    pub channels: u16,
+    pub sample_rates: SampleRateRange,
+}
+
+/// Outcome of [`negotiate`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiated {
+    /// Index into the candidate list
+    pub index: usize,
+    pub sample_rate: u32,
+}
+
+/// Pick the candidate closest to `config`
+///
+/// The sample rate matters most, since resampling later costs CPU and
+/// quality, followed by the channel layout. Among equally close rates the
+/// higher one wins. `default_rate` stands in for an unset preferred rate.
+pub fn negotiate(
+    config: &AudioInputConfig,
+    candidates: &[ConfigCandidate],
+    default_rate: u32,
+) -> Option<Negotiated> {
+    let wanted_rate = config.sample_rate.unwrap_or(default_rate);
+
+    candidates
+        .iter()
+        .enumerate()
+        .map(|(index, candidate)| {
+            let sample_rate = candidate.sample_rates.clamp(wanted_rate);
+            let distance = sample_rate.abs_diff(wanted_rate);
+            let penalty = config.channels.channel_penalty(candidate.channels);
+            (index, sample_rate, distance, penalty)
+        })
+        .min_by_key(|&(_, sample_rate, distance, penalty)| {
+            (distance, penalty, std::cmp::Reverse(sample_rate))
+        })
+        .map(|(index, sample_rate, _, _)| Negotiated { index, sample_rate })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(channels: u16, min: u32, max: u32) -> ConfigCandidate {
+        ConfigCandidate {
+            channels,
+            sample_rates: SampleRateRange::new(min, max),
+        }
+    }
+
+    #[test]
+    fn test_negotiate_prefers_exact_rate() {
+        let candidates = [candidate(2, 44100, 48000), candidate(2, 8000, 16000)];
+        let config = AudioInputConfig::new().with_sample_rate(16000);
+
+        let negotiated = negotiate(&config, &candidates, 48000).unwrap();
+        assert_eq!(negotiated, Negotiated { index: 1, sample_rate: 16000 });
+    }
+
+    #[test]
+    fn test_negotiate_falls_back_to_closest_rate() {
+        let candidates = [candidate(1, 44100, 48000)];
+        let config = AudioInputConfig::new().with_sample_rate(16000);
+
+        let negotiated = negotiate(&config, &candidates, 48000).unwrap();
+        assert_eq!(negotiated.sample_rate, 44100);
+    }
+
+    #[test]
+    fn test_negotiate_uses_channel_mode() {
+        let candidates = [candidate(2, 48000, 48000), candidate(1, 48000, 48000)];
+
+        let mono = negotiate(&AudioInputConfig::new(), &candidates, 48000).unwrap();
+        assert_eq!(mono.index, 1);
+
+        let stereo = AudioInputConfig::new().with_channels(ChannelMode::Stereo);
+        assert_eq!(negotiate(&stereo, &candidates, 48000).unwrap().index, 0);
+    }
+}
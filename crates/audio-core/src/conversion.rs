@@ -58,6 +58,33 @@ pub fn convert_to_mono(data: &[f32], channels: usize) -> Vec<f32> {
         .collect()
 }
 
+/// Convert interleaved audio from `from` to `to` channels
+///
+/// Downmixing to mono averages all channels and mono input is copied into
+/// every output channel. Otherwise channels are matched by position: extra
+/// input channels are dropped and missing ones are filled with silence.
+pub fn remix_channels(data: &[f32], from: usize, to: usize) -> Vec<f32> {
+    if from == to {
+        return data.to_vec();
+    }
+
+    if to == 1 {
+        return convert_to_mono(data, from);
+    }
+
+    let mut output = Vec::with_capacity(data.len() / from * to);
+
+    for frame in data.chunks_exact(from) {
+        if from == 1 {
+            output.extend(std::iter::repeat_n(frame[0], to));
+        } else {
+            output.extend((0..to).map(|ch| frame.get(ch).copied().unwrap_or(0.0)));
+        }
+    }
+
+    output
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -93,4 +120,11 @@ mod tests {
         let mono_output = convert_to_mono(&mono_input, 1);
         assert_eq!(mono_input, mono_output);
     }
+
+    #[test]
+    fn test_remix_channels() {
+        assert_eq!(remix_channels(&[0.25, -0.5], 1, 2), vec![0.25, 0.25, -0.5, -0.5]);
+        assert_eq!(remix_channels(&[0.1, 0.2, 0.3, 0.4, 0.5, 0.6], 3, 2), vec![0.1, 0.2, 0.4, 0.5]);
+        assert_eq!(remix_channels(&[0.5, -0.5], 2, 1), vec![0.0]);
+    }
 }
@@ -0,0 +1,268 @@
+//! Helpers for backends built on cpal
+//!
+//! The Linux and macOS backends both open devices through cpal, so the
+//! mapping from cpal stream configurations onto [`AudioDevice`](crate::AudioDevice)
+//! capabilities, and the handling of cpal stream errors, live here rather
+//! than in each backend.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use cpal::traits::DeviceTrait;
+
+use crate::device::{DeviceType, SampleFormat, SampleRateRange};
+use crate::error::AudioError;
+
+/// Backend errors in a row, with no audio in between, after which a device
+/// counts as lost
+const MAX_BACKEND_ERRORS: u32 = 8;
+
+/// Minimum time between two logs of backend errors
+const BACKEND_ERROR_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Description of the error cpal's ALSA host reports for a device that went
+/// away
+const ALSA_POLL_ERROR: &str = "`alsa::poll()` returned POLLERR";
+
+/// Channel count, sample rate ranges and sample formats of a device
+pub type Capabilities = (u16, Vec<SampleRateRange>, Vec<SampleFormat>);
+
+/// Collect channel count, sample rate ranges and formats from cpal
+///
+/// Returns `None` if the device has no stream in the direction of
+/// `device_type`; monitors are read like inputs.
+pub fn cpal_capabilities(device: &cpal::Device, device_type: &DeviceType) -> Option<Capabilities> {
+    let configs: Vec<cpal::SupportedStreamConfigRange> = match device_type {
+        DeviceType::Output => device
+            .supported_output_configs()
+            .map(|c| c.collect())
+            .unwrap_or_default(),
+        _ => device
+            .supported_input_configs()
+            .map(|c| c.collect())
+            .unwrap_or_default(),
+    };
+
+    merge_configs(&configs)
+}
+
+/// Merge the stream configurations of a device into its capabilities
+fn merge_configs(configs: &[cpal::SupportedStreamConfigRange]) -> Option<Capabilities> {
+    if configs.is_empty() {
+        return None;
+    }
+
+    let mut channels = 0;
+    let mut sample_rates = Vec::new();
+    let mut sample_formats = Vec::new();
+
+    for config in configs {
+        channels = channels.max(config.channels());
+
+        let range = SampleRateRange::new(config.min_sample_rate().0, config.max_sample_rate().0);
+        if !sample_rates.contains(&range) {
+            sample_rates.push(range);
+        }
+
+        if let Some(format) = cpal_sample_format(config.sample_format()) {
+            if !sample_formats.contains(&format) {
+                sample_formats.push(format);
+            }
+        }
+    }
+
+    Some((channels, sample_rates, sample_formats))
+}
+
+/// Map a cpal sample format onto the core sample formats
+pub fn cpal_sample_format(format: cpal::SampleFormat) -> Option<SampleFormat> {
+    match format {
+        cpal::SampleFormat::I8 => Some(SampleFormat::I8),
+        cpal::SampleFormat::I16 => Some(SampleFormat::I16),
+        cpal::SampleFormat::I32 => Some(SampleFormat::I32),
+        cpal::SampleFormat::I64 => Some(SampleFormat::I64),
+        cpal::SampleFormat::U8 => Some(SampleFormat::U8),
+        cpal::SampleFormat::U16 => Some(SampleFormat::U16),
+        cpal::SampleFormat::U32 => Some(SampleFormat::U32),
+        cpal::SampleFormat::U64 => Some(SampleFormat::U64),
+        cpal::SampleFormat::F32 => Some(SampleFormat::F32),
+        cpal::SampleFormat::F64 => Some(SampleFormat::F64),
+        _ => None,
+    }
+}
+
+/// Maps asynchronous cpal stream errors to the error ending the stream
+///
+/// cpal keeps a stream running after backend-specific errors such as
+/// overruns, so those are only logged, at most once every few seconds. Once
+/// running, the ALSA host never reports
+/// [`DeviceNotAvailable`](cpal::StreamError::DeviceNotAvailable) though: an
+/// unplugged device shows up as a POLLERR, repeated for as long as its worker
+/// keeps polling. A POLLERR, or a run of backend errors with no audio in
+/// between, is therefore a lost device too.
+pub struct StreamErrorFilter {
+    device_name: String,
+    /// Backend errors since the last audio, reset through [`StreamActivity`]
+    errors: Arc<AtomicU32>,
+    last_log: Option<Instant>,
+    /// Backend errors left out of the log since `last_log`
+    unlogged: u32,
+    /// Set once the loss of the device was reported
+    lost: bool,
+}
+
+impl StreamErrorFilter {
+    pub fn new(device_name: impl Into<String>) -> Self {
+        Self {
+            device_name: device_name.into(),
+            errors: Arc::new(AtomicU32::new(0)),
+            last_log: None,
+            unlogged: 0,
+            lost: false,
+        }
+    }
+
+    /// Handle the data callback uses to report incoming audio
+    pub fn activity(&self) -> StreamActivity {
+        StreamActivity(self.errors.clone())
+    }
+
+    /// Map `err` to the error ending the stream
+    ///
+    /// Returns `None` for errors the stream survives, and for every error
+    /// after the loss of the device was reported.
+    pub fn filter(&mut self, err: cpal::StreamError) -> Option<AudioError> {
+        if self.lost {
+            return None;
+        }
+
+        let description = match err {
+            cpal::StreamError::DeviceNotAvailable => return Some(self.device_lost()),
+            cpal::StreamError::BackendSpecific { err } => err.description,
+        };
+
+        let errors = self.errors.fetch_add(1, Ordering::Relaxed) + 1;
+        if description == ALSA_POLL_ERROR || errors >= MAX_BACKEND_ERRORS {
+            tracing::warn!(device = %self.device_name, errors, "Audio backend error: {}", description);
+            return Some(self.device_lost());
+        }
+
+        let now = Instant::now();
+        if self
+            .last_log
+            .is_some_and(|last| now.duration_since(last) < BACKEND_ERROR_LOG_INTERVAL)
+        {
+            self.unlogged += 1;
+            return None;
+        }
+        tracing::warn!(
+            device = %self.device_name,
+            unlogged = self.unlogged,
+            "Audio backend error: {}",
+            description
+        );
+        self.last_log = Some(now);
+        self.unlogged = 0;
+        None
+    }
+
+    fn device_lost(&mut self) -> AudioError {
+        self.lost = true;
+        AudioError::DeviceDisconnected(self.device_name.clone())
+    }
+}
+
+/// Reports audio arriving on a stream watched by a [`StreamErrorFilter`]
+#[derive(Debug, Clone)]
+pub struct StreamActivity(Arc<AtomicU32>);
+
+impl StreamActivity {
+    /// Note that the stream delivered audio, so it is still running
+    pub fn audio_received(&self) {
+        self.0.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::{SampleRate, SupportedBufferSize};
+
+    fn config(channels: u16, min: u32, max: u32, format: cpal::SampleFormat) -> cpal::SupportedStreamConfigRange {
+        cpal::SupportedStreamConfigRange::new(
+            channels,
+            SampleRate(min),
+            SampleRate(max),
+            SupportedBufferSize::Unknown,
+            format,
+        )
+    }
+
+    #[test]
+    fn test_merge_configs() {
+        let configs = [
+            config(1, 8000, 48000, cpal::SampleFormat::I16),
+            config(2, 8000, 48000, cpal::SampleFormat::I16),
+            config(2, 8000, 48000, cpal::SampleFormat::F32),
+            config(2, 96000, 96000, cpal::SampleFormat::F32),
+        ];
+
+        let (channels, sample_rates, sample_formats) = merge_configs(&configs).unwrap();
+        assert_eq!(channels, 2);
+        assert_eq!(sample_rates, [SampleRateRange::new(8000, 48000), SampleRateRange::fixed(96000)]);
+        assert_eq!(sample_formats, [SampleFormat::I16, SampleFormat::F32]);
+    }
+
+    #[test]
+    fn test_merge_configs_without_streams() {
+        assert_eq!(merge_configs(&[]), None);
+    }
+
+    fn backend_error(description: &str) -> cpal::StreamError {
+        cpal::StreamError::BackendSpecific {
+            err: cpal::BackendSpecificError {
+                description: description.to_string(),
+            },
+        }
+    }
+
+    fn is_lost(error: Option<AudioError>) -> bool {
+        matches!(error, Some(AudioError::DeviceDisconnected(name)) if name == "USB mic")
+    }
+
+    #[test]
+    fn test_filter_device_not_available_is_lost() {
+        let mut filter = StreamErrorFilter::new("USB mic");
+        assert!(is_lost(filter.filter(cpal::StreamError::DeviceNotAvailable)));
+
+        // The loss is only reported once
+        assert!(filter.filter(cpal::StreamError::DeviceNotAvailable).is_none());
+    }
+
+    #[test]
+    fn test_filter_alsa_poll_error_is_lost() {
+        let mut filter = StreamErrorFilter::new("USB mic");
+        assert!(is_lost(filter.filter(backend_error(ALSA_POLL_ERROR))));
+        assert!(filter.filter(backend_error(ALSA_POLL_ERROR)).is_none());
+    }
+
+    #[test]
+    fn test_filter_backend_errors_without_audio_are_lost() {
+        let mut filter = StreamErrorFilter::new("USB mic");
+        for _ in 1..MAX_BACKEND_ERRORS {
+            assert!(filter.filter(backend_error("overrun")).is_none());
+        }
+        assert!(is_lost(filter.filter(backend_error("overrun"))));
+    }
+
+    #[test]
+    fn test_filter_backend_errors_between_audio_are_survived() {
+        let mut filter = StreamErrorFilter::new("USB mic");
+        let activity = filter.activity();
+        for _ in 0..MAX_BACKEND_ERRORS * 4 {
+            assert!(filter.filter(backend_error("overrun")).is_none());
+            activity.audio_received();
+        }
+    }
+}
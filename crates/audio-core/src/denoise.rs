@@ -0,0 +1,614 @@
+//! Noise suppression by spectral subtraction
+//!
+//! Steady background noise such as ventilation hum or fan noise has a
+//! spectrum that changes slowly, while speech comes and goes. A
+//! [`NoiseSuppressor`] splits the signal into overlapping frames, learns
+//! the noise level in every frequency band from the frames where the band
+//! holds nothing louder, and attenuates each band by how much of its energy
+//! the noise accounts for. [`NoiseSuppression`] applies it as a pipeline
+//! stage and [`NoiseSuppressStream`] to a stream; both can hand out the
+//! unprocessed audio alongside, aligned sample for sample, so both versions
+//! can be recorded and compared.
+
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::channel::mpsc;
+use futures::Stream as FuturesStream;
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::{frames_to_duration, AudioChunk, FrameClock};
+use crate::error::AudioError;
+use crate::fft::{Complex, Fft};
+use crate::processor::{AudioProcessor, ProcessedStream};
+use crate::traits::AudioStream;
+
+/// Longest analysis frame; the frame is the largest power of two within it
+const MAX_FRAME: Duration = Duration::from_millis(32);
+
+/// Amount of over-subtraction at full strength
+///
+/// Subtracting twice the estimated noise keeps the fluctuations of the
+/// noise around its mean from surviving as isolated tones.
+const OVERSUBTRACTION: f32 = 2.0;
+
+/// Power relative to the noise estimate above which a band holds more
+/// than noise
+///
+/// The power of a noise band fluctuates around its mean; it stays below
+/// four times the mean in 98% of frames.
+const SIGNAL_RATIO: f32 = 4.0;
+
+/// Smoothing factor with which the noise estimate follows bands holding
+/// only noise
+const NOISE_SMOOTHING: f32 = 0.1;
+
+/// Share of the previous frame's gain kept in each band
+///
+/// Smoothing the gains over time removes most of the "musical noise" of
+/// plain spectral subtraction.
+const GAIN_SMOOTHING: f32 = 0.5;
+
+/// Configuration of a [`NoiseSuppressor`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct NoiseSuppressorConfig {
+    /// How much of the estimated noise is removed, from 0 (none) to 1
+    pub strength: f32,
+    /// Largest attenuation of any frequency band, in dB
+    pub max_attenuation_db: f32,
+    /// Time constant with which the noise estimate rises to noise that
+    /// suddenly got much louder
+    pub noise_adaptation: Duration,
+    /// Pass the audio through unprocessed, with the same delay
+    pub bypass: bool,
+}
+
+impl Default for NoiseSuppressorConfig {
+    fn default() -> Self {
+        Self {
+            strength: 0.8,
+            max_attenuation_db: 20.0,
+            noise_adaptation: Duration::from_secs(10),
+            bypass: false,
+        }
+    }
+}
+
+impl NoiseSuppressorConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_strength(mut self, strength: f32) -> Self {
+        self.strength = strength;
+        self
+    }
+
+    pub fn with_max_attenuation_db(mut self, max_attenuation_db: f32) -> Self {
+        self.max_attenuation_db = max_attenuation_db;
+        self
+    }
+
+    pub fn with_noise_adaptation(mut self, noise_adaptation: Duration) -> Self {
+        self.noise_adaptation = noise_adaptation;
+        self
+    }
+
+    pub fn with_bypass(mut self, bypass: bool) -> Self {
+        self.bypass = bypass;
+        self
+    }
+}
+
+/// Audio released by a [`NoiseSuppressor`]
+///
+/// Both buffers hold the same frames, interleaved, before and after
+/// suppression.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Suppressed {
+    pub raw: Vec<f32>,
+    pub cleaned: Vec<f32>,
+}
+
+/// Analysis state of one channel
+struct Channel {
+    /// Input samples not yet fully analysed, starting at the next frame
+    input: Vec<f32>,
+    /// Overlap-add accumulator, starting at the next output sample
+    output: Vec<f32>,
+    /// Estimated noise power per band
+    noise: Option<Vec<f32>>,
+    gains: Vec<f32>,
+}
+
+/// Streaming spectral-subtraction noise suppressor
+///
+/// Works on interleaved audio of a fixed rate and channel count, each
+/// channel on its own. Output lags the input by one analysis frame of
+/// about 20 to 30 ms; [`NoiseSuppressor::flush`] releases the rest at the
+/// end of a stream.
+pub struct NoiseSuppressor {
+    config: NoiseSuppressorConfig,
+    sample_rate: u32,
+    channels: usize,
+    size: usize,
+    hop: usize,
+    fft: Fft,
+    /// Square root of a periodic Hann window, used for analysis and synthesis
+    window: Vec<f32>,
+    state: Vec<Channel>,
+    /// Unprocessed input not yet released, interleaved
+    raw: VecDeque<f32>,
+    /// Output samples per channel still to discard for the initial padding
+    discard: usize,
+    noise_rise: f32,
+    floor: f32,
+}
+
+impl NoiseSuppressor {
+    pub fn new(sample_rate: u32, channels: u16, config: NoiseSuppressorConfig) -> Self {
+        let sample_rate = sample_rate.max(1);
+        let channels = channels.max(1) as usize;
+
+        let longest = (MAX_FRAME.as_secs_f64() * sample_rate as f64) as usize;
+        let size = if longest.is_power_of_two() {
+            longest
+        } else {
+            (longest.next_power_of_two() / 2).max(4)
+        };
+        let hop = size / 2;
+
+        let window = (0..size)
+            .map(|n| (0.5 - 0.5 * (2.0 * PI * n as f64 / size as f64).cos()).sqrt() as f32)
+            .collect();
+
+        let bins = size / 2 + 1;
+        let state = (0..channels)
+            .map(|_| Channel {
+                // Padding puts the first real sample in the second half of the first frame
+                input: vec![0.0; size - hop],
+                output: vec![0.0; size],
+                noise: None,
+                gains: vec![1.0; bins],
+            })
+            .collect();
+
+        let hop_time = hop as f32 / sample_rate as f32;
+        let noise_rise = (hop_time / config.noise_adaptation.as_secs_f32().max(f32::EPSILON)).min(1.0);
+        let floor = 10f32.powf(-config.max_attenuation_db.abs() / 10.0);
+
+        Self {
+            config,
+            sample_rate,
+            channels,
+            size,
+            hop,
+            fft: Fft::new(size),
+            window,
+            state,
+            raw: VecDeque::new(),
+            discard: size - hop,
+            noise_rise,
+            floor,
+        }
+    }
+
+    pub fn config(&self) -> &NoiseSuppressorConfig {
+        &self.config
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    /// Frames by which the output lags the input
+    pub fn latency_frames(&self) -> usize {
+        self.size
+    }
+
+    pub fn set_strength(&mut self, strength: f32) {
+        self.config.strength = strength;
+    }
+
+    pub fn set_bypass(&mut self, bypass: bool) {
+        self.config.bypass = bypass;
+    }
+
+    /// Feed interleaved `samples` and return the frames completed so far
+    pub fn process(&mut self, samples: &[f32]) -> Suppressed {
+        self.raw.extend(samples);
+        for (c, channel) in self.state.iter_mut().enumerate() {
+            channel
+                .input
+                .extend(samples.iter().skip(c).step_by(self.channels));
+        }
+
+        let mut cleaned = Vec::new();
+        while self.state[0].input.len() >= self.size {
+            let mut block = vec![0.0; self.hop * self.channels];
+            for c in 0..self.channels {
+                let hop = self.analyse(c);
+                for (i, sample) in hop.into_iter().enumerate() {
+                    block[i * self.channels + c] = sample;
+                }
+            }
+
+            let skip = self.discard.min(self.hop);
+            self.discard -= skip;
+            cleaned.extend_from_slice(&block[skip * self.channels..]);
+        }
+
+        let raw: Vec<f32> = self.raw.drain(..cleaned.len()).collect();
+        if self.config.bypass {
+            cleaned.clone_from(&raw);
+        }
+        Suppressed { raw, cleaned }
+    }
+
+    /// Release the audio still held, as if followed by silence
+    ///
+    /// The suppressor can be used for a new stream afterwards.
+    pub fn flush(&mut self) -> Suppressed {
+        let remaining = self.raw.len();
+        let mut out = self.process(&vec![0.0; self.size * self.channels]);
+        out.raw.truncate(remaining);
+        out.cleaned.truncate(remaining);
+
+        let config = self.config.clone();
+        *self = Self::new(self.sample_rate, self.channels as u16, config);
+        out
+    }
+
+    /// Process the next frame of channel `c` and return the completed hop
+    fn analyse(&mut self, c: usize) -> Vec<f32> {
+        let size = self.size;
+        let bins = size / 2 + 1;
+        let alpha = OVERSUBTRACTION * self.config.strength.clamp(0.0, 1.0);
+        let channel = &mut self.state[c];
+
+        let mut spectrum: Vec<Complex> = channel.input[..size]
+            .iter()
+            .zip(&self.window)
+            .map(|(&x, &w)| Complex::new(x * w, 0.0))
+            .collect();
+        self.fft.forward(&mut spectrum);
+
+        let power: Vec<f32> = spectrum[..bins].iter().map(|bin| bin.norm_sqr()).collect();
+        let noise = channel.noise.get_or_insert_with(|| power.clone());
+        for (n, &p) in noise.iter_mut().zip(&power) {
+            // Bands carrying speech only nudge the estimate, so steady noise
+            // growing louder is still picked up
+            let rate = if p < SIGNAL_RATIO * *n { NOISE_SMOOTHING } else { self.noise_rise };
+            *n += (p - *n) * rate;
+        }
+
+        for k in 0..bins {
+            let target = if power[k] > 0.0 {
+                (1.0 - alpha * noise[k] / power[k]).max(self.floor).sqrt()
+            } else {
+                1.0
+            };
+            let gain = GAIN_SMOOTHING * channel.gains[k] + (1.0 - GAIN_SMOOTHING) * target;
+            channel.gains[k] = gain;
+
+            spectrum[k] = spectrum[k].scale(gain);
+            if k > 0 && k < size / 2 {
+                spectrum[size - k] = spectrum[k].conj();
+            }
+        }
+        self.fft.inverse(&mut spectrum);
+
+        for ((out, bin), &w) in channel.output.iter_mut().zip(&spectrum).zip(&self.window) {
+            *out += bin.re * w;
+        }
+
+        channel.input.drain(..self.hop);
+        let hop: Vec<f32> = channel.output.drain(..self.hop).collect();
+        channel.output.resize(size, 0.0);
+        hop
+    }
+}
+
+// ============================================================================
+// NoiseSuppression processor
+// ============================================================================
+
+/// Processor suppressing noise
+///
+/// Chunks are delivered with the timestamps of the audio they hold, so the
+/// processing delay does not shift the timeline. A change of sample rate
+/// or channel count starts a new suppressor after the old one has been
+/// drained.
+pub struct NoiseSuppression {
+    config: NoiseSuppressorConfig,
+    /// Created from the first chunk, which sets the format
+    suppressor: Option<NoiseSuppressor>,
+    clock: FrameClock,
+    /// Input and output frames since the suppressor started
+    received: u64,
+    delivered: u64,
+    sequence: u64,
+    raw: Option<mpsc::UnboundedSender<AudioChunk>>,
+}
+
+impl NoiseSuppression {
+    pub fn new(config: NoiseSuppressorConfig) -> Self {
+        Self {
+            config,
+            suppressor: None,
+            clock: FrameClock::default(),
+            received: 0,
+            delivered: 0,
+            sequence: 0,
+            raw: None,
+        }
+    }
+
+    /// Receive the unprocessed audio from now on
+    ///
+    /// Each raw chunk holds the same frames, with the same timestamp and
+    /// sequence number, as the processed chunk delivered with it. Only the
+    /// most recent receiver gets chunks.
+    pub fn subscribe_raw(&mut self) -> mpsc::UnboundedReceiver<AudioChunk> {
+        let (tx, rx) = mpsc::unbounded();
+        self.raw = Some(tx);
+        rx
+    }
+
+    /// The suppressor, once audio has arrived
+    pub fn suppressor(&self) -> Option<&NoiseSuppressor> {
+        self.suppressor.as_ref()
+    }
+
+    pub fn set_strength(&mut self, strength: f32) {
+        self.config.strength = strength;
+        if let Some(suppressor) = self.suppressor.as_mut() {
+            suppressor.set_strength(strength);
+        }
+    }
+
+    /// Deliver the audio unprocessed, keeping the timing unchanged
+    pub fn set_bypass(&mut self, bypass: bool) {
+        self.config.bypass = bypass;
+        if let Some(suppressor) = self.suppressor.as_mut() {
+            suppressor.set_bypass(bypass);
+        }
+    }
+
+    fn chunk(&mut self, audio: Suppressed, rate: u32, channels: u16, gap_fill: bool) -> Vec<AudioChunk> {
+        if audio.cleaned.is_empty() {
+            return Vec::new();
+        }
+
+        let timestamp = self.clock.timestamp_of(self.delivered, rate);
+        self.delivered += (audio.cleaned.len() / channels as usize) as u64;
+
+        if let Some(tx) = &self.raw {
+            let mut raw = AudioChunk::new(audio.raw, rate, channels, timestamp, self.sequence);
+            raw.gap_fill = gap_fill;
+            if tx.unbounded_send(raw).is_err() {
+                self.raw = None;
+            }
+        }
+
+        let mut chunk = AudioChunk::new(audio.cleaned, rate, channels, timestamp, self.sequence);
+        chunk.gap_fill = gap_fill;
+        self.sequence += 1;
+        vec![chunk]
+    }
+}
+
+impl AudioProcessor for NoiseSuppression {
+    fn process(&mut self, chunk: AudioChunk) -> Result<Vec<AudioChunk>, AudioError> {
+        let format = self.suppressor.as_ref().map(|s| (s.sample_rate(), s.channels()));
+        let mut output = Vec::new();
+        if format != Some((chunk.sample_rate, chunk.channels)) {
+            output = self.flush()?;
+            self.suppressor = Some(NoiseSuppressor::new(
+                chunk.sample_rate,
+                chunk.channels,
+                self.config.clone(),
+            ));
+            self.received = 0;
+            self.delivered = 0;
+        }
+
+        self.clock.anchor(self.received, chunk.timestamp);
+        self.received += chunk.frames() as u64;
+
+        let audio = self
+            .suppressor
+            .as_mut()
+            .expect("suppressor created above")
+            .process(&chunk.samples);
+        output.extend(self.chunk(audio, chunk.sample_rate, chunk.channels, chunk.gap_fill));
+        Ok(output)
+    }
+
+    fn flush(&mut self) -> Result<Vec<AudioChunk>, AudioError> {
+        let Some(suppressor) = self.suppressor.as_mut() else {
+            return Ok(Vec::new());
+        };
+
+        let (rate, channels) = (suppressor.sample_rate(), suppressor.channels());
+        let audio = suppressor.flush();
+        let output = self.chunk(audio, rate, channels, false);
+        self.received = 0;
+        self.delivered = 0;
+        Ok(output)
+    }
+
+    fn reset(&mut self) {
+        self.suppressor = None;
+        self.received = 0;
+        self.delivered = 0;
+        self.sequence = 0;
+    }
+
+    fn latency(&self) -> Duration {
+        self.suppressor.as_ref().map_or(Duration::ZERO, |suppressor| {
+            frames_to_duration(suppressor.latency_frames() as u64, suppressor.sample_rate())
+        })
+    }
+}
+
+// ============================================================================
+// NoiseSuppressStream implementation
+// ============================================================================
+
+/// Adapter suppressing noise in a stream
+///
+/// See [`NoiseSuppression`]; the audio still buffered is delivered when
+/// the stream ends or changes format.
+pub struct NoiseSuppressStream<S> {
+    stream: ProcessedStream<S, NoiseSuppression>,
+}
+
+impl<S: AudioStream> NoiseSuppressStream<S> {
+    pub fn new(inner: S, config: NoiseSuppressorConfig) -> Self {
+        Self {
+            stream: ProcessedStream::new(inner, NoiseSuppression::new(config)),
+        }
+    }
+
+    /// Receive the unprocessed audio from now on
+    ///
+    /// See [`NoiseSuppression::subscribe_raw`].
+    pub fn subscribe_raw(&mut self) -> mpsc::UnboundedReceiver<AudioChunk> {
+        self.stream.processor_mut().subscribe_raw()
+    }
+
+    /// The suppressor, once audio has arrived
+    pub fn suppressor(&self) -> Option<&NoiseSuppressor> {
+        self.stream.processor().suppressor()
+    }
+
+    pub fn set_strength(&mut self, strength: f32) {
+        self.stream.processor_mut().set_strength(strength);
+    }
+
+    /// Deliver the audio unprocessed, keeping the timing unchanged
+    pub fn set_bypass(&mut self, bypass: bool) {
+        self.stream.processor_mut().set_bypass(bypass);
+    }
+}
+
+impl<S: AudioStream> AudioStream for NoiseSuppressStream<S> {
+    fn sample_rate(&self) -> u32 {
+        self.stream.sample_rate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.stream.channels()
+    }
+}
+
+impl<S: AudioStream> FuturesStream for NoiseSuppressStream<S> {
+    type Item = Result<AudioChunk, AudioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.stream).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ChunkStream;
+    use futures::StreamExt;
+
+    /// Uniform white noise of the given amplitude
+    fn noise(amplitude: f32) -> impl Fn(f64) -> f32 {
+        move |t| {
+            let n = (t * 16000.0).round() as u64;
+            let u = (n.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 40) as f32 / (1u64 << 24) as f32;
+            amplitude * (2.0 * u - 1.0)
+        }
+    }
+
+    fn tone(t: f64) -> f32 {
+        0.3 * (2.0 * PI * 440.0 * t).sin() as f32
+    }
+
+    fn power(samples: &[f32]) -> f32 {
+        samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32
+    }
+
+    #[tokio::test]
+    async fn test_raw_and_cleaned_stay_aligned() {
+        let input = ChunkStream::new(16000, 1.0, 0.0, |t| tone(t) + noise(0.05)(t));
+        let mut stream = NoiseSuppressStream::new(input, NoiseSuppressorConfig::default());
+        let raw = stream.subscribe_raw();
+
+        let chunks: Vec<AudioChunk> = stream.by_ref().map(Result::unwrap).collect().await;
+        drop(stream);
+        let raw: Vec<AudioChunk> = raw.collect().await;
+
+        assert_eq!(chunks.len(), raw.len());
+        for (cleaned, raw) in chunks.iter().zip(&raw) {
+            assert_eq!((cleaned.timestamp, cleaned.sequence), (raw.timestamp, raw.sequence));
+            assert_eq!(cleaned.frames(), raw.frames());
+        }
+        for pair in chunks.windows(2) {
+            assert_eq!(pair[0].end_timestamp(), pair[1].timestamp);
+        }
+
+        // Every input frame comes out once, on the input timeline
+        let samples: Vec<f32> = raw.iter().flat_map(|chunk| chunk.samples.clone()).collect();
+        assert_eq!(samples.len(), 16000);
+        assert_eq!(raw[0].timestamp, Duration::from_secs(1));
+        for (n, sample) in samples.iter().enumerate().step_by(997) {
+            assert_eq!(*sample, tone(n as f64 / 16000.0) + noise(0.05)(n as f64 / 16000.0));
+        }
+    }
+
+    #[test]
+    fn test_zero_strength_reconstructs_input() {
+        let input: Vec<f32> = (0..8000).map(|n| tone(n as f64 / 16000.0)).collect();
+        let config = NoiseSuppressorConfig::new().with_strength(0.0);
+        let mut suppressor = NoiseSuppressor::new(16000, 1, config);
+
+        let mut out = suppressor.process(&input[..3000]);
+        let rest = suppressor.process(&input[3000..]);
+        out.cleaned.extend(rest.cleaned);
+        out.cleaned.extend(suppressor.flush().cleaned);
+
+        assert_eq!(out.cleaned.len(), input.len());
+        for (a, b) in out.cleaned.iter().zip(&input) {
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_removes_steady_noise_and_keeps_speech() {
+        // Noise alone for two seconds, then syllables of a tone over the same noise
+        let speech = |t: f64| if t >= 2.0 && (t * 4.0).fract() < 0.6 { tone(t) } else { 0.0 };
+        let input: Vec<f32> = (0..64000)
+            .map(|n| n as f64 / 16000.0)
+            .map(|t| noise(0.05)(t) + speech(t))
+            .collect();
+
+        let mut suppressor = NoiseSuppressor::new(16000, 1, NoiseSuppressorConfig::default());
+        let mut out = suppressor.process(&input);
+        out.cleaned.extend(suppressor.flush().cleaned);
+
+        let quiet = 16000..32000;
+        let reduction = 10.0 * (power(&input[quiet.clone()]) / power(&out.cleaned[quiet])).log10();
+        assert!(reduction > 10.0, "noise reduced by {} dB", reduction);
+
+        // The syllables keep their level
+        let talking = 48000..64000;
+        let reference: Vec<f32> = talking.clone().map(|n| speech(n as f64 / 16000.0)).collect();
+        let projection: f32 = out.cleaned[talking.clone()].iter().zip(&reference).map(|(a, b)| a * b).sum();
+        let level = projection / reference.iter().map(|s| s * s).sum::<f32>();
+        assert!((level - 1.0).abs() < 0.15, "speech level {}", level);
+    }
+}
@@ -4,21 +4,152 @@ use serde::{Deserialize, Serialize};
 pub enum DeviceType {
     Input,
     Output,
+    /// Loopback source carrying what is played on an output (not a microphone)
+    Monitor,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Transport or kind hint for a device
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DeviceKind {
+    Builtin,
+    Usb,
+    Bluetooth,
+    Virtual,
+    Monitor,
+    #[default]
+    Unknown,
+}
+
+/// Sample format supported by a device
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum SampleFormat {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+}
+
+/// Inclusive range of supported sample rates in Hz
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SampleRateRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl SampleRateRange {
+    pub fn new(min: u32, max: u32) -> Self {
+        Self {
+            min: min.min(max),
+            max: max.max(min),
+        }
+    }
+
+    /// A range containing a single fixed rate
+    pub fn fixed(rate: u32) -> Self {
+        Self::new(rate, rate)
+    }
+
+    pub fn contains(&self, rate: u32) -> bool {
+        (self.min..=self.max).contains(&rate)
+    }
+
+    /// Clamp `rate` into this range
+    pub fn clamp(&self, rate: u32) -> u32 {
+        rate.clamp(self.min, self.max)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct AudioDevice {
+    /// Stable backend-specific identifier, usable to reopen the device
+    ///
+    /// Unlike `name`, the id survives renames and is unique even when two
+    /// identical devices are connected.
+    pub id: String,
     pub name: String,
     pub device_type: DeviceType,
     pub is_default: bool,
+    pub kind: DeviceKind,
+    /// Maximum number of channels the device offers
+    pub channels: u16,
+    pub sample_rates: Vec<SampleRateRange>,
+    pub sample_formats: Vec<SampleFormat>,
 }
 
 impl AudioDevice {
-    pub fn new(name: String, device_type: DeviceType, is_default: bool) -> Self {
+    pub fn new(id: String, name: String, device_type: DeviceType, is_default: bool) -> Self {
         Self {
+            id,
             name,
             device_type,
             is_default,
+            kind: DeviceKind::Unknown,
+            channels: 0,
+            sample_rates: Vec::new(),
+            sample_formats: Vec::new(),
         }
     }
+
+    /// Set the transport/kind hint
+    pub fn with_kind(mut self, kind: DeviceKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Set the channel count, sample rates and sample formats
+    pub fn with_capabilities(
+        mut self,
+        channels: u16,
+        sample_rates: Vec<SampleRateRange>,
+        sample_formats: Vec<SampleFormat>,
+    ) -> Self {
+        self.channels = channels;
+        self.sample_rates = sample_rates;
+        self.sample_formats = sample_formats;
+        self
+    }
+
+    /// Check whether the device reports support for `rate`
+    pub fn supports_sample_rate(&self, rate: u32) -> bool {
+        self.sample_rates.iter().any(|range| range.contains(rate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_rate_range_normalizes_bounds() {
+        let range = SampleRateRange::new(48000, 8000);
+        assert_eq!(range.min, 8000);
+        assert_eq!(range.max, 48000);
+        assert!(range.contains(16000));
+        assert_eq!(range.clamp(96000), 48000);
+    }
+
+    #[test]
+    fn test_supports_sample_rate() {
+        let device = AudioDevice::new(
+            "usb-1".to_string(),
+            "Headset".to_string(),
+            DeviceType::Input,
+            false,
+        )
+        .with_capabilities(
+            1,
+            vec![SampleRateRange::fixed(16000), SampleRateRange::new(44100, 48000)],
+            vec![SampleFormat::I16],
+        );
+
+        assert!(device.supports_sample_rate(16000));
+        assert!(device.supports_sample_rate(48000));
+        assert!(!device.supports_sample_rate(22050));
+    }
 }
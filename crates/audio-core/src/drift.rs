@@ -0,0 +1,603 @@
+//! Sample clock drift measurement and compensation
+//!
+//! Every device runs from its own crystal, so a nominal 48 kHz stream may
+//! really deliver 48 010 frames per second. [`DriftEstimator`] compares the
+//! frames a stream delivers with the capture timestamps of its chunks and
+//! reports the difference in parts per million. [`DriftCorrector`] uses
+//! that estimate to resample a stream onto the capture clock, and
+//! [`DriftCorrection`] and [`DriftCorrectedStream`] apply it to a pipeline
+//! or a whole [`AudioStream`], so two recordings made from different
+//! devices stay in sync.
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::Stream as FuturesStream;
+
+use crate::chunk::{frames_to_duration, AudioChunk};
+use crate::config::StreamFormat;
+use crate::error::AudioError;
+use crate::processor::{AudioProcessor, ProcessedStream};
+use crate::resample::Resampler;
+use crate::traits::AudioStream;
+
+/// Spacing of the observations kept for the fit, in seconds
+const POINT_INTERVAL: f64 = 0.5;
+
+/// Observations older than this many seconds are forgotten
+const WINDOW: f64 = 120.0;
+
+/// Observation span in seconds before an estimate is reported
+const MIN_SPAN: f64 = 10.0;
+
+/// Timestamp error in seconds treated as lost audio rather than drift
+const DISCONTINUITY: f64 = 0.1;
+
+/// Misplacement in seconds beyond which audio is realigned at once
+///
+/// Larger errors come from lost audio rather than drift, so they are fixed
+/// by moving the audio instead of by rate correction.
+const REALIGN_THRESHOLD: f64 = 0.05;
+
+/// Rate correction per second of placement error
+///
+/// Removes the residual offset the drift estimate leaves over roughly
+/// 20 seconds, slowly enough to be inaudible.
+const OFFSET_GAIN: f64 = 0.05;
+
+/// Largest rate correction applied, in parts per million
+const MAX_CORRECTION_PPM: f64 = 5000.0;
+
+/// Smoothing factor of the placement error, per chunk
+const ERROR_SMOOTHING: f64 = 0.05;
+
+/// Measures the real sample rate of a stream against the capture clock
+///
+/// Feed it every chunk with [`DriftEstimator::update`]. The rate is a least
+/// squares fit of delivered frames over the last two minutes of timestamps,
+/// which averages out the jitter of individual callbacks. A jump in the
+/// timestamps, such as a dropout, restarts the measurement.
+#[derive(Debug, Clone)]
+pub struct DriftEstimator {
+    nominal_rate: u32,
+    /// Capture time of frame 0 of the current measurement
+    origin: Option<Duration>,
+    /// Frames delivered since `origin`
+    frames: u64,
+    /// (seconds since origin, frames since origin)
+    points: VecDeque<(f64, f64)>,
+    rate: Option<f64>,
+}
+
+impl DriftEstimator {
+    pub fn new(nominal_rate: u32) -> Self {
+        Self {
+            nominal_rate: nominal_rate.max(1),
+            origin: None,
+            frames: 0,
+            points: VecDeque::new(),
+            rate: None,
+        }
+    }
+
+    pub fn nominal_rate(&self) -> u32 {
+        self.nominal_rate
+    }
+
+    /// Start over, e.g. after the nominal rate changed
+    pub fn reset(&mut self, nominal_rate: u32) {
+        *self = Self::new(nominal_rate);
+    }
+
+    /// Record a chunk of `frames` frames captured at `timestamp`
+    pub fn update(&mut self, timestamp: Duration, frames: usize) {
+        let origin = *self.origin.get_or_insert(timestamp);
+        let t = timestamp.as_secs_f64() - origin.as_secs_f64();
+        let f = self.frames as f64;
+
+        let expected = f / self.measured_rate();
+        if (t - expected).abs() > DISCONTINUITY {
+            tracing::debug!(
+                error = t - expected,
+                "Timestamp discontinuity, restarting drift measurement"
+            );
+            self.origin = Some(timestamp);
+            self.frames = frames as u64;
+            self.points.clear();
+            self.points.push_back((0.0, 0.0));
+            return;
+        }
+
+        let due = self
+            .points
+            .back()
+            .is_none_or(|&(last, _)| t - last >= POINT_INTERVAL);
+        if due {
+            self.points.push_back((t, f));
+            while self.points.front().is_some_and(|&(first, _)| t - first > WINDOW) {
+                self.points.pop_front();
+            }
+            self.fit();
+        }
+
+        self.frames += frames as u64;
+    }
+
+    fn fit(&mut self) {
+        let (Some(&(first, _)), Some(&(last, _))) = (self.points.front(), self.points.back()) else {
+            return;
+        };
+        if last - first < MIN_SPAN {
+            return;
+        }
+
+        let n = self.points.len() as f64;
+        let mean_t = self.points.iter().map(|&(t, _)| t).sum::<f64>() / n;
+        let mean_f = self.points.iter().map(|&(_, f)| f).sum::<f64>() / n;
+
+        let (mut covariance, mut variance) = (0.0, 0.0);
+        for &(t, f) in &self.points {
+            covariance += (t - mean_t) * (f - mean_f);
+            variance += (t - mean_t) * (t - mean_t);
+        }
+
+        if variance > 0.0 {
+            self.rate = Some(covariance / variance);
+        }
+    }
+
+    /// Measured frames per second of capture clock time, if known yet
+    pub fn rate(&self) -> Option<f64> {
+        self.rate
+    }
+
+    /// Measured rate, falling back to the nominal rate
+    pub fn measured_rate(&self) -> f64 {
+        self.rate.unwrap_or(self.nominal_rate as f64)
+    }
+
+    /// How much faster than nominal the stream runs, in parts per million
+    ///
+    /// Zero until enough audio has been observed.
+    pub fn ppm(&self) -> f64 {
+        self.rate.map_or(0.0, |rate| (rate / self.nominal_rate as f64 - 1.0) * 1e6)
+    }
+}
+
+/// Converted audio and where it belongs on the output timeline
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placement {
+    /// Output frame of the first sample; negative before the timeline origin
+    pub frame: i64,
+    /// Interleaved samples at the output rate
+    pub samples: Vec<f32>,
+    /// Whether the audio was moved instead of continuing the previous placement
+    pub realigned: bool,
+}
+
+/// Resamples a stream onto a timeline driven by the capture clock
+///
+/// Output frame `n` belongs at `origin + n / output_rate` on the capture
+/// clock. The measured drift of the input feeds forward into the resampler,
+/// and the remaining placement error is removed by a slow correction, so
+/// the output stays within a few milliseconds of its timestamps however
+/// long it runs. Errors too large to be drift, such as lost buffers, are
+/// fixed by realigning at once.
+pub struct DriftCorrector {
+    resampler: Resampler,
+    estimator: DriftEstimator,
+    /// Output frame just after the last placement
+    end: Option<i64>,
+    /// Smoothed placement error in seconds; positive when audio arrives late
+    error: f64,
+}
+
+impl DriftCorrector {
+    pub fn new(input_rate: u32, output_rate: u32, channels: u16) -> Self {
+        Self {
+            resampler: Resampler::new(input_rate, output_rate, channels),
+            estimator: DriftEstimator::new(input_rate),
+            end: None,
+            error: 0.0,
+        }
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.resampler.output_rate()
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.resampler.channels()
+    }
+
+    /// Measured drift of the input against the capture clock, in ppm
+    pub fn ppm(&self) -> f64 {
+        self.estimator.ppm()
+    }
+
+    /// Rate correction currently applied, in ppm
+    pub fn correction_ppm(&self) -> f64 {
+        self.resampler.drift_correction()
+    }
+
+    /// Delay of the resampler lookahead
+    pub fn latency(&self) -> Duration {
+        frames_to_duration(self.resampler.latency_frames() as u64, self.resampler.input_rate())
+    }
+
+    /// Convert `chunk` and place it on the timeline starting at `origin`
+    pub fn process(&mut self, chunk: &AudioChunk, origin: Duration) -> Placement {
+        if chunk.sample_rate != self.estimator.nominal_rate() {
+            self.estimator.reset(chunk.sample_rate);
+            self.resampler.set_input_rate(chunk.sample_rate);
+        }
+        self.estimator.update(chunk.timestamp, chunk.frames());
+
+        // Capture time of the first output frame this chunk completes
+        let lead = self.resampler.buffered_frames().max(0.0) / self.resampler.input_rate() as f64;
+        let start = chunk.timestamp.as_secs_f64() - lead - origin.as_secs_f64();
+
+        let samples = self.resampler.process(&chunk.samples);
+        let rate = self.resampler.output_rate() as f64;
+
+        let (frame, realigned) = match self.end {
+            Some(end) if (start - end as f64 / rate).abs() <= REALIGN_THRESHOLD => {
+                let error = start - end as f64 / rate;
+                self.error += (error - self.error) * ERROR_SMOOTHING;
+
+                let correction = self.estimator.ppm() - self.error * OFFSET_GAIN * 1e6;
+                self.resampler
+                    .set_drift_correction(correction.clamp(-MAX_CORRECTION_PPM, MAX_CORRECTION_PPM));
+                (end, false)
+            }
+            end => {
+                if let Some(end) = end {
+                    tracing::debug!(error = start - end as f64 / rate, "Realigning drifting stream");
+                }
+                self.error = 0.0;
+                ((start * rate).round() as i64, true)
+            }
+        };
+
+        self.end = Some(frame + (samples.len() / self.resampler.channels() as usize) as i64);
+        Placement {
+            frame,
+            samples,
+            realigned,
+        }
+    }
+
+    /// Place the audio still buffered in the resampler, at the end of a stream
+    pub fn flush(&mut self) -> Placement {
+        let samples = self.resampler.flush();
+        let frame = self.end.unwrap_or_default();
+
+        self.end = Some(frame + (samples.len() / self.resampler.channels() as usize) as i64);
+        Placement {
+            frame,
+            samples,
+            realigned: false,
+        }
+    }
+}
+
+// ============================================================================
+// DriftCorrection processor
+// ============================================================================
+
+/// Processor keeping a stream in step with the capture clock
+///
+/// The output rate is measured against the capture clock rather than the
+/// device crystal: after an hour it has delivered exactly an hour of
+/// frames, and chunk timestamps follow from the frame count. Lost audio is
+/// replaced by [`AudioChunk::gap_fill`] silence so the timeline never
+/// shrinks. Rate changes are absorbed; a change of channel count starts a
+/// new timeline.
+pub struct DriftCorrection {
+    /// Output rate; the rate of the first chunk unless set
+    output_rate: Option<u32>,
+    /// Created from the first chunk, which sets the input format
+    corrector: Option<DriftCorrector>,
+    /// Capture time of output frame 0
+    origin: Option<Duration>,
+    /// Output frames delivered since `origin`
+    emitted: u64,
+    sequence: u64,
+}
+
+impl Default for DriftCorrection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DriftCorrection {
+    pub fn new() -> Self {
+        Self {
+            output_rate: None,
+            corrector: None,
+            origin: None,
+            emitted: 0,
+            sequence: 0,
+        }
+    }
+
+    pub fn with_output_rate(mut self, output_rate: u32) -> Self {
+        self.output_rate = Some(output_rate);
+        self
+    }
+
+    /// Measured drift of the input against the capture clock, in ppm
+    pub fn drift_ppm(&self) -> f64 {
+        self.corrector.as_ref().map_or(0.0, DriftCorrector::ppm)
+    }
+
+    /// Rate correction currently applied, in ppm
+    pub fn correction_ppm(&self) -> f64 {
+        self.corrector.as_ref().map_or(0.0, DriftCorrector::correction_ppm)
+    }
+
+    fn chunk(&mut self, samples: Vec<f32>, rate: u32, channels: u16, gap_fill: bool) -> AudioChunk {
+        let timestamp = self.origin.unwrap_or_default() + frames_to_duration(self.emitted, rate);
+        let mut chunk = AudioChunk::new(samples, rate, channels, timestamp, self.sequence);
+        chunk.gap_fill = gap_fill;
+
+        self.emitted += chunk.frames() as u64;
+        self.sequence += 1;
+        chunk
+    }
+
+    /// Deliver `placement`, filling or trimming so it starts at the next frame
+    fn deliver(&mut self, placement: Placement, gap_fill: bool) -> Vec<AudioChunk> {
+        let Some(corrector) = &self.corrector else {
+            return Vec::new();
+        };
+        let rate = corrector.output_rate();
+        let channels = corrector.channels();
+        let mut samples = placement.samples;
+        let mut output = Vec::new();
+
+        let late = (self.emitted as i64 - placement.frame).max(0) as usize;
+        if late > 0 {
+            samples.drain(..(late * channels as usize).min(samples.len()));
+        }
+
+        let early = (placement.frame - self.emitted as i64).max(0) as usize;
+        if early > 0 && !samples.is_empty() {
+            tracing::debug!(frames = early, "Filling gap in drift corrected stream");
+            output.push(self.chunk(vec![0.0; early * channels as usize], rate, channels, true));
+        }
+
+        if !samples.is_empty() {
+            output.push(self.chunk(samples, rate, channels, gap_fill));
+        }
+        output
+    }
+}
+
+impl AudioProcessor for DriftCorrection {
+    fn process(&mut self, chunk: AudioChunk) -> Result<Vec<AudioChunk>, AudioError> {
+        // Rate changes are picked up by the corrector; a new channel layout
+        // starts over once the audio buffered in the old one is out
+        let mut output = Vec::new();
+        if self.corrector.as_ref().map(DriftCorrector::channels) != Some(chunk.channels) {
+            output = self.flush()?;
+            let output_rate = *self.output_rate.get_or_insert(chunk.sample_rate);
+            self.corrector = Some(DriftCorrector::new(chunk.sample_rate, output_rate, chunk.channels));
+            self.origin = None;
+            self.emitted = 0;
+        }
+
+        let origin = *self.origin.get_or_insert(chunk.timestamp);
+        let placement = self
+            .corrector
+            .as_mut()
+            .expect("corrector created above")
+            .process(&chunk, origin);
+        output.extend(self.deliver(placement, chunk.gap_fill));
+        Ok(output)
+    }
+
+    fn flush(&mut self) -> Result<Vec<AudioChunk>, AudioError> {
+        let Some(corrector) = self.corrector.as_mut() else {
+            return Ok(Vec::new());
+        };
+
+        let tail = corrector.flush();
+        Ok(self.deliver(tail, false))
+    }
+
+    fn reset(&mut self) {
+        self.corrector = None;
+        self.origin = None;
+        self.emitted = 0;
+        self.sequence = 0;
+    }
+
+    fn latency(&self) -> Duration {
+        self.corrector.as_ref().map_or(Duration::ZERO, DriftCorrector::latency)
+    }
+
+    fn output_format(&self, input: StreamFormat) -> StreamFormat {
+        StreamFormat {
+            sample_rate: self.output_rate.unwrap_or(input.sample_rate),
+            ..input
+        }
+    }
+}
+
+/// Processor measuring the drift of a stream against the capture clock
+///
+/// Unlike [`DriftCorrection`], it hands the audio on untouched, so the
+/// drift can be reported while correcting it is left to settings. A rate
+/// change starts the measurement over.
+#[derive(Default)]
+pub struct DriftMeasurement {
+    /// Created from the first chunk, which sets the nominal rate
+    estimator: Option<DriftEstimator>,
+}
+
+impl DriftMeasurement {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Measured drift of the input against the capture clock, in ppm
+    pub fn drift_ppm(&self) -> f64 {
+        self.estimator.as_ref().map_or(0.0, DriftEstimator::ppm)
+    }
+}
+
+impl AudioProcessor for DriftMeasurement {
+    fn process(&mut self, chunk: AudioChunk) -> Result<Vec<AudioChunk>, AudioError> {
+        let estimator = self
+            .estimator
+            .get_or_insert_with(|| DriftEstimator::new(chunk.sample_rate));
+        if estimator.nominal_rate() != chunk.sample_rate {
+            estimator.reset(chunk.sample_rate);
+        }
+        estimator.update(chunk.timestamp, chunk.frames());
+        Ok(vec![chunk])
+    }
+
+    fn reset(&mut self) {
+        self.estimator = None;
+    }
+}
+
+// ============================================================================
+// DriftCorrectedStream implementation
+// ============================================================================
+
+/// Adapter keeping a stream in step with the capture clock
+///
+/// The output has the input's initial sample rate; see [`DriftCorrection`]
+/// for how the timeline is kept. Rate changes are absorbed like in
+/// [`crate::ResampleStream`].
+pub struct DriftCorrectedStream<S> {
+    stream: ProcessedStream<S, DriftCorrection>,
+}
+
+impl<S: AudioStream> DriftCorrectedStream<S> {
+    pub fn new(inner: S) -> Self {
+        let correction = DriftCorrection::new().with_output_rate(inner.sample_rate());
+
+        Self {
+            stream: ProcessedStream::new(inner, correction),
+        }
+    }
+
+    /// Measured drift of the input against the capture clock, in ppm
+    pub fn drift_ppm(&self) -> f64 {
+        self.stream.processor().drift_ppm()
+    }
+
+    /// Rate correction currently applied, in ppm
+    pub fn correction_ppm(&self) -> f64 {
+        self.stream.processor().correction_ppm()
+    }
+}
+
+impl<S: AudioStream> AudioStream for DriftCorrectedStream<S> {
+    fn sample_rate(&self) -> u32 {
+        self.stream.sample_rate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.stream.channels()
+    }
+}
+
+impl<S: AudioStream> FuturesStream for DriftCorrectedStream<S> {
+    type Item = Result<AudioChunk, AudioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.stream).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ChunkStream;
+    use futures::StreamExt;
+
+    /// Feed `seconds` of 10 ms chunks from a clock `ppm` fast, with timestamp jitter
+    fn feed(estimator: &mut DriftEstimator, start: Duration, seconds: u64, ppm: f64) {
+        let nominal = estimator.nominal_rate() as f64;
+        let actual = nominal * (1.0 + ppm * 1e-6);
+        let chunk = (nominal / 100.0) as usize;
+
+        for i in 0..seconds * 100 {
+            let frames = i as f64 * chunk as f64;
+            // Deterministic jitter of up to +-1 ms
+            let jitter = ((i * 7919) % 21) as f64 * 1e-4 - 1e-3;
+            let timestamp = start + Duration::from_secs_f64(frames / actual + 0.001 + jitter);
+            estimator.update(timestamp, chunk);
+        }
+    }
+
+    #[test]
+    fn test_measures_fast_clock() {
+        let mut estimator = DriftEstimator::new(48000);
+        assert_eq!(estimator.ppm(), 0.0);
+
+        feed(&mut estimator, Duration::from_secs(5), 60, 500.0);
+        let ppm = estimator.ppm();
+        assert!((ppm - 500.0).abs() < 10.0, "{} ppm", ppm);
+    }
+
+    #[test]
+    fn test_discontinuity_restarts_measurement() {
+        let mut estimator = DriftEstimator::new(16000);
+        feed(&mut estimator, Duration::from_secs(1), 30, -200.0);
+        assert!((estimator.ppm() + 200.0).abs() < 10.0);
+
+        // A second of audio goes missing; the old estimate is kept until the
+        // new measurement is long enough
+        feed(&mut estimator, Duration::from_secs(32), 5, -200.0);
+        assert!((estimator.ppm() + 200.0).abs() < 10.0);
+        feed(&mut estimator, Duration::from_secs(40), 30, 300.0);
+        assert!((estimator.ppm() - 300.0).abs() < 10.0, "{} ppm", estimator.ppm());
+    }
+
+    #[tokio::test]
+    async fn test_corrected_stream_follows_capture_clock() {
+        // Three minutes from a clock running 1000 ppm slow, minus a lost second
+        let input = ChunkStream::new(16000, 180.0, -1000.0, |_| 0.1)
+            .with_dropout(Duration::from_secs(90), Duration::from_secs(1));
+        let mut stream = DriftCorrectedStream::new(input);
+
+        let mut chunks = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            chunks.push(chunk.unwrap());
+        }
+        assert!((stream.drift_ppm() + 1000.0).abs() < 20.0, "{} ppm", stream.drift_ppm());
+
+        // The samples span 180.18 s of capture time, including the second
+        // lost to the dropout, and the output covers all of it
+        let frames: usize = chunks.iter().map(AudioChunk::frames).sum();
+        let expected = (180.0 / (1.0 - 1000e-6) * 16000.0) as usize;
+        assert!(frames.abs_diff(expected) < 16 * 5, "{} frames", frames);
+
+        let filled: usize = chunks.iter().filter(|c| c.gap_fill).map(AudioChunk::frames).sum();
+        assert!(filled.abs_diff(16000) < 16 * 5, "{} frames filled", filled);
+    }
+
+    #[tokio::test]
+    async fn test_measurement_leaves_audio_untouched() {
+        let mut input = ChunkStream::new(16000, 60.0, 400.0, |t| (t * 50.0).sin() as f32);
+        let mut measurement = DriftMeasurement::new();
+
+        while let Some(chunk) = input.next().await {
+            let chunk = chunk.unwrap();
+            assert_eq!(measurement.process(chunk.clone()).unwrap(), vec![chunk]);
+        }
+        assert!((measurement.drift_ppm() - 400.0).abs() < 10.0, "{} ppm", measurement.drift_ppm());
+
+        measurement.reset();
+        assert_eq!(measurement.drift_ppm(), 0.0);
+    }
+}
@@ -0,0 +1,645 @@
+//! Acoustic echo cancellation
+//!
+//! Without headphones, the microphone picks up the remote side of a call a
+//! second time from the speakers. The speaker capture holds the exact
+//! signal that was played, so it can serve as the far-end reference: an
+//! [`EchoCanceller`] estimates how long the played audio takes to reach the
+//! microphone, models the path from speaker to microphone with an adaptive
+//! filter, subtracts the predicted echo, and attenuates what the filter
+//! leaves behind. [`EchoCancelStream`] applies it to a microphone stream
+//! with the speaker stream as reference.
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::Stream as FuturesStream;
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::{duration_to_frames, frames_to_duration, AudioChunk};
+use crate::error::AudioError;
+use crate::meter::{db_to_linear, smoothing};
+use crate::mixer::{MixMode, MixStream, MixerConfig};
+use crate::traits::AudioStream;
+
+/// Length of the envelope blocks used for delay estimation
+const ENVELOPE_BLOCK: Duration = Duration::from_millis(4);
+
+/// Amount of audio correlated for each delay estimate
+const ENVELOPE_WINDOW: Duration = Duration::from_secs(2);
+
+/// Interval between delay estimates
+const DELAY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Envelope correlation from which a delay estimate is trusted
+const DELAY_CONFIDENCE: f64 = 0.5;
+
+/// Time constant of the signal power estimates
+const POWER_SMOOTHING: Duration = Duration::from_millis(20);
+
+/// Time constant with which the residual echo estimate follows the filter
+const LEAK_SMOOTHING: Duration = Duration::from_secs(1);
+
+/// Reference power below which the far end counts as silent (-70 dBFS)
+pub(crate) const ACTIVITY_FLOOR: f32 = 1e-7;
+
+/// Echo reduction after which the filter counts as converged (3 dB)
+const CONVERGED_RATIO: f32 = 2.0;
+
+/// Regularisation of the filter normalisation, per tap
+const REGULARIZATION: f64 = 1e-6;
+
+/// Configuration of an [`EchoCanceller`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct EchoCancellerConfig {
+    /// Processing rate in Hz
+    pub sample_rate: u32,
+    /// Length of the echo path the adaptive filter models
+    ///
+    /// Longer filters capture more of the room reverberation at a higher
+    /// CPU cost.
+    pub filter_length: Duration,
+    /// Largest delay between playback and its echo that is searched for
+    pub max_delay: Duration,
+    /// Adaptation speed of the filter, from 0 to 1
+    pub step_size: f32,
+    /// Strength of the residual echo suppression; 0 disables it
+    pub suppression: f32,
+    /// Largest attenuation applied by the suppression, in dB
+    pub suppression_floor_db: f32,
+}
+
+impl Default for EchoCancellerConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 16000,
+            filter_length: Duration::from_millis(64),
+            max_delay: Duration::from_millis(500),
+            step_size: 0.5,
+            suppression: 1.0,
+            suppression_floor_db: -30.0,
+        }
+    }
+}
+
+impl EchoCancellerConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn with_filter_length(mut self, filter_length: Duration) -> Self {
+        self.filter_length = filter_length;
+        self
+    }
+
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn with_step_size(mut self, step_size: f32) -> Self {
+        self.step_size = step_size;
+        self
+    }
+
+    pub fn with_suppression(mut self, suppression: f32) -> Self {
+        self.suppression = suppression;
+        self
+    }
+
+    pub fn with_suppression_floor_db(mut self, suppression_floor_db: f32) -> Self {
+        self.suppression_floor_db = suppression_floor_db;
+        self
+    }
+}
+
+/// Best alignment of the near-end envelope with the far-end one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct EnvelopeMatch {
+    /// Delay of the near end behind the far end, in frames
+    pub delay: usize,
+    /// Correlation of the envelopes at that delay; zero when nothing
+    /// correlates
+    pub correlation: f64,
+    /// Amplitude of the near-end envelope per unit of far-end envelope
+    pub gain: f64,
+}
+
+/// Finds the delay of the echo by correlating signal envelopes
+///
+/// Envelopes are robust to the filtering of the echo path, so the
+/// correlation peaks at the bulk delay even when the echo looks nothing
+/// like the reference sample by sample.
+pub(crate) struct DelayEstimator {
+    block: usize,
+    max_lag: usize,
+    window: usize,
+    interval: usize,
+    /// Mean magnitude of the block being filled
+    near_sum: f64,
+    far_sum: f64,
+    filled: usize,
+    near: VecDeque<f64>,
+    far: VecDeque<f64>,
+    /// Blocks since the last estimate
+    since: usize,
+}
+
+impl DelayEstimator {
+    pub fn new(sample_rate: u32, max_delay: Duration) -> Self {
+        let block = duration_to_frames(ENVELOPE_BLOCK, sample_rate).max(1) as usize;
+        let blocks = |duration: Duration| (duration_to_frames(duration, sample_rate) as usize).div_ceil(block);
+
+        Self {
+            block,
+            max_lag: blocks(max_delay),
+            window: blocks(ENVELOPE_WINDOW).max(1),
+            interval: blocks(DELAY_INTERVAL).max(1),
+            near_sum: 0.0,
+            far_sum: 0.0,
+            filled: 0,
+            near: VecDeque::new(),
+            far: VecDeque::new(),
+            since: 0,
+        }
+    }
+
+    /// Add one frame; returns a new estimate now and then
+    pub fn push(&mut self, near: f32, far: f32) -> Option<EnvelopeMatch> {
+        self.near_sum += near.abs() as f64;
+        self.far_sum += far.abs() as f64;
+        self.filled += 1;
+        if self.filled < self.block {
+            return None;
+        }
+
+        self.near.push_back(self.near_sum);
+        self.far.push_back(self.far_sum);
+        self.near_sum = 0.0;
+        self.far_sum = 0.0;
+        self.filled = 0;
+
+        if self.near.len() > self.window {
+            self.near.pop_front();
+        }
+        if self.far.len() > self.window + self.max_lag {
+            self.far.pop_front();
+        }
+
+        self.since += 1;
+        if self.since < self.interval || self.far.len() < self.interval + self.max_lag {
+            return None;
+        }
+        self.since = 0;
+
+        let best = self.estimate();
+        Some(EnvelopeMatch {
+            delay: best.map_or(0, |(lag, _, _)| lag * self.block),
+            correlation: best.map_or(0.0, |(_, correlation, _)| correlation),
+            gain: best.map_or(0.0, |(_, _, gain)| gain),
+        })
+    }
+
+    /// Lag in blocks at which the envelopes correlate best, with the
+    /// correlation and gain there
+    fn estimate(&self) -> Option<(usize, f64, f64)> {
+        // Until the window has filled, correlate what there is
+        let n = self.near.len().min(self.far.len() - self.max_lag);
+        let near: Vec<f64> = self.near.iter().skip(self.near.len() - n).copied().collect();
+        let far: Vec<f64> = self.far.iter().copied().collect();
+
+        let near_mean = near.iter().sum::<f64>() / n as f64;
+        let near_var: f64 = near.iter().map(|v| (v - near_mean).powi(2)).sum();
+        if near_var <= f64::EPSILON {
+            return None;
+        }
+
+        let mut best: Option<(usize, f64, f64)> = None;
+        for lag in 0..=self.max_lag {
+            // Far block `i - lag` lines up with near block `i`
+            let start = far.len() - n - lag;
+            let far = &far[start..start + n];
+
+            let far_mean = far.iter().sum::<f64>() / n as f64;
+            let far_var: f64 = far.iter().map(|v| (v - far_mean).powi(2)).sum();
+            if far_var <= f64::EPSILON {
+                continue;
+            }
+
+            let covariance: f64 = near
+                .iter()
+                .zip(far)
+                .map(|(a, b)| (a - near_mean) * (b - far_mean))
+                .sum();
+            let correlation = covariance / (near_var * far_var).sqrt();
+
+            if best.is_none_or(|(_, peak, _)| correlation > peak) {
+                best = Some((lag, correlation, covariance / far_var));
+            }
+        }
+
+        best
+    }
+}
+
+/// Removes the echo of a far-end reference from a near-end signal
+///
+/// Both signals are mono at the configured rate and must be aligned on the
+/// capture clock: frame `n` of each was captured at the same moment. The
+/// echo must arrive after its reference, by at most the configured maximum
+/// delay.
+///
+/// The filter is a normalised LMS filter whose window is placed around the
+/// estimated delay. While the near end talks over the echo, adaptation
+/// slows down in proportion to how much of the residual the filter
+/// explains, so the filter does not learn the local speech.
+pub struct EchoCanceller {
+    config: EchoCancellerConfig,
+    taps: usize,
+    max_offset: usize,
+    /// `weights[j]` multiplies the reference `offset + taps - 1 - j` frames ago
+    weights: Vec<f32>,
+    /// Reference history, newest last
+    history: Vec<f32>,
+    /// Frames between the newest reference frame and the newest filter tap
+    offset: usize,
+    /// Energy of the reference under the filter window
+    energy: f64,
+    delay: Option<usize>,
+    estimator: DelayEstimator,
+    near_power: f32,
+    error_power: f32,
+    echo_power: f32,
+    /// Share of the echo estimate left in the output after cancellation
+    leak: f32,
+    gain: f32,
+    converged: bool,
+    power_coef: f32,
+    leak_coef: f32,
+    floor_gain: f32,
+}
+
+impl EchoCanceller {
+    pub fn new(config: EchoCancellerConfig) -> Self {
+        let rate = config.sample_rate.max(1);
+        let taps = duration_to_frames(config.filter_length, rate).max(1) as usize;
+        let max_offset = duration_to_frames(config.max_delay, rate) as usize;
+
+        Self {
+            taps,
+            max_offset,
+            weights: vec![0.0; taps],
+            history: vec![0.0; max_offset + taps + 1],
+            offset: 0,
+            energy: 0.0,
+            delay: None,
+            estimator: DelayEstimator::new(rate, config.max_delay),
+            near_power: 0.0,
+            error_power: 0.0,
+            echo_power: 0.0,
+            leak: 1.0,
+            gain: 1.0,
+            converged: false,
+            power_coef: smoothing(frames_to_duration(1, rate), POWER_SMOOTHING),
+            leak_coef: smoothing(frames_to_duration(1, rate), LEAK_SMOOTHING),
+            floor_gain: db_to_linear(config.suppression_floor_db.min(0.0)),
+            config: EchoCancellerConfig {
+                sample_rate: rate,
+                ..config
+            },
+        }
+    }
+
+    pub fn config(&self) -> &EchoCancellerConfig {
+        &self.config
+    }
+
+    /// Estimated delay between playback and its echo
+    pub fn delay(&self) -> Option<Duration> {
+        self.delay
+            .map(|frames| frames_to_duration(frames as u64, self.config.sample_rate))
+    }
+
+    /// Whether the filter has learned the echo path
+    pub fn is_converged(&self) -> bool {
+        self.converged
+    }
+
+    /// Forget the learned echo path and all history
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config.clone());
+    }
+
+    /// Cancel the echo of `far` in `near`
+    ///
+    /// Missing reference frames are treated as silence.
+    pub fn process(&mut self, near: &[f32], far: &[f32]) -> Vec<f32> {
+        near.iter()
+            .enumerate()
+            .map(|(i, &sample)| self.process_frame(sample, far.get(i).copied().unwrap_or(0.0)))
+            .collect()
+    }
+
+    fn process_frame(&mut self, near: f32, far: f32) -> f32 {
+        let estimate = self.estimator.push(near, far);
+        let confident = estimate.filter(|estimate| estimate.correlation >= DELAY_CONFIDENCE);
+        if let Some(estimate) = confident {
+            self.delay = Some(estimate.delay);
+            self.place_window(estimate.delay);
+        }
+        self.push_reference(far);
+
+        let end = self.history.len() - self.offset;
+        let window = &self.history[end - self.taps..end];
+        let echo: f32 = self.weights.iter().zip(window).map(|(w, x)| w * x).sum();
+        let error = near - echo;
+
+        let coef = self.power_coef;
+        self.near_power += (near * near - self.near_power) * coef;
+        self.error_power += (error * error - self.error_power) * coef;
+        self.echo_power += (echo * echo - self.echo_power) * coef;
+
+        if self.energy / self.taps as f64 > ACTIVITY_FLOOR as f64 {
+            self.adapt(error);
+        }
+
+        self.suppress(error)
+    }
+
+    /// Append a reference frame and slide the filter window
+    fn push_reference(&mut self, far: f32) {
+        self.history.push(far);
+
+        let end = self.history.len() - self.offset;
+        let entering = self.history[end - 1] as f64;
+        let leaving = self.history[end - 1 - self.taps] as f64;
+        self.energy = (self.energy + entering * entering - leaving * leaving).max(0.0);
+
+        // Keep the history bounded, recomputing the energy to shed rounding errors
+        let capacity = self.max_offset + self.taps + 1;
+        if self.history.len() >= 2 * capacity {
+            self.history.drain(..self.history.len() - capacity);
+            self.update_energy();
+        }
+    }
+
+    fn update_energy(&mut self) {
+        let end = self.history.len() - self.offset;
+        self.energy = self.history[end - self.taps..end]
+            .iter()
+            .map(|&x| x as f64 * x as f64)
+            .sum();
+    }
+
+    /// Move the filter window so the echo delay falls into its first quarter
+    ///
+    /// The learned response moves along with the window, so an unchanged
+    /// echo path keeps being cancelled.
+    fn place_window(&mut self, delay: usize) {
+        let covered = self.offset + self.taps / 8..self.offset + self.taps * 3 / 4;
+        if covered.contains(&delay) {
+            return;
+        }
+
+        let offset = delay.saturating_sub(self.taps / 4).min(self.max_offset);
+        let shift = offset.abs_diff(self.offset).min(self.taps);
+        if offset > self.offset {
+            self.weights.rotate_left(shift);
+            self.weights[self.taps - shift..].fill(0.0);
+        } else {
+            self.weights.rotate_right(shift);
+            self.weights[..shift].fill(0.0);
+        }
+
+        tracing::debug!(delay, offset, "Echo delay changed, moving filter window");
+        self.offset = offset;
+        self.update_energy();
+    }
+
+    fn adapt(&mut self, error: f32) {
+        // A filter making the signal louder has diverged; start over
+        if self.error_power > 2.0 * self.near_power && self.near_power > ACTIVITY_FLOOR {
+            tracing::debug!("Echo canceller diverged, resetting filter");
+            self.weights.fill(0.0);
+            self.converged = false;
+            return;
+        }
+        if !self.converged && self.near_power > CONVERGED_RATIO * self.error_power {
+            self.converged = true;
+        }
+
+        // Residual not explained by the echo estimate is most likely local
+        // speech, which the filter must not learn
+        let confidence = if self.converged {
+            (self.echo_power / self.error_power.max(f32::MIN_POSITIVE)).min(1.0)
+        } else {
+            1.0
+        };
+        let step = self.config.step_size * confidence * error
+            / (self.energy + REGULARIZATION * self.taps as f64) as f32;
+
+        let end = self.history.len() - self.offset;
+        let window = &self.history[end - self.taps..end];
+        for (w, x) in self.weights.iter_mut().zip(window) {
+            *w += step * x;
+        }
+
+        // A residual far above the expected leak is local speech, not echo
+        let leak = self.error_power / self.echo_power.max(f32::MIN_POSITIVE);
+        if self.echo_power > ACTIVITY_FLOOR && leak < 4.0 * self.leak {
+            self.leak += (leak.min(1.0) - self.leak) * self.leak_coef;
+        }
+    }
+
+    /// Attenuate the echo left after cancellation
+    fn suppress(&mut self, error: f32) -> f32 {
+        if self.config.suppression <= 0.0 {
+            return error;
+        }
+
+        let residual = self.config.suppression * self.leak * self.echo_power;
+        let target = if self.error_power > 0.0 {
+            (1.0 - residual / self.error_power).clamp(self.floor_gain, 1.0)
+        } else {
+            1.0
+        };
+
+        // Close quickly on echo, open slowly to avoid pumping
+        let coef = if target < self.gain {
+            self.power_coef * 4.0
+        } else {
+            self.power_coef
+        };
+        self.gain += (target - self.gain) * coef.min(1.0);
+        error * self.gain
+    }
+}
+
+/// Microphone stream with the echo of the speaker capture removed
+///
+/// Both sources are resampled to the processing rate and aligned on their
+/// capture timestamps, as in a [`MixStream`]. The output is the cleaned
+/// microphone in mono at the processing rate. Errors of either source are
+/// passed through.
+pub struct EchoCancelStream<M, S> {
+    inner: MixStream<M, S>,
+    canceller: EchoCanceller,
+}
+
+impl<M: AudioStream, S: AudioStream> EchoCancelStream<M, S> {
+    pub fn new(mic: M, speaker: S, config: EchoCancellerConfig) -> Self {
+        let canceller = EchoCanceller::new(config);
+        let mixer = MixerConfig::new()
+            .with_sample_rate(canceller.config().sample_rate)
+            .with_mode(MixMode::Separate);
+
+        Self {
+            inner: MixStream::new(mic, speaker, mixer),
+            canceller,
+        }
+    }
+
+    pub fn canceller(&self) -> &EchoCanceller {
+        &self.canceller
+    }
+
+    /// Estimated delay between playback and its echo
+    pub fn delay(&self) -> Option<Duration> {
+        self.canceller.delay()
+    }
+}
+
+impl<M: AudioStream, S: AudioStream> AudioStream for EchoCancelStream<M, S> {
+    fn sample_rate(&self) -> u32 {
+        self.canceller.config().sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+}
+
+impl<M: AudioStream, S: AudioStream> FuturesStream for EchoCancelStream<M, S> {
+    type Item = Result<AudioChunk, AudioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.as_mut().get_mut();
+
+        let item = futures::ready!(Pin::new(&mut this.inner).poll_next(cx));
+        Poll::Ready(item.map(|item| {
+            item.map(|chunk| {
+                let (near, far): (Vec<f32>, Vec<f32>) = chunk
+                    .samples
+                    .chunks_exact(2)
+                    .map(|frame| (frame[0], frame[1]))
+                    .unzip();
+                let cleaned = this.canceller.process(&near, &far);
+                AudioChunk::new(cleaned, chunk.sample_rate, 1, chunk.timestamp, chunk.sequence)
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ChunkStream;
+    use futures::StreamExt;
+    use std::f64::consts::PI;
+
+    /// Noise with a syllable-like envelope, held for each 1/16000 s
+    fn far_end(t: f64) -> f32 {
+        if t < 0.0 {
+            return 0.0;
+        }
+        let n = (t * 16000.0) as u64;
+        let noise = (n.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 40) as f32 / (1u64 << 24) as f32 - 0.5;
+        let envelope = 0.6 + 0.4 * (2.0 * PI * 3.0 * t).sin() as f32;
+        noise * envelope
+    }
+
+    fn power(samples: &[f32]) -> f32 {
+        samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32
+    }
+
+    /// Reduction in dB from `before` to `after`
+    fn reduction_db(before: &[f32], after: &[f32]) -> f32 {
+        10.0 * (power(before) / power(after)).log10()
+    }
+
+    #[test]
+    fn test_cancels_delayed_echo_and_keeps_near_end() {
+        let rate = 16000;
+        let delay = 1920; // 120 ms
+        let far: Vec<f32> = (0..6 * rate).map(|n| far_end(n as f64 / rate as f64)).collect();
+        let echo: Vec<f32> = (0..far.len())
+            .map(|n| {
+                let tap = |k: usize| n.checked_sub(delay + k).map_or(0.0, |i| far[i]);
+                0.4 * tap(0) + 0.2 * tap(7) - 0.1 * tap(23)
+            })
+            .collect();
+        // The near end talks during the last two seconds
+        let near: Vec<f32> = (0..far.len())
+            .map(|n| {
+                let t = n as f64 / rate as f64;
+                if t >= 4.0 { 0.05 * (2.0 * PI * 300.0 * t).sin() as f32 } else { 0.0 }
+            })
+            .collect();
+        let mic: Vec<f32> = echo.iter().zip(&near).map(|(e, s)| e + s).collect();
+
+        let config = EchoCancellerConfig::new().with_suppression(0.0);
+        let mut canceller = EchoCanceller::new(config.clone());
+        let out = canceller.process(&mic, &far);
+
+        let estimated = canceller.delay().unwrap();
+        assert!(estimated.abs_diff(Duration::from_millis(120)) <= Duration::from_millis(8));
+        assert!(canceller.is_converged());
+
+        let echo_only = 3 * rate..4 * rate;
+        let erle = reduction_db(&mic[echo_only.clone()], &out[echo_only.clone()]);
+        assert!(erle > 20.0, "filter reduced echo by {} dB", erle);
+
+        // Suppression removes more echo, but not the near end
+        let out = EchoCanceller::new(config.with_suppression(1.0)).process(&mic, &far);
+        let suppressed = reduction_db(&mic[echo_only.clone()], &out[echo_only]);
+        assert!(suppressed > erle, "suppression reduced echo by {} dB", suppressed);
+
+        let talking = 5 * rate..6 * rate;
+        let kept = reduction_db(&near[talking.clone()], &out[talking]);
+        assert!(kept.abs() < 3.0, "near end changed by {} dB", kept);
+    }
+
+    #[tokio::test]
+    async fn test_stream_cleans_mic() {
+        // The mic hears the speaker 80 ms later
+        let mic = ChunkStream::new(48000, 5.0, 0.0, |t| 0.5 * far_end(t - 0.08));
+        let speaker = ChunkStream::new(44100, 5.0, 0.0, far_end);
+
+        let mut stream = EchoCancelStream::new(mic, speaker, EchoCancellerConfig::default());
+        assert_eq!((stream.sample_rate(), stream.channels()), (16000, 1));
+
+        let mut samples = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.unwrap();
+            assert_eq!(chunk.channels, 1);
+            samples.extend(chunk.samples);
+        }
+        assert!(samples.len().abs_diff(80000) < 100, "{} samples", samples.len());
+
+        let delay = stream.delay().unwrap();
+        assert!(delay.abs_diff(Duration::from_millis(80)) <= Duration::from_millis(8), "{:?}", delay);
+
+        let echo: Vec<f32> = (64000..78000)
+            .map(|n| 0.5 * far_end(n as f64 / 16000.0 - 0.08))
+            .collect();
+        let reduction = reduction_db(&echo, &samples[64000..78000]);
+        assert!(reduction > 15.0, "echo reduced by {} dB", reduction);
+    }
+}
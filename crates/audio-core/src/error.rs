@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::config::StreamFormat;
+
 #[derive(Error, Debug)]
 pub enum AudioError {
     #[error("No audio device found")]
@@ -25,4 +27,32 @@ pub enum AudioError {
 
     #[error("Platform not supported: {0}")]
     PlatformNotSupported(String),
+
+    #[error("Device disconnected: {0}")]
+    DeviceDisconnected(String),
+
+    #[error("Processing error: {0}")]
+    ProcessingError(String),
+
+    #[error(
+        "Stream format changed from {} Hz, {} channels to {} Hz, {} channels",
+        .previous.sample_rate,
+        .previous.channels,
+        .current.sample_rate,
+        .current.channels
+    )]
+    FormatChanged {
+        previous: StreamFormat,
+        current: StreamFormat,
+    },
+}
+
+impl AudioError {
+    /// Whether a stream keeps delivering audio after reporting this error
+    ///
+    /// A format change is informational: later chunks simply carry the new
+    /// format. Every other error ends the stream.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, AudioError::FormatChanged { .. })
+    }
 }
@@ -0,0 +1,475 @@
+//! Automatic device failover for capture streams
+//!
+//! A [`FailoverStream`] wraps the stream of one input. When that stream ends
+//! with a fatal error, typically because the device was unplugged, it
+//! reopens the input on a fallback device or the new system default and
+//! carries on as one continuous stream. The time spent without a device is
+//! filled with [`AudioChunk::gap_fill`] silence, so the audio timeline keeps
+//! matching the capture clock.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::channel::mpsc;
+use futures::Stream as FuturesStream;
+use serde::{Deserialize, Serialize};
+use tokio::time::Sleep;
+
+use crate::chunk::{capture_clock_now, duration_to_frames, AudioChunk};
+use crate::config::{AudioInputConfig, StreamFormat};
+use crate::error::AudioError;
+use crate::traits::{AudioInput, AudioStream};
+
+/// Default pause between rounds of reopen attempts
+const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How a capture recovers when its device goes away
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct FailoverConfig {
+    /// Reopen the capture on another device instead of ending it
+    pub enabled: bool,
+    /// Device ids tried in order before the system default
+    pub fallback_device_ids: Vec<String>,
+    /// Pause between rounds of reopen attempts
+    pub retry_interval: Duration,
+    /// Give up after this long without a device; `None` keeps trying until
+    /// the stream is dropped
+    pub max_outage: Option<Duration>,
+}
+
+impl Default for FailoverConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            fallback_device_ids: Vec::new(),
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+            max_outage: None,
+        }
+    }
+}
+
+impl FailoverConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Add a device to try before the system default
+    pub fn with_fallback_device(mut self, device_id: impl Into<String>) -> Self {
+        self.fallback_device_ids.push(device_id.into());
+        self
+    }
+
+    pub fn with_retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    pub fn with_max_outage(mut self, max_outage: Duration) -> Self {
+        self.max_outage = Some(max_outage);
+        self
+    }
+}
+
+/// Device switch reported by a [`FailoverStream`]
+///
+/// Device ids are `None` for the system default device.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FailoverEvent {
+    /// The device stopped delivering audio and reopening has started
+    DeviceLost {
+        device_id: Option<String>,
+        reason: String,
+    },
+    /// Capture resumed after `gap` of inserted silence
+    Switched {
+        device_id: Option<String>,
+        sample_rate: u32,
+        channels: u16,
+        gap: Duration,
+    },
+}
+
+/// Open `I` with `config` and start it
+///
+/// Pass `open_input::<SomeInput>` to [`FailoverStream::new`] to reopen a
+/// regular [`AudioInput`].
+pub fn open_input<I: AudioInput>(config: &AudioInputConfig) -> Result<I::Stream, AudioError> {
+    I::with_config(config)?.stream()
+}
+
+/// Time without a device
+struct Outage {
+    /// Capture clock reading when the device was lost
+    since: Duration,
+    /// Error that ended the previous stream, reported if recovery gives up
+    error: AudioError,
+}
+
+/// Stream that survives losing its device
+///
+/// Chunks are renumbered so sequence numbers stay consecutive across device
+/// switches. If the new device negotiates a different format, an
+/// [`AudioError::FormatChanged`] is delivered before its first chunk.
+///
+/// Reopening calls the opener from inside `poll_next`, and the retry timer
+/// relies on tokio, so the stream must be polled inside a tokio runtime
+/// with time enabled, on a thread that may block briefly.
+pub struct FailoverStream<S, F> {
+    open: F,
+    input_config: AudioInputConfig,
+    failover: FailoverConfig,
+    /// Device the current stream was opened on
+    device_id: Option<String>,
+    stream: Option<Pin<Box<S>>>,
+    sample_rate: u32,
+    channels: u16,
+    sequence: u64,
+    /// Capture time just after the last delivered chunk
+    last_end: Option<Duration>,
+    outage: Option<Outage>,
+    pending: VecDeque<Result<AudioChunk, AudioError>>,
+    sleep: Option<Pin<Box<Sleep>>>,
+    events: Option<mpsc::UnboundedSender<FailoverEvent>>,
+    ended: bool,
+}
+
+// The opener is never pinned, and the inner stream is pinned on the heap
+impl<S, F> Unpin for FailoverStream<S, F> {}
+
+impl<S, F> FailoverStream<S, F>
+where
+    S: AudioStream,
+    F: FnMut(&AudioInputConfig) -> Result<S, AudioError>,
+{
+    /// Open the initial stream with `open`
+    ///
+    /// `open` is called again with the device id replaced whenever the
+    /// stream has to be reopened on another device.
+    pub fn new(
+        input_config: AudioInputConfig,
+        failover: FailoverConfig,
+        mut open: F,
+    ) -> Result<Self, AudioError> {
+        let stream = open(&input_config)?;
+
+        Ok(Self {
+            device_id: input_config.device_id.clone(),
+            sample_rate: stream.sample_rate(),
+            channels: stream.channels(),
+            stream: Some(Box::pin(stream)),
+            open,
+            input_config,
+            failover,
+            sequence: 0,
+            last_end: None,
+            outage: None,
+            pending: VecDeque::new(),
+            sleep: None,
+            events: None,
+            ended: false,
+        })
+    }
+
+    /// Receive [`FailoverEvent`]s from now on
+    ///
+    /// Only the most recent receiver gets events.
+    pub fn subscribe(&mut self) -> mpsc::UnboundedReceiver<FailoverEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.events = Some(tx);
+        rx
+    }
+
+    /// Device currently captured, `None` for the system default
+    pub fn device_id(&self) -> Option<&str> {
+        self.device_id.as_deref()
+    }
+
+    fn notify(&mut self, event: FailoverEvent) {
+        if let Some(tx) = &self.events {
+            if tx.unbounded_send(event).is_err() {
+                self.events = None;
+            }
+        }
+    }
+
+    /// Devices to try, in order: the configured one, the fallbacks, then the default
+    fn candidates(&self) -> Vec<Option<String>> {
+        let mut candidates: Vec<Option<String>> = Vec::new();
+
+        let configured = self.input_config.device_id.iter();
+        for device_id in configured.chain(&self.failover.fallback_device_ids) {
+            if !candidates.iter().flatten().any(|id| id == device_id) {
+                candidates.push(Some(device_id.clone()));
+            }
+        }
+        candidates.push(None);
+
+        candidates
+    }
+
+    /// Drop the current stream after it ended, with `error` if it failed
+    fn lose(&mut self, error: Option<AudioError>) {
+        self.stream = None;
+
+        if !self.failover.enabled {
+            self.pending.extend(error.map(Err));
+            self.ended = true;
+            return;
+        }
+
+        let error =
+            error.unwrap_or_else(|| AudioError::StreamError("Stream ended unexpectedly".to_string()));
+        tracing::warn!(device_id = ?self.device_id, "Capture device lost: {}", error);
+
+        self.notify(FailoverEvent::DeviceLost {
+            device_id: self.device_id.clone(),
+            reason: error.to_string(),
+        });
+        self.outage = Some(Outage {
+            since: capture_clock_now(),
+            error,
+        });
+    }
+
+    /// Try every candidate device once; returns whether one could be opened
+    fn reopen(&mut self) -> bool {
+        for device_id in self.candidates() {
+            let mut config = self.input_config.clone();
+            config.device_id = device_id.clone();
+
+            match (self.open)(&config) {
+                Ok(stream) => {
+                    self.switch_to(device_id, stream);
+                    return true;
+                }
+                Err(e) => tracing::debug!(?device_id, "Failed to reopen capture: {}", e),
+            }
+        }
+
+        false
+    }
+
+    /// Continue on `stream`, filling the time since the last chunk with silence
+    fn switch_to(&mut self, device_id: Option<String>, stream: S) {
+        let now = capture_clock_now();
+        let gap_start = self
+            .last_end
+            .or(self.outage.take().map(|outage| outage.since))
+            .unwrap_or(now);
+        let gap = now.saturating_sub(gap_start);
+
+        let frames = duration_to_frames(gap, self.sample_rate) as usize;
+        if frames > 0 {
+            self.pending.push_back(Ok(AudioChunk::silence(
+                frames,
+                self.sample_rate,
+                self.channels,
+                gap_start,
+                self.sequence,
+            )));
+            self.sequence += 1;
+        }
+
+        let previous = StreamFormat {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            buffer_frames: None,
+        };
+        let current = StreamFormat {
+            sample_rate: stream.sample_rate(),
+            channels: stream.channels(),
+            buffer_frames: None,
+        };
+        if previous != current {
+            self.pending.push_back(Err(AudioError::FormatChanged { previous, current }));
+        }
+
+        tracing::info!(?device_id, ?gap, "Capture switched device");
+
+        self.outage = None;
+        self.sample_rate = current.sample_rate;
+        self.channels = current.channels;
+        self.device_id = device_id.clone();
+        self.stream = Some(Box::pin(stream));
+        self.notify(FailoverEvent::Switched {
+            device_id,
+            sample_rate: current.sample_rate,
+            channels: current.channels,
+            gap,
+        });
+    }
+}
+
+impl<S, F> AudioStream for FailoverStream<S, F>
+where
+    S: AudioStream,
+    F: FnMut(&AudioInputConfig) -> Result<S, AudioError>,
+{
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+}
+
+impl<S, F> FuturesStream for FailoverStream<S, F>
+where
+    S: AudioStream,
+    F: FnMut(&AudioInputConfig) -> Result<S, AudioError>,
+{
+    type Item = Result<AudioChunk, AudioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.as_mut().get_mut();
+
+        loop {
+            if let Some(item) = this.pending.pop_front() {
+                return Poll::Ready(Some(item));
+            }
+
+            if this.ended {
+                return Poll::Ready(None);
+            }
+
+            if let Some(stream) = this.stream.as_mut() {
+                match futures::ready!(stream.as_mut().poll_next(cx)) {
+                    Some(Ok(mut chunk)) => {
+                        chunk.sequence = this.sequence;
+                        this.sequence += 1;
+                        this.last_end = Some(chunk.end_timestamp());
+                        return Poll::Ready(Some(Ok(chunk)));
+                    }
+                    Some(Err(e)) if e.is_recoverable() => {
+                        if let AudioError::FormatChanged { current, .. } = &e {
+                            this.sample_rate = current.sample_rate;
+                            this.channels = current.channels;
+                        }
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    Some(Err(e)) => this.lose(Some(e)),
+                    None => this.lose(None),
+                }
+                continue;
+            }
+
+            if let Some(sleep) = this.sleep.as_mut() {
+                futures::ready!(sleep.as_mut().poll(cx));
+                this.sleep = None;
+            }
+
+            if this.reopen() {
+                continue;
+            }
+
+            let gave_up = match (&this.outage, this.failover.max_outage) {
+                (Some(outage), Some(max_outage)) => {
+                    capture_clock_now().saturating_sub(outage.since) >= max_outage
+                }
+                _ => false,
+            };
+            if gave_up {
+                if let Some(outage) = this.outage.take() {
+                    tracing::error!("No capture device available, giving up: {}", outage.error);
+                    this.pending.push_back(Err(outage.error));
+                }
+                this.ended = true;
+                continue;
+            }
+
+            this.sleep = Some(Box::pin(tokio::time::sleep(this.failover.retry_interval)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::Pacing;
+    use crate::generator::{GeneratorInput, GeneratorStream, Signal};
+    use futures::StreamExt;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn generator(config: &AudioInputConfig) -> GeneratorStream {
+        GeneratorInput::with_config(config)
+            .unwrap()
+            .with_signal(Signal::sine(440.0, 0.5))
+            .with_pacing(Pacing::Fast)
+            .with_duration(Duration::from_millis(100))
+            .stream()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_failover_fills_gap_and_switches() {
+        let attempts = Rc::new(RefCell::new(Vec::new()));
+        let opener_attempts = attempts.clone();
+        let open = move |config: &AudioInputConfig| {
+            let mut attempts = opener_attempts.borrow_mut();
+            attempts.push(config.device_id.clone());
+            // Initial open succeeds, the first round of reopening fails
+            match attempts.len() {
+                1 | 6 => Ok(generator(config)),
+                _ => Err(AudioError::NoDeviceFound),
+            }
+        };
+
+        let input_config = AudioInputConfig::new().with_device_id("usb").with_sample_rate(16000);
+        let failover = FailoverConfig::new()
+            .with_fallback_device("headset")
+            .with_retry_interval(Duration::from_millis(200));
+        let mut stream = FailoverStream::new(input_config, failover, open).unwrap();
+        let mut events = stream.subscribe();
+
+        let chunks: Vec<AudioChunk> = stream.by_ref().take(3).map(Result::unwrap).collect().await;
+        let fill = chunks.iter().find(|chunk| chunk.gap_fill).unwrap();
+        assert!(fill.samples.iter().all(|&s| s == 0.0));
+        assert!(fill.duration() >= Duration::from_millis(50));
+        assert_eq!(fill.timestamp, chunks[0].timestamp + Duration::from_millis(100));
+        assert!(chunks.windows(2).all(|w| w[1].sequence == w[0].sequence + 1));
+
+        let usb = Some("usb".to_string());
+        let headset = Some("headset".to_string());
+        assert_eq!(
+            *attempts.borrow(),
+            [usb.clone(), usb.clone(), headset.clone(), None, usb.clone(), headset.clone()]
+        );
+
+        assert!(matches!(
+            events.next().await,
+            Some(FailoverEvent::DeviceLost { device_id, .. }) if device_id == usb
+        ));
+        assert!(matches!(
+            events.next().await,
+            Some(FailoverEvent::Switched { device_id, .. }) if device_id == headset
+        ));
+        assert_eq!(stream.device_id(), Some("headset"));
+    }
+
+    #[tokio::test]
+    async fn test_failover_disabled_passes_end_through() {
+        let config = AudioInputConfig::new().with_sample_rate(16000);
+        let failover = FailoverConfig::new().with_enabled(false);
+        let stream = FailoverStream::new(config, failover, |config: &AudioInputConfig| {
+            Ok(generator(config))
+        })
+        .unwrap();
+
+        let chunks: Vec<AudioChunk> = stream.map(Result::unwrap).collect().await;
+        let frames: usize = chunks.iter().map(AudioChunk::frames).sum();
+        assert_eq!(frames, 1600);
+        assert!(chunks.iter().all(|chunk| !chunk.gap_fill));
+    }
+}
@@ -0,0 +1,149 @@
+//! Radix-2 fast Fourier transform for the spectral processors
+
+use std::f64::consts::PI;
+use std::ops::{Add, Mul, Sub};
+
+/// Complex number in single precision
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+
+    pub fn norm_sqr(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    pub fn scale(self, factor: f32) -> Self {
+        Self::new(self.re * factor, self.im * factor)
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+/// In-place FFT of a fixed power-of-two size
+pub(crate) struct Fft {
+    size: usize,
+    /// `exp(-2πik/size)` for `k` in `0..size/2`
+    twiddles: Vec<Complex>,
+}
+
+impl Fft {
+    /// # Panics
+    ///
+    /// Panics if `size` is not a power of two.
+    pub fn new(size: usize) -> Self {
+        assert!(size.is_power_of_two(), "FFT size {} is not a power of two", size);
+
+        let twiddles = (0..size / 2)
+            .map(|k| {
+                let angle = -2.0 * PI * k as f64 / size as f64;
+                Complex::new(angle.cos() as f32, angle.sin() as f32)
+            })
+            .collect();
+
+        Self { size, twiddles }
+    }
+
+    /// Transform `data` to the frequency domain
+    pub fn forward(&self, data: &mut [Complex]) {
+        self.transform(data, false);
+    }
+
+    /// Transform `data` back to the time domain, including the `1/size` scaling
+    pub fn inverse(&self, data: &mut [Complex]) {
+        self.transform(data, true);
+        let scale = 1.0 / self.size as f32;
+        for value in data.iter_mut() {
+            *value = value.scale(scale);
+        }
+    }
+
+    fn transform(&self, data: &mut [Complex], inverse: bool) {
+        assert_eq!(data.len(), self.size);
+
+        // Bit-reversal permutation
+        let bits = self.size.trailing_zeros();
+        for i in 0..self.size {
+            let j = i.reverse_bits() >> (usize::BITS - bits);
+            if i < j {
+                data.swap(i, j);
+            }
+        }
+
+        let mut len = 2;
+        while len <= self.size {
+            let stride = self.size / len;
+            for start in (0..self.size).step_by(len) {
+                for k in 0..len / 2 {
+                    let twiddle = self.twiddles[k * stride];
+                    let twiddle = if inverse { twiddle.conj() } else { twiddle };
+                    let even = data[start + k];
+                    let odd = data[start + k + len / 2] * twiddle;
+                    data[start + k] = even + odd;
+                    data[start + k + len / 2] = even - odd;
+                }
+            }
+            len *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_and_tone_bin() {
+        let fft = Fft::new(64);
+        let signal: Vec<Complex> = (0..64)
+            .map(|n| Complex::new((2.0 * PI * 5.0 * n as f64 / 64.0).cos() as f32, 0.0))
+            .collect();
+
+        let mut data = signal.clone();
+        fft.forward(&mut data);
+        // A cosine splits its energy between bins 5 and 64 - 5
+        assert!((data[5].re - 32.0).abs() < 1e-3);
+        assert!((data[59].re - 32.0).abs() < 1e-3);
+        assert!(data[6].norm_sqr() < 1e-6);
+
+        fft.inverse(&mut data);
+        for (a, b) in data.iter().zip(&signal) {
+            assert!((*a - *b).norm_sqr() < 1e-10);
+        }
+    }
+}
@@ -0,0 +1,420 @@
+//! WAV file playback as an audio input
+//!
+//! [`FileInput`] replays a PCM or float WAV file through the same
+//! [`AudioInput`]/[`AudioStream`] interface as the live devices, which makes
+//! downstream processing testable on machines without audio hardware. The
+//! debug recordings written by the desktop app (mono, 32-bit float) can be
+//! fed back directly.
+
+use std::fs::File;
+use std::future::Future;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::Stream as FuturesStream;
+use tokio::time::{Instant, Sleep};
+
+use crate::chunk::{capture_clock_now, frames_to_duration, AudioChunk};
+use crate::config::{AudioInputConfig, ChannelMode, StreamFormat};
+use crate::conversion::remix_channels;
+use crate::error::AudioError;
+use crate::traits::{AudioInput, AudioStream};
+
+/// Number of frames per emitted chunk unless configured otherwise
+const DEFAULT_CHUNK_FRAMES: u32 = 1024;
+
+/// How fast a [`FileStream`] emits chunks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Pacing {
+    /// Emit each chunk once its audio would have been captured live
+    #[default]
+    RealTime,
+    /// Emit chunks as fast as they are polled
+    Fast,
+}
+
+/// Audio input reading from a WAV file
+pub struct FileInput {
+    path: PathBuf,
+    file_channels: u16,
+    format: StreamFormat,
+    pacing: Pacing,
+    looping: bool,
+}
+
+impl FileInput {
+    /// Open a WAV file with its native channel layout and default chunk size
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, AudioError> {
+        let config = AudioInputConfig::new()
+            .with_device_id(path.as_ref().to_string_lossy())
+            .with_channels(ChannelMode::Native);
+
+        Self::with_config(&config)
+    }
+
+    /// Set how fast chunks are emitted
+    pub fn with_pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Restart from the beginning when the end of the file is reached
+    pub fn with_looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    /// Get the path of the file being read
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AudioInput for FileInput {
+    type Stream = FileStream;
+
+    /// Open the WAV file named by `config.device_id`
+    ///
+    /// The file's sample rate cannot be changed and is reported as is. The
+    /// channel mode remixes the file's channels and `buffer_frames` sets the
+    /// chunk size.
+    fn with_config(config: &AudioInputConfig) -> Result<Self, AudioError> {
+        let path = PathBuf::from(config.device_id.as_ref().ok_or(AudioError::NoDeviceFound)?);
+        let reader = open_reader(&path)?;
+        let spec = reader.spec();
+
+        let format = StreamFormat {
+            sample_rate: spec.sample_rate,
+            channels: config.channels.output_channels(spec.channels),
+            buffer_frames: Some(config.buffer_frames.unwrap_or(DEFAULT_CHUNK_FRAMES).max(1)),
+        };
+
+        Ok(Self {
+            path,
+            file_channels: spec.channels,
+            format,
+            pacing: Pacing::default(),
+            looping: false,
+        })
+    }
+
+    fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    fn stream(self) -> Result<FileStream, AudioError> {
+        let reader = open_reader(&self.path)?;
+
+        tracing::info!(
+            path = %self.path.display(),
+            sample_rate = self.format.sample_rate,
+            frames = reader.duration(),
+            "File playback initialized"
+        );
+
+        Ok(FileStream {
+            reader,
+            path: self.path,
+            file_channels: self.file_channels as usize,
+            format: self.format,
+            chunk_frames: self.format.buffer_frames.unwrap_or(DEFAULT_CHUNK_FRAMES) as usize,
+            pacing: self.pacing,
+            looping: self.looping,
+            started_at: None,
+            clock_origin: None,
+            frames_emitted: 0,
+            sequence: 0,
+            pending: None,
+            sleep: None,
+            finished: false,
+        })
+    }
+}
+
+/// Open a WAV file, rejecting formats we cannot convert to f32
+fn open_reader(path: &Path) -> Result<hound::WavReader<BufReader<File>>, AudioError> {
+    let reader = hound::WavReader::open(path).map_err(|e| wav_error(path, e))?;
+
+    let spec = reader.spec();
+    let supported = match spec.sample_format {
+        hound::SampleFormat::Float => spec.bits_per_sample == 32,
+        hound::SampleFormat::Int => (8..=32).contains(&spec.bits_per_sample),
+    };
+
+    if !supported || spec.channels == 0 {
+        return Err(AudioError::UnsupportedFormat);
+    }
+
+    Ok(reader)
+}
+
+/// Map a hound error for `path` to an [`AudioError`]
+fn wav_error(path: &Path, error: hound::Error) -> AudioError {
+    match error {
+        hound::Error::IoError(e) => AudioError::DeviceNotAvailable(format!("{}: {}", path.display(), e)),
+        hound::Error::Unsupported => AudioError::UnsupportedFormat,
+        e => AudioError::DeviceError(format!("{}: {}", path.display(), e)),
+    }
+}
+
+// ============================================================================
+// FileStream implementation
+// ============================================================================
+
+/// Stream of audio samples read from a WAV file
+///
+/// Real-time pacing relies on the tokio timer, so the stream must be polled
+/// inside a tokio runtime with time enabled.
+pub struct FileStream {
+    reader: hound::WavReader<BufReader<File>>,
+    path: PathBuf,
+    file_channels: usize,
+    format: StreamFormat,
+    chunk_frames: usize,
+    pacing: Pacing,
+    looping: bool,
+    started_at: Option<Instant>,
+    /// Capture clock reading for the first frame of the file
+    clock_origin: Option<Duration>,
+    frames_emitted: u64,
+    sequence: u64,
+    pending: Option<AudioChunk>,
+    sleep: Option<Pin<Box<Sleep>>>,
+    finished: bool,
+}
+
+impl FileStream {
+    /// Read up to one chunk of interleaved samples, looping if enabled
+    fn read_chunk(&mut self) -> Result<Vec<f32>, hound::Error> {
+        let wanted = self.chunk_frames * self.file_channels;
+        let mut samples = Vec::with_capacity(wanted);
+
+        loop {
+            self.read_samples(wanted - samples.len(), &mut samples)?;
+
+            let at_end = samples.len() < wanted;
+            if !at_end || !self.looping || self.reader.duration() == 0 {
+                break;
+            }
+
+            self.reader.seek(0)?;
+        }
+
+        // Drop a trailing partial frame from a truncated file
+        samples.truncate(samples.len() / self.file_channels * self.file_channels);
+        Ok(samples)
+    }
+
+    /// Append up to `count` samples converted to f32
+    fn read_samples(&mut self, count: usize, out: &mut Vec<f32>) -> Result<(), hound::Error> {
+        let spec = self.reader.spec();
+
+        match spec.sample_format {
+            hound::SampleFormat::Float => {
+                for sample in self.reader.samples::<f32>().take(count) {
+                    out.push(sample?);
+                }
+            }
+            hound::SampleFormat::Int => {
+                let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+                for sample in self.reader.samples::<i32>().take(count) {
+                    out.push(sample? as f32 * scale);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Time at which the audio up to `frames` would have been captured live
+    fn deadline(&mut self, frames: u64) -> Instant {
+        let started_at = *self.started_at.get_or_insert_with(Instant::now);
+        started_at + Duration::from_secs_f64(frames as f64 / self.format.sample_rate as f64)
+    }
+}
+
+impl AudioStream for FileStream {
+    fn sample_rate(&self) -> u32 {
+        self.format.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.format.channels
+    }
+}
+
+impl FuturesStream for FileStream {
+    type Item = Result<AudioChunk, AudioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.as_mut().get_mut();
+
+        if this.finished {
+            return Poll::Ready(None);
+        }
+
+        if this.pending.is_none() {
+            let samples = match this.read_chunk() {
+                Ok(samples) => samples,
+                Err(e) => {
+                    tracing::error!("Failed to read WAV file: {}", e);
+                    this.finished = true;
+                    return Poll::Ready(Some(Err(wav_error(&this.path, e))));
+                }
+            };
+
+            if samples.is_empty() {
+                this.finished = true;
+                return Poll::Ready(None);
+            }
+
+            // Stamp chunks as if the file had been captured live from the
+            // moment playback started
+            let origin = *this.clock_origin.get_or_insert_with(capture_clock_now);
+            let timestamp = origin + frames_to_duration(this.frames_emitted, this.format.sample_rate);
+
+            let frames = (samples.len() / this.file_channels) as u64;
+            if this.pacing == Pacing::RealTime {
+                let deadline = this.deadline(this.frames_emitted + frames);
+                this.sleep = Some(Box::pin(tokio::time::sleep_until(deadline)));
+            }
+
+            let samples = remix_channels(&samples, this.file_channels, this.format.channels as usize);
+            this.pending = Some(AudioChunk::new(
+                samples,
+                this.format.sample_rate,
+                this.format.channels,
+                timestamp,
+                this.sequence,
+            ));
+            this.frames_emitted += frames;
+            this.sequence += 1;
+        }
+
+        if let Some(sleep) = this.sleep.as_mut() {
+            if sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.sleep = None;
+        }
+
+        Poll::Ready(this.pending.take().map(Ok))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn write_wav(name: &str, spec: hound::WavSpec, frames: usize) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("heronote-{}-{}.wav", name, std::process::id()));
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+
+        for i in 0..frames * spec.channels as usize {
+            match spec.sample_format {
+                hound::SampleFormat::Float => writer.write_sample(0.5f32).unwrap(),
+                hound::SampleFormat::Int => writer.write_sample((i % 2) as i16 * i16::MIN).unwrap(),
+            }
+        }
+
+        writer.finalize().unwrap();
+        path
+    }
+
+    fn float_mono(sample_rate: u32) -> hound::WavSpec {
+        hound::WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fast_playback_reads_whole_file() {
+        let path = write_wav("fast", float_mono(16000), 2500);
+        let input = FileInput::open(&path).unwrap().with_pacing(Pacing::Fast);
+        assert_eq!(input.sample_rate(), 16000);
+
+        let chunks: Vec<AudioChunk> = input.stream().unwrap().map(Result::unwrap).collect().await;
+        let total: usize = chunks.iter().map(AudioChunk::frames).sum();
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(total, 2500);
+        assert!(chunks.iter().flat_map(|c| &c.samples).all(|&s| s == 0.5));
+
+        // Timestamps follow the file timeline without gaps
+        for (i, pair) in chunks.windows(2).enumerate() {
+            assert_eq!(pair[0].sequence, i as u64);
+            assert_eq!(pair[0].end_timestamp(), pair[1].timestamp);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_int_stereo_downmix() {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let path = write_wav("stereo", spec, 100);
+
+        let config = AudioInputConfig::new()
+            .with_device_id(path.to_string_lossy())
+            .with_buffer_frames(10);
+        let input = FileInput::with_config(&config).unwrap().with_pacing(Pacing::Fast);
+        assert_eq!(input.format().channels, 1);
+
+        let mut stream = input.stream().unwrap();
+        let chunk = stream.next().await.unwrap().unwrap();
+
+        // Channels alternate 0.0 and -1.0, averaging to -0.5
+        assert_eq!(chunk.channels, 1);
+        assert_eq!(chunk.samples.len(), 10);
+        assert!(chunk.samples.iter().all(|&s| (s + 0.5).abs() < f32::EPSILON));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_looping_restarts_file() {
+        let path = write_wav("loop", float_mono(16000), 100);
+        let input = FileInput::open(&path)
+            .unwrap()
+            .with_pacing(Pacing::Fast)
+            .with_looping(true);
+
+        let chunks: Vec<AudioChunk> = input.stream().unwrap().take(5).map(Result::unwrap).collect().await;
+
+        assert_eq!(chunks.len(), 5);
+        assert!(chunks.iter().all(|c| c.frames() == DEFAULT_CHUNK_FRAMES as usize));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_real_time_pacing() {
+        let path = write_wav("paced", float_mono(16000), 1600);
+        let input = FileInput::open(&path).unwrap();
+
+        let started = std::time::Instant::now();
+        let chunks: Vec<AudioChunk> = input.stream().unwrap().map(Result::unwrap).collect().await;
+
+        assert_eq!(chunks.iter().map(AudioChunk::frames).sum::<usize>(), 1600);
+        assert!(started.elapsed() >= Duration::from_millis(95));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let config = AudioInputConfig::new().with_device_id("/nonexistent/heronote.wav");
+        assert!(matches!(
+            FileInput::with_config(&config),
+            Err(AudioError::DeviceNotAvailable(_))
+        ));
+    }
+}
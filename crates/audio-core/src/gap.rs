@@ -0,0 +1,256 @@
+//! Detection and bookkeeping of lost audio
+//!
+//! Audio can be lost before it reaches a stream: a ring buffer overflows
+//! because the consumer fell behind, or the platform skips a stretch of
+//! capture altogether. Either way the recording would silently get shorter
+//! than real time and every later timestamp would be wrong. [`GapTracker`]
+//! lets ring-buffered backends account for the lost frames, so the stream
+//! can put the same amount of [`AudioChunk::gap_fill`] silence in their
+//! place. [`GapLog`] then records where the gaps ended up in a recording.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::{duration_to_frames, frames_to_duration, AudioChunk};
+
+/// Default lateness of a capture callback treated as lost audio
+///
+/// Smaller delays are scheduling jitter absorbed by the platform buffers.
+const DEFAULT_TOLERANCE: Duration = Duration::from_millis(250);
+
+/// Frames lost at a position of a ring-buffered stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LostFrames {
+    /// Stream frame where the silence goes
+    frame: u64,
+    frames: u64,
+}
+
+/// Accounts for audio lost between a capture callback and its stream
+///
+/// Stream frames count the audio written to the ring buffer plus the audio
+/// lost, so they follow wall-clock time. The producer reports every
+/// callback block through [`GapTracker::write`]; the consumer reads at most
+/// [`GapTracker::readable`] frames at a time and emits silence whenever
+/// [`GapTracker::take_gap`] returns lost frames at its read position.
+#[derive(Debug, Clone)]
+pub struct GapTracker {
+    sample_rate: u32,
+    tolerance: Duration,
+    /// Stream frames written so far, including lost ones
+    written: u64,
+    /// Expected capture time of the next callback block
+    expected: Option<Duration>,
+    pending: VecDeque<LostFrames>,
+}
+
+impl GapTracker {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            tolerance: DEFAULT_TOLERANCE,
+            written: 0,
+            expected: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Lateness of a callback block beyond which the time in between is
+    /// treated as lost
+    pub fn with_tolerance(mut self, tolerance: Duration) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Use `sample_rate` for the following blocks
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// Stream frames written so far, including lost ones
+    pub fn frames_written(&self) -> u64 {
+        self.written
+    }
+
+    /// Record a callback block of `frames` frames captured from `captured_at`,
+    /// of which the ring buffer only took the first `pushed`
+    ///
+    /// Returns the stream frame of the first pushed frame. A block arriving
+    /// later than the previous one implies is preceded by a gap covering the
+    /// missing time, and frames the ring buffer had no room for become a gap
+    /// after it.
+    pub fn write(&mut self, captured_at: Duration, frames: u64, pushed: u64) -> u64 {
+        if let Some(expected) = self.expected {
+            let late = captured_at.saturating_sub(expected);
+            if late > self.tolerance {
+                let lost = duration_to_frames(late, self.sample_rate);
+                tracing::warn!(frames = lost, ?late, "Capture skipped audio, inserting silence");
+                self.lose(lost);
+            }
+        }
+
+        let first = self.written;
+        self.written += pushed;
+
+        if pushed < frames {
+            let lost = frames - pushed;
+            tracing::warn!(frames = lost, "Audio dropped due to buffer overflow, inserting silence");
+            self.lose(lost);
+        }
+
+        self.expected = Some(captured_at + frames_to_duration(frames, self.sample_rate));
+        first
+    }
+
+    fn lose(&mut self, frames: u64) {
+        match self.pending.back_mut() {
+            Some(last) if last.frame + last.frames == self.written => last.frames += frames,
+            _ => self.pending.push_back(LostFrames {
+                frame: self.written,
+                frames,
+            }),
+        }
+        self.written += frames;
+    }
+
+    /// Frames that can be read from `position` before reaching the next gap
+    ///
+    /// `None` when no gap is pending.
+    pub fn readable(&self, position: u64) -> Option<u64> {
+        self.pending.front().map(|gap| gap.frame.saturating_sub(position))
+    }
+
+    /// Take the frames lost at `position`, if the next gap starts there
+    pub fn take_gap(&mut self, position: u64) -> Option<u64> {
+        if self.pending.front()?.frame > position {
+            return None;
+        }
+        self.pending.pop_front().map(|gap| gap.frames)
+    }
+}
+
+/// A stretch of a recording filled with silence in place of lost audio
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Gap {
+    /// Position of the silence in the recording
+    pub offset: Duration,
+    /// Capture time of the first missing frame
+    pub timestamp: Duration,
+    pub duration: Duration,
+}
+
+/// Record of the gaps in a recording
+///
+/// Feed every chunk written to the recording through [`GapLog::record`];
+/// consecutive [`AudioChunk::gap_fill`] chunks are merged into one gap.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GapLog {
+    /// Length of the recording so far
+    pub duration: Duration,
+    pub gaps: Vec<Gap>,
+    /// Whether the last chunk recorded was gap fill
+    #[serde(skip)]
+    in_gap: bool,
+}
+
+impl GapLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `chunk` to the end of the recording
+    ///
+    /// Returns the gap the chunk starts or extends, if it is gap fill.
+    pub fn record(&mut self, chunk: &AudioChunk) -> Option<&Gap> {
+        let offset = self.duration;
+        self.duration += chunk.duration();
+
+        if !chunk.gap_fill {
+            self.in_gap = false;
+            return None;
+        }
+
+        match self.gaps.last_mut() {
+            Some(gap) if self.in_gap => gap.duration += chunk.duration(),
+            _ => self.gaps.push(Gap {
+                offset,
+                timestamp: chunk.timestamp,
+                duration: chunk.duration(),
+            }),
+        }
+        self.in_gap = true;
+        self.gaps.last()
+    }
+
+    /// Total duration of silence inserted
+    pub fn total(&self) -> Duration {
+        self.gaps.iter().map(|gap| gap.duration).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overflow_becomes_gap_after_pushed_frames() {
+        let mut tracker = GapTracker::new(1000);
+
+        assert_eq!(tracker.write(Duration::from_secs(1), 100, 100), 0);
+        assert_eq!(tracker.write(Duration::from_millis(1100), 100, 40), 100);
+        // A block dropped entirely extends the same gap
+        assert_eq!(tracker.write(Duration::from_millis(1200), 100, 0), 200);
+        assert_eq!(tracker.write(Duration::from_millis(1300), 100, 100), 300);
+        assert_eq!(tracker.frames_written(), 400);
+
+        assert_eq!(tracker.readable(0), Some(140));
+        assert_eq!(tracker.take_gap(0), None);
+        assert_eq!(tracker.take_gap(140), Some(160));
+        assert_eq!(tracker.readable(300), None);
+    }
+
+    #[test]
+    fn test_late_block_becomes_gap_before_it() {
+        let mut tracker = GapTracker::new(1000).with_tolerance(Duration::from_millis(50));
+
+        tracker.write(Duration::from_secs(1), 100, 100);
+        // Jitter within the tolerance is not a gap
+        tracker.write(Duration::from_millis(1130), 100, 100);
+        assert_eq!(tracker.readable(0), None);
+
+        assert_eq!(tracker.write(Duration::from_millis(1730), 100, 100), 700);
+        assert_eq!(tracker.readable(0), Some(200));
+        assert_eq!(tracker.take_gap(200), Some(500));
+    }
+
+    #[test]
+    fn test_log_merges_consecutive_gap_fill() {
+        let audio = |ms| AudioChunk::new(vec![0.1; 10], 1000, 1, Duration::from_millis(ms), 0);
+        let silence = |ms| AudioChunk::silence(10, 1000, 1, Duration::from_millis(ms), 0);
+
+        let mut log = GapLog::new();
+        for chunk in [audio(0), silence(10), silence(20), audio(30), silence(40)] {
+            log.record(&chunk);
+        }
+
+        assert_eq!(log.duration, Duration::from_millis(50));
+        assert_eq!(log.total(), Duration::from_millis(30));
+        assert_eq!(
+            log.gaps,
+            vec![
+                Gap {
+                    offset: Duration::from_millis(10),
+                    timestamp: Duration::from_millis(10),
+                    duration: Duration::from_millis(20),
+                },
+                Gap {
+                    offset: Duration::from_millis(40),
+                    timestamp: Duration::from_millis(40),
+                    duration: Duration::from_millis(10),
+                },
+            ]
+        );
+    }
+}
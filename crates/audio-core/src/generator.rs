@@ -0,0 +1,710 @@
+//! Synthetic signal generator as an audio input
+//!
+//! [`GeneratorInput`] produces deterministic test signals through the
+//! [`AudioInput`]/[`AudioStream`] interface: tones, sweeps, seeded noise,
+//! silence and on/off bursts. Dropouts and sample-rate changes can be
+//! scheduled on the timeline to exercise the consumers' recovery paths
+//! without a device.
+
+use std::collections::VecDeque;
+use std::f64::consts::TAU;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::Stream as FuturesStream;
+use tokio::time::{Instant, Sleep};
+
+use crate::chunk::{capture_clock_now, AudioChunk};
+use crate::config::{AudioInputConfig, StreamFormat};
+use crate::conversion::remix_channels;
+use crate::error::AudioError;
+use crate::file::Pacing;
+use crate::traits::{AudioInput, AudioStream};
+
+/// Sample rate used when the config does not request one
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+/// Number of frames per emitted chunk unless configured otherwise
+const DEFAULT_CHUNK_FRAMES: u32 = 1024;
+
+/// Seed used unless one is set explicitly
+const DEFAULT_SEED: u64 = 0x4845_524F_4E4F_5445;
+
+/// Signal description for a [`GeneratorInput`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Signal {
+    Silence,
+    Sine {
+        frequency: f32,
+        amplitude: f32,
+    },
+    /// Linear sweep from `start` to `end` Hz, restarting every `period`
+    Chirp {
+        start: f32,
+        end: f32,
+        period: Duration,
+        amplitude: f32,
+    },
+    WhiteNoise {
+        amplitude: f32,
+    },
+    PinkNoise {
+        amplitude: f32,
+    },
+    /// `signal` gated on for `on`, then silent for `off`, repeating
+    Bursts {
+        signal: Box<Signal>,
+        on: Duration,
+        off: Duration,
+    },
+    /// Each signal played for its duration in turn, repeating
+    Sequence(Vec<(Signal, Duration)>),
+}
+
+impl Signal {
+    pub fn sine(frequency: f32, amplitude: f32) -> Self {
+        Signal::Sine {
+            frequency,
+            amplitude,
+        }
+    }
+
+    pub fn chirp(start: f32, end: f32, period: Duration, amplitude: f32) -> Self {
+        Signal::Chirp {
+            start,
+            end,
+            period,
+            amplitude,
+        }
+    }
+
+    pub fn white_noise(amplitude: f32) -> Self {
+        Signal::WhiteNoise { amplitude }
+    }
+
+    pub fn pink_noise(amplitude: f32) -> Self {
+        Signal::PinkNoise { amplitude }
+    }
+
+    /// Gate this signal on and off, e.g. to mimic talk spurts
+    pub fn bursts(self, on: Duration, off: Duration) -> Self {
+        Signal::Bursts {
+            signal: Box::new(self),
+            on,
+            off,
+        }
+    }
+}
+
+/// Event scheduled on the generator timeline
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Event {
+    /// Audio for `length` is generated but never delivered
+    Dropout { length: Duration },
+    /// The stream continues at a new sample rate
+    RateChange { sample_rate: u32 },
+}
+
+/// Audio input producing synthetic signals
+pub struct GeneratorInput {
+    signal: Signal,
+    format: StreamFormat,
+    seed: u64,
+    pacing: Pacing,
+    duration: Option<Duration>,
+    events: Vec<(Duration, Event)>,
+}
+
+impl GeneratorInput {
+    /// Create a mono generator at the default rate playing `signal`
+    pub fn from_signal(signal: Signal) -> Self {
+        Self::from_config(&AudioInputConfig::default()).with_signal(signal)
+    }
+
+    fn from_config(config: &AudioInputConfig) -> Self {
+        let format = StreamFormat {
+            sample_rate: config.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE).max(1),
+            channels: config.channels.output_channels(1),
+            buffer_frames: Some(config.buffer_frames.unwrap_or(DEFAULT_CHUNK_FRAMES).max(1)),
+        };
+
+        Self {
+            signal: Signal::Silence,
+            format,
+            seed: DEFAULT_SEED,
+            pacing: Pacing::RealTime,
+            duration: None,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn with_signal(mut self, signal: Signal) -> Self {
+        self.signal = signal;
+        self
+    }
+
+    /// Seed for the noise generators
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Set how fast chunks are emitted
+    pub fn with_pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// End the stream after `duration` of timeline; endless by default
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// Drop the audio between `at` and `at + length`
+    pub fn with_dropout(mut self, at: Duration, length: Duration) -> Self {
+        self.events.push((at, Event::Dropout { length }));
+        self
+    }
+
+    /// Switch to `sample_rate` at `at`
+    pub fn with_rate_change(mut self, at: Duration, sample_rate: u32) -> Self {
+        self.events.push((
+            at,
+            Event::RateChange {
+                sample_rate: sample_rate.max(1),
+            },
+        ));
+        self
+    }
+}
+
+impl AudioInput for GeneratorInput {
+    type Stream = GeneratorStream;
+
+    /// Create a silent generator with the requested rate, channels and chunk size
+    ///
+    /// Every request is granted. `device_id` is ignored.
+    fn with_config(config: &AudioInputConfig) -> Result<Self, AudioError> {
+        Ok(Self::from_config(config))
+    }
+
+    fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    fn stream(self) -> Result<GeneratorStream, AudioError> {
+        let mut events = self.events;
+        events.sort_by_key(|(at, _)| *at);
+
+        Ok(GeneratorStream {
+            source: Source::new(&self.signal),
+            rng: Rng::new(self.seed),
+            format: self.format,
+            chunk_frames: self.format.buffer_frames.unwrap_or(DEFAULT_CHUNK_FRAMES) as usize,
+            pacing: self.pacing,
+            end: self.duration.map(|d| d.as_secs_f64()),
+            events: events
+                .into_iter()
+                .map(|(at, event)| (at.as_secs_f64(), event))
+                .collect(),
+            segment_start: 0.0,
+            segment_frames: 0,
+            started_at: None,
+            clock_origin: None,
+            sequence: 0,
+            pending_error: None,
+            pending: None,
+            sleep: None,
+        })
+    }
+}
+
+// ============================================================================
+// Signal synthesis
+// ============================================================================
+
+/// SplitMix64 generator, small and good enough for test noise
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform sample in [-1, 1)
+    fn next_bipolar(&mut self) -> f32 {
+        ((self.next_u64() >> 40) as f32 / (1u64 << 23) as f32) - 1.0
+    }
+}
+
+/// Running state of a [`Signal`]
+enum Source {
+    Silence,
+    Sine {
+        frequency: f64,
+        amplitude: f32,
+        phase: f64,
+    },
+    Chirp {
+        start: f64,
+        end: f64,
+        period: f64,
+        amplitude: f32,
+        phase: f64,
+    },
+    White {
+        amplitude: f32,
+    },
+    Pink {
+        amplitude: f32,
+        state: [f32; 7],
+    },
+    Bursts {
+        inner: Box<Source>,
+        on: f64,
+        off: f64,
+    },
+    Sequence {
+        parts: Vec<(Source, f64)>,
+        total: f64,
+    },
+}
+
+impl Source {
+    fn new(signal: &Signal) -> Self {
+        match signal {
+            Signal::Silence => Source::Silence,
+            Signal::Sine {
+                frequency,
+                amplitude,
+            } => Source::Sine {
+                frequency: *frequency as f64,
+                amplitude: *amplitude,
+                phase: 0.0,
+            },
+            Signal::Chirp {
+                start,
+                end,
+                period,
+                amplitude,
+            } => Source::Chirp {
+                start: *start as f64,
+                end: *end as f64,
+                period: period.as_secs_f64(),
+                amplitude: *amplitude,
+                phase: 0.0,
+            },
+            Signal::WhiteNoise { amplitude } => Source::White {
+                amplitude: *amplitude,
+            },
+            Signal::PinkNoise { amplitude } => Source::Pink {
+                amplitude: *amplitude,
+                state: [0.0; 7],
+            },
+            Signal::Bursts { signal, on, off } => Source::Bursts {
+                inner: Box::new(Source::new(signal)),
+                on: on.as_secs_f64(),
+                off: off.as_secs_f64(),
+            },
+            Signal::Sequence(parts) => {
+                let parts: Vec<(Source, f64)> = parts
+                    .iter()
+                    .map(|(signal, duration)| (Source::new(signal), duration.as_secs_f64()))
+                    .collect();
+                let total = parts.iter().map(|(_, duration)| duration).sum();
+                Source::Sequence { parts, total }
+            }
+        }
+    }
+
+    /// Produce the sample at time `t`, `dt` seconds after the previous one
+    fn next(&mut self, t: f64, dt: f64, rng: &mut Rng) -> f32 {
+        match self {
+            Source::Silence => 0.0,
+            Source::Sine {
+                frequency,
+                amplitude,
+                phase,
+            } => {
+                let sample = phase.sin() as f32 * *amplitude;
+                *phase = (*phase + TAU * *frequency * dt) % TAU;
+                sample
+            }
+            Source::Chirp {
+                start,
+                end,
+                period,
+                amplitude,
+                phase,
+            } => {
+                let position = if *period > 0.0 { (t % *period) / *period } else { 0.0 };
+                let frequency = *start + (*end - *start) * position;
+                let sample = phase.sin() as f32 * *amplitude;
+                *phase = (*phase + TAU * frequency * dt) % TAU;
+                sample
+            }
+            Source::White { amplitude } => rng.next_bipolar() * *amplitude,
+            Source::Pink { amplitude, state } => {
+                // Paul Kellet's refined pink noise filter
+                let white = rng.next_bipolar();
+                state[0] = 0.99886 * state[0] + white * 0.055_517_9;
+                state[1] = 0.99332 * state[1] + white * 0.075_075_9;
+                state[2] = 0.96900 * state[2] + white * 0.153_852;
+                state[3] = 0.86650 * state[3] + white * 0.310_485_6;
+                state[4] = 0.55000 * state[4] + white * 0.532_952_2;
+                state[5] = -0.7616 * state[5] - white * 0.016_898;
+                let pink = state.iter().sum::<f32>() + white * 0.5362;
+                state[6] = white * 0.115_926;
+                (pink * 0.11).clamp(-1.0, 1.0) * *amplitude
+            }
+            Source::Bursts { inner, on, off } => {
+                let sample = inner.next(t, dt, rng);
+                let cycle = *on + *off;
+                let gated_on = cycle <= 0.0 || t % cycle < *on;
+                if gated_on {
+                    sample
+                } else {
+                    0.0
+                }
+            }
+            Source::Sequence { parts, total } => {
+                if *total <= 0.0 {
+                    return 0.0;
+                }
+
+                let mut offset = t % *total;
+                for (source, duration) in parts.iter_mut() {
+                    if offset < *duration {
+                        return source.next(t, dt, rng);
+                    }
+                    offset -= *duration;
+                }
+
+                0.0
+            }
+        }
+    }
+}
+
+// ============================================================================
+// GeneratorStream implementation
+// ============================================================================
+
+/// Stream of synthetic audio samples
+///
+/// Real-time pacing relies on the tokio timer, so the stream must be polled
+/// inside a tokio runtime with time enabled.
+pub struct GeneratorStream {
+    source: Source,
+    rng: Rng,
+    format: StreamFormat,
+    chunk_frames: usize,
+    pacing: Pacing,
+    /// Timeline position in seconds at which the stream ends
+    end: Option<f64>,
+    /// Pending events as (timeline seconds, event), sorted
+    events: VecDeque<(f64, Event)>,
+    /// Timeline position of the last rate change
+    segment_start: f64,
+    /// Frames produced since the last rate change
+    segment_frames: u64,
+    started_at: Option<Instant>,
+    /// Capture clock reading for timeline position zero
+    clock_origin: Option<Duration>,
+    sequence: u64,
+    /// Error to report before the pending chunk
+    pending_error: Option<AudioError>,
+    pending: Option<AudioChunk>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl GeneratorStream {
+    /// Current timeline position in seconds
+    fn position(&self) -> f64 {
+        self.segment_start + self.segment_frames as f64 / self.format.sample_rate as f64
+    }
+
+    /// Frames from the current position until timeline position `t`
+    fn frames_until(&self, t: f64) -> u64 {
+        let frame = ((t - self.segment_start) * self.format.sample_rate as f64).round();
+        (frame.max(0.0) as u64).saturating_sub(self.segment_frames)
+    }
+
+    /// Generate `frames` mono samples, advancing the timeline
+    fn generate(&mut self, frames: usize) -> Vec<f32> {
+        let dt = 1.0 / self.format.sample_rate as f64;
+
+        (0..frames)
+            .map(|_| {
+                let t = self.position();
+                self.segment_frames += 1;
+                self.source.next(t, dt, &mut self.rng)
+            })
+            .collect()
+    }
+
+    /// Apply events that are due and produce the next chunk, if any
+    ///
+    /// Returns the timeline position of the chunk's first frame along with
+    /// its mono samples.
+    fn next_chunk(&mut self) -> Option<(f64, Vec<f32>)> {
+        loop {
+            if let Some((at, event)) = self.events.front().copied() {
+                if self.frames_until(at) == 0 {
+                    self.events.pop_front();
+                    self.apply(event);
+                    continue;
+                }
+            }
+
+            let mut frames = self.chunk_frames as u64;
+            if let Some((at, _)) = self.events.front() {
+                frames = frames.min(self.frames_until(*at));
+            }
+            if let Some(end) = self.end {
+                frames = frames.min(self.frames_until(end));
+            }
+
+            if frames == 0 {
+                return None;
+            }
+
+            let start = self.position();
+            return Some((start, self.generate(frames as usize)));
+        }
+    }
+
+    fn apply(&mut self, event: Event) {
+        match event {
+            Event::Dropout { length } => {
+                let target = self.position() + length.as_secs_f64();
+                let mut frames = self.frames_until(target);
+                if let Some(end) = self.end {
+                    frames = frames.min(self.frames_until(end));
+                }
+
+                tracing::debug!(frames, "Injecting dropout");
+                self.generate(frames as usize);
+            }
+            Event::RateChange { sample_rate } => {
+                tracing::debug!(
+                    before = self.format.sample_rate,
+                    after = sample_rate,
+                    "Injecting sample rate change"
+                );
+                let previous = self.format;
+                self.segment_start = self.position();
+                self.segment_frames = 0;
+                self.format.sample_rate = sample_rate;
+                self.pending_error = Some(AudioError::FormatChanged {
+                    previous,
+                    current: self.format,
+                });
+            }
+        }
+    }
+}
+
+impl AudioStream for GeneratorStream {
+    fn sample_rate(&self) -> u32 {
+        self.format.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.format.channels
+    }
+}
+
+impl FuturesStream for GeneratorStream {
+    type Item = Result<AudioChunk, AudioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.as_mut().get_mut();
+
+        if this.pending.is_none() {
+            let Some((start, samples)) = this.next_chunk() else {
+                return Poll::Ready(None);
+            };
+
+            // Dropouts skip timeline positions, so they show up as gaps
+            // between chunk timestamps
+            let origin = *this.clock_origin.get_or_insert_with(capture_clock_now);
+            let timestamp = origin + Duration::from_secs_f64(start);
+
+            if this.pacing == Pacing::RealTime {
+                let started_at = *this.started_at.get_or_insert_with(Instant::now);
+                let deadline = started_at + Duration::from_secs_f64(this.position());
+                this.sleep = Some(Box::pin(tokio::time::sleep_until(deadline)));
+            }
+
+            this.pending = Some(AudioChunk::new(
+                remix_channels(&samples, 1, this.format.channels as usize),
+                this.format.sample_rate,
+                this.format.channels,
+                timestamp,
+                this.sequence,
+            ));
+            this.sequence += 1;
+        }
+
+        // Report a rate change ahead of the first chunk in the new format
+        if let Some(error) = this.pending_error.take() {
+            return Poll::Ready(Some(Err(error)));
+        }
+
+        if let Some(sleep) = this.sleep.as_mut() {
+            if sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.sleep = None;
+        }
+
+        Poll::Ready(this.pending.take().map(Ok))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn fast(signal: Signal, sample_rate: u32) -> GeneratorInput {
+        let config = AudioInputConfig::new().with_sample_rate(sample_rate);
+        GeneratorInput::with_config(&config)
+            .unwrap()
+            .with_signal(signal)
+            .with_pacing(Pacing::Fast)
+            .with_duration(Duration::from_secs(1))
+    }
+
+    async fn collect(input: GeneratorInput) -> Vec<f32> {
+        let chunks: Vec<AudioChunk> = input.stream().unwrap().map(Result::unwrap).collect().await;
+        chunks.into_iter().flat_map(|chunk| chunk.samples).collect()
+    }
+
+    #[tokio::test]
+    async fn test_sine_frequency() {
+        let samples = collect(fast(Signal::sine(440.0, 0.5), 16000)).await;
+        assert_eq!(samples.len(), 16000);
+
+        let crossings = samples
+            .windows(2)
+            .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+            .count();
+        assert!((878..=882).contains(&crossings), "{} crossings", crossings);
+        assert!(samples.iter().all(|s| s.abs() <= 0.5));
+    }
+
+    #[tokio::test]
+    async fn test_noise_is_seeded() {
+        let a = collect(fast(Signal::white_noise(1.0), 8000).with_seed(7)).await;
+        let b = collect(fast(Signal::white_noise(1.0), 8000).with_seed(7)).await;
+        let c = collect(fast(Signal::white_noise(1.0), 8000).with_seed(8)).await;
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[tokio::test]
+    async fn test_pink_noise_is_correlated() {
+        let lag1 = |samples: &[f32]| {
+            let energy: f32 = samples.iter().map(|s| s * s).sum();
+            samples.windows(2).map(|w| w[0] * w[1]).sum::<f32>() / energy
+        };
+
+        let white = collect(fast(Signal::white_noise(1.0), 8000)).await;
+        let pink = collect(fast(Signal::pink_noise(1.0), 8000)).await;
+
+        assert!(lag1(&white).abs() < 0.1);
+        assert!(lag1(&pink) > 0.5);
+    }
+
+    #[tokio::test]
+    async fn test_bursts_are_sample_accurate() {
+        let signal = Signal::sine(1000.0, 1.0).bursts(Duration::from_millis(100), Duration::from_millis(100));
+        let samples = collect(fast(signal, 8000)).await;
+
+        // The 1 kHz tone is never zero twice in a row while on
+        let on = &samples[1..800];
+        assert!(on.windows(2).all(|w| w[0] != 0.0 || w[1] != 0.0));
+        assert!(samples[800..1600].iter().all(|&s| s == 0.0));
+        assert!(samples[1601..2400].iter().any(|&s| s != 0.0));
+    }
+
+    #[tokio::test]
+    async fn test_dropout_removes_audio() {
+        let input = fast(Signal::sine(100.0, 1.0), 8000)
+            .with_dropout(Duration::from_millis(500), Duration::from_millis(250));
+        let chunks: Vec<AudioChunk> = input.stream().unwrap().map(Result::unwrap).collect().await;
+
+        assert_eq!(chunks.iter().map(AudioChunk::frames).sum::<usize>(), 6000);
+        // Chunks are split at the dropout boundary, which leaves a 250 ms gap
+        // in the timestamps while sequence numbers stay consecutive
+        let gaps: Vec<Duration> = chunks
+            .windows(2)
+            .map(|pair| {
+                assert_eq!(pair[0].sequence + 1, pair[1].sequence);
+                pair[1].timestamp.saturating_sub(pair[0].end_timestamp())
+            })
+            .filter(|gap| *gap > Duration::from_micros(1))
+            .collect();
+        assert_eq!(gaps.len(), 1);
+        assert!(gaps[0].abs_diff(Duration::from_millis(250)) < Duration::from_micros(1));
+    }
+
+    #[tokio::test]
+    async fn test_rate_change() {
+        let input = fast(Signal::Silence, 48000).with_rate_change(Duration::from_millis(500), 16000);
+        let mut stream = input.stream().unwrap();
+        let mut frames = [0usize, 0usize];
+        let mut changes = Vec::new();
+
+        while let Some(item) = stream.next().await {
+            let chunk = match item {
+                Ok(chunk) => chunk,
+                Err(AudioError::FormatChanged { previous, current }) => {
+                    changes.push((previous.sample_rate, current.sample_rate));
+                    continue;
+                }
+                Err(e) => panic!("unexpected error {}", e),
+            };
+            assert_eq!(chunk.sample_rate, stream.sample_rate());
+            match chunk.sample_rate {
+                48000 => frames[0] += chunk.frames(),
+                16000 => frames[1] += chunk.frames(),
+                rate => panic!("unexpected rate {}", rate),
+            }
+        }
+
+        assert_eq!(frames, [24000, 8000]);
+        assert_eq!(changes, [(48000, 16000)]);
+    }
+
+    #[tokio::test]
+    async fn test_stereo_output() {
+        let config = AudioInputConfig::new()
+            .with_sample_rate(8000)
+            .with_channels(crate::ChannelMode::Stereo)
+            .with_buffer_frames(80);
+        let input = GeneratorInput::with_config(&config)
+            .unwrap()
+            .with_signal(Signal::sine(50.0, 1.0))
+            .with_pacing(Pacing::Fast);
+
+        let chunk = input.stream().unwrap().next().await.unwrap().unwrap();
+        assert_eq!(chunk.samples.len(), 160);
+        assert_eq!(chunk.frames(), 80);
+        assert!(chunk.samples.chunks(2).all(|frame| frame[0] == frame[1]));
+    }
+}
@@ -0,0 +1,474 @@
+//! Capture health monitoring
+//!
+//! A capture can run for an hour and deliver nothing useful: a microphone
+//! muted in hardware, a speaker tap without permission delivering zeros, a
+//! device stuck at a constant value, or a stream that stopped delivering
+//! altogether. A [`HealthMonitor`] watches the audio passing through it and
+//! raises a [`HealthWarning`] while such a condition lasts, so it can be
+//! fixed during the recording rather than discovered afterwards.
+//!
+//! The audio is checked in windows of half a second, so the timeouts of a
+//! [`HealthConfig`] take effect in steps of that length. Stalls are checked
+//! against the wall clock through [`HealthMonitor::check_stall`], since a
+//! stalled stream delivers no chunks to check.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc;
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::{duration_to_frames, frames_to_duration, AudioChunk};
+use crate::error::AudioError;
+use crate::meter::db_to_linear;
+use crate::processor::AudioProcessor;
+
+/// Length of audio over which the conditions are checked
+const WINDOW: Duration = Duration::from_millis(500);
+
+/// Configuration of a [`HealthMonitor`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// Time without chunks after which the stream counts as stalled
+    pub stall_timeout: Duration,
+    /// Peak level below which audio counts as digital silence, in dBFS
+    pub silence_threshold_db: f32,
+    /// Time of digital silence before it is reported
+    pub silence_timeout: Duration,
+    /// Level of the mean of the samples from which it counts as a DC
+    /// offset, in dBFS
+    pub dc_threshold_db: f32,
+    /// Time of DC offset before it is reported
+    pub dc_timeout: Duration,
+    /// Level from which a sample counts as clipped, in dBFS
+    pub clip_threshold_db: f32,
+    /// Share of clipped samples from which a window counts as clipping
+    pub clip_ratio: f32,
+    /// Time of clipping before it is reported
+    pub clipping_timeout: Duration,
+    /// Share of the audio lost to gaps from which drops are reported
+    pub max_drop_rate: f32,
+    /// Time over which the drop rate is measured
+    pub drop_window: Duration,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            stall_timeout: Duration::from_secs(3),
+            silence_threshold_db: -90.0,
+            silence_timeout: Duration::from_secs(5),
+            dc_threshold_db: -30.0,
+            dc_timeout: Duration::from_secs(2),
+            clip_threshold_db: -0.1,
+            clip_ratio: 0.001,
+            clipping_timeout: Duration::from_secs(2),
+            max_drop_rate: 0.01,
+            drop_window: Duration::from_secs(10),
+        }
+    }
+}
+
+impl HealthConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_stall_timeout(mut self, stall_timeout: Duration) -> Self {
+        self.stall_timeout = stall_timeout;
+        self
+    }
+
+    pub fn with_silence_threshold_db(mut self, silence_threshold_db: f32) -> Self {
+        self.silence_threshold_db = silence_threshold_db;
+        self
+    }
+
+    pub fn with_silence_timeout(mut self, silence_timeout: Duration) -> Self {
+        self.silence_timeout = silence_timeout;
+        self
+    }
+
+    pub fn with_dc_threshold_db(mut self, dc_threshold_db: f32) -> Self {
+        self.dc_threshold_db = dc_threshold_db;
+        self
+    }
+
+    pub fn with_dc_timeout(mut self, dc_timeout: Duration) -> Self {
+        self.dc_timeout = dc_timeout;
+        self
+    }
+
+    pub fn with_clip_threshold_db(mut self, clip_threshold_db: f32) -> Self {
+        self.clip_threshold_db = clip_threshold_db;
+        self
+    }
+
+    pub fn with_clip_ratio(mut self, clip_ratio: f32) -> Self {
+        self.clip_ratio = clip_ratio;
+        self
+    }
+
+    pub fn with_clipping_timeout(mut self, clipping_timeout: Duration) -> Self {
+        self.clipping_timeout = clipping_timeout;
+        self
+    }
+
+    pub fn with_max_drop_rate(mut self, max_drop_rate: f32) -> Self {
+        self.max_drop_rate = max_drop_rate;
+        self
+    }
+
+    pub fn with_drop_window(mut self, drop_window: Duration) -> Self {
+        self.drop_window = drop_window;
+        self
+    }
+}
+
+/// Kind of problem a [`HealthWarning`] reports
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthIssue {
+    Stalled,
+    DigitalSilence,
+    DcOffset,
+    Clipping,
+    Drops,
+}
+
+/// Problem found with a stream
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HealthWarning {
+    /// No chunks arrived for `duration`
+    Stalled { duration: Duration },
+    /// The audio has been silent to the last bit for `duration`, as from a
+    /// device muted in hardware or a capture without permission
+    DigitalSilence { duration: Duration },
+    /// The mean of the samples sits at `offset_db` dBFS instead of zero
+    DcOffset { offset_db: f32 },
+    /// Audio has been clipping for `duration`, with `clip_ratio` of the
+    /// samples at full scale in the last window
+    Clipping { duration: Duration, clip_ratio: f32 },
+    /// `drop_rate` of the audio was lost to gaps recently
+    Drops { drop_rate: f32 },
+}
+
+impl HealthWarning {
+    pub fn issue(&self) -> HealthIssue {
+        match self {
+            Self::Stalled { .. } => HealthIssue::Stalled,
+            Self::DigitalSilence { .. } => HealthIssue::DigitalSilence,
+            Self::DcOffset { .. } => HealthIssue::DcOffset,
+            Self::Clipping { .. } => HealthIssue::Clipping,
+            Self::Drops { .. } => HealthIssue::Drops,
+        }
+    }
+}
+
+/// A warning raised when a problem starts, or cleared when it ends
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum HealthEvent {
+    Raised(HealthWarning),
+    Cleared { issue: HealthIssue },
+}
+
+/// Audio gathered for the window being filled
+#[derive(Default)]
+struct Window {
+    /// Captured and gap-filled frames
+    frames: u64,
+    dropped: u64,
+    samples: u64,
+    sum: f64,
+    peak: f32,
+    clipped: u64,
+}
+
+/// Processor watching a stream for signs of a broken capture
+///
+/// Audio passes through unchanged. Each problem is raised once when it
+/// starts and cleared when it ends; [`AudioChunk::gap_fill`] silence counts
+/// towards the drop rate only.
+pub struct HealthMonitor {
+    config: HealthConfig,
+    silence_level: f32,
+    dc_level: f32,
+    clip_level: f32,
+    sample_rate: u32,
+    window_len: u64,
+    window: Window,
+    /// Frames and dropped frames of the windows within the drop window
+    recent: VecDeque<(u64, u64)>,
+    /// How long the audio has been silent, offset and clipping
+    silent_for: Duration,
+    offset_for: Duration,
+    clipping_for: Duration,
+    /// When the last chunk arrived, or stall checks started
+    last_activity: Option<Instant>,
+    active: Vec<HealthIssue>,
+    events: Option<mpsc::UnboundedSender<HealthEvent>>,
+}
+
+impl HealthMonitor {
+    pub fn new(config: HealthConfig) -> Self {
+        Self {
+            silence_level: db_to_linear(config.silence_threshold_db),
+            dc_level: db_to_linear(config.dc_threshold_db),
+            clip_level: db_to_linear(config.clip_threshold_db.min(0.0)),
+            config,
+            sample_rate: 0,
+            window_len: 1,
+            window: Window::default(),
+            recent: VecDeque::new(),
+            silent_for: Duration::ZERO,
+            offset_for: Duration::ZERO,
+            clipping_for: Duration::ZERO,
+            last_activity: None,
+            active: Vec::new(),
+            events: None,
+        }
+    }
+
+    pub fn config(&self) -> &HealthConfig {
+        &self.config
+    }
+
+    /// Receive [`HealthEvent`]s from now on
+    ///
+    /// Only the most recent receiver gets events.
+    pub fn subscribe(&mut self) -> mpsc::UnboundedReceiver<HealthEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.events = Some(tx);
+        rx
+    }
+
+    /// Whether a warning about `issue` is currently raised
+    pub fn is_raised(&self, issue: HealthIssue) -> bool {
+        self.active.contains(&issue)
+    }
+
+    /// Raise [`HealthWarning::Stalled`] if no chunk arrived for the stall
+    /// timeout as of `now`
+    ///
+    /// The first call starts the clock for a stream that has not delivered
+    /// anything yet.
+    pub fn check_stall(&mut self, now: Instant) {
+        let last = *self.last_activity.get_or_insert(now);
+        let duration = now.saturating_duration_since(last);
+        if duration >= self.config.stall_timeout {
+            self.raise(HealthWarning::Stalled { duration });
+        }
+    }
+
+    fn raise(&mut self, warning: HealthWarning) {
+        let issue = warning.issue();
+        if self.is_raised(issue) {
+            return;
+        }
+
+        tracing::warn!(?warning, "Capture health warning");
+        self.active.push(issue);
+        self.notify(HealthEvent::Raised(warning));
+    }
+
+    fn clear(&mut self, issue: HealthIssue) {
+        if !self.is_raised(issue) {
+            return;
+        }
+
+        tracing::info!(?issue, "Capture health warning cleared");
+        self.active.retain(|&active| active != issue);
+        self.notify(HealthEvent::Cleared { issue });
+    }
+
+    fn notify(&mut self, event: HealthEvent) {
+        if let Some(tx) = &self.events {
+            if tx.unbounded_send(event).is_err() {
+                self.events = None;
+            }
+        }
+    }
+
+    fn measure(&mut self, chunk: &AudioChunk) {
+        self.last_activity = Some(Instant::now());
+        self.clear(HealthIssue::Stalled);
+
+        if chunk.sample_rate != self.sample_rate {
+            self.sample_rate = chunk.sample_rate;
+            self.window_len = duration_to_frames(WINDOW, chunk.sample_rate).max(1);
+            self.window = Window::default();
+            self.recent.clear();
+        }
+
+        let frames = chunk.frames() as u64;
+        self.window.frames += frames;
+        if chunk.gap_fill {
+            self.window.dropped += frames;
+        } else {
+            for &sample in &chunk.samples {
+                self.window.sum += sample as f64;
+                self.window.peak = self.window.peak.max(sample.abs());
+                if sample.abs() >= self.clip_level {
+                    self.window.clipped += 1;
+                }
+            }
+            self.window.samples += chunk.samples.len() as u64;
+        }
+
+        if self.window.frames >= self.window_len {
+            let window = std::mem::take(&mut self.window);
+            self.evaluate(window);
+        }
+    }
+
+    /// Update the problems with a completed window
+    fn evaluate(&mut self, window: Window) {
+        let length = frames_to_duration(window.frames, self.sample_rate);
+
+        self.recent.push_back((window.frames, window.dropped));
+        let mut total: u64 = self.recent.iter().map(|&(frames, _)| frames).sum();
+        let limit = duration_to_frames(self.config.drop_window, self.sample_rate).max(self.window_len);
+        while total > limit && self.recent.len() > 1 {
+            if let Some((frames, _)) = self.recent.pop_front() {
+                total -= frames;
+            }
+        }
+        let dropped: u64 = self.recent.iter().map(|&(_, dropped)| dropped).sum();
+        let drop_rate = dropped as f32 / total.max(1) as f32;
+        if drop_rate > self.config.max_drop_rate {
+            self.raise(HealthWarning::Drops { drop_rate });
+        } else {
+            self.clear(HealthIssue::Drops);
+        }
+
+        // Windows of only gap-filled silence say nothing about the signal
+        if window.samples == 0 {
+            return;
+        }
+
+        if window.peak < self.silence_level {
+            self.silent_for += length;
+            if self.silent_for >= self.config.silence_timeout {
+                self.raise(HealthWarning::DigitalSilence {
+                    duration: self.silent_for,
+                });
+            }
+        } else {
+            self.silent_for = Duration::ZERO;
+            self.clear(HealthIssue::DigitalSilence);
+        }
+
+        let offset = (window.sum / window.samples as f64).abs() as f32;
+        if offset >= self.dc_level {
+            self.offset_for += length;
+            if self.offset_for >= self.config.dc_timeout {
+                self.raise(HealthWarning::DcOffset {
+                    offset_db: 20.0 * offset.log10(),
+                });
+            }
+        } else {
+            self.offset_for = Duration::ZERO;
+            self.clear(HealthIssue::DcOffset);
+        }
+
+        let clip_ratio = window.clipped as f32 / window.samples as f32;
+        if clip_ratio >= self.config.clip_ratio {
+            self.clipping_for += length;
+            if self.clipping_for >= self.config.clipping_timeout {
+                self.raise(HealthWarning::Clipping {
+                    duration: self.clipping_for,
+                    clip_ratio,
+                });
+            }
+        } else {
+            self.clipping_for = Duration::ZERO;
+            self.clear(HealthIssue::Clipping);
+        }
+    }
+}
+
+impl AudioProcessor for HealthMonitor {
+    fn process(&mut self, chunk: AudioChunk) -> Result<Vec<AudioChunk>, AudioError> {
+        self.measure(&chunk);
+        Ok(vec![chunk])
+    }
+
+    fn reset(&mut self) {
+        let events = self.events.take();
+        *self = Self::new(self.config.clone());
+        self.events = events;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `seconds` of 10 ms chunks of `value`, every `gap_every`th one
+    /// gap-filled
+    fn feed(monitor: &mut HealthMonitor, seconds: usize, value: f32, gap_every: Option<usize>) {
+        for i in 0..seconds * 100 {
+            let mut chunk = AudioChunk::new(vec![value; 160], 16000, 1, Duration::ZERO, i as u64);
+            if gap_every.is_some_and(|n| i % n == 0) {
+                chunk = AudioChunk::silence(160, 16000, 1, Duration::ZERO, i as u64);
+            }
+            monitor.process(chunk).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_silence_and_dc_raise_and_clear() {
+        let mut monitor = HealthMonitor::new(HealthConfig::default());
+        let events = monitor.subscribe();
+
+        feed(&mut monitor, 4, 0.0, None);
+        assert!(!monitor.is_raised(HealthIssue::DigitalSilence));
+        feed(&mut monitor, 2, 0.0, None);
+        assert!(monitor.is_raised(HealthIssue::DigitalSilence));
+
+        // A constant value is no longer silent, but all offset
+        feed(&mut monitor, 3, 0.2, None);
+        assert!(!monitor.is_raised(HealthIssue::DigitalSilence));
+        assert!(monitor.is_raised(HealthIssue::DcOffset));
+
+        // Dropping the monitor ends the events after those already sent
+        drop(monitor);
+        let events: Vec<HealthEvent> = futures::executor::block_on_stream(events).collect();
+        assert!(matches!(
+            events[0],
+            HealthEvent::Raised(HealthWarning::DigitalSilence { duration })
+                if duration == Duration::from_secs(5)
+        ));
+        assert_eq!(events[1], HealthEvent::Cleared { issue: HealthIssue::DigitalSilence });
+        assert!(matches!(
+            events[2],
+            HealthEvent::Raised(HealthWarning::DcOffset { offset_db }) if offset_db > -14.0
+        ));
+        assert_eq!(events.len(), 3);
+    }
+
+    #[test]
+    fn test_clipping_drops_and_stall() {
+        let mut monitor = HealthMonitor::new(HealthConfig::default());
+
+        feed(&mut monitor, 3, 1.0, None);
+        assert!(monitor.is_raised(HealthIssue::Clipping));
+
+        // One chunk in twenty lost is a 5% drop rate
+        feed(&mut monitor, 2, 0.01, Some(20));
+        assert!(!monitor.is_raised(HealthIssue::Clipping));
+        assert!(monitor.is_raised(HealthIssue::Drops));
+
+        let now = Instant::now();
+        monitor.check_stall(now + Duration::from_secs(1));
+        assert!(!monitor.is_raised(HealthIssue::Stalled));
+        monitor.check_stall(now + Duration::from_secs(5));
+        assert!(monitor.is_raised(HealthIssue::Stalled));
+
+        feed(&mut monitor, 1, 0.01, None);
+        assert!(!monitor.is_raised(HealthIssue::Stalled));
+    }
+}
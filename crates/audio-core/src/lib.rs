@@ -1,7 +1,64 @@
+mod agc;
+mod bleed;
+mod chunk;
+mod config;
+pub mod conversion;
+#[cfg(feature = "cpal")]
+mod cpal_device;
 mod error;
+mod denoise;
 mod device;
+mod drift;
+mod echo;
+mod failover;
+mod fft;
+mod file;
+mod gap;
+mod generator;
+mod health;
+mod meter;
+mod mixer;
+#[cfg(feature = "onnx")]
+mod onnx_vad;
+mod preroll;
+mod processor;
+mod resample;
+mod sink;
+#[cfg(test)]
+mod testing;
 mod traits;
+mod vad;
+mod watcher;
 
+pub use agc::{Agc, AgcConfig, AgcStream};
+pub use bleed::{BleedConfig, BleedDetector, BleedEstimate, BleedStatus, BleedStream};
+pub use chunk::{capture_clock_now, duration_to_frames, frames_to_duration, AudioChunk, FrameClock};
+#[cfg(feature = "cpal")]
+pub use cpal_device::{cpal_capabilities, cpal_sample_format, Capabilities, StreamActivity, StreamErrorFilter};
+pub use config::{negotiate, AudioInputConfig, ChannelMode, ConfigCandidate, Negotiated, StreamFormat};
 pub use error::AudioError;
-pub use device::{AudioDevice, DeviceType};
+pub use denoise::{NoiseSuppressStream, NoiseSuppression, NoiseSuppressor, NoiseSuppressorConfig, Suppressed};
+pub use device::{AudioDevice, DeviceKind, DeviceType, SampleFormat, SampleRateRange};
+pub use echo::{EchoCancelStream, EchoCanceller, EchoCancellerConfig};
+pub use drift::{
+    DriftCorrectedStream, DriftCorrection, DriftCorrector, DriftEstimator, DriftMeasurement, Placement,
+};
+pub use failover::{open_input, FailoverConfig, FailoverEvent, FailoverStream};
+pub use file::{FileInput, FileStream, Pacing};
+pub use gap::{Gap, GapLog, GapTracker};
+pub use generator::{GeneratorInput, GeneratorStream, Signal};
+pub use health::{HealthConfig, HealthEvent, HealthIssue, HealthMonitor, HealthWarning};
+pub use meter::{Meter, MeterConfig, MeterReading, SILENCE_DB};
+pub use mixer::{MixMode, MixSource, MixStream, MixerConfig};
+#[cfg(feature = "onnx")]
+pub use onnx_vad::{OnnxVad, OnnxVadConfig};
+pub use preroll::{PreRollBuffer, PreRollConfig, PreRollHistory, PreRollRecorder};
+pub use processor::{AudioProcessor, Gain, Pipeline, PipelineConfig, ProcessedStream, ProcessorConfig};
+pub use resample::{Resample, ResampleQuality, ResampleStream, Resampler};
+pub use sink::{Tap, TapHandle, TapStream, WavSink};
 pub use traits::{AudioInput, AudioStream};
+pub use vad::{
+    detect_speech, EnergyVad, EnergyVadConfig, SpeechSegmenter, VadConfig, VadEvent, VadFrame, VadStream,
+    VoiceActivity, VoiceActivityDetector,
+};
+pub use watcher::{diff_devices, DeviceEvent, DeviceTracker, DeviceWatcher};
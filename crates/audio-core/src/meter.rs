@@ -0,0 +1,425 @@
+//! Level metering
+//!
+//! A [`Meter`] measures the audio passing through it without changing it:
+//! RMS and peak levels and clipped samples per chunk, the true peak
+//! between samples, and the momentary and short-term loudness of EBU R128.
+//! The levels of the chunks are gathered until they are read with
+//! [`Meter::take_reading`], so a display reading at a slower rate than
+//! chunks arrive still sees every peak.
+
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::{duration_to_frames, AudioChunk};
+use crate::error::AudioError;
+use crate::processor::AudioProcessor;
+
+/// Level reported for silence, in dBFS or LUFS
+pub const SILENCE_DB: f32 = -100.0;
+
+/// Amplitude factor of a level in dB
+pub(crate) fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Exponential smoothing coefficient for updates every `step` with time
+/// constant `tau`
+pub(crate) fn smoothing(step: Duration, tau: Duration) -> f32 {
+    (step.as_secs_f64() / tau.as_secs_f64().max(f64::EPSILON)).min(1.0) as f32
+}
+
+/// Length of the blocks loudness is measured in
+const LOUDNESS_BLOCK: Duration = Duration::from_millis(100);
+
+/// Blocks in the momentary (400 ms) and short-term (3 s) loudness windows
+const MOMENTARY_BLOCKS: usize = 4;
+const SHORT_TERM_BLOCKS: usize = 30;
+
+/// Oversampling of the true-peak measurement
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+
+/// Samples the true-peak interpolation looks at
+const TRUE_PEAK_TAPS: usize = 12;
+
+/// Configuration of a [`Meter`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct MeterConfig {
+    /// Level from which a sample counts as clipped, in dBFS
+    pub clip_threshold_db: f32,
+}
+
+impl Default for MeterConfig {
+    fn default() -> Self {
+        Self {
+            clip_threshold_db: -0.1,
+        }
+    }
+}
+
+impl MeterConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_clip_threshold_db(mut self, clip_threshold_db: f32) -> Self {
+        self.clip_threshold_db = clip_threshold_db;
+        self
+    }
+}
+
+/// Levels measured by a [`Meter`]
+///
+/// Levels are in dBFS and loudness in LUFS, never below [`SILENCE_DB`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct MeterReading {
+    /// RMS level across all channels
+    pub rms_db: f32,
+    /// Highest sample
+    pub peak_db: f32,
+    /// Highest level between samples, estimated by oversampling
+    pub true_peak_db: f32,
+    /// Clipped samples since the meter started
+    pub clip_count: u64,
+    /// Loudness of the last 400 ms
+    pub momentary_lufs: f32,
+    /// Loudness of the last 3 s
+    pub short_term_lufs: f32,
+}
+
+impl Default for MeterReading {
+    fn default() -> Self {
+        Self {
+            rms_db: SILENCE_DB,
+            peak_db: SILENCE_DB,
+            true_peak_db: SILENCE_DB,
+            clip_count: 0,
+            momentary_lufs: SILENCE_DB,
+            short_term_lufs: SILENCE_DB,
+        }
+    }
+}
+
+fn amplitude_db(amplitude: f32) -> f32 {
+    if amplitude > 0.0 {
+        (20.0 * amplitude.log10()).max(SILENCE_DB)
+    } else {
+        SILENCE_DB
+    }
+}
+
+// ============================================================================
+// Filters
+// ============================================================================
+
+/// Second-order IIR filter, transposed direct form II
+#[derive(Debug, Clone, Copy, Default)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// K-weighting of ITU-R BS.1770: a high shelf for the acoustic effect of
+/// the head followed by a high pass, designed for any sample rate
+#[derive(Debug, Clone, Copy)]
+struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    fn new(sample_rate: u32) -> Self {
+        let fs = sample_rate as f64;
+
+        let k = (PI * 1681.974450955533 / fs).tan();
+        let q = 0.7071752369554196;
+        let vh = 10f64.powf(3.999843853973347 / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad {
+            b0: (vh + vb * k / q + k * k) / a0,
+            b1: 2.0 * (k * k - vh) / a0,
+            b2: (vh - vb * k / q + k * k) / a0,
+            a1: 2.0 * (k * k - 1.0) / a0,
+            a2: (1.0 - k / q + k * k) / a0,
+            ..Default::default()
+        };
+
+        let k = (PI * 38.13547087602444 / fs).tan();
+        let q = 0.5003270373238773;
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad {
+            b0: 1.0,
+            b1: -2.0,
+            b2: 1.0,
+            a1: 2.0 * (k * k - 1.0) / a0,
+            a2: (1.0 - k / q + k * k) / a0,
+            ..Default::default()
+        };
+
+        Self { shelf, high_pass }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        self.high_pass.process(self.shelf.process(x))
+    }
+}
+
+/// Interpolation of the points between samples for the true peak
+///
+/// Hann-windowed sinc filters, one for each position between two samples.
+struct TruePeak {
+    phases: [[f32; TRUE_PEAK_TAPS]; TRUE_PEAK_OVERSAMPLING - 1],
+}
+
+impl TruePeak {
+    fn new() -> Self {
+        let half = TRUE_PEAK_TAPS as f64 / 2.0;
+        let mut phases = [[0.0; TRUE_PEAK_TAPS]; TRUE_PEAK_OVERSAMPLING - 1];
+        for (p, taps) in phases.iter_mut().enumerate() {
+            let fraction = (p + 1) as f64 / TRUE_PEAK_OVERSAMPLING as f64;
+            for (j, tap) in taps.iter_mut().enumerate() {
+                // Distance of sample j from the point between the two middle samples
+                let x = fraction + half - 1.0 - j as f64;
+                let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+                let window = 0.5 * (1.0 + (PI * x / half).cos());
+                *tap = (sinc * window) as f32;
+            }
+        }
+        Self { phases }
+    }
+
+    /// Highest magnitude between the two middle samples of `history`
+    fn between(&self, history: &[f32; TRUE_PEAK_TAPS]) -> f32 {
+        self.phases.iter().fold(0.0f32, |peak, taps| {
+            let value: f32 = taps.iter().zip(history).map(|(t, s)| t * s).sum();
+            peak.max(value.abs())
+        })
+    }
+}
+
+// ============================================================================
+// Meter
+// ============================================================================
+
+/// Per-channel state of the meter
+struct ChannelState {
+    weighting: KWeighting,
+    history: [f32; TRUE_PEAK_TAPS],
+}
+
+/// Levels gathered since the last reading
+#[derive(Default)]
+struct Period {
+    energy: f64,
+    samples: u64,
+    peak: f32,
+    true_peak: f32,
+}
+
+/// Processor measuring the level and loudness of the audio passing through
+///
+/// All channels are weighted equally for loudness, as left, right and
+/// centre are in BS.1770. A change of sample rate or channel count
+/// restarts the loudness measurement.
+pub struct Meter {
+    config: MeterConfig,
+    clip_threshold: f32,
+    true_peak: TruePeak,
+    sample_rate: u32,
+    channels: Vec<ChannelState>,
+    block_len: usize,
+    /// Weighted energy and length of the loudness block being filled
+    block_energy: f64,
+    block_filled: usize,
+    /// Mean weighted energy of the latest blocks, newest last
+    blocks: VecDeque<f64>,
+    period: Period,
+    clip_count: u64,
+}
+
+impl Meter {
+    pub fn new(config: MeterConfig) -> Self {
+        let clip_threshold = db_to_linear(config.clip_threshold_db.min(0.0));
+        Self {
+            config,
+            clip_threshold,
+            true_peak: TruePeak::new(),
+            sample_rate: 0,
+            channels: Vec::new(),
+            block_len: 1,
+            block_energy: 0.0,
+            block_filled: 0,
+            blocks: VecDeque::with_capacity(SHORT_TERM_BLOCKS),
+            period: Period::default(),
+            clip_count: 0,
+        }
+    }
+
+    pub fn config(&self) -> &MeterConfig {
+        &self.config
+    }
+
+    /// Levels since the last call, and the loudness as of now
+    ///
+    /// Levels read as silence when no audio arrived in between.
+    pub fn take_reading(&mut self) -> MeterReading {
+        let period = std::mem::take(&mut self.period);
+        let rms = if period.samples > 0 {
+            (period.energy / period.samples as f64).sqrt() as f32
+        } else {
+            0.0
+        };
+
+        MeterReading {
+            rms_db: amplitude_db(rms),
+            peak_db: amplitude_db(period.peak),
+            true_peak_db: amplitude_db(period.true_peak.max(period.peak)),
+            clip_count: self.clip_count,
+            momentary_lufs: self.loudness(MOMENTARY_BLOCKS),
+            short_term_lufs: self.loudness(SHORT_TERM_BLOCKS),
+        }
+    }
+
+    /// Loudness of the last `blocks` loudness blocks, counting blocks not
+    /// measured yet as silence
+    fn loudness(&self, blocks: usize) -> f32 {
+        let energy: f64 = self.blocks.iter().rev().take(blocks).sum();
+        let mean = energy / blocks as f64;
+        if mean > 0.0 {
+            (-0.691 + 10.0 * mean.log10() as f32).max(SILENCE_DB)
+        } else {
+            SILENCE_DB
+        }
+    }
+
+    fn configure(&mut self, sample_rate: u32, channels: usize) {
+        let weighting = KWeighting::new(sample_rate);
+        self.sample_rate = sample_rate;
+        self.channels = (0..channels)
+            .map(|_| ChannelState {
+                weighting,
+                history: [0.0; TRUE_PEAK_TAPS],
+            })
+            .collect();
+        self.block_len = duration_to_frames(LOUDNESS_BLOCK, sample_rate).max(1) as usize;
+        self.block_energy = 0.0;
+        self.block_filled = 0;
+        self.blocks.clear();
+    }
+
+    fn measure(&mut self, chunk: &AudioChunk) {
+        let channels = chunk.channels.max(1) as usize;
+        if chunk.sample_rate != self.sample_rate || channels != self.channels.len() {
+            self.configure(chunk.sample_rate, channels);
+        }
+
+        for frame in chunk.samples.chunks_exact(channels) {
+            let mut weighted = 0.0;
+            for (state, &sample) in self.channels.iter_mut().zip(frame) {
+                let magnitude = sample.abs();
+                self.period.peak = self.period.peak.max(magnitude);
+                self.period.energy += (sample * sample) as f64;
+                if magnitude >= self.clip_threshold {
+                    self.clip_count += 1;
+                }
+
+                state.history.copy_within(1.., 0);
+                state.history[TRUE_PEAK_TAPS - 1] = sample;
+                self.period.true_peak = self.period.true_peak.max(self.true_peak.between(&state.history));
+
+                let filtered = state.weighting.process(sample as f64);
+                weighted += filtered * filtered;
+            }
+            self.period.samples += channels as u64;
+
+            self.block_energy += weighted;
+            self.block_filled += 1;
+            if self.block_filled == self.block_len {
+                if self.blocks.len() == SHORT_TERM_BLOCKS {
+                    self.blocks.pop_front();
+                }
+                self.blocks.push_back(self.block_energy / self.block_len as f64);
+                self.block_energy = 0.0;
+                self.block_filled = 0;
+            }
+        }
+    }
+}
+
+impl AudioProcessor for Meter {
+    fn process(&mut self, chunk: AudioChunk) -> Result<Vec<AudioChunk>, AudioError> {
+        self.measure(&chunk);
+        Ok(vec![chunk])
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new(self.config.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measure(samples: impl Fn(usize) -> f32, seconds: usize) -> Meter {
+        let mut meter = Meter::new(MeterConfig::default());
+        for i in 0..seconds * 100 {
+            let samples = (i * 480..(i + 1) * 480).map(&samples).collect();
+            meter
+                .process(AudioChunk::new(samples, 48000, 1, Duration::ZERO, i as u64))
+                .unwrap();
+        }
+        meter
+    }
+
+    #[test]
+    fn test_sine_levels_and_loudness() {
+        // A 1 kHz sine at half scale: -9 dBFS RMS, and BS.1770 reads a
+        // 1 kHz sine at the loudness of its RMS level
+        let mut meter = measure(|n| 0.5 * (2.0 * PI * 1000.0 * n as f64 / 48000.0).sin() as f32, 3);
+        let reading = meter.take_reading();
+
+        assert!((reading.rms_db + 9.03).abs() < 0.05, "{:?}", reading);
+        assert!((reading.peak_db + 6.02).abs() < 0.05, "{:?}", reading);
+        assert!((reading.momentary_lufs + 9.03).abs() < 0.1, "{:?}", reading);
+        assert!((reading.short_term_lufs + 9.03).abs() < 0.1, "{:?}", reading);
+        assert_eq!(reading.clip_count, 0);
+
+        // Nothing arrived since, but the loudness window still holds audio
+        let reading = meter.take_reading();
+        assert_eq!(reading.peak_db, SILENCE_DB);
+        assert!(reading.short_term_lufs > -10.0);
+    }
+
+    #[test]
+    fn test_true_peak_and_clipping() {
+        // At a quarter of the sample rate, with the samples falling 45°
+        // off the crests, every sample is 3 dB below the peak of the wave
+        let mut meter = measure(|n| (PI / 2.0 * n as f64 + PI / 4.0).sin() as f32, 1);
+        let reading = meter.take_reading();
+        assert!((reading.peak_db + 3.01).abs() < 0.05, "{:?}", reading);
+        assert!(reading.true_peak_db > -0.5, "{:?}", reading);
+        assert_eq!(reading.clip_count, 0);
+
+        let mut meter = measure(|n| if n % 100 == 0 { 1.0 } else { 0.0 }, 1);
+        assert_eq!(meter.take_reading().clip_count, 480);
+    }
+}
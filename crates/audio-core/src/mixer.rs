@@ -0,0 +1,459 @@
+//! Mixing microphone and speaker capture into one meeting stream
+//!
+//! The two captures run on different devices, so they differ in sample
+//! rate, buffer timing and clock speed. [`MixStream`] resamples both to a
+//! common rate and places every chunk on a shared timeline derived from
+//! the capture timestamps. A [`DriftCorrector`] per source keeps it aligned
+//! with that timeline over meetings of any length.
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::Stream as FuturesStream;
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::{duration_to_frames, frames_to_duration, AudioChunk};
+use crate::conversion::convert_to_mono;
+use crate::drift::DriftCorrector;
+use crate::error::AudioError;
+use crate::traits::AudioStream;
+
+/// Default output rate of the mix
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+/// How far in seconds one source may run ahead before the other is
+/// assumed silent for the time being
+const MAX_WAIT: f64 = 0.25;
+
+/// How the two sources are combined
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MixMode {
+    /// Sum both sources into one mono channel
+    #[default]
+    Sum,
+    /// Keep the sources as two channels: microphone first, speaker second
+    Separate,
+}
+
+/// Configuration of a [`MixStream`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct MixerConfig {
+    /// Output sample rate in Hz
+    pub sample_rate: u32,
+    pub mode: MixMode,
+    /// Linear gain applied to the microphone
+    pub mic_gain: f32,
+    /// Linear gain applied to the speaker capture
+    pub speaker_gain: f32,
+}
+
+impl Default for MixerConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            mode: MixMode::default(),
+            mic_gain: 1.0,
+            speaker_gain: 1.0,
+        }
+    }
+}
+
+impl MixerConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: MixMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_mic_gain(mut self, gain: f32) -> Self {
+        self.mic_gain = gain;
+        self
+    }
+
+    pub fn with_speaker_gain(mut self, gain: f32) -> Self {
+        self.speaker_gain = gain;
+        self
+    }
+}
+
+/// Input of a [`MixStream`]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MixSource {
+    Mic,
+    Speaker,
+}
+
+/// One source's audio on the output timeline
+struct Track {
+    corrector: DriftCorrector,
+    gain: f32,
+    /// Mono samples for output frames `emitted..end`
+    queue: VecDeque<f32>,
+    /// Output frame just after the last queued sample
+    end: u64,
+    ended: bool,
+}
+
+impl Track {
+    fn new(input_rate: u32, output_rate: u32, gain: f32) -> Self {
+        Self {
+            corrector: DriftCorrector::new(input_rate, output_rate, 1),
+            gain,
+            queue: VecDeque::new(),
+            end: 0,
+            ended: false,
+        }
+    }
+
+    /// Resample `chunk` and queue it where its timestamp puts it
+    ///
+    /// `origin` is the capture time of output frame 0.
+    fn push(&mut self, chunk: &AudioChunk, origin: Duration) {
+        let mono = AudioChunk::new(
+            convert_to_mono(&chunk.samples, chunk.channels as usize),
+            chunk.sample_rate,
+            1,
+            chunk.timestamp,
+            chunk.sequence,
+        );
+
+        let placement = self.corrector.process(&mono, origin);
+        self.place(placement.frame, placement.samples);
+    }
+
+    /// Output frame of the first queued sample
+    fn start(&self) -> u64 {
+        self.end - self.queue.len() as u64
+    }
+
+    /// Queue `samples` starting exactly at output frame `target`
+    fn place(&mut self, target: i64, samples: Vec<f32>) {
+        let start = self.start();
+
+        // Audio for frames already delivered is lost
+        let late = (start as i64 - target).max(0) as usize;
+        let samples = samples.get(late..).unwrap_or_default();
+        let target = target.max(start as i64) as u64;
+
+        if target >= self.end {
+            self.queue.extend(std::iter::repeat_n(0.0, (target - self.end) as usize));
+        } else {
+            // Audio already queued overlaps the chunk; keep the newer audio
+            self.queue.truncate((target - start) as usize);
+        }
+        self.queue.extend(samples);
+        self.end = start + self.queue.len() as u64;
+    }
+
+    /// Take the samples for `frames` output frames, padding with silence
+    fn take(&mut self, frames: usize) -> Vec<f32> {
+        let start = self.start();
+        let available = frames.min(self.queue.len());
+        let mut samples: Vec<f32> = self.queue.drain(..available).map(|s| s * self.gain).collect();
+        samples.resize(frames, 0.0);
+
+        self.end = self.end.max(start + frames as u64);
+        samples
+    }
+
+    /// Queue the audio still held by the resampler
+    fn finish(&mut self) {
+        let tail = self.corrector.flush();
+        self.place(tail.frame, tail.samples);
+    }
+}
+
+/// Single time-aligned stream from a microphone and a speaker capture
+///
+/// Output frame `n` holds what both sources captured at
+/// `origin + n / sample_rate`, where the origin is the timestamp of the
+/// first chunk received. Sources are downmixed to mono before mixing.
+///
+/// Rate changes of either source are absorbed. A fatal error from either
+/// source ends the mix once the audio before it has been delivered; a
+/// source that simply ends is treated as silent from then on.
+pub struct MixStream<M, S> {
+    mic: Pin<Box<M>>,
+    speaker: Pin<Box<S>>,
+    config: MixerConfig,
+    tracks: [Track; 2],
+    /// Capture time of output frame 0
+    origin: Option<Duration>,
+    /// Output frames delivered so far
+    emitted: u64,
+    sequence: u64,
+    error: Option<AudioError>,
+    ended: bool,
+}
+
+impl<M: AudioStream, S: AudioStream> MixStream<M, S> {
+    pub fn new(mic: M, speaker: S, config: MixerConfig) -> Self {
+        let rate = config.sample_rate.max(1);
+        let tracks = [
+            Track::new(mic.sample_rate(), rate, config.mic_gain),
+            Track::new(speaker.sample_rate(), rate, config.speaker_gain),
+        ];
+
+        Self {
+            mic: Box::pin(mic),
+            speaker: Box::pin(speaker),
+            config: MixerConfig {
+                sample_rate: rate,
+                ..config
+            },
+            tracks,
+            origin: None,
+            emitted: 0,
+            sequence: 0,
+            error: None,
+            ended: false,
+        }
+    }
+
+    fn track(&self, source: MixSource) -> &Track {
+        match source {
+            MixSource::Mic => &self.tracks[0],
+            MixSource::Speaker => &self.tracks[1],
+        }
+    }
+
+    /// Measured clock drift of `source` against the capture clock, in ppm
+    pub fn drift_ppm(&self, source: MixSource) -> f64 {
+        self.track(source).corrector.ppm()
+    }
+
+    /// Rate correction currently applied to `source`, in ppm
+    pub fn correction_ppm(&self, source: MixSource) -> f64 {
+        self.track(source).corrector.correction_ppm()
+    }
+
+    /// Set the gain of `source`, effective for audio not yet delivered
+    pub fn set_gain(&mut self, source: MixSource, gain: f32) {
+        match source {
+            MixSource::Mic => self.tracks[0].gain = gain,
+            MixSource::Speaker => self.tracks[1].gain = gain,
+        }
+    }
+
+    fn receive(&mut self, index: usize, item: Option<Result<AudioChunk, AudioError>>) {
+        match item {
+            Some(Ok(chunk)) => {
+                if chunk.is_empty() {
+                    return;
+                }
+                let origin = *self.origin.get_or_insert(chunk.timestamp);
+                self.tracks[index].push(&chunk, origin);
+            }
+            Some(Err(e)) if e.is_recoverable() => {
+                // Rate changes are picked up from the chunks themselves
+                tracing::debug!("Mix source reported: {}", e);
+            }
+            Some(Err(e)) => {
+                self.error.get_or_insert(e);
+                self.tracks[index].finish();
+                self.tracks[index].ended = true;
+            }
+            None => {
+                self.tracks[index].finish();
+                self.tracks[index].ended = true;
+            }
+        }
+    }
+
+    /// Output frame up to which the mix can be delivered
+    fn ready(&self) -> u64 {
+        let max_wait = duration_to_frames(
+            Duration::from_secs_f64(MAX_WAIT),
+            self.config.sample_rate,
+        );
+        let finished = self.error.is_some() || self.tracks.iter().all(|track| track.ended);
+
+        let lead = self.tracks.iter().map(|track| track.end).max().unwrap_or(0);
+        if finished {
+            return lead;
+        }
+
+        let lag = self
+            .tracks
+            .iter()
+            .filter(|track| !track.ended)
+            .map(|track| track.end)
+            .min()
+            .unwrap_or(lead);
+
+        // Don't hold the mix back for a source that has gone quiet
+        lag.max(lead.saturating_sub(max_wait))
+    }
+
+    fn mix(&mut self, frames: usize) -> AudioChunk {
+        let mic = self.tracks[0].take(frames);
+        let speaker = self.tracks[1].take(frames);
+
+        let samples = match self.config.mode {
+            MixMode::Sum => mic.iter().zip(&speaker).map(|(m, s)| m + s).collect(),
+            MixMode::Separate => mic.iter().zip(&speaker).flat_map(|(&m, &s)| [m, s]).collect(),
+        };
+
+        let origin = self.origin.unwrap_or_default();
+        let chunk = AudioChunk::new(
+            samples,
+            self.config.sample_rate,
+            self.channels(),
+            origin + frames_to_duration(self.emitted, self.config.sample_rate),
+            self.sequence,
+        );
+
+        self.emitted += frames as u64;
+        self.sequence += 1;
+        chunk
+    }
+}
+
+impl<M: AudioStream, S: AudioStream> AudioStream for MixStream<M, S> {
+    fn sample_rate(&self) -> u32 {
+        self.config.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        match self.config.mode {
+            MixMode::Sum => 1,
+            MixMode::Separate => 2,
+        }
+    }
+}
+
+impl<M: AudioStream, S: AudioStream> FuturesStream for MixStream<M, S> {
+    type Item = Result<AudioChunk, AudioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.as_mut().get_mut();
+
+        if this.ended {
+            return Poll::Ready(None);
+        }
+
+        // Drain both sources so each registers its waker
+        while this.error.is_none() && !this.tracks[0].ended {
+            match this.mic.as_mut().poll_next(cx) {
+                Poll::Ready(item) => this.receive(0, item),
+                Poll::Pending => break,
+            }
+        }
+        while this.error.is_none() && !this.tracks[1].ended {
+            match this.speaker.as_mut().poll_next(cx) {
+                Poll::Ready(item) => this.receive(1, item),
+                Poll::Pending => break,
+            }
+        }
+
+        let ready = this.ready();
+        if ready > this.emitted {
+            let frames = (ready - this.emitted) as usize;
+            return Poll::Ready(Some(Ok(this.mix(frames))));
+        }
+
+        if let Some(error) = this.error.take() {
+            this.ended = true;
+            return Poll::Ready(Some(Err(error)));
+        }
+
+        if this.tracks.iter().all(|track| track.ended) {
+            this.ended = true;
+            return Poll::Ready(None);
+        }
+
+        Poll::Pending
+    }
+}
+
+// Both inputs are pinned on the heap
+impl<M, S> Unpin for MixStream<M, S> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ChunkStream;
+    use futures::StreamExt;
+    use std::f64::consts::PI;
+
+    /// Index of the first sample above half scale in every `channels`-th sample
+    fn first_click(samples: &[f32], channels: usize, channel: usize) -> usize {
+        samples
+            .iter()
+            .skip(channel)
+            .step_by(channels)
+            .position(|s| s.abs() > 0.5)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sum_applies_gain() {
+        let mic = ChunkStream::new(48000, 1.0, 0.0, |t| 0.5 * (2.0 * PI * 440.0 * t).sin() as f32);
+        let speaker = ChunkStream::new(44100, 1.0, 0.0, |_| 0.0);
+
+        let mixer = MixerConfig::new().with_sample_rate(16000).with_mic_gain(0.5);
+        let stream = MixStream::new(mic, speaker, mixer);
+        let chunks: Vec<AudioChunk> = stream.map(Result::unwrap).collect().await;
+
+        let samples: Vec<f32> = chunks.iter().flat_map(|chunk| chunk.samples.clone()).collect();
+        assert!(samples.len().abs_diff(16000) < 50, "{} samples", samples.len());
+
+        let peak = samples[1000..15000].iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!((peak - 0.25).abs() < 0.01, "peak {}", peak);
+    }
+
+    #[tokio::test]
+    async fn test_separate_aligns_sources() {
+        // The same click at 0.5 s, captured at different rates
+        let click = |t: f64| if (0.5..0.501).contains(&t) { 1.0 } else { 0.0 };
+        let mic = ChunkStream::new(48000, 1.0, 0.0, click);
+        let speaker = ChunkStream::new(44100, 1.0, 0.0, click);
+
+        let mixer = MixerConfig::new().with_sample_rate(16000).with_mode(MixMode::Separate);
+        let stream = MixStream::new(mic, speaker, mixer);
+        assert_eq!(stream.channels(), 2);
+
+        let chunks: Vec<AudioChunk> = stream.map(Result::unwrap).collect().await;
+        let samples: Vec<f32> = chunks.iter().flat_map(|chunk| chunk.samples.clone()).collect();
+
+        let mic_click = first_click(&samples, 2, 0);
+        let speaker_click = first_click(&samples, 2, 1);
+        assert!(mic_click.abs_diff(8000) <= 2, "mic click at {}", mic_click);
+        assert!(mic_click.abs_diff(speaker_click) <= 1);
+    }
+
+    #[tokio::test]
+    async fn test_compensates_drift() {
+        // A click every 10 s on both sources; the mic clock runs 1000 ppm fast
+        let clicks = |t: f64| if t % 10.0 < 0.001 && t > 1.0 { 1.0 } else { 0.0 };
+        let mic = ChunkStream::new(16000, 120.0, 1000.0, clicks);
+        let speaker = ChunkStream::new(16000, 120.0, 0.0, clicks);
+
+        let mixer = MixerConfig::new().with_sample_rate(16000).with_mode(MixMode::Separate);
+        let mut stream = MixStream::new(mic, speaker, mixer);
+
+        let mut samples = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            samples.extend(chunk.unwrap().samples);
+        }
+        assert!((stream.drift_ppm(MixSource::Mic) - 1000.0).abs() < 20.0);
+
+        // Without compensation the last click would be 110 ms off
+        let tail = &samples[2 * 16000 * 100..];
+        let offset = first_click(tail, 2, 0).abs_diff(first_click(tail, 2, 1));
+        assert!(offset <= 16, "clicks {} frames apart", offset);
+    }
+}
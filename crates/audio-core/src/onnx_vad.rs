@@ -0,0 +1,402 @@
+//! Voice activity detection with ONNX models
+//!
+//! [`OnnxVad`] runs a Silero-style recurrent model on the CPU: every call
+//! takes a fixed window of mono audio at the model rate, preceded by the
+//! tail of the previous window, plus the recurrent state returned by the
+//! previous call. The model is loaded from a local file; nothing is
+//! downloaded. The ONNX Runtime library itself is loaded at run time from
+//! the path in the `ORT_DYLIB_PATH` environment variable, or from the
+//! system library path.
+
+use std::path::PathBuf;
+
+use ort::session::Session;
+use ort::value::Tensor;
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::AudioChunk;
+use crate::error::AudioError;
+use crate::resample::{ResampleQuality, Resampler};
+use crate::vad::{VadFrame, VoiceActivityDetector};
+
+/// Shape and tuning of an ONNX voice activity model
+///
+/// The defaults match Silero VAD v5 at 16 kHz.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct OnnxVadConfig {
+    /// Model file
+    pub model_path: PathBuf,
+    /// Sample rate the model expects; audio is resampled to it
+    pub sample_rate: u32,
+    /// Samples of new audio per model call
+    pub frame_samples: usize,
+    /// Samples of the previous window repeated before the new audio
+    pub context_samples: usize,
+    /// Shape of the recurrent state tensor
+    pub state_shape: Vec<usize>,
+    /// Threads used for inference
+    pub threads: usize,
+}
+
+impl Default for OnnxVadConfig {
+    fn default() -> Self {
+        Self {
+            model_path: PathBuf::from("silero_vad.onnx"),
+            sample_rate: 16000,
+            frame_samples: 512,
+            context_samples: 64,
+            state_shape: vec![2, 1, 128],
+            threads: 1,
+        }
+    }
+}
+
+impl OnnxVadConfig {
+    pub fn new(model_path: impl Into<PathBuf>) -> Self {
+        Self {
+            model_path: model_path.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn with_frame_samples(mut self, frame_samples: usize) -> Self {
+        self.frame_samples = frame_samples;
+        self
+    }
+
+    pub fn with_context_samples(mut self, context_samples: usize) -> Self {
+        self.context_samples = context_samples;
+        self
+    }
+
+    pub fn with_state_shape(mut self, state_shape: Vec<usize>) -> Self {
+        self.state_shape = state_shape;
+        self
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Check that the shape describes windows a model can be run on
+    fn validate(&self) -> Result<(), AudioError> {
+        let problem = if self.sample_rate == 0 {
+            "the sample rate is zero".to_string()
+        } else if self.frame_samples == 0 {
+            "a window holds no samples".to_string()
+        } else if self.context_samples > self.frame_samples {
+            format!(
+                "the context of {} samples is longer than a window of {}",
+                self.context_samples, self.frame_samples
+            )
+        } else if self.state_shape.is_empty() || self.state_shape.contains(&0) {
+            format!("the state shape {:?} holds no values", self.state_shape)
+        } else {
+            return Ok(());
+        };
+        Err(AudioError::ProcessingError(format!(
+            "Invalid voice activity model configuration: {}",
+            problem
+        )))
+    }
+}
+
+/// Voice activity detector running an ONNX model
+///
+/// Input of any rate and channel count is mixed down to mono and resampled
+/// to the model rate. Analysis frames are reported in frames of the input,
+/// so their positions line up with those of other detectors.
+pub struct OnnxVad {
+    config: OnnxVadConfig,
+    session: Session,
+    windows: Windows,
+    context: Vec<f32>,
+    state: Vec<f32>,
+}
+
+impl OnnxVad {
+    /// Load the model in `config`
+    ///
+    /// Fails without loading anything if the context is longer than a
+    /// window, or the rate, window or state is empty.
+    pub fn new(config: OnnxVadConfig) -> Result<Self, AudioError> {
+        config.validate()?;
+
+        let session = Session::builder()
+            .and_then(|builder| builder.with_intra_threads(config.threads.max(1)))
+            .and_then(|builder| builder.commit_from_file(&config.model_path))
+            .map_err(|e| {
+                AudioError::ProcessingError(format!("{}: {}", config.model_path.display(), e))
+            })?;
+
+        let state = vec![0.0; config.state_shape.iter().product()];
+        let context = vec![0.0; config.context_samples];
+        let windows = Windows::new(config.sample_rate, config.frame_samples);
+
+        Ok(Self {
+            config,
+            session,
+            windows,
+            context,
+            state,
+        })
+    }
+
+    pub fn config(&self) -> &OnnxVadConfig {
+        &self.config
+    }
+
+    /// Run the model on one window of new audio
+    fn infer(&mut self, window: &[f32]) -> Result<f32, AudioError> {
+        let mut input = Vec::with_capacity(self.context.len() + window.len());
+        input.extend_from_slice(&self.context);
+        input.extend_from_slice(window);
+
+        let shape = self.config.state_shape.clone();
+        let inputs = ort::inputs![
+            "input" => Tensor::from_array(([1usize, input.len()], input)).map_err(model_error)?,
+            "state" => Tensor::from_array((shape, self.state.clone())).map_err(model_error)?,
+            "sr" => Tensor::from_array(((), vec![self.config.sample_rate as i64])).map_err(model_error)?,
+        ];
+
+        let outputs = self.session.run(inputs).map_err(model_error)?;
+        let (_, probability) = outputs["output"].try_extract_tensor::<f32>().map_err(model_error)?;
+        let (_, state) = outputs["stateN"].try_extract_tensor::<f32>().map_err(model_error)?;
+
+        if state.len() != self.state.len() {
+            return Err(AudioError::ProcessingError(format!(
+                "Voice activity model returned a state of {} values, expected {}",
+                state.len(),
+                self.state.len()
+            )));
+        }
+        let probability = probability.first().copied().unwrap_or(0.0);
+        self.state.copy_from_slice(state);
+
+        let tail = window.len().saturating_sub(self.context.len());
+        self.context.copy_from_slice(&window[tail..]);
+
+        Ok(probability)
+    }
+}
+
+fn model_error(e: ort::Error) -> AudioError {
+    AudioError::ProcessingError(format!("Voice activity model failed: {}", e))
+}
+
+/// A window of model-rate audio and the input frames it covers
+struct Window {
+    samples: Vec<f32>,
+    frame: u64,
+    frames: u64,
+}
+
+/// Cuts input of any rate into mono windows at the model rate
+struct Windows {
+    /// Sample rate the model expects
+    sample_rate: u32,
+    /// Samples per window
+    window: usize,
+    /// Converts the input to the model rate; recreated when the input rate changes
+    resampler: Option<Resampler>,
+    /// Input frame where the resampler started
+    origin: u64,
+    /// Input frames seen so far
+    position: u64,
+    /// Model-rate samples produced since `origin`, including those in `pending`
+    produced: u64,
+    pending: Vec<f32>,
+}
+
+impl Windows {
+    fn new(sample_rate: u32, window: usize) -> Self {
+        Self {
+            sample_rate,
+            window: window.max(1),
+            resampler: None,
+            origin: 0,
+            position: 0,
+            produced: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Input frame corresponding to model-rate sample `sample`
+    fn input_frame(&self, sample: u64, input_rate: u32) -> u64 {
+        let ratio = input_rate as f64 / self.sample_rate as f64;
+        self.origin + (sample as f64 * ratio).round() as u64
+    }
+
+    /// Add `chunk`, returning the windows it completes
+    fn push(&mut self, chunk: &AudioChunk) -> Vec<Window> {
+        let input_rate = chunk.sample_rate;
+        if self.resampler.as_ref().map(Resampler::input_rate) != Some(input_rate) {
+            // Audio still buffered at the old rate is dropped; the model
+            // state carries over, so detection continues across the change
+            self.resampler = Some(Resampler::with_quality(
+                input_rate,
+                self.sample_rate,
+                1,
+                ResampleQuality::Fast,
+            ));
+            self.origin = self.position;
+            self.produced = 0;
+            self.pending.clear();
+        }
+
+        let channels = chunk.channels.max(1) as usize;
+        let mono: Vec<f32> = chunk
+            .samples
+            .chunks_exact(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect();
+        self.position += mono.len() as u64;
+
+        let Some(resampler) = self.resampler.as_mut() else {
+            return Vec::new();
+        };
+        let resampled = resampler.process(&mono);
+        self.produced += resampled.len() as u64;
+        self.pending.extend(resampled);
+
+        let window = self.window;
+        let mut windows = Vec::new();
+        let mut first = self.produced - self.pending.len() as u64;
+
+        while self.pending.len() >= window {
+            let samples: Vec<f32> = self.pending.drain(..window).collect();
+            let start = self.input_frame(first, input_rate);
+            let end = self.input_frame(first + window as u64, input_rate);
+            windows.push(Window {
+                samples,
+                frame: start,
+                frames: end - start,
+            });
+            first += window as u64;
+        }
+
+        windows
+    }
+
+    fn reset(&mut self) {
+        self.resampler = None;
+        self.origin = 0;
+        self.position = 0;
+        self.produced = 0;
+        self.pending.clear();
+    }
+}
+
+impl VoiceActivityDetector for OnnxVad {
+    fn process(&mut self, chunk: &AudioChunk) -> Result<Vec<VadFrame>, AudioError> {
+        self.windows
+            .push(chunk)
+            .into_iter()
+            .map(|window| {
+                Ok(VadFrame {
+                    frame: window.frame,
+                    frames: window.frames,
+                    probability: self.infer(&window.samples)?,
+                })
+            })
+            .collect()
+    }
+
+    fn reset(&mut self) {
+        self.windows.reset();
+        self.context.fill(0.0);
+        self.state.fill(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Push `chunks` chunks of 100 ms of mono audio at `rate`
+    fn push(windows: &mut Windows, rate: u32, chunks: u64) -> Vec<Window> {
+        let frames = rate as usize / 10;
+        (0..chunks)
+            .flat_map(|n| {
+                let chunk = AudioChunk::new(vec![0.1; frames], rate, 1, Duration::from_millis(n * 100), n);
+                windows.push(&chunk)
+            })
+            .collect()
+    }
+
+    /// Assert that `windows` follow each other from `start` on
+    fn assert_contiguous(windows: &[Window], start: u64) {
+        let mut next = start;
+        for window in windows {
+            assert_eq!(window.frame, next);
+            next += window.frames;
+        }
+    }
+
+    #[test]
+    fn test_windows_map_to_input_frames_across_rate_change() {
+        let mut windows = Windows::new(16000, 512);
+
+        // At 48 kHz a window of 512 model samples covers 1536 input frames
+        let before = push(&mut windows, 48000, 10);
+        assert!(!before.is_empty());
+        assert!(before.iter().all(|w| w.samples.len() == 512 && w.frames.abs_diff(1536) <= 1));
+        assert_contiguous(&before, 0);
+        let last = before.last().unwrap();
+        assert!(last.frame + last.frames <= 48000);
+
+        // After the change windows count from the first frame at the new rate
+        let after = push(&mut windows, 16000, 10);
+        assert!(!after.is_empty());
+        assert!(after.iter().all(|w| w.frames == 512));
+        assert_contiguous(&after, 48000);
+
+        windows.reset();
+        assert_contiguous(&push(&mut windows, 16000, 10), 0);
+    }
+
+    #[test]
+    fn test_stereo_input_is_mixed_down() {
+        let mut windows = Windows::new(16000, 512);
+        let chunk = AudioChunk::new([0.5, -0.5].repeat(16000), 16000, 2, Duration::ZERO, 0);
+
+        let mixed = windows.push(&chunk);
+        assert!(!mixed.is_empty());
+        assert_contiguous(&mixed, 0);
+        assert!(mixed.iter().flat_map(|w| &w.samples).all(|s| s.abs() < 1e-3));
+    }
+
+    #[test]
+    fn test_config_checks() {
+        assert!(OnnxVadConfig::default().validate().is_ok());
+        assert!(OnnxVadConfig::default().with_context_samples(512).validate().is_ok());
+
+        let invalid = [
+            OnnxVadConfig::default().with_frame_samples(32),
+            OnnxVadConfig::default().with_frame_samples(0),
+            OnnxVadConfig::default().with_sample_rate(0),
+            OnnxVadConfig::default().with_state_shape(Vec::new()),
+            OnnxVadConfig::default().with_state_shape(vec![2, 0, 128]),
+        ];
+        for config in invalid {
+            assert!(config.validate().is_err(), "{:?}", config);
+        }
+    }
+
+    #[test]
+    fn test_invalid_config_fails_before_loading_the_model() {
+        let config = OnnxVadConfig::new("missing.onnx").with_frame_samples(32);
+        let Err(AudioError::ProcessingError(message)) = OnnxVad::new(config) else {
+            panic!("expected a configuration error");
+        };
+        assert!(message.contains("context of 64 samples"), "{}", message);
+    }
+}
@@ -0,0 +1,729 @@
+//! Rolling history of a capture
+//!
+//! Meetings often turn important before anyone starts recording. A
+//! [`PreRollBuffer`] keeps the last minutes of the audio passing through
+//! its [`PreRollRecorder`]s, so a recording can start with what was said
+//! before it. Recent audio stays in memory, in a ring buffer as speaker
+//! capture uses to hand over its samples; older audio is spilled to WAV
+//! segments on disk when a spill directory is set, and dropped otherwise.
+
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ringbuf::{
+    traits::{Consumer, Observer, Producer},
+    HeapRb,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::{duration_to_frames, frames_to_duration, AudioChunk, FrameClock};
+use crate::error::AudioError;
+use crate::processor::AudioProcessor;
+
+/// Length of the files older audio is spilled to
+const SEGMENT_DURATION: Duration = Duration::from_secs(10);
+
+/// Length of the chunks a [`PreRollHistory`] yields
+const HISTORY_CHUNK: Duration = Duration::from_millis(100);
+
+/// Buffers created in this process, naming their spill directories
+static BUFFER_COUNT: AtomicU64 = AtomicU64::new(0);
+
+type Writer = hound::WavWriter<BufWriter<File>>;
+type Reader = hound::WavReader<BufReader<File>>;
+
+/// Configuration of a [`PreRollBuffer`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct PreRollConfig {
+    /// Whether audio is kept at all
+    pub enabled: bool,
+    /// Length of the history kept
+    pub duration: Duration,
+    /// Part of the history held in memory
+    ///
+    /// Without a spill directory the history is no longer than this.
+    pub memory: Duration,
+    /// Directory older audio is spilled to
+    pub spill_dir: Option<PathBuf>,
+}
+
+impl Default for PreRollConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            duration: Duration::from_secs(5 * 60),
+            memory: Duration::from_secs(60),
+            spill_dir: None,
+        }
+    }
+}
+
+impl PreRollConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    pub fn with_memory(mut self, memory: Duration) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    pub fn with_spill_dir(mut self, spill_dir: impl Into<PathBuf>) -> Self {
+        self.spill_dir = Some(spill_dir.into());
+        self
+    }
+}
+
+/// Directory of the spilled segments of one buffer, removed once neither
+/// the buffer nor a history taken from it uses it
+struct SpillDir(PathBuf);
+
+impl Drop for SpillDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// WAV file holding the frames from `first_frame` on
+struct Segment {
+    path: PathBuf,
+    first_frame: u64,
+    frames: u64,
+}
+
+impl Segment {
+    fn end(&self) -> u64 {
+        self.first_frame + self.frames
+    }
+}
+
+/// Recent audio in memory, older audio in spilled segments
+struct PreRoll {
+    config: PreRollConfig,
+    /// Sample rate and channel count of the audio kept
+    format: Option<(u32, u16)>,
+    ring: Option<HeapRb<f32>>,
+    /// First frame held in the ring
+    start: u64,
+    /// Frames recorded so far
+    end: u64,
+    clock: FrameClock,
+    dir: Option<Arc<SpillDir>>,
+    segments: VecDeque<Segment>,
+    /// Segment being written
+    writer: Option<(Writer, Segment)>,
+    /// Set once spilling failed, leaving only the memory
+    spill_failed: bool,
+    /// Frames of [`AudioChunk::gap_fill`] silence, oldest first
+    gaps: VecDeque<Range<u64>>,
+    /// Samples evicted from the ring
+    scratch: Vec<f32>,
+}
+
+impl PreRoll {
+    fn new(config: PreRollConfig) -> Self {
+        Self {
+            config,
+            format: None,
+            ring: None,
+            start: 0,
+            end: 0,
+            clock: FrameClock::default(),
+            dir: None,
+            segments: VecDeque::new(),
+            writer: None,
+            spill_failed: false,
+            gaps: VecDeque::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, chunk: &AudioChunk) {
+        if !self.config.enabled || chunk.is_empty() {
+            return;
+        }
+
+        let format = (chunk.sample_rate, chunk.channels.max(1));
+        if self.format != Some(format) {
+            // Audio in another format cannot continue the history
+            if self.format.is_some() {
+                tracing::info!(
+                    "Pre-roll starts over at {} Hz, {} channels",
+                    chunk.sample_rate,
+                    chunk.channels
+                );
+            }
+            self.clear();
+            let memory = self.config.memory.min(self.config.duration);
+            let frames = duration_to_frames(memory, format.0).max(1) as usize;
+            self.ring = Some(HeapRb::new(frames * format.1 as usize));
+            self.format = Some(format);
+        }
+
+        let Some(capacity) = self.ring.as_ref().map(|ring| ring.capacity().get()) else {
+            return;
+        };
+        for piece in chunk.samples.chunks(capacity) {
+            let vacant = self.ring.as_ref().map_or(0, |ring| ring.vacant_len());
+            self.evict(piece.len().saturating_sub(vacant));
+            if let Some(ring) = self.ring.as_mut() {
+                ring.push_slice(piece);
+            }
+        }
+
+        let frames = chunk.frames() as u64;
+        if chunk.gap_fill {
+            match self.gaps.back_mut() {
+                Some(gap) if gap.end == self.end => gap.end += frames,
+                _ => self.gaps.push_back(self.end..self.end + frames),
+            }
+        }
+
+        self.clock.anchor(self.end, chunk.timestamp);
+        self.end += frames;
+        self.drop_expired();
+    }
+
+    /// Move the oldest `samples` out of memory, to disk if spilling
+    fn evict(&mut self, samples: usize) {
+        let (Some(ring), Some((_, channels))) = (self.ring.as_mut(), self.format) else {
+            return;
+        };
+        if samples == 0 {
+            return;
+        }
+
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.resize(samples, 0.0);
+        let popped = ring.pop_slice(&mut scratch);
+        scratch.truncate(popped);
+
+        let first_frame = self.start;
+        self.start += (popped / channels as usize) as u64;
+        if self.config.spill_dir.is_some() && !self.spill_failed {
+            if let Err(e) = self.spill(&scratch, first_frame) {
+                tracing::warn!("Pre-roll stops spilling to disk: {}", e);
+                self.spill_failed = true;
+                if let Some((_, segment)) = self.writer.take() {
+                    let _ = fs::remove_file(&segment.path);
+                }
+            }
+        }
+        self.scratch = scratch;
+    }
+
+    fn spill(&mut self, samples: &[f32], first_frame: u64) -> Result<(), hound::Error> {
+        let Some((sample_rate, channels)) = self.format else {
+            return Ok(());
+        };
+
+        if self.writer.is_none() {
+            let dir = self.spill_dir()?;
+            let path = dir.join(format!("{:012}.wav", first_frame));
+            let spec = hound::WavSpec {
+                channels,
+                sample_rate,
+                bits_per_sample: 32,
+                sample_format: hound::SampleFormat::Float,
+            };
+            let segment = Segment {
+                path,
+                first_frame,
+                frames: 0,
+            };
+            self.writer = Some((hound::WavWriter::create(&segment.path, spec)?, segment));
+        }
+
+        let Some((writer, segment)) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        for &sample in samples {
+            writer.write_sample(sample)?;
+        }
+        segment.frames += (samples.len() / channels as usize) as u64;
+
+        if segment.frames >= duration_to_frames(SEGMENT_DURATION, sample_rate) {
+            self.finish_segment()?;
+        }
+        Ok(())
+    }
+
+    /// Directory of this buffer's segments, created on first use
+    fn spill_dir(&mut self) -> Result<PathBuf, hound::Error> {
+        if let Some(dir) = &self.dir {
+            return Ok(dir.0.clone());
+        }
+
+        let Some(parent) = &self.config.spill_dir else {
+            return Err(hound::Error::Unsupported);
+        };
+        let dir = parent.join(format!(
+            "heronote-preroll-{}-{}",
+            std::process::id(),
+            BUFFER_COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)?;
+        tracing::debug!(dir = %dir.display(), "Pre-roll spills to disk");
+
+        self.dir = Some(Arc::new(SpillDir(dir.clone())));
+        Ok(dir)
+    }
+
+    /// Complete the segment being written
+    fn finish_segment(&mut self) -> Result<(), hound::Error> {
+        let Some((writer, segment)) = self.writer.take() else {
+            return Ok(());
+        };
+        writer.finalize()?;
+        self.segments.push_back(segment);
+        Ok(())
+    }
+
+    /// Delete the segments that only hold audio older than the history
+    fn drop_expired(&mut self) {
+        let Some((sample_rate, _)) = self.format else {
+            return;
+        };
+
+        let first = self.end.saturating_sub(duration_to_frames(self.config.duration, sample_rate));
+        while self.segments.front().is_some_and(|segment| segment.end() <= first) {
+            if let Some(segment) = self.segments.pop_front() {
+                let _ = fs::remove_file(&segment.path);
+            }
+        }
+        while self.gaps.front().is_some_and(|gap| gap.end <= first) {
+            self.gaps.pop_front();
+        }
+    }
+
+    /// Hand the history over and start a new one
+    fn take(&mut self) -> Option<PreRollHistory> {
+        let (sample_rate, channels) = self.format?;
+
+        if let Err(e) = self.finish_segment() {
+            tracing::warn!("Pre-roll lost its latest spilled audio: {}", e);
+        }
+
+        let first = self.end.saturating_sub(duration_to_frames(self.config.duration, sample_rate));
+        let segments = std::mem::take(&mut self.segments);
+        let memory = self.ring.as_mut().map_or_else(Vec::new, |ring| ring.pop_iter().collect());
+        let next = segments.front().map_or(self.start, |segment| segment.first_frame).max(first);
+        let history = PreRollHistory {
+            sample_rate,
+            channels,
+            clock: self.clock,
+            segments,
+            reader: None,
+            memory,
+            memory_start: self.start,
+            next,
+            end: self.end,
+            gaps: std::mem::take(&mut self.gaps),
+            sequence: 0,
+            _dir: self.dir.clone(),
+        };
+
+        self.start = self.end;
+        (!history.is_empty()).then_some(history)
+    }
+
+    /// Forget all audio kept
+    fn clear(&mut self) {
+        let written = self.writer.take().map(|(_, segment)| segment);
+        for segment in written.into_iter().chain(self.segments.drain(..)) {
+            let _ = fs::remove_file(&segment.path);
+        }
+        if let Some(ring) = self.ring.as_mut() {
+            ring.clear();
+        }
+        self.gaps.clear();
+        self.start = self.end;
+    }
+
+    fn buffered(&self) -> Duration {
+        let Some((sample_rate, _)) = self.format else {
+            return Duration::ZERO;
+        };
+
+        let spilled = self.segments.front().map(|segment| segment.first_frame);
+        let written = self.writer.as_ref().map(|(_, segment)| segment.first_frame);
+        let first = spilled.or(written).unwrap_or(self.start);
+        let frames = (self.end - first).min(duration_to_frames(self.config.duration, sample_rate));
+        frames_to_duration(frames, sample_rate)
+    }
+}
+
+/// The last minutes of a capture, kept while it runs
+///
+/// Clones share the same history. Recorders from one buffer feed the same
+/// history, so it continues when a capture is restarted with a recorder
+/// from the same buffer. A disabled buffer keeps nothing.
+#[derive(Clone)]
+pub struct PreRollBuffer {
+    inner: Arc<Mutex<PreRoll>>,
+}
+
+impl Default for PreRollBuffer {
+    fn default() -> Self {
+        Self::new(PreRollConfig::default())
+    }
+}
+
+impl PreRollBuffer {
+    pub fn new(config: PreRollConfig) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(PreRoll::new(config))),
+        }
+    }
+
+    pub fn config(&self) -> PreRollConfig {
+        self.inner.lock().unwrap().config.clone()
+    }
+
+    /// Change the configuration, discarding the history kept so far
+    pub fn set_config(&self, config: PreRollConfig) {
+        *self.inner.lock().unwrap() = PreRoll::new(config);
+    }
+
+    /// A processor keeping the audio passing through in this buffer
+    pub fn recorder(&self) -> PreRollRecorder {
+        PreRollRecorder {
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Length of the history kept
+    pub fn buffered(&self) -> Duration {
+        self.inner.lock().unwrap().buffered()
+    }
+
+    /// Take the history kept so far, leaving the buffer to start a new one
+    ///
+    /// Returns `None` if nothing was kept.
+    pub fn take_history(&self) -> Option<PreRollHistory> {
+        self.inner.lock().unwrap().take()
+    }
+
+    /// Forget the history kept so far
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().clear();
+    }
+}
+
+/// Processor keeping a copy of its audio in a [`PreRollBuffer`]
+///
+/// Audio in a new format replaces the history rather than adding to it.
+pub struct PreRollRecorder {
+    inner: Arc<Mutex<PreRoll>>,
+}
+
+impl AudioProcessor for PreRollRecorder {
+    fn process(&mut self, chunk: AudioChunk) -> Result<Vec<AudioChunk>, AudioError> {
+        self.inner.lock().unwrap().record(&chunk);
+        Ok(vec![chunk])
+    }
+
+    fn reset(&mut self) {}
+}
+
+/// Audio taken from a [`PreRollBuffer`], oldest first
+///
+/// Yields chunks of 100 ms, stamped on the capture clock. Gap fill
+/// silence comes in chunks of its own, marked as
+/// [`AudioChunk::gap_fill`] again. Spilled segments are read as the
+/// history is consumed and deleted once read.
+pub struct PreRollHistory {
+    sample_rate: u32,
+    channels: u16,
+    clock: FrameClock,
+    segments: VecDeque<Segment>,
+    /// Segment being read
+    reader: Option<(Reader, Segment)>,
+    memory: Vec<f32>,
+    /// Frame of the first sample in memory
+    memory_start: u64,
+    /// Next frame to deliver
+    next: u64,
+    end: u64,
+    /// Frames of gap fill silence, oldest first
+    gaps: VecDeque<Range<u64>>,
+    sequence: u64,
+    _dir: Option<Arc<SpillDir>>,
+}
+
+impl PreRollHistory {
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Length of the audio still to be delivered
+    pub fn duration(&self) -> Duration {
+        frames_to_duration(self.end - self.next, self.sample_rate)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.next >= self.end
+    }
+
+    /// Write the remaining audio to a 32-bit float WAV file, returning its
+    /// length
+    pub fn save(self, path: &Path) -> Result<Duration, AudioError> {
+        let error = |e: hound::Error| AudioError::ProcessingError(format!("Failed to save pre-roll: {}", e));
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| error(e.into()))?;
+        }
+        let spec = hound::WavSpec {
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let sample_rate = self.sample_rate;
+        let mut writer = hound::WavWriter::create(path, spec).map_err(error)?;
+
+        let mut frames = 0;
+        for chunk in self {
+            let chunk = chunk?;
+            for &sample in &chunk.samples {
+                writer.write_sample(sample).map_err(error)?;
+            }
+            frames += chunk.frames() as u64;
+        }
+        writer.finalize().map_err(error)?;
+
+        tracing::info!(path = %path.display(), frames, "Pre-roll saved");
+        Ok(frames_to_duration(frames, sample_rate))
+    }
+
+    /// Read up to `frames` frames from the spilled segments, moving the
+    /// next frame past audio that is missing from them
+    fn read_spilled(&mut self, frames: u64) -> Result<Vec<f32>, hound::Error> {
+        loop {
+            if let Some((reader, segment)) = &mut self.reader {
+                if self.next < segment.end() {
+                    let frames = uniform_run(&self.gaps, self.next, frames);
+                    let count = frames.min(segment.end() - self.next) * self.channels as u64;
+                    let samples = reader
+                        .samples::<f32>()
+                        .take(count as usize)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if !samples.is_empty() {
+                        return Ok(samples);
+                    }
+                }
+                if let Some((_, segment)) = self.reader.take() {
+                    let _ = fs::remove_file(&segment.path);
+                }
+            }
+
+            let Some(segment) = self.segments.pop_front() else {
+                // Spilling stopped; the rest is in memory
+                self.next = self.next.max(self.memory_start);
+                return Ok(Vec::new());
+            };
+            if segment.end() <= self.next {
+                let _ = fs::remove_file(&segment.path);
+                continue;
+            }
+
+            self.next = self.next.max(segment.first_frame);
+            let mut reader = hound::WavReader::open(&segment.path)?;
+            reader.seek((self.next - segment.first_frame) as u32)?;
+            self.reader = Some((reader, segment));
+        }
+    }
+}
+
+impl Iterator for PreRollHistory {
+    type Item = Result<AudioChunk, AudioError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let channels = self.channels as usize;
+        let frames = duration_to_frames(HISTORY_CHUNK, self.sample_rate).max(1);
+
+        let mut samples = Vec::new();
+        while samples.is_empty() && !self.is_empty() {
+            samples = if self.next < self.memory_start {
+                match self.read_spilled(frames.min(self.memory_start - self.next)) {
+                    Ok(samples) => samples,
+                    Err(e) => {
+                        self.next = self.end;
+                        let message = format!("Failed to read spilled pre-roll: {}", e);
+                        return Some(Err(AudioError::ProcessingError(message)));
+                    }
+                }
+            } else {
+                let offset = (self.next - self.memory_start) as usize * channels;
+                let frames = uniform_run(&self.gaps, self.next, frames.min(self.end - self.next));
+                let count = (frames as usize * channels)
+                    .min(self.memory.len().saturating_sub(offset));
+                if count == 0 {
+                    self.next = self.end;
+                }
+                self.memory[offset.min(self.memory.len())..][..count].to_vec()
+            };
+
+            if !samples.is_empty() {
+                let first = self.next;
+                self.next += (samples.len() / channels) as u64;
+                let mut chunk = AudioChunk::new(
+                    samples,
+                    self.sample_rate,
+                    self.channels,
+                    self.clock.timestamp_of(first, self.sample_rate),
+                    self.sequence,
+                );
+                while self.gaps.front().is_some_and(|gap| gap.end <= first) {
+                    self.gaps.pop_front();
+                }
+                chunk.gap_fill = self.gaps.front().is_some_and(|gap| gap.start <= first);
+                self.sequence += 1;
+                return Some(Ok(chunk));
+            }
+        }
+        None
+    }
+}
+
+/// Frames from `frame` on, at most `frames`, that are either all gap fill
+/// or all audio
+fn uniform_run(gaps: &VecDeque<Range<u64>>, frame: u64, frames: u64) -> u64 {
+    match gaps.iter().find(|gap| gap.end > frame) {
+        Some(gap) if gap.start <= frame => frames.min(gap.end - frame),
+        Some(gap) => frames.min(gap.start - frame),
+        None => frames,
+    }
+}
+
+impl Drop for PreRollHistory {
+    fn drop(&mut self) {
+        let unread = self.reader.take().map(|(_, segment)| segment);
+        for segment in unread.into_iter().chain(self.segments.drain(..)) {
+            let _ = fs::remove_file(&segment.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `seconds` of mono audio at 1 kHz whose samples count the frames
+    fn feed(buffer: &PreRollBuffer, seconds: u64) {
+        let mut recorder = buffer.recorder();
+        for block in 0..seconds * 10 {
+            let samples = (block * 100..(block + 1) * 100).map(|n| n as f32).collect();
+            let timestamp = Duration::from_millis(block * 100);
+            recorder.process(AudioChunk::new(samples, 1000, 1, timestamp, block)).unwrap();
+        }
+    }
+
+    fn collect(history: PreRollHistory) -> Vec<AudioChunk> {
+        history.map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn test_keeps_last_audio_in_memory_and_off_by_default() {
+        let disabled = PreRollBuffer::default();
+        feed(&disabled, 1);
+        assert!(disabled.take_history().is_none());
+
+        let config = PreRollConfig::new()
+            .with_enabled(true)
+            .with_memory(Duration::from_millis(1500));
+        let buffer = PreRollBuffer::new(config);
+        feed(&buffer, 5);
+        assert_eq!(buffer.buffered(), Duration::from_millis(1500));
+
+        let chunks = collect(buffer.take_history().unwrap());
+        let samples: Vec<f32> = chunks.iter().flat_map(|chunk| chunk.samples.clone()).collect();
+        assert_eq!(samples, (3500..5000).map(|n| n as f32).collect::<Vec<_>>());
+        assert_eq!(chunks[0].timestamp, Duration::from_millis(3500));
+        assert_eq!(chunks[0].sequence, 0);
+
+        // The history was handed over
+        assert!(buffer.take_history().is_none());
+    }
+
+    #[test]
+    fn test_spills_older_audio_to_disk() {
+        let dir = std::env::temp_dir().join(format!("heronote-preroll-test-{}", std::process::id()));
+        let config = PreRollConfig::new()
+            .with_enabled(true)
+            .with_duration(Duration::from_secs(25))
+            .with_memory(Duration::from_secs(2))
+            .with_spill_dir(&dir);
+        let buffer = PreRollBuffer::new(config);
+
+        feed(&buffer, 40);
+        assert_eq!(buffer.buffered(), Duration::from_secs(25));
+
+        let history = buffer.take_history().unwrap();
+        assert_eq!(history.duration(), Duration::from_secs(25));
+        let chunks = collect(history);
+        let samples: Vec<f32> = chunks.iter().flat_map(|chunk| chunk.samples.clone()).collect();
+        assert_eq!(samples, (15000..40000).map(|n| n as f32).collect::<Vec<_>>());
+        assert_eq!(chunks[0].timestamp, Duration::from_secs(15));
+
+        // Read segments are deleted, and the directory goes with the buffer
+        drop(buffer);
+        assert!(fs::read_dir(&dir).unwrap().next().is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_history_keeps_gap_fill_apart() {
+        let dir = std::env::temp_dir().join(format!("heronote-preroll-gaps-{}", std::process::id()));
+        let config = PreRollConfig::new()
+            .with_enabled(true)
+            .with_duration(Duration::from_millis(4950))
+            .with_memory(Duration::from_secs(2))
+            .with_spill_dir(&dir);
+        let buffer = PreRollBuffer::new(config);
+
+        // Gap fill at 1.2-1.5 s, spilled, and at 4.0-4.1 s, still in memory
+        let is_gap = |frame: u64| (1200..1500).contains(&frame) || (4000..4100).contains(&frame);
+        let mut recorder = buffer.recorder();
+        for block in 0..50 {
+            let samples = (block * 100..(block + 1) * 100).map(|n| n as f32).collect();
+            let mut chunk = AudioChunk::new(samples, 1000, 1, Duration::from_millis(block * 100), block);
+            chunk.gap_fill = is_gap(block * 100);
+            recorder.process(chunk).unwrap();
+        }
+
+        // The history starts mid-block, so its chunks end on the gap edges
+        let chunks = collect(buffer.take_history().unwrap());
+        let samples: Vec<f32> = chunks.iter().flat_map(|chunk| chunk.samples.clone()).collect();
+        assert_eq!(samples, (50..5000).map(|n| n as f32).collect::<Vec<_>>());
+        for chunk in &chunks {
+            assert!(chunk.samples.iter().all(|&n| is_gap(n as u64) == chunk.gap_fill));
+        }
+        let filled: usize = chunks.iter().filter(|c| c.gap_fill).map(AudioChunk::frames).sum();
+        assert_eq!(filled, 400);
+
+        drop(buffer);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
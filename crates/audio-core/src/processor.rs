@@ -0,0 +1,514 @@
+//! Composable audio processing
+//!
+//! An [`AudioProcessor`] turns chunks into chunks: a resampler, a gain
+//! stage, a voice activity detector, a noise suppressor, a level meter or
+//! a recording.
+//! Processors that buffer audio, like the resampler, may return fewer or
+//! more chunks than they receive and hand over the rest when flushed.
+//!
+//! A [`Pipeline`] chains processors into one, and [`ProcessedStream`] runs
+//! a processor behind any [`AudioStream`]. Pipelines can also be described
+//! by a [`PipelineConfig`], which serializes, so the chain can come from
+//! settings rather than code:
+//!
+//! ```
+//! use heronote_audio_core::{PipelineConfig, ProcessorConfig};
+//!
+//! let config = PipelineConfig::new()
+//!     .with_stage(ProcessorConfig::Resample { sample_rate: 16000 })
+//!     .with_stage(ProcessorConfig::Gain { gain_db: 6.0 });
+//! let pipeline = config.build();
+//! assert_eq!(pipeline.len(), 2);
+//! ```
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::Stream as FuturesStream;
+use serde::{Deserialize, Serialize};
+
+use crate::agc::{Agc, AgcConfig};
+use crate::chunk::AudioChunk;
+use crate::config::StreamFormat;
+use crate::denoise::{NoiseSuppression, NoiseSuppressorConfig};
+use crate::drift::{DriftCorrection, DriftMeasurement};
+use crate::error::AudioError;
+use crate::health::{HealthConfig, HealthMonitor};
+use crate::meter::{db_to_linear, Meter, MeterConfig};
+use crate::resample::Resample;
+use crate::sink::WavSink;
+use crate::traits::AudioStream;
+use crate::vad::{EnergyVad, EnergyVadConfig, VadConfig, VoiceActivity};
+
+/// One step of audio processing
+///
+/// Processors receive the chunks of a single stream in order. They follow
+/// changes of sample rate and channel count from the chunks themselves;
+/// [`AudioProcessor::flush`] is called before a change that reaches their
+/// output, so audio buffered in the old format is delivered first.
+pub trait AudioProcessor: Any + Send {
+    /// Process one chunk, returning the chunks it completes
+    fn process(&mut self, chunk: AudioChunk) -> Result<Vec<AudioChunk>, AudioError>;
+
+    /// Hand over the audio still buffered, at the end of a stream or before
+    /// a format change
+    fn flush(&mut self) -> Result<Vec<AudioChunk>, AudioError> {
+        Ok(Vec::new())
+    }
+
+    /// Forget all audio seen so far, as if newly created
+    fn reset(&mut self);
+
+    /// Delay between audio entering the processor and leaving it
+    fn latency(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Format of the output for input in `input`
+    fn output_format(&self, input: StreamFormat) -> StreamFormat {
+        input
+    }
+}
+
+impl AudioProcessor for Box<dyn AudioProcessor> {
+    fn process(&mut self, chunk: AudioChunk) -> Result<Vec<AudioChunk>, AudioError> {
+        (**self).process(chunk)
+    }
+
+    fn flush(&mut self) -> Result<Vec<AudioChunk>, AudioError> {
+        (**self).flush()
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn latency(&self) -> Duration {
+        (**self).latency()
+    }
+
+    fn output_format(&self, input: StreamFormat) -> StreamFormat {
+        (**self).output_format(input)
+    }
+}
+
+// ============================================================================
+// Pipeline
+// ============================================================================
+
+/// Processors applied one after the other
+///
+/// The output of each stage is the input of the next. Flushing a pipeline
+/// flushes every stage in order, passing the tail of each one through the
+/// stages after it.
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn AudioProcessor>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a stage
+    pub fn with(mut self, processor: impl AudioProcessor) -> Self {
+        self.push(Box::new(processor));
+        self
+    }
+
+    /// Append a stage
+    pub fn push(&mut self, processor: Box<dyn AudioProcessor>) {
+        self.stages.push(processor);
+    }
+
+    /// Insert a stage at `index`, before the stage there
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is greater than the number of stages.
+    pub fn insert(&mut self, index: usize, processor: Box<dyn AudioProcessor>) {
+        self.stages.insert(index, processor);
+    }
+
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// The first stage of type `P`
+    ///
+    /// Gives access to the state of a stage after the pipeline was built,
+    /// for example to read the gain of an [`Agc`] or subscribe to the
+    /// events of a [`VoiceActivity`] stage.
+    pub fn find<P: AudioProcessor>(&self) -> Option<&P> {
+        self.stages
+            .iter()
+            .find_map(|stage| (stage.as_ref() as &dyn Any).downcast_ref())
+    }
+
+    /// The first stage of type `P`
+    pub fn find_mut<P: AudioProcessor>(&mut self) -> Option<&mut P> {
+        self.stages
+            .iter_mut()
+            .find_map(|stage| (stage.as_mut() as &mut dyn Any).downcast_mut())
+    }
+}
+
+impl AudioProcessor for Pipeline {
+    fn process(&mut self, chunk: AudioChunk) -> Result<Vec<AudioChunk>, AudioError> {
+        let mut chunks = vec![chunk];
+        for stage in &mut self.stages {
+            let mut output = Vec::with_capacity(chunks.len());
+            for chunk in chunks {
+                output.extend(stage.process(chunk)?);
+            }
+            chunks = output;
+        }
+        Ok(chunks)
+    }
+
+    fn flush(&mut self) -> Result<Vec<AudioChunk>, AudioError> {
+        // Each stage processes the tails of the stages before it, then its own
+        let mut chunks = Vec::new();
+        for stage in &mut self.stages {
+            let mut output = Vec::new();
+            for chunk in chunks {
+                output.extend(stage.process(chunk)?);
+            }
+            output.extend(stage.flush()?);
+            chunks = output;
+        }
+        Ok(chunks)
+    }
+
+    fn reset(&mut self) {
+        for stage in &mut self.stages {
+            stage.reset();
+        }
+    }
+
+    fn latency(&self) -> Duration {
+        self.stages.iter().map(|stage| stage.latency()).sum()
+    }
+
+    fn output_format(&self, input: StreamFormat) -> StreamFormat {
+        self.stages
+            .iter()
+            .fold(input, |format, stage| stage.output_format(format))
+    }
+}
+
+// ============================================================================
+// Gain
+// ============================================================================
+
+/// Fixed gain
+pub struct Gain {
+    gain_db: f32,
+    factor: f32,
+}
+
+impl Gain {
+    pub fn new(gain_db: f32) -> Self {
+        Self {
+            gain_db,
+            factor: db_to_linear(gain_db),
+        }
+    }
+
+    pub fn gain_db(&self) -> f32 {
+        self.gain_db
+    }
+
+    pub fn set_gain_db(&mut self, gain_db: f32) {
+        *self = Self::new(gain_db);
+    }
+}
+
+impl AudioProcessor for Gain {
+    fn process(&mut self, mut chunk: AudioChunk) -> Result<Vec<AudioChunk>, AudioError> {
+        for sample in &mut chunk.samples {
+            *sample *= self.factor;
+        }
+        Ok(vec![chunk])
+    }
+
+    fn reset(&mut self) {}
+}
+
+// ============================================================================
+// Pipeline description
+// ============================================================================
+
+/// Serializable description of one processor
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProcessorConfig {
+    /// Convert to a fixed sample rate
+    Resample { sample_rate: u32 },
+    /// Keep the stream in step with the capture clock
+    DriftCorrection,
+    /// Measure the drift against the capture clock, leaving the audio as is
+    DriftMeasurement,
+    /// Apply a fixed gain
+    Gain { gain_db: f32 },
+    /// Automatic gain control
+    Agc(AgcConfig),
+    /// Voice activity detection with an [`EnergyVad`]
+    Vad {
+        #[serde(default)]
+        config: VadConfig,
+        #[serde(default)]
+        detector: EnergyVadConfig,
+    },
+    /// Spectral noise suppression
+    NoiseSuppression(NoiseSuppressorConfig),
+    /// Level and loudness metering
+    Meter(MeterConfig),
+    /// Warnings about a broken capture
+    Health(HealthConfig),
+    /// Record the audio passing through to a WAV file
+    Record { path: PathBuf },
+}
+
+impl ProcessorConfig {
+    /// Create the processor described
+    pub fn build(&self) -> Box<dyn AudioProcessor> {
+        match self {
+            Self::Resample { sample_rate } => Box::new(Resample::new(*sample_rate)),
+            Self::DriftCorrection => Box::new(DriftCorrection::new()),
+            Self::DriftMeasurement => Box::new(DriftMeasurement::new()),
+            Self::Gain { gain_db } => Box::new(Gain::new(*gain_db)),
+            Self::Agc(config) => Box::new(Agc::new(config.clone())),
+            Self::Vad { config, detector } => Box::new(VoiceActivity::with_detector(
+                EnergyVad::new(detector.clone()),
+                config.clone(),
+            )),
+            Self::NoiseSuppression(config) => Box::new(NoiseSuppression::new(config.clone())),
+            Self::Meter(config) => Box::new(Meter::new(config.clone())),
+            Self::Health(config) => Box::new(HealthMonitor::new(config.clone())),
+            Self::Record { path } => Box::new(WavSink::new(path)),
+        }
+    }
+}
+
+/// Serializable description of a [`Pipeline`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct PipelineConfig {
+    /// Processors in the order audio flows through them
+    pub stages: Vec<ProcessorConfig>,
+}
+
+impl PipelineConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_stage(mut self, stage: ProcessorConfig) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    /// Create the pipeline described
+    pub fn build(&self) -> Pipeline {
+        let mut pipeline = Pipeline::new();
+        for stage in &self.stages {
+            pipeline.push(stage.build());
+        }
+        pipeline
+    }
+}
+
+// ============================================================================
+// ProcessedStream implementation
+// ============================================================================
+
+/// Adapter running an [`AudioProcessor`] over a stream
+///
+/// A [`AudioError::FormatChanged`] is passed on in terms of the output
+/// format, after flushing the processor, unless the processor hides the
+/// change: a resampler to a fixed rate absorbs rate changes. Other errors
+/// pass through; a fatal one, or the end of the stream, flushes the
+/// processor first. An error returned by the processor is delivered like
+/// one from the stream.
+pub struct ProcessedStream<S, P> {
+    inner: Pin<Box<S>>,
+    processor: P,
+    pending: VecDeque<Result<AudioChunk, AudioError>>,
+    ended: bool,
+}
+
+impl<S: AudioStream, P: AudioProcessor> ProcessedStream<S, P> {
+    pub fn new(inner: S, processor: P) -> Self {
+        Self {
+            inner: Box::pin(inner),
+            processor,
+            pending: VecDeque::new(),
+            ended: false,
+        }
+    }
+
+    /// The wrapped stream
+    pub fn get_ref(&self) -> &S {
+        self.inner.as_ref().get_ref()
+    }
+
+    pub fn processor(&self) -> &P {
+        &self.processor
+    }
+
+    pub fn processor_mut(&mut self) -> &mut P {
+        &mut self.processor
+    }
+
+    fn input_format(&self) -> StreamFormat {
+        StreamFormat {
+            sample_rate: self.inner.sample_rate(),
+            channels: self.inner.channels(),
+            buffer_frames: None,
+        }
+    }
+
+    fn deliver(&mut self, result: Result<Vec<AudioChunk>, AudioError>) {
+        match result {
+            Ok(chunks) => self.pending.extend(chunks.into_iter().map(Ok)),
+            Err(e) => {
+                self.ended |= !e.is_recoverable();
+                self.pending.push_back(Err(e));
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        let result = self.processor.flush();
+        self.deliver(result);
+    }
+
+    fn format_changed(&mut self, previous: StreamFormat, current: StreamFormat) {
+        let previous = self.processor.output_format(previous);
+        let current = self.processor.output_format(current);
+        if (previous.sample_rate, previous.channels) == (current.sample_rate, current.channels) {
+            return;
+        }
+
+        self.flush();
+        self.pending
+            .push_back(Err(AudioError::FormatChanged { previous, current }));
+    }
+}
+
+impl<S: AudioStream, P: AudioProcessor> AudioStream for ProcessedStream<S, P> {
+    fn sample_rate(&self) -> u32 {
+        self.processor.output_format(self.input_format()).sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.processor.output_format(self.input_format()).channels
+    }
+}
+
+impl<S: AudioStream, P: AudioProcessor> FuturesStream for ProcessedStream<S, P> {
+    type Item = Result<AudioChunk, AudioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.as_mut().get_mut();
+
+        loop {
+            if let Some(item) = this.pending.pop_front() {
+                return Poll::Ready(Some(item));
+            }
+
+            if this.ended {
+                return Poll::Ready(None);
+            }
+
+            match futures::ready!(this.inner.as_mut().poll_next(cx)) {
+                Some(Ok(chunk)) => {
+                    let result = this.processor.process(chunk);
+                    this.deliver(result);
+                }
+                Some(Err(AudioError::FormatChanged { previous, current })) => {
+                    this.format_changed(previous, current)
+                }
+                Some(Err(e)) => {
+                    if !e.is_recoverable() {
+                        this.flush();
+                        this.ended = true;
+                    }
+                    this.pending.push_back(Err(e));
+                }
+                None => {
+                    this.flush();
+                    this.ended = true;
+                }
+            }
+        }
+    }
+}
+
+// The inner stream is pinned on the heap
+impl<S, P> Unpin for ProcessedStream<S, P> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ChunkStream;
+    use futures::executor::block_on_stream;
+    use std::f64::consts::PI;
+
+    fn tone(t: f64) -> f32 {
+        (0.25 * (2.0 * PI * 440.0 * t).sin()) as f32
+    }
+
+    #[test]
+    fn test_pipeline_chains_stages_and_flushes_tail() {
+        let pipeline = Pipeline::new()
+            .with(Resample::new(16000))
+            .with(Gain::new(-6.0));
+        let stream = ProcessedStream::new(ChunkStream::new(48000, 1.0, 0.0, tone), pipeline);
+        assert_eq!(stream.sample_rate(), 16000);
+
+        let mut stream = block_on_stream(stream);
+        let chunks: Vec<AudioChunk> = stream.by_ref().map(Result::unwrap).collect();
+        assert!(chunks.iter().all(|chunk| chunk.sample_rate == 16000));
+        assert!(stream.into_inner().processor().latency() > Duration::ZERO);
+
+        // The resampler tail arrives at the end, through the gain stage
+        let frames: usize = chunks.iter().map(AudioChunk::frames).sum();
+        assert_eq!(frames, 16000);
+
+        let peak = chunks
+            .iter()
+            .flat_map(|chunk| &chunk.samples)
+            .fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!((peak - 0.125).abs() < 0.01, "peak {}", peak);
+    }
+
+    #[test]
+    fn test_config_round_trips_and_finds_stages() {
+        let config = PipelineConfig::new()
+            .with_stage(ProcessorConfig::DriftCorrection)
+            .with_stage(ProcessorConfig::Agc(AgcConfig::default()))
+            .with_stage(ProcessorConfig::Gain { gain_db: 3.0 });
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains(r#""type":"drift_correction""#), "{}", json);
+        let parsed: PipelineConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, config);
+
+        let mut pipeline = parsed.build();
+        assert!(pipeline.find::<Agc>().is_some());
+        assert!(pipeline.find::<Resample>().is_none());
+        pipeline.find_mut::<Gain>().unwrap().set_gain_db(0.0);
+        assert_eq!(pipeline.find::<Gain>().unwrap().gain_db(), 0.0);
+    }
+}
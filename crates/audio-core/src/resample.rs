@@ -0,0 +1,570 @@
+//! Streaming sample rate conversion
+//!
+//! [`Resampler`] is a band-limited windowed-sinc interpolator that keeps
+//! its filter history between calls, so audio can be fed in chunks of any
+//! size without discontinuities at chunk boundaries. [`Resample`] applies
+//! it to chunks as a pipeline stage, and [`ResampleStream`] wraps any
+//! [`AudioStream`] and delivers its chunks at a fixed rate, following input
+//! rate changes without a restart.
+
+use std::f64::consts::PI;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::Stream as FuturesStream;
+
+use crate::chunk::{frames_to_duration, AudioChunk};
+use crate::config::StreamFormat;
+use crate::error::AudioError;
+use crate::processor::{AudioProcessor, ProcessedStream};
+use crate::traits::AudioStream;
+
+/// Kernel table entries per zero crossing of the sinc
+const TABLE_RESOLUTION: usize = 128;
+
+/// Passband edge relative to the lower of the two Nyquist frequencies
+///
+/// Leaves room for the transition band so content near Nyquist does not
+/// alias back when downsampling.
+const CUTOFF: f64 = 0.95;
+
+/// Trade-off between filter length and conversion quality
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResampleQuality {
+    /// 8 zero crossings per side; enough for speech recognition
+    Fast,
+    /// 16 zero crossings per side
+    #[default]
+    Balanced,
+    /// 32 zero crossings per side; for archival recordings
+    High,
+}
+
+impl ResampleQuality {
+    fn zero_crossings(self) -> usize {
+        match self {
+            ResampleQuality::Fast => 8,
+            ResampleQuality::Balanced => 16,
+            ResampleQuality::High => 32,
+        }
+    }
+}
+
+/// Streaming windowed-sinc resampler for interleaved `f32` audio
+///
+/// The output frame `n` sits at input position `n * input_rate / output_rate`,
+/// so the first output frame lines up with the first input frame. Computing
+/// a frame needs input up to half a filter length past it; that lookahead
+/// stays buffered until more input arrives or [`Resampler::flush`] is called.
+pub struct Resampler {
+    input_rate: u32,
+    output_rate: u32,
+    channels: usize,
+    /// Input clock error compensated on top of the nominal input rate
+    drift_ppm: f64,
+    zero_crossings: usize,
+    /// One side of the Blackman-windowed sinc, sampled at [`TABLE_RESOLUTION`]
+    kernel: Vec<f32>,
+    /// Interleaved input frames that later output frames still depend on
+    history: Vec<f32>,
+    /// Input position of the next output frame, in frames into `history`
+    position: f64,
+    /// Scratch buffer for the weights of one output frame
+    weights: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32, channels: u16) -> Self {
+        Self::with_quality(input_rate, output_rate, channels, ResampleQuality::default())
+    }
+
+    pub fn with_quality(
+        input_rate: u32,
+        output_rate: u32,
+        channels: u16,
+        quality: ResampleQuality,
+    ) -> Self {
+        let zero_crossings = quality.zero_crossings();
+        let kernel = (0..=zero_crossings * TABLE_RESOLUTION + 1)
+            .map(|i| {
+                let x = i as f64 / TABLE_RESOLUTION as f64;
+                (sinc(x) * blackman(x / zero_crossings as f64)) as f32
+            })
+            .collect();
+
+        let mut resampler = Self {
+            input_rate: input_rate.max(1),
+            output_rate: output_rate.max(1),
+            channels: channels.max(1) as usize,
+            drift_ppm: 0.0,
+            zero_crossings,
+            kernel,
+            history: Vec::new(),
+            position: 0.0,
+            weights: Vec::new(),
+        };
+        resampler.reset();
+        resampler
+    }
+
+    pub fn input_rate(&self) -> u32 {
+        self.input_rate
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    /// Change the input rate without losing the buffered audio
+    ///
+    /// Samples already buffered keep their positions, so the transition is
+    /// continuous; only the few frames whose filter window straddles the
+    /// change are computed from a mix of both rates.
+    pub fn set_input_rate(&mut self, input_rate: u32) {
+        self.input_rate = input_rate.max(1);
+    }
+
+    /// Compensate an input clock running `ppm` parts per million fast
+    ///
+    /// A device with a nominal rate of 48 kHz that actually delivers 48048
+    /// frames per second runs 1000 ppm fast; converting with this correction
+    /// keeps the output in step with real time. Negative values compensate
+    /// a slow clock.
+    pub fn set_drift_correction(&mut self, ppm: f64) {
+        self.drift_ppm = ppm;
+    }
+
+    pub fn drift_correction(&self) -> f64 {
+        self.drift_ppm
+    }
+
+    /// Forget all buffered audio, as if newly created
+    pub fn reset(&mut self) {
+        // Zeros before the first frame keep the filter window in bounds
+        let padding = self.half_width().ceil() as usize;
+        self.history = vec![0.0; padding * self.channels];
+        self.position = padding as f64;
+    }
+
+    /// Input frames buffered at or after the position of the next output frame
+    ///
+    /// A chunk passed to [`Resampler::process`] starts this many input frames
+    /// after the first output frame it completes, which lets callers derive
+    /// output timestamps.
+    pub fn buffered_frames(&self) -> f64 {
+        (self.history.len() / self.channels) as f64 - self.position
+    }
+
+    /// Input frames of lookahead the filter needs before it can emit a frame
+    pub fn latency_frames(&self) -> usize {
+        self.half_width().ceil() as usize
+    }
+
+    /// Ratio of input to output frames
+    fn step(&self) -> f64 {
+        self.input_rate as f64 * (1.0 + self.drift_ppm * 1e-6) / self.output_rate as f64
+    }
+
+    /// Kernel stretch factor; below 1 when downsampling to lower the cutoff
+    fn scale(&self) -> f64 {
+        // At equal rates the kernel degenerates to a unit impulse
+        if self.input_rate == self.output_rate && self.drift_ppm == 0.0 {
+            return 1.0;
+        }
+
+        CUTOFF * (self.output_rate as f64 / self.input_rate as f64).min(1.0)
+    }
+
+    /// One side of the filter window, in input frames
+    fn half_width(&self) -> f64 {
+        self.zero_crossings as f64 / self.scale()
+    }
+
+    fn kernel_at(&self, x: f64) -> f32 {
+        let index = x.abs() * TABLE_RESOLUTION as f64;
+        let i = index as usize;
+        if i + 1 >= self.kernel.len() {
+            return 0.0;
+        }
+
+        let frac = (index - i as f64) as f32;
+        self.kernel[i] + (self.kernel[i + 1] - self.kernel[i]) * frac
+    }
+
+    /// Resample a block of interleaved input frames
+    ///
+    /// Returns every output frame that can be computed so far; the rest is
+    /// produced by later calls.
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        self.history.extend_from_slice(input);
+        self.drain()
+    }
+
+    /// Produce the remaining output by padding the input with silence
+    ///
+    /// Call once at the end of a stream. The resampler is reset afterwards.
+    pub fn flush(&mut self) -> Vec<f32> {
+        // Output frames are only owed up to the end of the real input
+        let end = (self.history.len() / self.channels) as f64;
+        let padding = self.latency_frames() + 1;
+        self.history.resize(self.history.len() + padding * self.channels, 0.0);
+
+        let mut output = Vec::new();
+        while self.position < end {
+            if !self.compute_frame(&mut output) {
+                break;
+            }
+        }
+
+        self.reset();
+        output
+    }
+
+    fn drain(&mut self) -> Vec<f32> {
+        let mut output = Vec::new();
+        while self.compute_frame(&mut output) {}
+
+        // Drop input frames no later output frame can reach
+        let first_needed = (self.position - self.half_width()).floor().max(0.0) as usize;
+        let frames = self.history.len() / self.channels;
+        let drop = first_needed.min(frames);
+        if drop > 0 {
+            self.history.drain(..drop * self.channels);
+            self.position -= drop as f64;
+        }
+
+        output
+    }
+
+    /// Append the output frame at the current position if its window is complete
+    fn compute_frame(&mut self, output: &mut Vec<f32>) -> bool {
+        let half_width = self.half_width();
+        let frames = self.history.len() / self.channels;
+        if self.position + half_width >= frames as f64 {
+            return false;
+        }
+
+        let scale = self.scale();
+        let first = (self.position - half_width).ceil().max(0.0) as usize;
+        let last = ((self.position + half_width).floor() as usize).min(frames - 1);
+
+        self.weights.clear();
+        let mut total = 0.0;
+        for frame in first..=last {
+            let weight = self.kernel_at((frame as f64 - self.position) * scale);
+            self.weights.push(weight);
+            total += weight;
+        }
+
+        // Normalizing keeps the DC gain at exactly 1 for every phase
+        let norm = if total.abs() > f32::EPSILON { 1.0 / total } else { 0.0 };
+
+        for channel in 0..self.channels {
+            let mut acc = 0.0;
+            for (i, weight) in self.weights.iter().enumerate() {
+                acc += weight * self.history[(first + i) * self.channels + channel];
+            }
+            output.push(acc * norm);
+        }
+
+        self.position += self.step();
+        true
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Blackman window over `x` in `[-1, 1]`
+fn blackman(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        return 0.0;
+    }
+
+    0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+}
+
+// ============================================================================
+// Resample processor
+// ============================================================================
+
+/// Processor delivering audio at a fixed sample rate
+///
+/// The resampler follows input rate changes without losing its buffered
+/// audio, and starts over when the channel count changes. Chunks carry
+/// the timestamps of the audio they hold.
+pub struct Resample {
+    output_rate: u32,
+    quality: ResampleQuality,
+    /// Created from the first chunk, which sets the input format
+    resampler: Option<Resampler>,
+    sequence: u64,
+    /// Timestamp just after the last delivered chunk
+    next_timestamp: Option<Duration>,
+}
+
+impl Resample {
+    pub fn new(output_rate: u32) -> Self {
+        Self::with_quality(output_rate, ResampleQuality::default())
+    }
+
+    pub fn with_quality(output_rate: u32, quality: ResampleQuality) -> Self {
+        Self {
+            output_rate: output_rate.max(1),
+            quality,
+            resampler: None,
+            sequence: 0,
+            next_timestamp: None,
+        }
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    /// Wrap `samples` in a chunk starting at `timestamp`
+    fn chunk(
+        &mut self,
+        samples: Vec<f32>,
+        channels: u16,
+        timestamp: Duration,
+        gap_fill: bool,
+    ) -> Vec<AudioChunk> {
+        if samples.is_empty() {
+            return Vec::new();
+        }
+
+        let mut chunk = AudioChunk::new(samples, self.output_rate, channels, timestamp, self.sequence);
+        chunk.gap_fill = gap_fill;
+        self.sequence += 1;
+        self.next_timestamp = Some(chunk.end_timestamp());
+        vec![chunk]
+    }
+}
+
+impl AudioProcessor for Resample {
+    fn process(&mut self, chunk: AudioChunk) -> Result<Vec<AudioChunk>, AudioError> {
+        // Audio buffered in another channel layout goes out first
+        let mut output = Vec::new();
+        if self.resampler.as_ref().map(Resampler::channels) != Some(chunk.channels) {
+            output = self.flush()?;
+            self.resampler = Some(Resampler::with_quality(
+                chunk.sample_rate,
+                self.output_rate,
+                chunk.channels,
+                self.quality,
+            ));
+        }
+        let resampler = self.resampler.as_mut().expect("resampler created above");
+
+        if chunk.sample_rate != resampler.input_rate() {
+            tracing::debug!(
+                before = resampler.input_rate(),
+                after = chunk.sample_rate,
+                "Resampler following input rate change"
+            );
+            resampler.set_input_rate(chunk.sample_rate);
+        }
+
+        // The first completed output frame lies this far before the chunk
+        let lead = Duration::from_secs_f64(
+            resampler.buffered_frames().max(0.0) / resampler.input_rate() as f64,
+        );
+        let timestamp = chunk.timestamp.saturating_sub(lead);
+
+        let samples = resampler.process(&chunk.samples);
+        output.extend(self.chunk(samples, chunk.channels, timestamp, chunk.gap_fill));
+        Ok(output)
+    }
+
+    /// Deliver the buffered tail, continuing from the last chunk
+    fn flush(&mut self) -> Result<Vec<AudioChunk>, AudioError> {
+        let Some(resampler) = self.resampler.as_mut() else {
+            return Ok(Vec::new());
+        };
+
+        let channels = resampler.channels();
+        let samples = resampler.flush();
+        let timestamp = self.next_timestamp.unwrap_or_default();
+        Ok(self.chunk(samples, channels, timestamp, false))
+    }
+
+    fn reset(&mut self) {
+        self.resampler = None;
+        self.sequence = 0;
+        self.next_timestamp = None;
+    }
+
+    fn latency(&self) -> Duration {
+        self.resampler.as_ref().map_or(Duration::ZERO, |resampler| {
+            frames_to_duration(resampler.latency_frames() as u64, resampler.input_rate())
+        })
+    }
+
+    fn output_format(&self, input: StreamFormat) -> StreamFormat {
+        StreamFormat {
+            sample_rate: self.output_rate,
+            ..input
+        }
+    }
+}
+
+// ============================================================================
+// ResampleStream implementation
+// ============================================================================
+
+/// Adapter delivering any [`AudioStream`] at a fixed sample rate
+///
+/// Input rate changes are absorbed: the [`AudioError::FormatChanged`]
+/// reporting them is not passed on, since the output rate stays the same.
+/// A change in channel count still is, after the audio buffered in the old
+/// layout has been delivered.
+pub struct ResampleStream<S> {
+    stream: ProcessedStream<S, Resample>,
+}
+
+impl<S: AudioStream> ResampleStream<S> {
+    pub fn new(inner: S, output_rate: u32) -> Self {
+        Self::with_quality(inner, output_rate, ResampleQuality::default())
+    }
+
+    pub fn with_quality(inner: S, output_rate: u32, quality: ResampleQuality) -> Self {
+        Self {
+            stream: ProcessedStream::new(inner, Resample::with_quality(output_rate, quality)),
+        }
+    }
+}
+
+impl<S: AudioStream> AudioStream for ResampleStream<S> {
+    fn sample_rate(&self) -> u32 {
+        self.stream.sample_rate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.stream.channels()
+    }
+}
+
+impl<S: AudioStream> FuturesStream for ResampleStream<S> {
+    type Item = Result<AudioChunk, AudioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.stream).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::frames_to_duration;
+    use crate::config::AudioInputConfig;
+    use crate::file::Pacing;
+    use crate::generator::{GeneratorInput, Signal};
+    use crate::traits::AudioInput;
+    use futures::StreamExt;
+
+    fn sine(frequency: f64, rate: u32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|n| (2.0 * PI * frequency * n as f64 / rate as f64).sin() as f32)
+            .collect()
+    }
+
+    #[test]
+    fn test_downsample_sine_matches_ideal() {
+        let input = sine(1000.0, 48000, 48000);
+        let mut resampler = Resampler::new(48000, 16000, 1);
+        let mut output = resampler.process(&input);
+        output.extend(resampler.flush());
+
+        assert_eq!(output.len(), 16000);
+
+        let ideal = sine(1000.0, 16000, 16000);
+        // Skip the edges, where the filter sees the silence around the input
+        let error = output[100..15900]
+            .iter()
+            .zip(&ideal[100..15900])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        assert!(error < 1e-3, "max error {}", error);
+    }
+
+    #[test]
+    fn test_chunking_does_not_change_output() {
+        let input = sine(440.0, 44100, 10000);
+
+        let mut whole = Resampler::new(44100, 48000, 1);
+        let mut expected = whole.process(&input);
+        expected.extend(whole.flush());
+
+        let mut chunked = Resampler::new(44100, 48000, 1);
+        let mut output = Vec::new();
+        for block in input.chunks(37) {
+            output.extend(chunked.process(block));
+        }
+        output.extend(chunked.flush());
+
+        assert_eq!(output.len(), expected.len());
+        assert!(output.iter().zip(&expected).all(|(a, b)| (a - b).abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_same_rate_is_identity() {
+        let input: Vec<f32> = sine(440.0, 16000, 500).iter().flat_map(|&s| [s, -s]).collect();
+        let mut resampler = Resampler::new(16000, 16000, 2);
+        let mut output = resampler.process(&input);
+        output.extend(resampler.flush());
+
+        assert_eq!(output.len(), input.len());
+        assert!(output.iter().zip(&input).all(|(a, b)| (a - b).abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_downsample_rejects_above_nyquist() {
+        // 12 kHz is above the 8 kHz Nyquist frequency of the output
+        let input = sine(12000.0, 48000, 48000);
+        let mut resampler = Resampler::new(48000, 16000, 1);
+        let output = resampler.process(&input);
+
+        let peak = output[100..].iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak < 0.01, "alias peak {}", peak);
+    }
+
+    #[tokio::test]
+    async fn test_stream_follows_rate_change() {
+        let config = AudioInputConfig::new().with_sample_rate(48000);
+        let input = GeneratorInput::with_config(&config)
+            .unwrap()
+            .with_signal(Signal::sine(440.0, 0.5))
+            .with_pacing(Pacing::Fast)
+            .with_duration(Duration::from_secs(2))
+            .with_rate_change(Duration::from_secs(1), 44100);
+
+        let stream = ResampleStream::new(input.stream().unwrap(), 16000);
+        assert_eq!(stream.sample_rate(), 16000);
+
+        // The rate change is absorbed rather than reported
+        let chunks: Vec<AudioChunk> = stream.map(Result::unwrap).collect().await;
+        assert!(chunks.iter().all(|chunk| chunk.sample_rate == 16000));
+        assert!(chunks.windows(2).all(|w| w[1].sequence == w[0].sequence + 1));
+
+        let frames: usize = chunks.iter().map(AudioChunk::frames).sum();
+        assert!(frames.abs_diff(32000) <= 2, "{} frames", frames);
+
+        // Timestamps stay on the input timeline across the change
+        let start = chunks[0].timestamp;
+        let last = chunks.last().unwrap();
+        let expected = start + frames_to_duration((frames - last.frames()) as u64, 16000);
+        let drift = last.timestamp.abs_diff(expected);
+        assert!(drift < Duration::from_millis(2), "drift {:?}", drift);
+    }
+}
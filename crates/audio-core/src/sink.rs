@@ -0,0 +1,387 @@
+//! Processors handing audio out of a pipeline
+//!
+//! A [`WavSink`] writes the audio passing through a pipeline to a WAV file
+//! and hands the chunks on unchanged, so recordings can be taken at any
+//! point of the chain: before processing for the raw signal, at the end
+//! for what downstream consumers get. A [`Tap`] does the same for other
+//! consumers, which read the audio as a [`TapStream`].
+
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::channel::mpsc;
+use futures::Stream as FuturesStream;
+
+use crate::chunk::AudioChunk;
+use crate::conversion::remix_channels;
+use crate::error::AudioError;
+use crate::processor::AudioProcessor;
+use crate::resample::Resampler;
+use crate::traits::AudioStream;
+
+type Writer = hound::WavWriter<BufWriter<File>>;
+
+/// Processor recording its input to a 32-bit float WAV file
+///
+/// The file is created, along with its directory, when the first chunk
+/// arrives and takes that chunk's format. Later chunks in another format,
+/// as from a device taking over mid-recording, are remixed to the file's
+/// channel count and resampled to its rate, so the recording goes on in
+/// one file. Recording problems are logged and stop the recording, never
+/// the audio. The file is completed when the sink is flushed, reset or
+/// dropped; audio after a flush is appended to it, while a reset starts the
+/// file over.
+pub struct WavSink {
+    path: PathBuf,
+    writer: Option<Writer>,
+    /// Sample rate and channel count of the file
+    format: Option<(u32, u16)>,
+    /// Sample rate and channel count of the last chunk
+    input: Option<(u32, u16)>,
+    /// Converts audio at another rate to the rate of the file
+    resampler: Option<Resampler>,
+    frames: u64,
+    failed: bool,
+}
+
+impl WavSink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            writer: None,
+            format: None,
+            input: None,
+            resampler: None,
+            frames: 0,
+            failed: false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Frames recorded so far
+    pub fn frames_written(&self) -> u64 {
+        self.frames
+    }
+
+    /// Create the file in the format of `chunk`, or reopen it after a flush
+    fn open(&mut self, chunk: &AudioChunk) -> Result<(u32, u16), hound::Error> {
+        if let Some(format) = self.format {
+            if self.writer.is_none() {
+                self.writer = Some(hound::WavWriter::append(&self.path)?);
+            }
+            return Ok(format);
+        }
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let spec = hound::WavSpec {
+            channels: chunk.channels,
+            sample_rate: chunk.sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        self.writer = Some(hound::WavWriter::create(&self.path, spec)?);
+
+        let format = (chunk.sample_rate, chunk.channels);
+        self.format = Some(format);
+        self.input = Some(format);
+        tracing::debug!(path = %self.path.display(), "Recording started");
+        Ok(format)
+    }
+
+    fn write(&mut self, chunk: &AudioChunk) -> Result<(), hound::Error> {
+        let (sample_rate, channels) = self.open(chunk)?;
+
+        let input = (chunk.sample_rate, chunk.channels);
+        if self.input != Some(input) {
+            tracing::info!(
+                path = %self.path.display(),
+                "Recording converts audio at {} Hz, {} channels to {} Hz, {} channels",
+                chunk.sample_rate,
+                chunk.channels,
+                sample_rate,
+                channels
+            );
+            self.input = Some(input);
+        }
+
+        if input == (sample_rate, channels) && self.resampler.is_none() {
+            return self.write_samples(&chunk.samples, channels);
+        }
+
+        let samples = remix_channels(&chunk.samples, chunk.channels as usize, channels as usize);
+        if chunk.sample_rate == sample_rate && self.resampler.is_none() {
+            return self.write_samples(&samples, channels);
+        }
+
+        // Once started, the resampler also carries audio back at the file
+        // rate, so the switch does not drop its buffered frames
+        let resampler = self
+            .resampler
+            .get_or_insert_with(|| Resampler::new(chunk.sample_rate, sample_rate, channels));
+        resampler.set_input_rate(chunk.sample_rate);
+        let samples = resampler.process(&samples);
+        self.write_samples(&samples, channels)
+    }
+
+    fn write_samples(&mut self, samples: &[f32], channels: u16) -> Result<(), hound::Error> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        for &sample in samples {
+            writer.write_sample(sample)?;
+        }
+        self.frames += (samples.len() / channels as usize) as u64;
+        Ok(())
+    }
+
+    /// Complete the file
+    fn finish(&mut self) {
+        if let (Some(mut resampler), Some((_, channels))) = (self.resampler.take(), self.format) {
+            let tail = resampler.flush();
+            if let Err(e) = self.write_samples(&tail, channels) {
+                tracing::warn!(path = %self.path.display(), "Recording left out converted audio: {}", e);
+            }
+        }
+
+        let Some(writer) = self.writer.take() else {
+            return;
+        };
+
+        match writer.finalize() {
+            Ok(()) => tracing::info!(
+                path = %self.path.display(),
+                frames = self.frames,
+                "Recording saved"
+            ),
+            Err(e) => tracing::error!(path = %self.path.display(), "Failed to finalize recording: {}", e),
+        }
+    }
+}
+
+impl AudioProcessor for WavSink {
+    fn process(&mut self, chunk: AudioChunk) -> Result<Vec<AudioChunk>, AudioError> {
+        if !self.failed {
+            if let Err(e) = self.write(&chunk) {
+                tracing::warn!(path = %self.path.display(), "Recording stopped: {}", e);
+                self.failed = true;
+                self.finish();
+            }
+        }
+        Ok(vec![chunk])
+    }
+
+    fn flush(&mut self) -> Result<Vec<AudioChunk>, AudioError> {
+        self.finish();
+        Ok(Vec::new())
+    }
+
+    fn reset(&mut self) {
+        self.finish();
+        self.format = None;
+        self.input = None;
+        self.frames = 0;
+        self.failed = false;
+    }
+}
+
+// ============================================================================
+// Tap
+// ============================================================================
+
+/// Format a [`TapStream`] reports before it has seen any audio
+const DEFAULT_TAP_FORMAT: (u32, u16) = (48000, 1);
+
+#[derive(Default)]
+struct TapShared {
+    subscribers: Vec<mpsc::UnboundedSender<AudioChunk>>,
+    /// Sample rate and channel count of the last chunk
+    format: Option<(u32, u16)>,
+}
+
+/// Source of [`TapStream`]s for the audio of [`Tap`] processors
+///
+/// A handle outlives the pipelines it taps: subscribers keep receiving
+/// when a pipeline is replaced by another one with a tap from the same
+/// handle, as when a capture is restarted.
+#[derive(Clone, Default)]
+pub struct TapHandle {
+    shared: Arc<Mutex<TapShared>>,
+}
+
+impl TapHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A processor handing its audio to the subscribers of this handle
+    pub fn tap(&self) -> Tap {
+        Tap {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Receive the audio of the taps from now on
+    ///
+    /// The stream ends once the handle and all its taps are dropped.
+    pub fn subscribe(&self) -> TapStream {
+        let (tx, rx) = mpsc::unbounded();
+        let mut shared = self.shared.lock().unwrap();
+        shared.subscribers.push(tx);
+
+        let (sample_rate, channels) = shared.format.unwrap_or(DEFAULT_TAP_FORMAT);
+        TapStream {
+            chunks: rx,
+            sample_rate,
+            channels,
+        }
+    }
+}
+
+/// Processor handing a copy of its audio to the subscribers of a
+/// [`TapHandle`]
+///
+/// Audio is only copied while someone is subscribed.
+pub struct Tap {
+    shared: Arc<Mutex<TapShared>>,
+}
+
+impl AudioProcessor for Tap {
+    fn process(&mut self, chunk: AudioChunk) -> Result<Vec<AudioChunk>, AudioError> {
+        let mut shared = self.shared.lock().unwrap();
+        shared.format = Some((chunk.sample_rate, chunk.channels));
+        shared
+            .subscribers
+            .retain(|tx| tx.unbounded_send(chunk.clone()).is_ok());
+        Ok(vec![chunk])
+    }
+
+    fn reset(&mut self) {}
+}
+
+/// Audio of the taps of a [`TapHandle`]
+///
+/// Reports the format of the last chunk it delivered; before the first
+/// one, the format last seen by the taps, or 48 kHz mono.
+pub struct TapStream {
+    chunks: mpsc::UnboundedReceiver<AudioChunk>,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl AudioStream for TapStream {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+}
+
+impl FuturesStream for TapStream {
+    type Item = Result<AudioChunk, AudioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let chunk = futures::ready!(Pin::new(&mut self.chunks).poll_next(cx));
+        if let Some(chunk) = &chunk {
+            self.sample_rate = chunk.sample_rate;
+            self.channels = chunk.channels;
+        }
+        Poll::Ready(chunk.map(Ok))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::config::AudioInputConfig;
+    use crate::file::Pacing;
+    use crate::generator::{GeneratorInput, Signal};
+    use crate::processor::ProcessedStream;
+    use crate::traits::AudioInput;
+
+    #[test]
+    fn test_records_first_format_and_passes_audio_on() {
+        let path = std::env::temp_dir()
+            .join(format!("heronote-sink-{}", std::process::id()))
+            .join("mic.wav");
+        let mut sink = WavSink::new(&path);
+
+        let stereo = AudioChunk::new(vec![0.5, -0.5, 0.25, -0.25], 16000, 2, Duration::ZERO, 0);
+        let output = sink.process(stereo.clone()).unwrap();
+        assert_eq!(output[0].samples, stereo.samples);
+
+        // A mono chunk is copied into both channels of the stereo file
+        let mono = AudioChunk::new(vec![1.0; 3], 16000, 1, Duration::ZERO, 1);
+        assert_eq!(sink.process(mono.clone()).unwrap(), vec![mono]);
+        sink.flush().unwrap();
+        assert_eq!(sink.frames_written(), 5);
+
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        let samples: Vec<f32> = reader.samples().map(Result::unwrap).collect();
+        assert_eq!(samples[..4], stereo.samples);
+        assert_eq!(samples[4..], [1.0; 6]);
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_records_across_format_change() {
+        let path = std::env::temp_dir()
+            .join(format!("heronote-sink-change-{}", std::process::id()))
+            .join("mic.wav");
+        let config = AudioInputConfig::new().with_sample_rate(48000);
+        let input = GeneratorInput::with_config(&config)
+            .unwrap()
+            .with_signal(Signal::sine(440.0, 0.5))
+            .with_pacing(Pacing::Fast)
+            .with_duration(Duration::from_secs(1))
+            .with_rate_change(Duration::from_millis(500), 16000);
+
+        let stream = ProcessedStream::new(input.stream().unwrap(), WavSink::new(&path));
+        let changes = futures::executor::block_on_stream(stream)
+            .filter(|item| matches!(item, Err(AudioError::FormatChanged { .. })))
+            .count();
+        assert_eq!(changes, 1);
+
+        // The 16 kHz half second is resampled into the 48 kHz file
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().sample_rate, 48000);
+        let samples: Vec<f32> = reader.samples().map(Result::unwrap).collect();
+        assert!(samples.len().abs_diff(48000) <= 1, "{} frames", samples.len());
+        assert!(samples[36000..].iter().any(|&s| s.abs() > 0.4));
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_tap_reaches_subscribers_across_taps() {
+        let handle = TapHandle::new();
+        let mut first = handle.tap();
+        let mut stream = futures::executor::block_on_stream(handle.subscribe());
+
+        let chunk = AudioChunk::new(vec![0.5; 320], 16000, 2, Duration::ZERO, 0);
+        assert_eq!(first.process(chunk.clone()).unwrap(), vec![chunk.clone()]);
+        drop(first);
+
+        // A tap replacing the first one feeds the same subscriber
+        handle.tap().process(chunk.clone()).unwrap();
+        assert_eq!(stream.next().unwrap().unwrap(), chunk);
+        assert_eq!(stream.next().unwrap().unwrap(), chunk);
+        assert_eq!(stream.sample_rate(), 16000);
+        assert_eq!(stream.channels(), 2);
+    }
+}
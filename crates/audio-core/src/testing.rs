@@ -0,0 +1,69 @@
+//! Helpers shared by the unit tests
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::Stream as FuturesStream;
+
+use crate::chunk::AudioChunk;
+use crate::error::AudioError;
+use crate::traits::AudioStream;
+
+/// Stream replaying prepared chunks
+pub struct ChunkStream {
+    chunks: VecDeque<AudioChunk>,
+    sample_rate: u32,
+}
+
+impl ChunkStream {
+    /// `seconds` of 10 ms mono chunks from `signal`, timestamped by a clock
+    /// running `ppm` fast
+    ///
+    /// `signal` is evaluated at the true capture time of every sample.
+    pub fn new(sample_rate: u32, seconds: f64, ppm: f64, signal: impl Fn(f64) -> f32) -> Self {
+        let actual = sample_rate as f64 * (1.0 + ppm * 1e-6);
+        let chunk = sample_rate as usize / 100;
+        let total = (seconds * sample_rate as f64) as usize;
+
+        let chunks = (0..total / chunk)
+            .map(|i| {
+                let first = i * chunk;
+                let samples = (first..first + chunk)
+                    .map(|n| signal(n as f64 / actual))
+                    .collect();
+                let timestamp = Duration::from_secs(1) + Duration::from_secs_f64(first as f64 / actual);
+                AudioChunk::new(samples, sample_rate, 1, timestamp, i as u64)
+            })
+            .collect();
+
+        Self { chunks, sample_rate }
+    }
+
+    /// Drop the chunks covering `length` of audio from `at`
+    pub fn with_dropout(mut self, at: Duration, length: Duration) -> Self {
+        let start = self.chunks.front().map(|chunk| chunk.timestamp).unwrap_or_default() + at;
+        self.chunks
+            .retain(|chunk| chunk.timestamp < start || chunk.timestamp >= start + length);
+        self
+    }
+}
+
+impl FuturesStream for ChunkStream {
+    type Item = Result<AudioChunk, AudioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(self.chunks.pop_front().map(Ok))
+    }
+}
+
+impl AudioStream for ChunkStream {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+}
@@ -1,3 +1,5 @@
+use crate::chunk::AudioChunk;
+use crate::config::{AudioInputConfig, StreamFormat};
 use crate::error::AudioError;
 
 /// Trait for audio input sources (microphone, speaker loopback)
@@ -5,17 +7,41 @@ pub trait AudioInput: Sized {
     type Stream: AudioStream;
 
     /// Create a new audio input with default device
-    fn new() -> Result<Self, AudioError>;
+    fn new() -> Result<Self, AudioError> {
+        Self::with_config(&AudioInputConfig::default())
+    }
+
+    /// Create an audio input from a requested configuration
+    ///
+    /// The backend negotiates the closest configuration the device supports;
+    /// [`AudioInput::format`] reports what was actually obtained.
+    fn with_config(config: &AudioInputConfig) -> Result<Self, AudioError>;
+
+    /// Get the negotiated stream format
+    fn format(&self) -> StreamFormat;
 
     /// Get the sample rate in Hz
-    fn sample_rate(&self) -> u32;
+    fn sample_rate(&self) -> u32 {
+        self.format().sample_rate
+    }
 
     /// Start capturing and return an audio stream
     fn stream(self) -> Result<Self::Stream, AudioError>;
 }
 
-/// Trait for audio streams that produce samples
-pub trait AudioStream: futures::Stream<Item = Vec<f32>> {
+/// Trait for audio streams that produce timestamped chunks of samples
+///
+/// Chunks carry consecutive sequence numbers and capture timestamps on the
+/// shared [`crate::capture_clock_now`] clock, so streams from different
+/// inputs can be aligned and lost buffers detected downstream.
+///
+/// Problems during capture are delivered in-band as `Err` items. After an
+/// error that is not [`AudioError::is_recoverable`], such as
+/// [`AudioError::DeviceDisconnected`], the stream ends.
+pub trait AudioStream: futures::Stream<Item = Result<AudioChunk, AudioError>> {
     /// Get the sample rate of this stream
     fn sample_rate(&self) -> u32;
+
+    /// Get the number of interleaved channels in every chunk
+    fn channels(&self) -> u16;
 }
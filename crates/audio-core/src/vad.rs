@@ -0,0 +1,750 @@
+//! Voice activity detection
+//!
+//! Detection happens in two steps. A [`VoiceActivityDetector`] turns audio
+//! into a speech probability for every analysis frame; [`EnergyVad`] does
+//! this by comparing frame energy against an adaptive estimate of the
+//! background noise, and other backends plug in through the same trait. A
+//! [`SpeechSegmenter`] then turns the probabilities into speech boundaries:
+//! a run of speech frames longer than the attack time starts speech, and a
+//! pause longer than the hangover time ends it. Boundaries are reported as
+//! [`VadEvent`]s carrying their exact frame position, so callers can cut the
+//! audio at the boundaries even though the events themselves arrive after
+//! the attack or hangover has elapsed.
+//!
+//! [`VoiceActivity`] runs both steps as a pipeline stage, and [`VadStream`]
+//! over an [`AudioStream`], while passing the chunks through unchanged.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::channel::mpsc;
+use futures::{Stream as FuturesStream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::{duration_to_frames, AudioChunk, FrameClock};
+use crate::error::AudioError;
+use crate::processor::{AudioProcessor, ProcessedStream};
+use crate::traits::AudioStream;
+
+/// Level assumed for a frame of digital silence, in dBFS
+const SILENCE_DB: f32 = -120.0;
+
+/// Smoothing factor applied when the energy drops below the noise floor
+///
+/// The floor follows quiet passages within a few frames, so it recovers
+/// quickly from starting on speech or from a noise source going away.
+const FLOOR_FALL: f32 = 0.2;
+
+/// Level difference in dB around the threshold over which the energy
+/// probability goes from about 0.27 to 0.73
+const PROBABILITY_SLOPE_DB: f32 = 2.0;
+
+/// Speech probability of one analysis frame
+///
+/// Positions count the frames of the analysed stream from its first chunk,
+/// whatever rate the detector works at internally, so detectors can be
+/// compared frame by frame on the same recording.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VadFrame {
+    /// First frame covered
+    pub frame: u64,
+    /// Number of frames covered
+    pub frames: u64,
+    /// Probability that the frame contains speech, from 0 to 1
+    pub probability: f32,
+}
+
+impl VadFrame {
+    /// Frame just after the ones covered
+    pub fn end(&self) -> u64 {
+        self.frame + self.frames
+    }
+}
+
+/// Detector turning audio into per-frame speech probabilities
+///
+/// Implementations may buffer audio, so a chunk can complete any number of
+/// analysis frames, including frames that started in earlier chunks.
+pub trait VoiceActivityDetector {
+    /// Analyse `chunk` and return the analysis frames it completes
+    fn process(&mut self, chunk: &AudioChunk) -> Result<Vec<VadFrame>, AudioError>;
+
+    /// Forget all audio seen so far
+    fn reset(&mut self);
+}
+
+impl<D: VoiceActivityDetector + ?Sized> VoiceActivityDetector for Box<D> {
+    fn process(&mut self, chunk: &AudioChunk) -> Result<Vec<VadFrame>, AudioError> {
+        (**self).process(chunk)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+}
+
+/// Run `detector` over a whole stream and collect its analysis frames
+///
+/// Meant for comparing detectors offline: replay the same recording through
+/// a [`FileInput`](crate::FileInput) with [`Pacing::Fast`](crate::Pacing::Fast)
+/// once per detector and compare the probabilities frame by frame.
+///
+/// ```no_run
+/// # use heronote_audio_core::*;
+/// # async fn compare(other: &mut impl VoiceActivityDetector) -> Result<(), AudioError> {
+/// let open = || FileInput::open("meeting.wav").map(|input| input.with_pacing(Pacing::Fast));
+/// let energy = detect_speech(open()?.stream()?, &mut EnergyVad::default()).await?;
+/// let model = detect_speech(open()?.stream()?, other).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn detect_speech<S, D>(stream: S, detector: &mut D) -> Result<Vec<VadFrame>, AudioError>
+where
+    S: AudioStream,
+    D: VoiceActivityDetector + ?Sized,
+{
+    let mut stream = Box::pin(stream);
+    let mut frames = Vec::new();
+
+    while let Some(item) = stream.next().await {
+        match item {
+            Ok(chunk) => frames.extend(detector.process(&chunk)?),
+            Err(e) if e.is_recoverable() => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(frames)
+}
+
+// ============================================================================
+// Energy detector
+// ============================================================================
+
+/// Tuning of an [`EnergyVad`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct EnergyVadConfig {
+    /// Length of the analysis frames
+    pub frame: Duration,
+    /// How far above the noise floor a frame must be to count as speech, in dB
+    pub threshold_db: f32,
+    /// Frames quieter than this never count as speech, in dBFS
+    pub min_level_db: f32,
+    /// Time constant with which the noise floor rises to louder backgrounds
+    pub noise_adaptation: Duration,
+}
+
+impl Default for EnergyVadConfig {
+    fn default() -> Self {
+        Self {
+            frame: Duration::from_millis(10),
+            threshold_db: 9.0,
+            min_level_db: -55.0,
+            noise_adaptation: Duration::from_secs(5),
+        }
+    }
+}
+
+impl EnergyVadConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_frame(mut self, frame: Duration) -> Self {
+        self.frame = frame;
+        self
+    }
+
+    pub fn with_threshold_db(mut self, threshold_db: f32) -> Self {
+        self.threshold_db = threshold_db;
+        self
+    }
+
+    pub fn with_min_level_db(mut self, min_level_db: f32) -> Self {
+        self.min_level_db = min_level_db;
+        self
+    }
+
+    pub fn with_noise_adaptation(mut self, noise_adaptation: Duration) -> Self {
+        self.noise_adaptation = noise_adaptation;
+        self
+    }
+}
+
+/// Voice activity detector comparing frame energy to the noise floor
+///
+/// The probability is 0.5 for a frame exactly at the threshold and
+/// approaches 1 within a few dB above it.
+pub struct EnergyVad {
+    config: EnergyVadConfig,
+    sample_rate: u32,
+    /// Analysis frame length at `sample_rate`
+    frame_len: u64,
+    /// Frames seen so far
+    position: u64,
+    /// Energy and length of the analysis frame being filled
+    energy: f64,
+    filled: u64,
+    /// Level of the last complete analysis frame in dBFS
+    level_db: f32,
+    noise_floor_db: Option<f32>,
+}
+
+impl Default for EnergyVad {
+    fn default() -> Self {
+        Self::new(EnergyVadConfig::default())
+    }
+}
+
+impl EnergyVad {
+    pub fn new(config: EnergyVadConfig) -> Self {
+        Self {
+            config,
+            sample_rate: 0,
+            frame_len: 1,
+            position: 0,
+            energy: 0.0,
+            filled: 0,
+            level_db: SILENCE_DB,
+            noise_floor_db: None,
+        }
+    }
+
+    pub fn config(&self) -> &EnergyVadConfig {
+        &self.config
+    }
+
+    /// Level of the most recent analysis frame, in dBFS
+    pub fn level_db(&self) -> f32 {
+        self.level_db
+    }
+
+    /// Current estimate of the background noise level, in dBFS
+    pub fn noise_floor_db(&self) -> Option<f32> {
+        self.noise_floor_db
+    }
+
+    /// Speech probability of the analysis frame just completed
+    fn analyse(&mut self, energy: f64) -> f32 {
+        let level = if energy > 0.0 {
+            (10.0 * energy.log10()).max(SILENCE_DB as f64) as f32
+        } else {
+            SILENCE_DB
+        };
+        self.level_db = level;
+
+        let floor = *self.noise_floor_db.get_or_insert(level);
+        let threshold = (floor + self.config.threshold_db).max(self.config.min_level_db);
+        let probability = 1.0 / (1.0 + (-(level - threshold) / PROBABILITY_SLOPE_DB).exp());
+
+        self.update_noise_floor(level, probability >= 0.5);
+        probability
+    }
+
+    /// Follow quieter backgrounds quickly and louder ones slowly
+    ///
+    /// Loud frames do not raise the floor, so speech is not mistaken for
+    /// noise however long it lasts.
+    fn update_noise_floor(&mut self, level: f32, voiced: bool) {
+        let Some(floor) = self.noise_floor_db.as_mut() else {
+            return;
+        };
+
+        if level < *floor {
+            *floor += (level - *floor) * FLOOR_FALL;
+        } else if !voiced {
+            let rise = self.config.frame.as_secs_f32() / self.config.noise_adaptation.as_secs_f32().max(f32::EPSILON);
+            *floor += (level - *floor) * rise.min(1.0);
+        }
+    }
+}
+
+impl VoiceActivityDetector for EnergyVad {
+    fn process(&mut self, chunk: &AudioChunk) -> Result<Vec<VadFrame>, AudioError> {
+        if chunk.sample_rate != self.sample_rate {
+            self.sample_rate = chunk.sample_rate;
+            self.frame_len = duration_to_frames(self.config.frame, chunk.sample_rate).max(1);
+            self.energy = 0.0;
+            self.filled = 0;
+        }
+
+        let mut frames = Vec::new();
+        let channels = chunk.channels.max(1) as usize;
+
+        for frame in chunk.samples.chunks_exact(channels) {
+            self.energy += frame.iter().map(|&s| (s * s) as f64).sum::<f64>() / channels as f64;
+            self.filled += 1;
+            self.position += 1;
+
+            if self.filled == self.frame_len {
+                let energy = self.energy / self.filled as f64;
+                frames.push(VadFrame {
+                    frame: self.position - self.filled,
+                    frames: self.filled,
+                    probability: self.analyse(energy),
+                });
+                self.energy = 0.0;
+                self.filled = 0;
+            }
+        }
+
+        Ok(frames)
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new(self.config.clone());
+    }
+}
+
+// ============================================================================
+// Speech segmentation
+// ============================================================================
+
+/// Tuning of a [`SpeechSegmenter`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct VadConfig {
+    /// Probability from which a frame counts as speech
+    pub threshold: f32,
+    /// How long speech must last before it is reported
+    pub attack: Duration,
+    /// How long a pause must last before speech is reported as ended
+    pub hangover: Duration,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.5,
+            attack: Duration::from_millis(30),
+            hangover: Duration::from_millis(300),
+        }
+    }
+}
+
+impl VadConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_attack(mut self, attack: Duration) -> Self {
+        self.attack = attack;
+        self
+    }
+
+    pub fn with_hangover(mut self, hangover: Duration) -> Self {
+        self.hangover = hangover;
+        self
+    }
+}
+
+/// Speech boundary found by a [`SpeechSegmenter`]
+///
+/// `frame` counts the frames of the stream, starting at 0 with the first
+/// chunk; `timestamp` is the capture time of that frame.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum VadEvent {
+    /// Speech starts at the first frame of the first speech analysis frame
+    SpeechStart { frame: u64, timestamp: Duration },
+    /// Speech ends after the last frame of the last speech analysis frame
+    SpeechEnd { frame: u64, timestamp: Duration },
+}
+
+impl VadEvent {
+    /// Position of the boundary
+    pub fn frame(&self) -> u64 {
+        match self {
+            Self::SpeechStart { frame, .. } | Self::SpeechEnd { frame, .. } => *frame,
+        }
+    }
+
+    /// Capture time of the boundary
+    pub fn timestamp(&self) -> Duration {
+        match self {
+            Self::SpeechStart { timestamp, .. } | Self::SpeechEnd { timestamp, .. } => *timestamp,
+        }
+    }
+}
+
+/// Turns per-frame speech probabilities into speech boundaries
+pub struct SpeechSegmenter {
+    config: VadConfig,
+    sample_rate: u32,
+    /// Attack and hangover lengths at `sample_rate`
+    attack_len: u64,
+    hangover_len: u64,
+    /// Frames seen so far
+    position: u64,
+    clock: FrameClock,
+    speaking: bool,
+    /// First frame of the current run of speech frames, while silent
+    onset: Option<u64>,
+    /// Frame just after the last speech frame, while speaking
+    last_voiced: u64,
+}
+
+impl SpeechSegmenter {
+    pub fn new(config: VadConfig) -> Self {
+        Self {
+            config,
+            sample_rate: 0,
+            attack_len: 0,
+            hangover_len: 0,
+            position: 0,
+            clock: FrameClock::default(),
+            speaking: false,
+            onset: None,
+            last_voiced: 0,
+        }
+    }
+
+    pub fn config(&self) -> &VadConfig {
+        &self.config
+    }
+
+    /// Whether speech is currently in progress
+    pub fn is_speech(&self) -> bool {
+        self.speaking
+    }
+
+    /// Forget all audio seen so far
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config.clone());
+    }
+
+    /// Return the speech boundaries completed by `frames`, the analysis
+    /// frames a detector returned for `chunk`
+    pub fn process(&mut self, chunk: &AudioChunk, frames: &[VadFrame]) -> Vec<VadEvent> {
+        if chunk.sample_rate != self.sample_rate {
+            self.sample_rate = chunk.sample_rate;
+            self.attack_len = duration_to_frames(self.config.attack, chunk.sample_rate);
+            self.hangover_len = duration_to_frames(self.config.hangover, chunk.sample_rate);
+        }
+        self.clock.anchor(self.position, chunk.timestamp);
+        self.position += chunk.frames() as u64;
+
+        frames.iter().filter_map(|frame| self.push(frame)).collect()
+    }
+
+    /// End speech in progress, at the end of a stream
+    pub fn flush(&mut self) -> Option<VadEvent> {
+        self.onset = None;
+
+        if !self.speaking {
+            return None;
+        }
+        self.speaking = false;
+        Some(VadEvent::SpeechEnd {
+            frame: self.last_voiced,
+            timestamp: self.timestamp_of(self.last_voiced),
+        })
+    }
+
+    fn push(&mut self, frame: &VadFrame) -> Option<VadEvent> {
+        let voiced = frame.probability >= self.config.threshold;
+
+        if self.speaking {
+            if voiced {
+                self.last_voiced = frame.end();
+            } else if frame.end().saturating_sub(self.last_voiced) >= self.hangover_len {
+                self.speaking = false;
+                return Some(VadEvent::SpeechEnd {
+                    frame: self.last_voiced,
+                    timestamp: self.timestamp_of(self.last_voiced),
+                });
+            }
+            return None;
+        }
+
+        if !voiced {
+            self.onset = None;
+            return None;
+        }
+
+        let onset = *self.onset.get_or_insert(frame.frame);
+        if frame.end() - onset < self.attack_len.max(1) {
+            return None;
+        }
+
+        self.speaking = true;
+        self.onset = None;
+        self.last_voiced = frame.end();
+        Some(VadEvent::SpeechStart {
+            frame: onset,
+            timestamp: self.timestamp_of(onset),
+        })
+    }
+
+    fn timestamp_of(&self, frame: u64) -> Duration {
+        self.clock.timestamp_of(frame, self.sample_rate)
+    }
+}
+
+// ============================================================================
+// VoiceActivity processor
+// ============================================================================
+
+/// Processor running voice activity detection
+///
+/// Chunks pass through unchanged; speech boundaries are delivered to the
+/// receiver returned by [`VoiceActivity::subscribe`]. Speech still in
+/// progress when the processor is flushed is closed at its last speech
+/// frame. A detector failure only stops detection, never the audio.
+pub struct VoiceActivity<D = EnergyVad> {
+    detector: Option<D>,
+    segmenter: SpeechSegmenter,
+    events: Option<mpsc::UnboundedSender<VadEvent>>,
+}
+
+impl VoiceActivity {
+    /// Detect speech with an [`EnergyVad`] in its default configuration
+    pub fn new(config: VadConfig) -> Self {
+        Self::with_detector(EnergyVad::default(), config)
+    }
+}
+
+impl<D: VoiceActivityDetector> VoiceActivity<D> {
+    pub fn with_detector(detector: D, config: VadConfig) -> Self {
+        Self {
+            detector: Some(detector),
+            segmenter: SpeechSegmenter::new(config),
+            events: None,
+        }
+    }
+
+    /// Receive [`VadEvent`]s from now on
+    ///
+    /// Only the most recent receiver gets events.
+    pub fn subscribe(&mut self) -> mpsc::UnboundedReceiver<VadEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.events = Some(tx);
+        rx
+    }
+
+    /// Whether speech is currently in progress
+    pub fn is_speech(&self) -> bool {
+        self.segmenter.is_speech()
+    }
+
+    /// The detector, unless it failed
+    pub fn detector(&self) -> Option<&D> {
+        self.detector.as_ref()
+    }
+
+    fn analyse(&mut self, chunk: &AudioChunk) {
+        let Some(detector) = self.detector.as_mut() else {
+            return;
+        };
+
+        match detector.process(chunk) {
+            Ok(frames) => {
+                let events = self.segmenter.process(chunk, &frames);
+                self.notify(events);
+            }
+            Err(e) => {
+                tracing::warn!("Voice activity detection stopped: {}", e);
+                self.detector = None;
+                self.finish();
+            }
+        }
+    }
+
+    fn notify(&mut self, events: impl IntoIterator<Item = VadEvent>) {
+        for event in events {
+            if let Some(tx) = &self.events {
+                if tx.unbounded_send(event).is_err() {
+                    self.events = None;
+                }
+            }
+        }
+    }
+
+    /// Close speech in progress
+    fn finish(&mut self) {
+        let event = self.segmenter.flush();
+        self.notify(event);
+    }
+}
+
+impl<D: VoiceActivityDetector + Send + 'static> AudioProcessor for VoiceActivity<D> {
+    fn process(&mut self, chunk: AudioChunk) -> Result<Vec<AudioChunk>, AudioError> {
+        self.analyse(&chunk);
+        Ok(vec![chunk])
+    }
+
+    fn flush(&mut self) -> Result<Vec<AudioChunk>, AudioError> {
+        self.finish();
+        Ok(Vec::new())
+    }
+
+    fn reset(&mut self) {
+        if let Some(detector) = self.detector.as_mut() {
+            detector.reset();
+        }
+        self.segmenter.reset();
+    }
+}
+
+// ============================================================================
+// VadStream implementation
+// ============================================================================
+
+/// Adapter running voice activity detection over a stream
+///
+/// Chunks and errors pass through unchanged; see [`VoiceActivity`] for how
+/// speech boundaries are delivered. Speech in progress is closed when the
+/// stream ends or changes format.
+pub struct VadStream<S, D = EnergyVad> {
+    stream: ProcessedStream<S, VoiceActivity<D>>,
+}
+
+impl<S: AudioStream> VadStream<S> {
+    /// Detect speech with an [`EnergyVad`] in its default configuration
+    pub fn new(inner: S, config: VadConfig) -> Self {
+        Self::with_detector(inner, EnergyVad::default(), config)
+    }
+}
+
+impl<S: AudioStream, D: VoiceActivityDetector + Send + 'static> VadStream<S, D> {
+    pub fn with_detector(inner: S, detector: D, config: VadConfig) -> Self {
+        Self {
+            stream: ProcessedStream::new(inner, VoiceActivity::with_detector(detector, config)),
+        }
+    }
+
+    /// Receive [`VadEvent`]s from now on
+    ///
+    /// Only the most recent receiver gets events.
+    pub fn subscribe(&mut self) -> mpsc::UnboundedReceiver<VadEvent> {
+        self.stream.processor_mut().subscribe()
+    }
+
+    /// Whether speech is currently in progress
+    pub fn is_speech(&self) -> bool {
+        self.stream.processor().is_speech()
+    }
+
+    /// The detector, unless it failed
+    pub fn detector(&self) -> Option<&D> {
+        self.stream.processor().detector()
+    }
+}
+
+impl<S: AudioStream, D: VoiceActivityDetector + Send + 'static> AudioStream for VadStream<S, D> {
+    fn sample_rate(&self) -> u32 {
+        self.stream.sample_rate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.stream.channels()
+    }
+}
+
+impl<S: AudioStream, D: VoiceActivityDetector + Send + 'static> FuturesStream for VadStream<S, D> {
+    type Item = Result<AudioChunk, AudioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.stream).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ChunkStream;
+
+    /// Noise at -60 dBFS with a tone at -20 dBFS from 1.0 s to 2.5 s,
+    /// interrupted by a 100 ms pause at 1.8 s
+    fn speech(t: f64) -> f32 {
+        let noise = ((t * 7919.0).sin() * 43758.5453).fract() as f32 * 0.002;
+        let talking = (1.0..2.5).contains(&t) && !(1.8..1.9).contains(&t);
+        if talking {
+            noise + 0.14 * (t * 2.0 * std::f64::consts::PI * 220.0).sin() as f32
+        } else {
+            noise
+        }
+    }
+
+    /// Run an [`EnergyVad`] and a segmenter over four seconds of [`speech`]
+    fn segment(config: VadConfig) -> (Vec<VadEvent>, SpeechSegmenter) {
+        let mut vad = EnergyVad::default();
+        let mut segmenter = SpeechSegmenter::new(config);
+        let stream = ChunkStream::new(16000, 4.0, 0.0, speech);
+
+        let mut events = Vec::new();
+        for chunk in futures::executor::block_on_stream(stream) {
+            let chunk = chunk.unwrap();
+            let frames = vad.process(&chunk).unwrap();
+            events.extend(segmenter.process(&chunk, &frames));
+        }
+        (events, segmenter)
+    }
+
+    #[test]
+    fn test_detects_speech_boundaries() {
+        let (events, mut segmenter) = segment(VadConfig::default());
+        assert!(segmenter.flush().is_none());
+
+        // The pause is shorter than the hangover, so speech continues
+        assert_eq!(events.len(), 2, "{:?}", events);
+        let VadEvent::SpeechStart { frame: start, timestamp } = events[0] else {
+            panic!("expected speech start, got {:?}", events[0]);
+        };
+        assert!(start.abs_diff(16000) <= 160, "start at {}", start);
+        assert_eq!(timestamp, Duration::from_secs(1) + Duration::from_micros(start * 1_000_000 / 16000));
+
+        let VadEvent::SpeechEnd { frame: end, .. } = events[1] else {
+            panic!("expected speech end, got {:?}", events[1]);
+        };
+        assert!(end.abs_diff(40000) <= 160, "end at {}", end);
+    }
+
+    #[test]
+    fn test_short_hangover_splits_on_pause() {
+        let (events, _) = segment(VadConfig::default().with_hangover(Duration::from_millis(50)));
+
+        let starts: Vec<u64> = events
+            .iter()
+            .filter(|e| matches!(e, VadEvent::SpeechStart { .. }))
+            .map(VadEvent::frame)
+            .collect();
+        assert_eq!(starts.len(), 2, "{:?}", events);
+        assert!(starts[1].abs_diff(30400) <= 160, "second start at {}", starts[1]);
+    }
+
+    #[tokio::test]
+    async fn test_detect_speech_covers_stream() {
+        let input = ChunkStream::new(16000, 4.0, 0.0, speech);
+        let frames = detect_speech(input, &mut EnergyVad::default()).await.unwrap();
+
+        assert_eq!(frames.len(), 400);
+        assert_eq!(frames.last().map(VadFrame::end), Some(64000));
+        assert!(frames[150].probability > 0.9);
+        assert!(frames[350].probability < 0.1);
+    }
+
+    #[tokio::test]
+    async fn test_stream_closes_speech_at_end() {
+        // Speech still going when the stream ends
+        let input = ChunkStream::new(16000, 2.0, 0.0, speech);
+        let mut stream = VadStream::new(input, VadConfig::default());
+        let events = stream.subscribe();
+
+        let chunks = stream.by_ref().count().await;
+        assert_eq!(chunks, 200);
+        assert!(!stream.is_speech());
+        drop(stream);
+
+        let events: Vec<VadEvent> = events.collect().await;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].frame(), 32000, "{:?}", events);
+    }
+}
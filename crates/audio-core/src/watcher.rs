@@ -0,0 +1,195 @@
+//! Device hot-plug and default-device notifications
+//!
+//! Backends observe the platform's device notifications and hand complete
+//! device lists to a [`DeviceTracker`]. The tracker diffs each list against
+//! the previous one, so consumers of the paired [`DeviceWatcher`] only see
+//! actual changes no matter how noisy the platform notifications are.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::channel::mpsc;
+use futures::Stream as FuturesStream;
+use serde::{Deserialize, Serialize};
+
+use crate::device::{AudioDevice, DeviceType};
+
+/// Change in the set of audio devices
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DeviceEvent {
+    Added { device: AudioDevice },
+    Removed { device: AudioDevice },
+    /// A different device became the default; `None` if none is left
+    DefaultChanged {
+        device_type: DeviceType,
+        device: Option<AudioDevice>,
+    },
+}
+
+/// Compute the events that turn the device list `old` into `new`
+///
+/// Devices are matched by id. Removals are reported first, then additions,
+/// then default changes, so a consumer applying them in order never refers
+/// to a device it does not know about.
+pub fn diff_devices(old: &[AudioDevice], new: &[AudioDevice]) -> Vec<DeviceEvent> {
+    let mut events = Vec::new();
+
+    for device in old {
+        if !new.iter().any(|d| d.id == device.id) {
+            events.push(DeviceEvent::Removed {
+                device: device.clone(),
+            });
+        }
+    }
+
+    for device in new {
+        if !old.iter().any(|d| d.id == device.id) {
+            events.push(DeviceEvent::Added {
+                device: device.clone(),
+            });
+        }
+    }
+
+    for device_type in [DeviceType::Input, DeviceType::Output, DeviceType::Monitor] {
+        let default_of = |devices: &[AudioDevice]| {
+            devices
+                .iter()
+                .find(|d| d.device_type == device_type && d.is_default)
+                .cloned()
+        };
+
+        let before = default_of(old);
+        let after = default_of(new);
+
+        if before.as_ref().map(|d| &d.id) != after.as_ref().map(|d| &d.id) {
+            events.push(DeviceEvent::DefaultChanged {
+                device_type,
+                device: after,
+            });
+        }
+    }
+
+    events
+}
+
+/// Producer side of a [`DeviceWatcher`]
+pub struct DeviceTracker {
+    devices: Vec<AudioDevice>,
+    tx: mpsc::UnboundedSender<DeviceEvent>,
+}
+
+impl DeviceTracker {
+    /// Diff `devices` against the previous list and send the changes
+    ///
+    /// Returns `false` once the watcher has been dropped, at which point the
+    /// backend should stop watching.
+    pub fn update(&mut self, devices: Vec<AudioDevice>) -> bool {
+        for event in diff_devices(&self.devices, &devices) {
+            tracing::debug!(?event, "Audio devices changed");
+            if self.tx.unbounded_send(event).is_err() {
+                return false;
+            }
+        }
+
+        self.devices = devices;
+        !self.tx.is_closed()
+    }
+
+    /// Get the most recent device list
+    pub fn devices(&self) -> &[AudioDevice] {
+        &self.devices
+    }
+}
+
+/// Stream of [`DeviceEvent`]s
+///
+/// The stream ends when the backend stops watching. Dropping the watcher
+/// releases the backend resources it holds.
+pub struct DeviceWatcher {
+    rx: mpsc::UnboundedReceiver<DeviceEvent>,
+    _guard: Option<Box<dyn Send>>,
+}
+
+impl DeviceWatcher {
+    /// Create a watcher and the tracker feeding it, starting from `devices`
+    pub fn channel(devices: Vec<AudioDevice>) -> (DeviceTracker, DeviceWatcher) {
+        let (tx, rx) = mpsc::unbounded();
+
+        let tracker = DeviceTracker { devices, tx };
+        let watcher = DeviceWatcher { rx, _guard: None };
+
+        (tracker, watcher)
+    }
+
+    /// Keep `guard` alive for as long as the watcher exists
+    ///
+    /// Backends use this to tie the lifetime of their notification thread
+    /// or listener registration to the watcher.
+    pub fn with_guard(mut self, guard: impl Send + 'static) -> Self {
+        self._guard = Some(Box::new(guard));
+        self
+    }
+}
+
+impl FuturesStream for DeviceWatcher {
+    type Item = DeviceEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.rx).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn device(id: &str, device_type: DeviceType, is_default: bool) -> AudioDevice {
+        AudioDevice::new(id.to_string(), id.to_string(), device_type, is_default)
+    }
+
+    #[test]
+    fn test_diff_added_and_removed() {
+        let old = [device("mic", DeviceType::Input, true), device("usb", DeviceType::Input, false)];
+        let new = [device("mic", DeviceType::Input, true), device("bt", DeviceType::Input, false)];
+
+        let events = diff_devices(&old, &new);
+        assert_eq!(
+            events,
+            [
+                DeviceEvent::Removed { device: old[1].clone() },
+                DeviceEvent::Added { device: new[1].clone() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_default_changed() {
+        let old = [device("mic", DeviceType::Input, true), device("usb", DeviceType::Input, false)];
+        let new = [device("mic", DeviceType::Input, false), device("usb", DeviceType::Input, true)];
+
+        let events = diff_devices(&old, &new);
+        assert_eq!(
+            events,
+            [DeviceEvent::DefaultChanged {
+                device_type: DeviceType::Input,
+                device: Some(new[1].clone()),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tracker_only_sends_changes() {
+        let initial = vec![device("speakers", DeviceType::Output, true)];
+        let (mut tracker, mut watcher) = DeviceWatcher::channel(initial.clone());
+
+        assert!(tracker.update(initial));
+        let headset = device("headset", DeviceType::Output, false);
+        assert!(tracker.update(vec![device("speakers", DeviceType::Output, true), headset.clone()]));
+        drop(tracker);
+
+        assert_eq!(watcher.next().await, Some(DeviceEvent::Added { device: headset }));
+        assert_eq!(watcher.next().await, None);
+    }
+}
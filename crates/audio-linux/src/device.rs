@@ -1,7 +1,320 @@
-use heronote_audio_core::{AudioDevice, AudioError};
+//! Linux device enumeration and lookup
+//!
+//! Device ids carry the backend they belong to:
+//!
+//! - `pulse:<source or sink name>` for devices listed by PulseAudio
+//! - `alsa:<pcm name>` for ALSA devices listed by cpal
+//!
+//! PulseAudio names (e.g. `alsa_input.usb-Jabra_...-00.mono-fallback`) are
+//! derived from the card's bus path and serial, so they stay stable across
+//! reboots and differ between two identical headsets.
+
+use cpal::traits::{DeviceTrait, HostTrait};
+use heronote_audio_core::{
+    cpal_capabilities, AudioDevice, AudioError, DeviceKind, DeviceType, SampleRateRange,
+};
+
+use crate::pulse::{DeviceEntry, PulseConnection, ServerDefaults, MAX_SAMPLE_RATE, MIN_SAMPLE_RATE};
+
+/// PulseAudio context name used for device enumeration
+const CONTEXT_NAME: &str = "Heronote Device List";
+
+/// Id prefix for devices served by PulseAudio
+const PULSE_ID_PREFIX: &str = "pulse:";
+
+/// Id prefix for ALSA devices opened through cpal
+const ALSA_ID_PREFIX: &str = "alsa:";
+
+/// Backend-specific part of a device id
+#[derive(Debug, PartialEq)]
+pub(crate) enum DeviceId<'a> {
+    Pulse(&'a str),
+    Alsa(&'a str),
+}
+
+/// Split a device id into its backend and name
+pub(crate) fn parse_device_id(id: &str) -> Result<DeviceId<'_>, AudioError> {
+    if let Some(name) = id.strip_prefix(PULSE_ID_PREFIX) {
+        Ok(DeviceId::Pulse(name))
+    } else if let Some(name) = id.strip_prefix(ALSA_ID_PREFIX) {
+        Ok(DeviceId::Alsa(name))
+    } else {
+        Err(AudioError::DeviceNotAvailable(id.to_string()))
+    }
+}
 
 /// List all available audio devices on Linux
+///
+/// Devices are enumerated through PulseAudio (or pipewire-pulse) when a
+/// sound server is running, which also yields the monitor sources used for
+/// speaker capture. Monitors are reported as [`DeviceType::Monitor`] so they
+/// are never offered as microphones. Without a sound server, the ALSA
+/// devices known to cpal are listed instead.
 pub fn list_devices() -> Result<Vec<AudioDevice>, AudioError> {
-    // TODO: Implement Linux device enumeration using ALSA/PulseAudio
-    Err(AudioError::PlatformNotSupported("Linux support coming soon".to_string()))
+    match list_pulse_devices() {
+        Ok(devices) => Ok(devices),
+        Err(e) => {
+            tracing::debug!("PulseAudio unavailable, listing ALSA devices: {}", e);
+            list_alsa_devices()
+        }
+    }
+}
+
+/// List sinks, sources and monitor sources from the PulseAudio server
+fn list_pulse_devices() -> Result<Vec<AudioDevice>, AudioError> {
+    let connection = PulseConnection::connect(CONTEXT_NAME)?;
+    list_pulse_devices_with(&connection)
+}
+
+/// List PulseAudio devices over an existing connection
+pub(crate) fn list_pulse_devices_with(
+    connection: &PulseConnection,
+) -> Result<Vec<AudioDevice>, AudioError> {
+    let defaults = connection.server_defaults()?;
+    Ok(pulse_devices(&defaults, connection.sources()?, connection.sinks()?))
+}
+
+/// Map PulseAudio sources and sinks onto devices, flagging monitors and the
+/// server defaults
+fn pulse_devices(
+    defaults: &ServerDefaults,
+    sources: Vec<DeviceEntry>,
+    sinks: Vec<DeviceEntry>,
+) -> Vec<AudioDevice> {
+    let mut devices = Vec::new();
+
+    // List input and monitor sources
+    for source in sources {
+        let (device_type, is_default) = if source.is_monitor {
+            (DeviceType::Monitor, false)
+        } else {
+            let is_default = defaults.default_source.as_deref() == Some(source.name.as_str());
+            (DeviceType::Input, is_default)
+        };
+
+        devices.push(pulse_device(source, device_type, is_default));
+    }
+
+    // List output sinks
+    for sink in sinks {
+        let is_default = defaults.default_sink.as_deref() == Some(sink.name.as_str());
+        devices.push(pulse_device(sink, DeviceType::Output, is_default));
+    }
+
+    devices
+}
+
+/// Build an [`AudioDevice`] from a PulseAudio sink or source
+///
+/// The server resamples to whatever rate a stream asks for, so every
+/// PulseAudio device reports the full rate range we are willing to request.
+fn pulse_device(entry: DeviceEntry, device_type: DeviceType, is_default: bool) -> AudioDevice {
+    AudioDevice::new(
+        format!("{}{}", PULSE_ID_PREFIX, entry.name),
+        entry.description,
+        device_type,
+        is_default,
+    )
+    .with_kind(entry.kind)
+    .with_capabilities(
+        entry.channels as u16,
+        vec![SampleRateRange::new(MIN_SAMPLE_RATE, MAX_SAMPLE_RATE)],
+        entry.sample_format.into_iter().collect(),
+    )
+}
+
+/// List ALSA devices through cpal (no monitor sources available)
+fn list_alsa_devices() -> Result<Vec<AudioDevice>, AudioError> {
+    let host = cpal::default_host();
+    let mut devices = Vec::new();
+
+    let default_input = host
+        .default_input_device()
+        .and_then(|d| d.name().ok());
+    let default_output = host
+        .default_output_device()
+        .and_then(|d| d.name().ok());
+
+    // List input devices
+    if let Ok(input_devices) = host.input_devices() {
+        for device in input_devices {
+            if let Ok(name) = device.name() {
+                let is_default = default_input.as_ref() == Some(&name);
+                devices.push(alsa_device(&device, name, DeviceType::Input, is_default));
+            }
+        }
+    }
+
+    // List output devices
+    if let Ok(output_devices) = host.output_devices() {
+        for device in output_devices {
+            if let Ok(name) = device.name() {
+                let is_default = default_output.as_ref() == Some(&name);
+                devices.push(alsa_device(&device, name, DeviceType::Output, is_default));
+            }
+        }
+    }
+
+    Ok(devices)
+}
+
+/// Build an [`AudioDevice`] from a cpal ALSA device
+fn alsa_device(
+    device: &cpal::Device,
+    name: String,
+    device_type: DeviceType,
+    is_default: bool,
+) -> AudioDevice {
+    let (channels, sample_rates, sample_formats) = cpal_capabilities(device, &device_type).unwrap_or_default();
+    let kind = alsa_kind(&name);
+
+    AudioDevice::new(format!("{}{}", ALSA_ID_PREFIX, name), name, device_type, is_default)
+        .with_kind(kind)
+        .with_capabilities(channels, sample_rates, sample_formats)
+}
+
+/// Guess the kind of an ALSA PCM from its name
+///
+/// PCMs such as `default`, `pulse` or `pipewire` are plugins routing to a
+/// sound server rather than hardware.
+fn alsa_kind(name: &str) -> DeviceKind {
+    match name.split(':').next() {
+        Some("default" | "pulse" | "pipewire" | "jack" | "null" | "dmix" | "dsnoop") => {
+            DeviceKind::Virtual
+        }
+        _ => DeviceKind::Unknown,
+    }
+}
+
+/// Get the default input device
+///
+/// On most desktops this is the ALSA `default` PCM, which is routed through
+/// PulseAudio or PipeWire to whatever source the user selected.
+pub fn get_default_input_device() -> Result<cpal::Device, AudioError> {
+    let host = cpal::default_host();
+    host.default_input_device()
+        .ok_or(AudioError::NoDeviceFound)
+}
+
+/// Get a specific input device by name
+pub fn get_input_device_by_name(name: &str) -> Result<cpal::Device, AudioError> {
+    let host = cpal::default_host();
+
+    if let Ok(devices) = host.input_devices() {
+        for device in devices {
+            if let Ok(device_name) = device.name() {
+                if device_name == name {
+                    return Ok(device);
+                }
+            }
+        }
+    }
+
+    Err(AudioError::DeviceNotAvailable(name.to_string()))
+}
+
+/// Look up a PulseAudio source that can be recorded as a microphone
+///
+/// Monitor sources are rejected; they belong to `SpeakerInput`.
+pub(crate) fn get_pulse_input_source(name: &str) -> Result<DeviceEntry, AudioError> {
+    let connection = PulseConnection::connect(CONTEXT_NAME)?;
+    let source = connection.source(name)?;
+
+    if source.is_monitor {
+        return Err(AudioError::DeviceNotAvailable(format!(
+            "{} is a monitor source, not an input",
+            name
+        )));
+    }
+
+    Ok(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use heronote_audio_core::SampleFormat;
+
+    fn entry(name: &str, is_monitor: bool) -> DeviceEntry {
+        DeviceEntry {
+            name: name.to_string(),
+            description: format!("{} description", name),
+            is_monitor,
+            kind: if is_monitor { DeviceKind::Monitor } else { DeviceKind::Usb },
+            channels: 2,
+            sample_rate: 48000,
+            sample_format: Some(SampleFormat::F32),
+        }
+    }
+
+    #[test]
+    fn test_parse_device_id() {
+        assert_eq!(
+            parse_device_id("pulse:alsa_input.usb-Jabra-00.mono-fallback").unwrap(),
+            DeviceId::Pulse("alsa_input.usb-Jabra-00.mono-fallback")
+        );
+        assert_eq!(parse_device_id("alsa:hw:CARD=PCH,DEV=0").unwrap(), DeviceId::Alsa("hw:CARD=PCH,DEV=0"));
+        assert_eq!(parse_device_id("pulse:").unwrap(), DeviceId::Pulse(""));
+
+        for id in ["default", "Pulse:speakers", "jack:system", ""] {
+            assert!(matches!(parse_device_id(id), Err(AudioError::DeviceNotAvailable(e)) if e == id));
+        }
+    }
+
+    #[test]
+    fn test_pulse_devices_flag_monitors_and_defaults() {
+        let defaults = ServerDefaults {
+            default_sink: Some("speakers".to_string()),
+            default_source: Some("headset".to_string()),
+            sample_rate: 48000,
+        };
+        let sources = vec![entry("builtin", false), entry("headset", false), entry("speakers.monitor", true)];
+        let sinks = vec![entry("speakers", false), entry("hdmi", false)];
+
+        let devices = pulse_devices(&defaults, sources, sinks);
+        let summary: Vec<_> = devices
+            .iter()
+            .map(|d| (d.id.as_str(), d.device_type.clone(), d.is_default))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("pulse:builtin", DeviceType::Input, false),
+                ("pulse:headset", DeviceType::Input, true),
+                ("pulse:speakers.monitor", DeviceType::Monitor, false),
+                ("pulse:speakers", DeviceType::Output, true),
+                ("pulse:hdmi", DeviceType::Output, false),
+            ]
+        );
+
+        let monitor = &devices[2];
+        assert_eq!(monitor.name, "speakers.monitor description");
+        assert_eq!(monitor.kind, DeviceKind::Monitor);
+        assert_eq!(monitor.channels, 2);
+        assert_eq!(monitor.sample_rates, [SampleRateRange::new(MIN_SAMPLE_RATE, MAX_SAMPLE_RATE)]);
+        assert_eq!(monitor.sample_formats, [SampleFormat::F32]);
+    }
+
+    #[test]
+    fn test_pulse_devices_never_default_a_monitor() {
+        // A monitor can be the server default source, but is no microphone
+        let defaults = ServerDefaults {
+            default_source: Some("speakers.monitor".to_string()),
+            ..Default::default()
+        };
+
+        let devices = pulse_devices(&defaults, vec![entry("speakers.monitor", true)], vec![]);
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].device_type, DeviceType::Monitor);
+        assert!(!devices[0].is_default);
+    }
+
+    #[test]
+    fn test_alsa_kind() {
+        assert_eq!(alsa_kind("default"), DeviceKind::Virtual);
+        assert_eq!(alsa_kind("pipewire"), DeviceKind::Virtual);
+        assert_eq!(alsa_kind("dsnoop:CARD=PCH,DEV=0"), DeviceKind::Virtual);
+        assert_eq!(alsa_kind("hw:CARD=PCH,DEV=0"), DeviceKind::Unknown);
+        assert_eq!(alsa_kind("sysdefault:CARD=PCH"), DeviceKind::Unknown);
+    }
 }
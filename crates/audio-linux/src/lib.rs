@@ -1,13 +1,20 @@
-//! Linux audio capture implementation (stub)
+//! Linux audio capture implementation
 //!
-//! This crate will contain the Linux-specific audio capture implementation
-//! using ALSA and PulseAudio.
+//! Microphone capture uses cpal's ALSA host. System audio capture records
+//! the default sink's PulseAudio monitor source, which also works on
+//! PipeWire through `pipewire-pulse`.
 
 mod mic;
+mod pulse;
 mod speaker;
 mod device;
+mod watcher;
 
-pub use heronote_audio_core::{AudioDevice, AudioError, DeviceType, AudioInput, AudioStream};
+pub use heronote_audio_core::{
+    AudioDevice, AudioError, AudioInput, AudioInputConfig, AudioStream, ChannelMode, DeviceEvent,
+    DeviceType, DeviceWatcher, StreamFormat,
+};
 pub use mic::{MicInput, MicStream};
 pub use speaker::{SpeakerInput, SpeakerStream};
 pub use device::list_devices;
+pub use watcher::watch_devices;
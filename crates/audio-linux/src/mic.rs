@@ -1,62 +1,414 @@
-//! Linux microphone capture implementation (stub)
+//! Linux microphone capture implementation
 //!
-//! This module will contain the Linux-specific microphone capture
-//! implementation using ALSA or PulseAudio.
+//! Captures microphone input through cpal's ALSA host. On desktops running
+//! PulseAudio or PipeWire, the ALSA `default` device is routed through the
+//! sound server, so the user's selected source is picked up automatically.
+//!
+//! Devices listed by PulseAudio (ids starting with `pulse:`) are recorded
+//! directly from their source on a [`RecordWorker`] thread instead.
 
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{
+    BufferSize, SampleFormat, Stream, StreamConfig, SupportedBufferSize, SupportedStreamConfig,
+    SupportedStreamConfigRange,
+};
 use futures::Stream as FuturesStream;
-use heronote_audio_core::{AudioError, AudioInput, AudioStream};
+use tokio::sync::mpsc as tokio_mpsc;
 
-/// Microphone input handler for Linux (stub)
-///
-/// This is a placeholder implementation. The actual Linux microphone
-/// capture will use ALSA or PulseAudio for audio input.
+use crate::device::{
+    get_default_input_device, get_input_device_by_name, get_pulse_input_source, parse_device_id,
+    DeviceId,
+};
+use crate::pulse::{negotiate_format, RecordEvent, RecordSpec, RecordWorker};
+use heronote_audio_core::conversion::{convert_i16_slice_to_f32, convert_i32_slice_to_f32, remix_channels};
+use heronote_audio_core::{
+    capture_clock_now, negotiate, AudioChunk, AudioError, AudioInput,
+    AudioInputConfig, AudioStream, ConfigCandidate, SampleRateRange, StreamActivity, StreamErrorFilter,
+    StreamFormat,
+};
+
+/// PulseAudio context name used for microphone capture
+const CONTEXT_NAME: &str = "Heronote Microphone Capture";
+
+/// PulseAudio stream name shown in mixers such as pavucontrol
+const STREAM_NAME: &str = "Heronote Microphone";
+
+/// Requested PulseAudio fragment size in frames
+const PULSE_FRAGMENT_FRAMES: u32 = 1024;
+
+/// Microphone input handler for Linux
 pub struct MicInput {
-    // Private field to prevent external construction
-    _private: (),
+    backend: MicBackend,
+    format: StreamFormat,
+}
+
+/// Audio backend a [`MicInput`] records from
+enum MicBackend {
+    Alsa {
+        device: cpal::Device,
+        config: SupportedStreamConfig,
+    },
+    Pulse {
+        source: String,
+        description: String,
+    },
 }
 
 impl AudioInput for MicInput {
     type Stream = MicStream;
 
-    fn new() -> Result<Self, AudioError> {
-        Err(AudioError::PlatformNotSupported(
-            "Linux mic capture coming soon".to_string(),
-        ))
+    fn with_config(config: &AudioInputConfig) -> Result<Self, AudioError> {
+        let Some(id) = config.device_id.as_deref() else {
+            let device = get_default_input_device()?;
+            return Self::from_device(device, config);
+        };
+
+        match parse_device_id(id)? {
+            DeviceId::Alsa(name) => {
+                let device = get_input_device_by_name(name)?;
+                Self::from_device(device, config)
+            }
+            DeviceId::Pulse(name) => {
+                let source = get_pulse_input_source(name)?;
+                let format = negotiate_format(
+                    config,
+                    source.sample_rate,
+                    source.channels,
+                    PULSE_FRAGMENT_FRAMES,
+                );
+
+                Ok(Self {
+                    backend: MicBackend::Pulse {
+                        source: source.name,
+                        description: source.description,
+                    },
+                    format,
+                })
+            }
+        }
     }
 
-    fn sample_rate(&self) -> u32 {
-        // This method can never be called because `new()` always returns Err,
-        // meaning no instance of MicInput can ever exist.
-        unreachable!("MicInput cannot be instantiated on Linux (stub)")
+    fn format(&self) -> StreamFormat {
+        self.format
     }
 
     fn stream(self) -> Result<MicStream, AudioError> {
-        // This method can never be called because `new()` always returns Err
-        unreachable!("MicInput cannot be instantiated on Linux (stub)")
+        let (tx, rx) = tokio_mpsc::unbounded_channel::<Result<AudioChunk, AudioError>>();
+        let format = self.format;
+        let mut sender = ChunkSender::new(tx, &format);
+
+        let capture = match &self.backend {
+            MicBackend::Alsa { device, config } => {
+                let stream = build_stream(device, config, &format, sender)?;
+
+                stream
+                    .play()
+                    .map_err(|e| AudioError::StreamError(e.to_string()))?;
+
+                MicCapture::Alsa(stream)
+            }
+            MicBackend::Pulse { source, .. } => {
+                let record = RecordSpec {
+                    context_name: CONTEXT_NAME,
+                    stream_name: STREAM_NAME,
+                    source: source.clone(),
+                    sample_rate: format.sample_rate,
+                    channels: format.channels as u8,
+                    fragment_frames: format.buffer_frames.unwrap_or(PULSE_FRAGMENT_FRAMES),
+                };
+
+                let worker = RecordWorker::spawn("heronote-mic", record, move |event| match event {
+                    RecordEvent::Samples { data, latency } => sender.send(data.to_vec(), latency),
+                    RecordEvent::Failed(error) => sender.fail(error),
+                })?;
+
+                MicCapture::Pulse(worker)
+            }
+        };
+
+        Ok(MicStream {
+            _capture: capture,
+            receiver: rx,
+            sample_rate: format.sample_rate,
+            channels: format.channels,
+            ended: false,
+        })
+    }
+}
+
+impl MicInput {
+    /// Create a MicInput with a specific device name
+    pub fn with_device_name(name: &str) -> Result<Self, AudioError> {
+        let device = get_input_device_by_name(name)?;
+        Self::from_device(device, &AudioInputConfig::default())
+    }
+
+    /// Create a MicInput for a device id returned by [`crate::list_devices`]
+    pub fn with_device_id(id: &str) -> Result<Self, AudioError> {
+        Self::with_config(&AudioInputConfig::new().with_device_id(id))
+    }
+
+    /// Negotiate the closest supported configuration of a cpal device
+    fn from_device(device: cpal::Device, config: &AudioInputConfig) -> Result<Self, AudioError> {
+        let default_config = device
+            .default_input_config()
+            .map_err(|e| AudioError::DeviceError(e.to_string()))?;
+
+        // Only consider formats the capture callback can convert
+        let ranges: Vec<SupportedStreamConfigRange> = device
+            .supported_input_configs()
+            .map_err(|e| AudioError::DeviceError(e.to_string()))?
+            .filter(|range| {
+                matches!(
+                    range.sample_format(),
+                    SampleFormat::F32 | SampleFormat::I16 | SampleFormat::I32
+                )
+            })
+            .collect();
+
+        let candidates: Vec<ConfigCandidate> = ranges
+            .iter()
+            .map(|range| ConfigCandidate {
+                channels: range.channels(),
+                sample_rates: SampleRateRange::new(
+                    range.min_sample_rate().0,
+                    range.max_sample_rate().0,
+                ),
+            })
+            .collect();
+
+        let (supported_config, buffer_frames) =
+            match negotiate(config, &candidates, default_config.sample_rate().0) {
+                Some(negotiated) => {
+                    let range = ranges[negotiated.index];
+                    let buffer_frames = config.buffer_frames.map(|frames| match range.buffer_size() {
+                        SupportedBufferSize::Range { min, max } => frames.clamp(*min, *max),
+                        SupportedBufferSize::Unknown => frames,
+                    });
+
+                    let supported_config =
+                        range.with_sample_rate(cpal::SampleRate(negotiated.sample_rate));
+
+                    (supported_config, buffer_frames)
+                }
+                None => (default_config, None),
+            };
+
+        let format = StreamFormat {
+            sample_rate: supported_config.sample_rate().0,
+            channels: config.channels.output_channels(supported_config.channels()),
+            buffer_frames,
+        };
+
+        if config.sample_rate.is_some_and(|rate| rate != format.sample_rate) {
+            tracing::info!(
+                requested = config.sample_rate,
+                actual = format.sample_rate,
+                "Device does not support the requested sample rate"
+            );
+        }
+
+        Ok(Self {
+            backend: MicBackend::Alsa {
+                device,
+                config: supported_config,
+            },
+            format,
+        })
+    }
+
+    /// Get the device name
+    pub fn device_name(&self) -> Result<String, AudioError> {
+        match &self.backend {
+            MicBackend::Alsa { device, .. } => device
+                .name()
+                .map_err(|e| AudioError::DeviceError(e.to_string())),
+            MicBackend::Pulse { description, .. } => Ok(description.clone()),
+        }
+    }
+}
+
+/// Build the input stream based on the sample format
+///
+/// This function handles the different sample formats (F32, I16, I32) and
+/// creates the appropriate stream that converts all audio to f32 with the
+/// channel count of `format`.
+fn build_stream(
+    device: &cpal::Device,
+    supported_config: &SupportedStreamConfig,
+    format: &StreamFormat,
+    mut sender: ChunkSender,
+) -> Result<Stream, AudioError> {
+    let channels = supported_config.channels() as usize;
+    let out_channels = format.channels as usize;
+    let sample_format = supported_config.sample_format();
+
+    let config = StreamConfig {
+        channels: supported_config.channels(),
+        sample_rate: supported_config.sample_rate(),
+        buffer_size: match format.buffer_frames {
+            Some(frames) => BufferSize::Fixed(frames),
+            None => BufferSize::Default,
+        },
+    };
+
+    let mut filter = StreamErrorFilter::new(device.name().unwrap_or_default());
+    sender.activity = Some(filter.activity());
+    let errors = sender.tx.clone();
+    let err_fn = move |err| {
+        let Some(error) = filter.filter(err) else {
+            return;
+        };
+        tracing::error!("Audio stream error: {}", error);
+        if let Err(e) = errors.send(Err(error)) {
+            tracing::debug!("Failed to send stream error (receiver dropped): {}", e);
+        }
+    };
+
+    match sample_format {
+        SampleFormat::F32 => device.build_input_stream(
+            &config,
+            move |data: &[f32], info: &cpal::InputCallbackInfo| {
+                sender.send(remix_channels(data, channels, out_channels), capture_latency(info));
+            },
+            err_fn,
+            None,
+        ),
+        SampleFormat::I16 => device.build_input_stream(
+            &config,
+            move |data: &[i16], info: &cpal::InputCallbackInfo| {
+                let float_data = convert_i16_slice_to_f32(data);
+                sender.send(remix_channels(&float_data, channels, out_channels), capture_latency(info));
+            },
+            err_fn,
+            None,
+        ),
+        SampleFormat::I32 => device.build_input_stream(
+            &config,
+            move |data: &[i32], info: &cpal::InputCallbackInfo| {
+                let float_data = convert_i32_slice_to_f32(data);
+                sender.send(remix_channels(&float_data, channels, out_channels), capture_latency(info));
+            },
+            err_fn,
+            None,
+        ),
+        _ => return Err(AudioError::UnsupportedFormat),
     }
+    .map_err(|e| AudioError::StreamBuildError(e.to_string()))
+}
+
+/// Time between the capture of a cpal buffer and its callback
+fn capture_latency(info: &cpal::InputCallbackInfo) -> Duration {
+    let timestamp = info.timestamp();
+    timestamp
+        .callback
+        .duration_since(&timestamp.capture)
+        .unwrap_or_default()
 }
 
-/// Stream of audio samples from the microphone (stub)
+/// Stamps captured buffers and sends them to the [`MicStream`]
+struct ChunkSender {
+    tx: tokio_mpsc::UnboundedSender<Result<AudioChunk, AudioError>>,
+    sample_rate: u32,
+    channels: u16,
+    sequence: u64,
+    /// Tells the cpal error callback that audio still arrives
+    activity: Option<StreamActivity>,
+}
+
+impl ChunkSender {
+    fn new(tx: tokio_mpsc::UnboundedSender<Result<AudioChunk, AudioError>>, format: &StreamFormat) -> Self {
+        Self {
+            tx,
+            sample_rate: format.sample_rate,
+            channels: format.channels,
+            sequence: 0,
+            activity: None,
+        }
+    }
+
+    /// Send samples captured `latency` ago through the channel
+    ///
+    /// In audio callbacks, we cannot block or handle errors in a complex way,
+    /// so we log warnings if the receiver has been dropped (which indicates
+    /// the stream is being shut down).
+    fn send(&mut self, samples: Vec<f32>, latency: Duration) {
+        if let Some(activity) = &self.activity {
+            activity.audio_received();
+        }
+
+        let chunk = AudioChunk::new(
+            samples,
+            self.sample_rate,
+            self.channels,
+            capture_clock_now().saturating_sub(latency),
+            self.sequence,
+        );
+        self.sequence += 1;
+
+        if let Err(e) = self.tx.send(Ok(chunk)) {
+            // Only log at debug level since this typically happens during shutdown
+            tracing::debug!("Failed to send audio samples (receiver dropped): {}", e);
+        }
+    }
+
+    /// Report a capture failure to the stream
+    fn fail(&self, error: AudioError) {
+        if let Err(e) = self.tx.send(Err(error)) {
+            tracing::debug!("Failed to send stream error (receiver dropped): {}", e);
+        }
+    }
+}
+
+// ============================================================================
+// MicStream implementation
+// ============================================================================
+
+/// Running capture that feeds a [`MicStream`]
+///
+/// Only held to keep the capture alive; dropping it stops recording.
+enum MicCapture {
+    Alsa(#[allow(dead_code)] Stream),
+    Pulse(#[allow(dead_code)] RecordWorker),
+}
+
+/// Stream of audio samples from the microphone
 pub struct MicStream {
-    // Private field to prevent external construction
-    _private: (),
+    _capture: MicCapture,
+    receiver: tokio_mpsc::UnboundedReceiver<Result<AudioChunk, AudioError>>,
+    sample_rate: u32,
+    channels: u16,
+    /// Set once a fatal error has been delivered
+    ended: bool,
 }
 
 impl AudioStream for MicStream {
     fn sample_rate(&self) -> u32 {
-        // This method can never be called because MicStream cannot be created
-        unreachable!("MicStream cannot be created on Linux (stub)")
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
     }
 }
 
 impl FuturesStream for MicStream {
-    type Item = Vec<f32>;
+    type Item = Result<AudioChunk, AudioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.ended {
+            return Poll::Ready(None);
+        }
+
+        let item = futures::ready!(Pin::new(&mut self.receiver).poll_recv(cx));
+        if let Some(Err(e)) = &item {
+            self.ended = !e.is_recoverable();
+        }
 
-    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        // This method can never be called because MicStream cannot be created
-        unreachable!("MicStream cannot be created on Linux (stub)")
+        Poll::Ready(item)
     }
 }
@@ -0,0 +1,838 @@
+//! Thin PulseAudio connection helper
+//!
+//! Wraps a threaded mainloop and a connected context, following the locking
+//! pattern recommended by the PulseAudio documentation. Every public method
+//! takes the mainloop lock itself, so callers never deal with it directly.
+//!
+//! PulseAudio objects are reference counted with `Rc` and are therefore not
+//! `Send`. A [`PulseConnection`] must stay on the thread that created it.
+
+use std::cell::{Cell, RefCell};
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use libpulse_binding::callbacks::ListResult;
+use libpulse_binding::context::introspect::{SinkInfo, SourceInfo};
+use libpulse_binding::context::subscribe::{
+    Facility, InterestMaskSet, Operation as SubscribeOperation,
+};
+use libpulse_binding::context::{Context, FlagSet as ContextFlagSet, State as ContextState};
+use libpulse_binding::def::BufferAttr;
+use libpulse_binding::mainloop::threaded::Mainloop;
+use libpulse_binding::operation::{Operation, State as OperationState};
+use libpulse_binding::proplist::{properties, Proplist};
+use libpulse_binding::sample::{Format, Spec};
+use libpulse_binding::stream::{
+    FlagSet as StreamFlagSet, Latency, PeekResult, State as StreamState, Stream,
+};
+
+use heronote_audio_core::{
+    frames_to_duration, AudioError, AudioInputConfig, DeviceKind, SampleFormat, SampleRateRange,
+    StreamFormat,
+};
+
+/// Application name reported to the sound server
+const APP_NAME: &str = "Heronote";
+
+/// Size in bytes of a single f32 sample
+const F32_SIZE: usize = std::mem::size_of::<f32>();
+
+/// Lowest sample rate we ask the server to resample to
+pub(crate) const MIN_SAMPLE_RATE: u32 = 8000;
+
+/// Highest sample rate we ask the server to resample to
+pub(crate) const MAX_SAMPLE_RATE: u32 = 192_000;
+
+/// Largest record fragment we ask for, one second at the highest rate
+const MAX_FRAGMENT_FRAMES: u32 = MAX_SAMPLE_RATE;
+
+/// Channel count assumed when the server does not report one
+const DEFAULT_CHANNELS: u8 = 2;
+
+/// Highest channel count PulseAudio accepts (`PA_CHANNELS_MAX`)
+const MAX_CHANNELS: u16 = 32;
+
+/// Server-wide defaults as reported by the server
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ServerDefaults {
+    pub default_sink: Option<String>,
+    pub default_source: Option<String>,
+    pub sample_rate: u32,
+}
+
+/// A sink or source as listed by the server
+#[derive(Debug, Clone)]
+pub(crate) struct DeviceEntry {
+    /// Internal PulseAudio name (e.g. `alsa_input.pci-0000_00_1f.3.analog-stereo`)
+    pub name: String,
+    /// Human readable description shown in mixers
+    pub description: String,
+    /// Whether this source monitors a sink
+    pub is_monitor: bool,
+    pub kind: DeviceKind,
+    pub channels: u8,
+    /// Native sample rate of the device
+    pub sample_rate: u32,
+    /// Native sample format, if it maps onto [`SampleFormat`]
+    pub sample_format: Option<SampleFormat>,
+}
+
+impl DeviceEntry {
+    fn from_sink(info: &SinkInfo) -> Option<Self> {
+        let name = info.name.as_deref()?.to_string();
+
+        Some(Self {
+            description: description_or(&info.description, &name),
+            kind: device_kind(&info.proplist, false),
+            channels: info.sample_spec.channels,
+            sample_rate: info.sample_spec.rate,
+            sample_format: sample_format(info.sample_spec.format),
+            is_monitor: false,
+            name,
+        })
+    }
+
+    fn from_source(info: &SourceInfo) -> Option<Self> {
+        let name = info.name.as_deref()?.to_string();
+        let is_monitor = info.monitor_of_sink.is_some();
+
+        Some(Self {
+            description: description_or(&info.description, &name),
+            kind: device_kind(&info.proplist, is_monitor),
+            channels: info.sample_spec.channels,
+            sample_rate: info.sample_spec.rate,
+            sample_format: sample_format(info.sample_spec.format),
+            is_monitor,
+            name,
+        })
+    }
+}
+
+/// A monitor source and its native sample spec
+#[derive(Debug, Clone)]
+pub(crate) struct MonitorSource {
+    pub name: String,
+    pub sample_rate: u32,
+    pub channels: u8,
+}
+
+/// Connected PulseAudio context running on its own threaded mainloop
+pub(crate) struct PulseConnection {
+    mainloop: Rc<RefCell<Mainloop>>,
+    context: Rc<RefCell<Context>>,
+}
+
+impl PulseConnection {
+    /// Connect to the default PulseAudio (or pipewire-pulse) server
+    pub(crate) fn connect(context_name: &str) -> Result<Self, AudioError> {
+        let mut proplist = Proplist::new()
+            .ok_or_else(|| AudioError::DeviceError("Failed to create PulseAudio proplist".to_string()))?;
+        proplist
+            .set_str(properties::APPLICATION_NAME, APP_NAME)
+            .map_err(|_| AudioError::DeviceError("Failed to set PulseAudio application name".to_string()))?;
+
+        let mainloop = Rc::new(RefCell::new(
+            Mainloop::new()
+                .ok_or_else(|| AudioError::DeviceError("Failed to create PulseAudio mainloop".to_string()))?,
+        ));
+
+        let context = Rc::new(RefCell::new(
+            Context::new_with_proplist(mainloop.borrow().deref(), context_name, &proplist)
+                .ok_or_else(|| AudioError::DeviceError("Failed to create PulseAudio context".to_string()))?,
+        ));
+
+        // Wake the waiting thread whenever the context reaches a terminal state
+        {
+            let ml_ref = Rc::clone(&mainloop);
+            let context_ref = Rc::clone(&context);
+            context
+                .borrow_mut()
+                .set_state_callback(Some(Box::new(move || {
+                    let state = unsafe { (*context_ref.as_ptr()).get_state() };
+                    if matches!(
+                        state,
+                        ContextState::Ready | ContextState::Failed | ContextState::Terminated
+                    ) {
+                        unsafe { (*ml_ref.as_ptr()).signal(false) };
+                    }
+                })));
+        }
+
+        context
+            .borrow_mut()
+            .connect(None, ContextFlagSet::NOFLAGS, None)
+            .map_err(|e| AudioError::DeviceNotAvailable(format!("PulseAudio server: {}", e)))?;
+
+        mainloop.borrow_mut().lock();
+
+        if let Err(e) = mainloop.borrow_mut().start() {
+            mainloop.borrow_mut().unlock();
+            return Err(AudioError::DeviceError(format!(
+                "Failed to start PulseAudio mainloop: {}",
+                e
+            )));
+        }
+
+        loop {
+            let state = context.borrow().get_state();
+            match state {
+                ContextState::Ready => break,
+                ContextState::Failed | ContextState::Terminated => {
+                    context.borrow_mut().set_state_callback(None);
+                    mainloop.borrow_mut().unlock();
+                    mainloop.borrow_mut().stop();
+                    return Err(AudioError::DeviceNotAvailable(
+                        "PulseAudio connection failed".to_string(),
+                    ));
+                }
+                _ => mainloop.borrow_mut().wait(),
+            }
+        }
+
+        // Clearing the callback also breaks the Rc cycle it created
+        context.borrow_mut().set_state_callback(None);
+        mainloop.borrow_mut().unlock();
+
+        Ok(Self { mainloop, context })
+    }
+
+    /// Query the server's default sink, default source and sample rate
+    pub(crate) fn server_defaults(&self) -> Result<ServerDefaults, AudioError> {
+        self.with_lock(|this| {
+            let result = Rc::new(RefCell::new(None));
+
+            let op = {
+                let result = Rc::clone(&result);
+                let ml_ref = Rc::clone(&this.mainloop);
+                this.context.borrow().introspect().get_server_info(move |info| {
+                    *result.borrow_mut() = Some(ServerDefaults {
+                        default_sink: info.default_sink_name.as_ref().map(|s| s.to_string()),
+                        default_source: info.default_source_name.as_ref().map(|s| s.to_string()),
+                        sample_rate: info.sample_spec.rate,
+                    });
+                    unsafe { (*ml_ref.as_ptr()).signal(false) };
+                })
+            };
+
+            this.wait_for(&op)?;
+            let defaults = result.borrow_mut().take();
+            defaults.ok_or_else(|| AudioError::DeviceError("PulseAudio returned no server info".to_string()))
+        })
+    }
+
+    /// List all sinks (outputs)
+    pub(crate) fn sinks(&self) -> Result<Vec<DeviceEntry>, AudioError> {
+        self.with_lock(|this| {
+            let entries = Rc::new(RefCell::new(Vec::new()));
+
+            let op = {
+                let entries = Rc::clone(&entries);
+                let ml_ref = Rc::clone(&this.mainloop);
+                this.context
+                    .borrow()
+                    .introspect()
+                    .get_sink_info_list(move |list| match list {
+                        ListResult::Item(info) => {
+                            entries.borrow_mut().extend(DeviceEntry::from_sink(info));
+                        }
+                        ListResult::End | ListResult::Error => {
+                            unsafe { (*ml_ref.as_ptr()).signal(false) };
+                        }
+                    })
+            };
+
+            this.wait_for(&op)?;
+            let entries = entries.take();
+            Ok(entries)
+        })
+    }
+
+    /// List all sources, including the monitors of every sink
+    pub(crate) fn sources(&self) -> Result<Vec<DeviceEntry>, AudioError> {
+        self.with_lock(|this| {
+            let entries = Rc::new(RefCell::new(Vec::new()));
+
+            let op = {
+                let entries = Rc::clone(&entries);
+                let ml_ref = Rc::clone(&this.mainloop);
+                this.context
+                    .borrow()
+                    .introspect()
+                    .get_source_info_list(move |list| match list {
+                        ListResult::Item(info) => {
+                            entries.borrow_mut().extend(DeviceEntry::from_source(info));
+                        }
+                        ListResult::End | ListResult::Error => {
+                            unsafe { (*ml_ref.as_ptr()).signal(false) };
+                        }
+                    })
+            };
+
+            this.wait_for(&op)?;
+            let entries = entries.take();
+            Ok(entries)
+        })
+    }
+
+    /// Look up a single source by its PulseAudio name
+    pub(crate) fn source(&self, name: &str) -> Result<DeviceEntry, AudioError> {
+        let entry = self.with_lock(|this| {
+            let result = Rc::new(RefCell::new(None));
+
+            let op = {
+                let result = Rc::clone(&result);
+                let ml_ref = Rc::clone(&this.mainloop);
+                this.context
+                    .borrow()
+                    .introspect()
+                    .get_source_info_by_name(name, move |list| match list {
+                        ListResult::Item(info) => {
+                            *result.borrow_mut() = DeviceEntry::from_source(info);
+                        }
+                        ListResult::End | ListResult::Error => {
+                            unsafe { (*ml_ref.as_ptr()).signal(false) };
+                        }
+                    })
+            };
+
+            this.wait_for(&op)?;
+            let entry = result.borrow_mut().take();
+            Ok(entry)
+        })?;
+
+        entry.ok_or_else(|| AudioError::DeviceNotAvailable(name.to_string()))
+    }
+
+    /// Resolve the monitor source of the default sink
+    ///
+    /// Falls back to the conventional `<sink>.monitor` name if the sink
+    /// does not report its monitor explicitly.
+    pub(crate) fn default_monitor(&self) -> Result<MonitorSource, AudioError> {
+        let defaults = self.server_defaults()?;
+        let sink = defaults.default_sink.ok_or(AudioError::NoDeviceFound)?;
+
+        let monitor = self.with_lock(|this| {
+            let result = Rc::new(RefCell::new(None));
+
+            let op = {
+                let result = Rc::clone(&result);
+                let ml_ref = Rc::clone(&this.mainloop);
+                this.context
+                    .borrow()
+                    .introspect()
+                    .get_sink_info_by_name(&sink, move |list| {
+                        if let ListResult::Item(info) = list {
+                            *result.borrow_mut() = Some((
+                                info.monitor_source_name.as_ref().map(|s| s.to_string()),
+                                info.sample_spec.rate,
+                                info.sample_spec.channels,
+                            ));
+                        } else {
+                            unsafe { (*ml_ref.as_ptr()).signal(false) };
+                        }
+                    })
+            };
+
+            this.wait_for(&op)?;
+            let monitor = result.borrow_mut().take();
+            Ok(monitor)
+        })?;
+
+        let (name, sample_rate, channels) = match monitor {
+            Some((name, rate, channels)) => {
+                (name.unwrap_or_else(|| format!("{}.monitor", sink)), rate, channels)
+            }
+            None => (format!("{}.monitor", sink), defaults.sample_rate, DEFAULT_CHANNELS),
+        };
+
+        Ok(MonitorSource {
+            name,
+            sample_rate,
+            channels,
+        })
+    }
+
+    /// Open an interleaved f32 record stream as described by `record`
+    ///
+    /// The server converts the source to the requested rate and channel
+    /// count, so any combination within [`MIN_SAMPLE_RATE`]..=[`MAX_SAMPLE_RATE`]
+    /// is accepted.
+    ///
+    /// `on_event` runs on the PulseAudio mainloop thread for every fragment
+    /// read from the server, along with the age of the fragment. Holes in
+    /// the stream are reported as silence so the timeline is preserved. If
+    /// the running stream is killed by the server, `on_event` receives a
+    /// final [`RecordEvent::Failed`].
+    pub(crate) fn record<F>(
+        &self,
+        record: &RecordSpec,
+        on_event: F,
+    ) -> Result<RecordStream, AudioError>
+    where
+        F: FnMut(RecordEvent<'_>) + 'static,
+    {
+        let source = record.source.as_str();
+        let spec = Spec {
+            format: Format::FLOAT32NE,
+            rate: record.sample_rate,
+            channels: record.channels,
+        };
+
+        if !spec.is_valid() {
+            return Err(AudioError::UnsupportedFormat);
+        }
+
+        let attr = BufferAttr {
+            maxlength: u32::MAX,
+            tlength: u32::MAX,
+            prebuf: u32::MAX,
+            minreq: u32::MAX,
+            fragsize: record
+                .fragment_frames
+                .saturating_mul(F32_SIZE as u32 * u32::from(record.channels)),
+        };
+
+        // Without DONT_MOVE the server silently moves the stream to another
+        // source, typically a microphone, when the recorded one disappears.
+        // Timing updates let every fragment be dated from the stream latency.
+        let flags = StreamFlagSet::ADJUST_LATENCY
+            | StreamFlagSet::DONT_MOVE
+            | StreamFlagSet::INTERPOLATE_TIMING
+            | StreamFlagSet::AUTO_TIMING_UPDATE;
+        let frame_samples = usize::from(record.channels);
+        let sample_rate = record.sample_rate;
+
+        // Shared by the read and state callbacks, which never run concurrently
+        let on_event = Rc::new(RefCell::new(on_event));
+        // Failures are only reported once the stream was running
+        let running = Rc::new(Cell::new(false));
+
+        self.with_lock(|this| {
+            let stream = Rc::new(RefCell::new(
+                Stream::new(&mut this.context.borrow_mut(), record.stream_name, &spec, None)
+                    .ok_or_else(|| AudioError::StreamBuildError("Failed to create PulseAudio stream".to_string()))?,
+            ));
+
+            {
+                let ml_ref = Rc::clone(&this.mainloop);
+                let stream_ref = Rc::clone(&stream);
+                let on_event = Rc::clone(&on_event);
+                let running = Rc::clone(&running);
+                let source = source.to_string();
+                stream
+                    .borrow_mut()
+                    .set_state_callback(Some(Box::new(move || {
+                        let state = unsafe { (*stream_ref.as_ptr()).get_state() };
+                        match state {
+                            StreamState::Ready => {
+                                unsafe { (*ml_ref.as_ptr()).signal(false) };
+                            }
+                            StreamState::Failed | StreamState::Terminated => {
+                                if state == StreamState::Failed {
+                                    tracing::error!("PulseAudio record stream failed");
+                                }
+                                if running.replace(false) {
+                                    let error = AudioError::DeviceDisconnected(source.clone());
+                                    (on_event.borrow_mut())(RecordEvent::Failed(error));
+                                }
+                                unsafe { (*ml_ref.as_ptr()).signal(false) };
+                            }
+                            _ => {}
+                        }
+                    })));
+            }
+
+            {
+                let stream_ref = Rc::clone(&stream);
+                let on_event = Rc::clone(&on_event);
+                let mut scratch: Vec<f32> = Vec::new();
+                stream
+                    .borrow_mut()
+                    .set_read_callback(Some(Box::new(move |_| {
+                        let stream = unsafe { &mut *stream_ref.as_ptr() };
+                        loop {
+                            // The oldest unread audio is the fragment about to be peeked
+                            let latency = record_latency(stream);
+                            match stream.peek() {
+                                Ok(PeekResult::Data(bytes)) => {
+                                    scratch.clear();
+                                    scratch.extend(bytes.chunks_exact(F32_SIZE).map(|b| {
+                                        f32::from_ne_bytes([b[0], b[1], b[2], b[3]])
+                                    }));
+                                }
+                                Ok(PeekResult::Hole(size)) => {
+                                    scratch.clear();
+                                    scratch.resize(size / F32_SIZE, 0.0);
+                                }
+                                Ok(PeekResult::Empty) => break,
+                                Err(e) => {
+                                    tracing::warn!("Failed to read PulseAudio stream: {}", e);
+                                    break;
+                                }
+                            }
+
+                            // Until the server sent timing information, the
+                            // fragment is taken to be complete just now
+                            let latency = latency.unwrap_or_else(|| {
+                                frames_to_duration((scratch.len() / frame_samples) as u64, sample_rate)
+                            });
+                            (on_event.borrow_mut())(RecordEvent::Samples {
+                                data: &scratch,
+                                latency,
+                            });
+
+                            if let Err(e) = stream.discard() {
+                                tracing::warn!("Failed to discard PulseAudio fragment: {}", e);
+                                break;
+                            }
+                        }
+                    })));
+            }
+
+            stream
+                .borrow_mut()
+                .connect_record(Some(source), Some(&attr), flags)
+                .map_err(|e| AudioError::StreamBuildError(format!("Failed to connect record stream: {}", e)))?;
+
+            loop {
+                let state = stream.borrow().get_state();
+                match state {
+                    StreamState::Ready => break,
+                    StreamState::Failed | StreamState::Terminated => {
+                        clear_stream_callbacks(&stream);
+                        return Err(AudioError::DeviceNotAvailable(source.to_string()));
+                    }
+                    _ => this.mainloop.borrow_mut().wait(),
+                }
+            }
+
+            running.set(true);
+            Ok(RecordStream { stream })
+        })
+    }
+
+    /// Call `on_change` whenever a sink or source appears or disappears, or
+    /// the server's defaults change
+    ///
+    /// `on_change` runs on the PulseAudio mainloop thread with the mainloop
+    /// locked, so it must not call back into this connection.
+    pub(crate) fn subscribe<F>(&self, mut on_change: F) -> Result<(), AudioError>
+    where
+        F: FnMut() + 'static,
+    {
+        self.with_lock(|this| {
+            // Property changes such as volume are irrelevant to the device list
+            this.context
+                .borrow_mut()
+                .set_subscribe_callback(Some(Box::new(move |facility, operation, _| {
+                    let relevant = match facility {
+                        Some(Facility::Sink) | Some(Facility::Source) => matches!(
+                            operation,
+                            Some(SubscribeOperation::New) | Some(SubscribeOperation::Removed)
+                        ),
+                        Some(Facility::Server) => true,
+                        _ => false,
+                    };
+
+                    if relevant {
+                        on_change();
+                    }
+                })));
+
+            let op = {
+                let ml_ref = Rc::clone(&this.mainloop);
+                this.context.borrow_mut().subscribe(
+                    InterestMaskSet::SINK | InterestMaskSet::SOURCE | InterestMaskSet::SERVER,
+                    move |_| unsafe { (*ml_ref.as_ptr()).signal(false) },
+                )
+            };
+
+            this.wait_for(&op)
+        })
+    }
+
+    /// Tear down a record stream previously created by [`Self::record`]
+    ///
+    /// The stream is released with the mainloop locked, as the mainloop
+    /// thread may still be using it.
+    pub(crate) fn stop_record(&self, record: RecordStream) {
+        self.with_lock(move |_| {
+            clear_stream_callbacks(&record.stream);
+            if let Err(e) = record.stream.borrow_mut().disconnect() {
+                tracing::debug!("Failed to disconnect PulseAudio stream: {}", e);
+            }
+            drop(record);
+        });
+    }
+
+    /// Run `f` while holding the mainloop lock
+    fn with_lock<T>(&self, f: impl FnOnce(&Self) -> T) -> T {
+        self.mainloop.borrow_mut().lock();
+        let result = f(self);
+        self.mainloop.borrow_mut().unlock();
+        result
+    }
+
+    /// Block until `op` completes (mainloop lock must be held)
+    fn wait_for<G: ?Sized>(&self, op: &Operation<G>) -> Result<(), AudioError> {
+        loop {
+            match op.get_state() {
+                OperationState::Running => self.mainloop.borrow_mut().wait(),
+                OperationState::Done => return Ok(()),
+                OperationState::Cancelled => {
+                    return Err(AudioError::DeviceError(
+                        "PulseAudio operation cancelled".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl Drop for PulseConnection {
+    fn drop(&mut self) {
+        self.mainloop.borrow_mut().lock();
+        self.context.borrow_mut().set_subscribe_callback(None);
+        self.context.borrow_mut().disconnect();
+        self.mainloop.borrow_mut().unlock();
+        self.mainloop.borrow_mut().stop();
+    }
+}
+
+/// Negotiate the record format for a source with the given native spec
+///
+/// The server resamples and remixes on our behalf, so the requested rate and
+/// channel layout are granted as long as they are within PulseAudio limits.
+/// Fragments are limited to one second at the highest rate.
+pub(crate) fn negotiate_format(
+    config: &AudioInputConfig,
+    sample_rate: u32,
+    channels: u8,
+    default_fragment_frames: u32,
+) -> StreamFormat {
+    let rates = SampleRateRange::new(MIN_SAMPLE_RATE, MAX_SAMPLE_RATE);
+
+    StreamFormat {
+        sample_rate: rates.clamp(config.sample_rate.unwrap_or(sample_rate)),
+        channels: config
+            .channels
+            .output_channels(u16::from(channels))
+            .min(MAX_CHANNELS),
+        buffer_frames: Some(
+            config
+                .buffer_frames
+                .unwrap_or(default_fragment_frames)
+                .clamp(1, MAX_FRAGMENT_FRAMES),
+        ),
+    }
+}
+
+/// Data or failure delivered by a record stream
+pub(crate) enum RecordEvent<'a> {
+    /// Interleaved samples read from the server, the first of which was
+    /// captured `latency` ago
+    ///
+    /// Fragments kept on the server while the mainloop was busy arrive in a
+    /// burst, each dated by the latency the server reports for it.
+    Samples { data: &'a [f32], latency: Duration },
+    /// The stream stopped for good, e.g. because its source was removed
+    Failed(AudioError),
+}
+
+/// Handle to an active record stream
+pub(crate) struct RecordStream {
+    stream: Rc<RefCell<Stream>>,
+}
+
+/// Parameters of a record stream
+#[derive(Debug, Clone)]
+pub(crate) struct RecordSpec {
+    /// Context name shown to the sound server
+    pub context_name: &'static str,
+    /// Stream name shown in mixers such as pavucontrol
+    pub stream_name: &'static str,
+    /// PulseAudio source name to record from
+    pub source: String,
+    pub sample_rate: u32,
+    pub channels: u8,
+    /// Requested fragment size in frames
+    pub fragment_frames: u32,
+}
+
+/// Capture thread owning a [`PulseConnection`] and one record stream
+///
+/// PulseAudio objects cannot leave the thread that created them, so the
+/// worker connects, opens the stream and then parks until it is dropped.
+/// Dropping the worker closes the stream and joins the thread.
+pub(crate) struct RecordWorker {
+    stop_tx: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RecordWorker {
+    /// Spawn the worker and wait until the record stream is running
+    pub(crate) fn spawn<F>(
+        thread_name: &str,
+        record: RecordSpec,
+        on_event: F,
+    ) -> Result<Self, AudioError>
+    where
+        F: FnMut(RecordEvent<'_>) + Send + 'static,
+    {
+        let (ready_tx, ready_rx) = mpsc::channel::<Result<(), AudioError>>();
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+
+        let thread = thread::Builder::new()
+            .name(thread_name.to_string())
+            .spawn(move || {
+                let connection = match PulseConnection::connect(record.context_name) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+
+                let stream = match connection.record(&record, on_event) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+
+                let _ = ready_tx.send(Ok(()));
+
+                // Blocks until the sender is dropped
+                let _ = stop_rx.recv();
+
+                connection.stop_record(stream);
+            })
+            .map_err(|e| AudioError::StreamError(format!("Failed to spawn capture thread: {}", e)))?;
+
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok(Self {
+                stop_tx: Some(stop_tx),
+                thread: Some(thread),
+            }),
+            Ok(Err(e)) => {
+                let _ = thread.join();
+                Err(e)
+            }
+            Err(_) => {
+                let _ = thread.join();
+                Err(AudioError::StreamError(
+                    "Capture thread exited during startup".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+impl Drop for RecordWorker {
+    fn drop(&mut self) {
+        // Closing the channel releases the worker thread
+        self.stop_tx.take();
+
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("PulseAudio capture thread panicked");
+            }
+        }
+    }
+}
+
+/// Age of the oldest unread audio of a record stream
+///
+/// `None` until the server has sent timing information. A monitor can
+/// deliver audio before the sink plays it, which counts as no latency.
+fn record_latency(stream: &Stream) -> Option<Duration> {
+    match stream.get_latency() {
+        Ok(Latency::Positive(latency)) => Some(Duration::from_micros(latency.0)),
+        Ok(Latency::Negative(_)) => Some(Duration::ZERO),
+        Ok(Latency::None) => None,
+        Err(e) => {
+            tracing::debug!("Failed to get PulseAudio stream latency: {}", e);
+            None
+        }
+    }
+}
+
+/// Drop stream callbacks, breaking the Rc cycles they hold
+fn clear_stream_callbacks(stream: &Rc<RefCell<Stream>>) {
+    let mut stream = stream.borrow_mut();
+    stream.set_read_callback(None);
+    stream.set_state_callback(None);
+}
+
+/// Use the description if present, otherwise the internal name
+fn description_or(description: &Option<std::borrow::Cow<'_, str>>, name: &str) -> String {
+    description
+        .as_deref()
+        .map(str::to_string)
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// Derive a transport hint from the device properties
+///
+/// ALSA-backed devices carry `device.bus`; devices without a bus and with an
+/// `abstract` or `filter` class are provided by software (null sinks,
+/// echo cancellers, loopbacks).
+fn device_kind(proplist: &Proplist, is_monitor: bool) -> DeviceKind {
+    if is_monitor {
+        return DeviceKind::Monitor;
+    }
+
+    match proplist.get_str(properties::DEVICE_BUS).as_deref() {
+        Some("usb") => return DeviceKind::Usb,
+        Some("bluetooth") => return DeviceKind::Bluetooth,
+        Some("pci") | Some("platform") => return DeviceKind::Builtin,
+        _ => {}
+    }
+
+    if proplist.get_str(properties::DEVICE_FORM_FACTOR).as_deref() == Some("internal") {
+        return DeviceKind::Builtin;
+    }
+
+    match proplist.get_str(properties::DEVICE_CLASS).as_deref() {
+        Some("abstract") | Some("filter") => DeviceKind::Virtual,
+        _ => DeviceKind::Unknown,
+    }
+}
+
+/// Map a PulseAudio sample format onto the core sample formats
+fn sample_format(format: Format) -> Option<SampleFormat> {
+    match format {
+        Format::U8 => Some(SampleFormat::U8),
+        Format::S16le | Format::S16be => Some(SampleFormat::I16),
+        Format::S32le | Format::S32be | Format::S24_32le | Format::S24_32be => Some(SampleFormat::I32),
+        Format::F32le | Format::F32be => Some(SampleFormat::F32),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use heronote_audio_core::ChannelMode;
+
+    #[test]
+    fn test_negotiate_format_clamps_to_pulse_limits() {
+        let config = AudioInputConfig::new()
+            .with_sample_rate(1_000_000)
+            .with_channels(ChannelMode::Native)
+            .with_buffer_frames(u32::MAX);
+
+        let format = negotiate_format(&config, 44100, 64, 1024);
+        assert_eq!(format.sample_rate, MAX_SAMPLE_RATE);
+        assert_eq!(format.channels, MAX_CHANNELS);
+        assert_eq!(format.buffer_frames, Some(MAX_FRAGMENT_FRAMES));
+
+        let format = negotiate_format(&AudioInputConfig::default(), 44100, 2, 1024);
+        assert_eq!(format.sample_rate, 44100);
+        assert_eq!(format.buffer_frames, Some(1024));
+    }
+}
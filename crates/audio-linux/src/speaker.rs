@@ -1,62 +1,365 @@
-//! Linux speaker capture implementation (stub)
+//! Linux speaker audio capture using PulseAudio monitor sources
 //!
-//! This module will contain the Linux-specific speaker capture
-//! implementation using PulseAudio monitor device or ALSA.
+//! Every PulseAudio sink exposes a `.monitor` source carrying whatever is
+//! being played on it. This module records the monitor of the default sink,
+//! or any monitor selected by id, which also works on PipeWire through
+//! `pipewire-pulse`.
+//!
+//! PulseAudio objects are not `Send`, so they live on a dedicated
+//! [`RecordWorker`] thread. Samples reach the async consumer through a
+//! lock-free ring buffer, the same way the macOS implementation hands them
+//! over from Core Audio.
 
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
 
 use futures::Stream as FuturesStream;
-use heronote_audio_core::{AudioError, AudioInput, AudioStream};
+use ringbuf::{
+    traits::{Consumer, Observer, Producer, Split},
+    HeapCons, HeapProd, HeapRb,
+};
 
-/// Speaker input handler for Linux (stub)
-///
-/// This is a placeholder implementation. The actual Linux speaker
-/// capture will use PulseAudio monitor device for capturing system audio.
+use crate::device::{parse_device_id, DeviceId};
+use crate::pulse::{negotiate_format, PulseConnection, RecordEvent, RecordSpec, RecordWorker};
+use heronote_audio_core::{
+    capture_clock_now, AudioChunk, AudioError, AudioInput, AudioInputConfig,
+    AudioStream, FrameClock, GapTracker, StreamFormat,
+};
+
+/// PulseAudio context name used for speaker capture
+const CONTEXT_NAME: &str = "Heronote Speaker Capture";
+
+/// PulseAudio stream name shown in mixers such as pavucontrol
+const STREAM_NAME: &str = "Heronote Audio Tap";
+
+/// Number of samples per read chunk from the ring buffer
+const SAMPLES_PER_CHUNK: usize = 1024;
+
+/// Ring buffer capacity multiplier to prevent overflow during async delays
+/// At 48kHz, this gives ~1.3 seconds of buffer (65536 samples)
+const BUFFER_CAPACITY_MULTIPLIER: usize = 64;
+
+/// Speaker input handler for capturing system audio on Linux
 pub struct SpeakerInput {
-    // Private field to prevent external construction
-    _private: (),
+    source_name: String,
+    format: StreamFormat,
+}
+
+/// Internal state for waker coordination between audio callback and async executor
+struct WakerState {
+    waker: Option<Waker>,
+    has_data: bool,
+    /// Capture times of the frames written to the ring buffer
+    clock: FrameClock,
+    /// Audio lost before reaching the ring buffer
+    gaps: GapTracker,
+    /// Failure to report once the buffered audio has been read
+    error: Option<AudioError>,
+}
+
+/// Context owned by the PulseAudio read callback
+struct AudioContext {
+    producer: HeapProd<f32>,
+    channels: usize,
+    waker_state: Arc<Mutex<WakerState>>,
 }
 
 impl AudioInput for SpeakerInput {
     type Stream = SpeakerStream;
 
-    fn new() -> Result<Self, AudioError> {
-        Err(AudioError::PlatformNotSupported(
-            "Linux speaker capture coming soon".to_string(),
-        ))
+    /// Create a SpeakerInput for a monitor source
+    ///
+    /// Without a device id, the monitor of the default sink is used. The id
+    /// must name a monitor source as listed by [`crate::list_devices`].
+    fn with_config(config: &AudioInputConfig) -> Result<Self, AudioError> {
+        let connection = PulseConnection::connect(CONTEXT_NAME)?;
+
+        let (source_name, sample_rate, channels) = match &config.device_id {
+            Some(id) => {
+                let DeviceId::Pulse(name) = parse_device_id(id)? else {
+                    return Err(AudioError::DeviceNotAvailable(id.clone()));
+                };
+
+                let source = connection.source(name)?;
+                if !source.is_monitor {
+                    return Err(AudioError::DeviceNotAvailable(format!(
+                        "{} is not a monitor source",
+                        id
+                    )));
+                }
+
+                (source.name, source.sample_rate, source.channels)
+            }
+            None => {
+                let monitor = connection.default_monitor()?;
+                (monitor.name, monitor.sample_rate, monitor.channels)
+            }
+        };
+
+        let format = negotiate_format(config, sample_rate, channels, SAMPLES_PER_CHUNK as u32);
+
+        tracing::debug!(source = %source_name, ?format, "Resolved monitor source");
+
+        Ok(Self {
+            source_name,
+            format,
+        })
     }
 
-    fn sample_rate(&self) -> u32 {
-        // This method can never be called because `new()` always returns Err,
-        // meaning no instance of SpeakerInput can ever exist.
-        unreachable!("SpeakerInput cannot be instantiated on Linux (stub)")
+    fn format(&self) -> StreamFormat {
+        self.format
     }
 
+    /// Start capturing system audio and return a stream of samples
     fn stream(self) -> Result<SpeakerStream, AudioError> {
-        // This method can never be called because `new()` always returns Err
-        unreachable!("SpeakerInput cannot be instantiated on Linux (stub)")
+        let channels = self.format.channels as usize;
+        let buffer_capacity = SAMPLES_PER_CHUNK * channels * BUFFER_CAPACITY_MULTIPLIER;
+        let rb = HeapRb::<f32>::new(buffer_capacity);
+        let (producer, consumer) = rb.split();
+
+        let sample_rate = self.format.sample_rate;
+        let waker_state = Arc::new(Mutex::new(WakerState {
+            waker: None,
+            has_data: false,
+            clock: FrameClock::default(),
+            gaps: GapTracker::new(sample_rate),
+            error: None,
+        }));
+
+        let mut ctx = AudioContext {
+            producer,
+            channels,
+            waker_state: waker_state.clone(),
+        };
+
+        let record = RecordSpec {
+            context_name: CONTEXT_NAME,
+            stream_name: STREAM_NAME,
+            source: self.source_name.clone(),
+            sample_rate,
+            channels: self.format.channels as u8,
+            fragment_frames: self.format.buffer_frames.unwrap_or(SAMPLES_PER_CHUNK as u32),
+        };
+
+        let worker = RecordWorker::spawn("heronote-speaker", record, move |event| match event {
+            RecordEvent::Samples { data, latency } => process_audio_data(&mut ctx, data, latency),
+            RecordEvent::Failed(error) => report_error(&ctx, error),
+        })?;
+
+        tracing::info!(source = %self.source_name, sample_rate, channels, "Speaker capture initialized");
+
+        Ok(SpeakerStream {
+            consumer,
+            waker_state,
+            sample_rate,
+            channels: self.format.channels,
+            read_buffer: vec![0.0f32; SAMPLES_PER_CHUNK * channels],
+            frames_read: 0,
+            sequence: 0,
+            ended: false,
+            _worker: worker,
+        })
+    }
+}
+
+impl SpeakerInput {
+    /// Get the name of the monitor source being captured
+    pub fn source_name(&self) -> &str {
+        &self.source_name
+    }
+}
+
+/// Push audio data whose first frame was captured `latency` ago to the
+/// ring buffer and wake the async consumer
+///
+/// Only whole frames are pushed so the consumer never sees channels shift.
+/// The latency comes from the server, so fragments it kept while the
+/// mainloop stalled keep their capture times even though they arrive
+/// together. Frames that do not fit, and time skipped between fragments,
+/// are recorded as gaps for the consumer to fill with silence.
+fn process_audio_data(ctx: &mut AudioContext, data: &[f32], latency: Duration) {
+    let frames = (data.len() / ctx.channels) as u64;
+    let captured_at = capture_clock_now().saturating_sub(latency);
+
+    let writable = ctx.producer.vacant_len() / ctx.channels * ctx.channels;
+    let pushed = ctx.producer.push_slice(&data[..data.len().min(writable)]);
+
+    let should_wake = {
+        let mut waker_state = ctx.waker_state.lock().unwrap();
+        let first_frame = waker_state
+            .gaps
+            .write(captured_at, frames, (pushed / ctx.channels) as u64);
+        waker_state.clock.anchor(first_frame, captured_at);
+
+        if pushed > 0 && !waker_state.has_data {
+            waker_state.has_data = true;
+            waker_state.waker.take()
+        } else {
+            None
+        }
+    };
+
+    if let Some(waker) = should_wake {
+        waker.wake();
     }
 }
 
-/// Stream of audio samples from system speaker output (stub)
+/// Hand a capture failure to the async consumer
+fn report_error(ctx: &AudioContext, error: AudioError) {
+    let waker = {
+        let mut waker_state = ctx.waker_state.lock().unwrap();
+        waker_state.error = Some(error);
+        waker_state.waker.take()
+    };
+
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+// ============================================================================
+// SpeakerStream implementation
+// ============================================================================
+
+/// Stream of audio samples from system speaker output
 pub struct SpeakerStream {
-    // Private field to prevent external construction
-    _private: (),
+    consumer: HeapCons<f32>,
+    waker_state: Arc<Mutex<WakerState>>,
+    sample_rate: u32,
+    channels: u16,
+    read_buffer: Vec<f32>,
+    /// Frames delivered so far, including gap fill
+    frames_read: u64,
+    sequence: u64,
+    /// Set once a capture failure has been delivered
+    ended: bool,
+    _worker: RecordWorker,
 }
 
 impl AudioStream for SpeakerStream {
     fn sample_rate(&self) -> u32 {
-        // This method can never be called because SpeakerStream cannot be created
-        unreachable!("SpeakerStream cannot be created on Linux (stub)")
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
     }
 }
 
 impl FuturesStream for SpeakerStream {
-    type Item = Vec<f32>;
+    type Item = Result<AudioChunk, AudioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.as_mut().get_mut();
+
+        if this.ended {
+            return Poll::Ready(None);
+        }
+
+        let (clock, readable, gap) = {
+            let mut state = this.waker_state.lock().unwrap();
+            let gap = state.gaps.take_gap(this.frames_read);
+            (state.clock, state.gaps.readable(this.frames_read), gap)
+        };
+
+        if let Some(frames) = gap {
+            let chunk = AudioChunk::silence(
+                frames as usize,
+                this.sample_rate,
+                this.channels,
+                clock.timestamp_of(this.frames_read, this.sample_rate),
+                this.sequence,
+            );
+            this.frames_read += frames;
+            this.sequence += 1;
+            return Poll::Ready(Some(Ok(chunk)));
+        }
+
+        // Stop at the next gap so the silence lands in the right place
+        let limit = readable.map_or(this.read_buffer.len(), |frames| {
+            (frames as usize * this.channels as usize).min(this.read_buffer.len())
+        });
+        let popped = this.consumer.pop_slice(&mut this.read_buffer[..limit]);
+
+        if popped > 0 {
+            let chunk = AudioChunk::new(
+                this.read_buffer[..popped].to_vec(),
+                this.sample_rate,
+                this.channels,
+                clock.timestamp_of(this.frames_read, this.sample_rate),
+                this.sequence,
+            );
+            this.frames_read += (popped / this.channels as usize) as u64;
+            this.sequence += 1;
+            return Poll::Ready(Some(Ok(chunk)));
+        }
+
+        {
+            let mut state = this.waker_state.lock().unwrap();
+            if let Some(error) = state.error.take() {
+                this.ended = true;
+                return Poll::Ready(Some(Err(error)));
+            }
+            state.has_data = false;
+            state.waker = Some(cx.waker().clone());
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for SpeakerStream {
+    fn drop(&mut self) {
+        tracing::info!("Speaker stream stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    use heronote_audio_core::frames_to_duration;
+
+    #[test]
+    fn test_late_contiguous_fragments_leave_no_gap() {
+        const FRAGMENT: usize = 1024;
+        let (sample_rate, channels) = (48000, 2);
+        let (producer, _consumer) = HeapRb::<f32>::new(FRAGMENT * channels * 32).split();
+        let waker_state = Arc::new(Mutex::new(WakerState {
+            waker: None,
+            has_data: false,
+            clock: FrameClock::default(),
+            gaps: GapTracker::new(sample_rate),
+            error: None,
+        }));
+        let mut ctx = AudioContext {
+            producer,
+            channels,
+            waker_state: waker_state.clone(),
+        };
+
+        let data = vec![0.25; FRAGMENT * channels];
+        let length = frames_to_duration(FRAGMENT as u64, sample_rate);
+        let start = capture_clock_now().saturating_sub(length);
+        process_audio_data(&mut ctx, &data, length);
+
+        // The mainloop stalls for half a second while the server keeps
+        // recording, then the kept fragments arrive at once, each with the
+        // latency the server reports for it
+        thread::sleep(Duration::from_millis(500));
+        let fragments = 20;
+        for n in 1..=fragments {
+            let captured_at = start + length * n;
+            process_audio_data(&mut ctx, &data, capture_clock_now().saturating_sub(captured_at));
+        }
 
-    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        // This method can never be called because SpeakerStream cannot be created
-        unreachable!("SpeakerStream cannot be created on Linux (stub)")
+        let mut state = waker_state.lock().unwrap();
+        assert_eq!(state.gaps.readable(0), None);
+        assert_eq!(state.gaps.take_gap(0), None);
+        assert_eq!(state.gaps.frames_written(), (fragments as u64 + 1) * FRAGMENT as u64);
+        assert!(state.clock.timestamp_of(0, sample_rate).abs_diff(start) < Duration::from_millis(5));
     }
 }
@@ -0,0 +1,217 @@
+//! Device hot-plug notifications from PulseAudio
+//!
+//! A dedicated thread subscribes to sink, source and server events. Every
+//! burst of events triggers a fresh device listing, which the core
+//! [`DeviceTracker`] turns into [`heronote_audio_core::DeviceEvent`]s.
+
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use heronote_audio_core::{AudioDevice, AudioError, DeviceTracker, DeviceWatcher};
+
+use crate::device::list_pulse_devices_with;
+use crate::pulse::PulseConnection;
+
+/// PulseAudio context name used for device notifications
+const CONTEXT_NAME: &str = "Heronote Device Watcher";
+
+/// Quiet period after an event before the devices are listed again
+///
+/// Plugging in a headset produces a sink, a source and a server change
+/// within a few milliseconds; waiting lets them collapse into one listing.
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+/// Message to the watcher thread
+enum Signal {
+    Changed,
+    Stop,
+}
+
+/// Start watching PulseAudio for device changes
+///
+/// The returned [`DeviceWatcher`] yields an event whenever a device is added
+/// or removed, or the default sink or source changes. Unlike
+/// [`crate::list_devices`], there is no ALSA fallback: watching requires a
+/// PulseAudio or PipeWire server.
+pub fn watch_devices() -> Result<DeviceWatcher, AudioError> {
+    let (signal_tx, signal_rx) = mpsc::channel::<Signal>();
+    let (ready_tx, ready_rx) = mpsc::channel::<Result<DeviceWatcher, AudioError>>();
+
+    let on_change = signal_tx.clone();
+    let thread = thread::Builder::new()
+        .name("heronote-devices".to_string())
+        .spawn(move || {
+            let setup = PulseConnection::connect(CONTEXT_NAME).and_then(|connection| {
+                connection.subscribe(move || {
+                    let _ = on_change.send(Signal::Changed);
+                })?;
+                let devices = list_pulse_devices_with(&connection)?;
+                Ok((connection, devices))
+            });
+
+            let (connection, devices) = match setup {
+                Ok(setup) => setup,
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                    return;
+                }
+            };
+
+            let (tracker, watcher) = DeviceWatcher::channel(devices);
+            if ready_tx.send(Ok(watcher)).is_err() {
+                return;
+            }
+
+            run(|| list_pulse_devices_with(&connection), tracker, &signal_rx);
+        })
+        .map_err(|e| AudioError::DeviceError(format!("Failed to spawn device watcher thread: {}", e)))?;
+
+    let worker = WatchWorker {
+        signal_tx,
+        thread: Some(thread),
+    };
+
+    match ready_rx.recv() {
+        Ok(Ok(watcher)) => Ok(watcher.with_guard(worker)),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(AudioError::DeviceError(
+            "Device watcher thread exited during startup".to_string(),
+        )),
+    }
+}
+
+/// Relist devices with `list` after every settled burst of events until
+/// told to stop
+fn run(
+    mut list: impl FnMut() -> Result<Vec<AudioDevice>, AudioError>,
+    mut tracker: DeviceTracker,
+    signals: &mpsc::Receiver<Signal>,
+) {
+    while let Ok(Signal::Changed) = signals.recv() {
+        loop {
+            match signals.recv_timeout(SETTLE_TIME) {
+                Ok(Signal::Changed) => continue,
+                Ok(Signal::Stop) | Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+            }
+        }
+
+        match list() {
+            Ok(devices) => {
+                if !tracker.update(devices) {
+                    return;
+                }
+            }
+            Err(e) => tracing::warn!("Failed to list devices after change: {}", e),
+        }
+    }
+}
+
+/// Watcher thread handle; dropping it stops and joins the thread
+struct WatchWorker {
+    signal_tx: mpsc::Sender<Signal>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for WatchWorker {
+    fn drop(&mut self) {
+        let _ = self.signal_tx.send(Signal::Stop);
+
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("Device watcher thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use heronote_audio_core::DeviceType;
+
+    /// Spawn `run` with a listing that counts its calls and reports each one
+    fn spawn_run(
+        tracker: DeviceTracker,
+        signals: mpsc::Receiver<Signal>,
+    ) -> (Arc<AtomicUsize>, mpsc::Receiver<()>, JoinHandle<()>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let (listed_tx, listed_rx) = mpsc::channel();
+
+        let calls = count.clone();
+        let thread = thread::spawn(move || {
+            let list = || {
+                let n = calls.fetch_add(1, Ordering::SeqCst);
+                let _ = listed_tx.send(());
+                let name = format!("mic {}", n);
+                Ok(vec![AudioDevice::new(name.clone(), name, DeviceType::Input, true)])
+            };
+            run(list, tracker, &signals);
+        });
+
+        (count, listed_rx, thread)
+    }
+
+    #[test]
+    fn test_burst_of_changes_lists_once() {
+        let (tracker, _watcher) = DeviceWatcher::channel(Vec::new());
+        let (signal_tx, signal_rx) = mpsc::channel();
+        for _ in 0..3 {
+            signal_tx.send(Signal::Changed).unwrap();
+        }
+
+        let (count, listed, thread) = spawn_run(tracker, signal_rx);
+        listed.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(listed.recv_timeout(SETTLE_TIME * 3).is_err());
+
+        // A later burst lists again
+        signal_tx.send(Signal::Changed).unwrap();
+        listed.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        signal_tx.send(Signal::Stop).unwrap();
+        thread.join().unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_stop_while_settling_skips_listing() {
+        let (tracker, _watcher) = DeviceWatcher::channel(Vec::new());
+        let (signal_tx, signal_rx) = mpsc::channel();
+        signal_tx.send(Signal::Changed).unwrap();
+        signal_tx.send(Signal::Stop).unwrap();
+
+        let (count, _listed, thread) = spawn_run(tracker, signal_rx);
+        thread.join().unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_disconnect_while_settling_skips_listing() {
+        let (tracker, _watcher) = DeviceWatcher::channel(Vec::new());
+        let (signal_tx, signal_rx) = mpsc::channel();
+        signal_tx.send(Signal::Changed).unwrap();
+        drop(signal_tx);
+
+        let (count, _listed, thread) = spawn_run(tracker, signal_rx);
+        thread.join().unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_dropped_watcher_stops_run() {
+        let (tracker, watcher) = DeviceWatcher::channel(Vec::new());
+        drop(watcher);
+        let (signal_tx, signal_rx) = mpsc::channel();
+        signal_tx.send(Signal::Changed).unwrap();
+
+        // Returns after the first listing even though signals can still come
+        let (count, _listed, thread) = spawn_run(tracker, signal_rx);
+        thread.join().unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        drop(signal_tx);
+    }
+}
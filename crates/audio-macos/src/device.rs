@@ -1,10 +1,27 @@
+use std::collections::HashMap;
+
+use cidre::core_audio as ca;
 use cpal::traits::{DeviceTrait, HostTrait};
-use heronote_audio_core::{AudioDevice, AudioError, DeviceType};
+use heronote_audio_core::{
+    cpal_capabilities, AudioDevice, AudioError, Capabilities, DeviceKind, DeviceType,
+};
+
+/// Core Audio identity of a device
+struct CoreAudioInfo {
+    name: String,
+    uid: String,
+    kind: DeviceKind,
+}
 
 /// List all available audio devices on macOS
+///
+/// Device ids are Core Audio UIDs, which persist across reconnects and
+/// reboots and tell apart two identical devices.
 pub fn list_devices() -> Result<Vec<AudioDevice>, AudioError> {
     let host = cpal::default_host();
-    let mut devices = Vec::new();
+    let core_audio = core_audio_devices();
+    let mut inputs = Vec::new();
+    let mut outputs = Vec::new();
 
     let default_input = host
         .default_input_device()
@@ -13,32 +30,102 @@ pub fn list_devices() -> Result<Vec<AudioDevice>, AudioError> {
         .default_output_device()
         .and_then(|d| d.name().ok());
 
-    // List input devices
-    if let Ok(input_devices) = host.input_devices() {
-        for device in input_devices {
-            if let Ok(name) = device.name() {
-                // Skip macOS TAP virtual devices
-                if name.contains("TAP") {
-                    continue;
-                }
+    let all_devices = host
+        .devices()
+        .map_err(|e| AudioError::DeviceError(e.to_string()))?;
 
+    // cpal enumerates devices in `kAudioHardwarePropertyDevices` order, so
+    // the n-th device with a given name is the n-th Core Audio device with
+    // that name.
+    let mut seen: HashMap<String, usize> = HashMap::new();
+
+    for device in all_devices {
+        let Ok(name) = device.name() else {
+            continue;
+        };
+
+        let occurrence = seen.entry(name.clone()).or_default();
+        let info = core_audio
+            .iter()
+            .filter(|info| info.name == name)
+            .nth(*occurrence);
+        *occurrence += 1;
+
+        // Skip macOS TAP virtual devices
+        if !name.contains("TAP") {
+            if let Some(capabilities) = cpal_capabilities(&device, &DeviceType::Input) {
                 let is_default = default_input.as_ref() == Some(&name);
-                devices.push(AudioDevice::new(name, DeviceType::Input, is_default));
+                inputs.push(describe_device(info, &name, DeviceType::Input, is_default, capabilities));
             }
         }
-    }
 
-    // List output devices
-    if let Ok(output_devices) = host.output_devices() {
-        for device in output_devices {
-            if let Ok(name) = device.name() {
-                let is_default = default_output.as_ref() == Some(&name);
-                devices.push(AudioDevice::new(name, DeviceType::Output, is_default));
-            }
+        if let Some(capabilities) = cpal_capabilities(&device, &DeviceType::Output) {
+            let is_default = default_output.as_ref() == Some(&name);
+            outputs.push(describe_device(info, &name, DeviceType::Output, is_default, capabilities));
         }
     }
 
-    Ok(devices)
+    inputs.extend(outputs);
+    Ok(inputs)
+}
+
+/// Build an [`AudioDevice`] from its Core Audio identity and cpal capabilities
+fn describe_device(
+    info: Option<&CoreAudioInfo>,
+    name: &str,
+    device_type: DeviceType,
+    is_default: bool,
+    (channels, sample_rates, sample_formats): Capabilities,
+) -> AudioDevice {
+    // Without a UID the name is the best identity we have
+    let (id, kind) = match info {
+        Some(info) => (info.uid.clone(), info.kind),
+        None => (name.to_string(), DeviceKind::Unknown),
+    };
+
+    AudioDevice::new(id, name.to_string(), device_type, is_default)
+        .with_kind(kind)
+        .with_capabilities(channels, sample_rates, sample_formats)
+}
+
+/// Read name, UID and transport type of every Core Audio device
+fn core_audio_devices() -> Vec<CoreAudioInfo> {
+    let devices = match ca::System::devices() {
+        Ok(devices) => devices,
+        Err(e) => {
+            tracing::warn!("Failed to list Core Audio devices: {:?}", e);
+            return Vec::new();
+        }
+    };
+
+    devices
+        .iter()
+        .filter_map(|device| {
+            let name = device.name().ok()?.to_string();
+            let uid = device.uid().ok()?.to_string();
+            let kind = device
+                .transport_type()
+                .map(device_kind)
+                .unwrap_or_default();
+
+            Some(CoreAudioInfo { name, uid, kind })
+        })
+        .collect()
+}
+
+/// Map a Core Audio transport type onto a device kind
+fn device_kind(transport: ca::DeviceTransportType) -> DeviceKind {
+    match transport {
+        ca::DeviceTransportType::BUILT_IN => DeviceKind::Builtin,
+        ca::DeviceTransportType::USB => DeviceKind::Usb,
+        ca::DeviceTransportType::BLUETOOTH | ca::DeviceTransportType::BLUETOOTH_LE => {
+            DeviceKind::Bluetooth
+        }
+        ca::DeviceTransportType::VIRTUAL | ca::DeviceTransportType::AGGREGATE => {
+            DeviceKind::Virtual
+        }
+        _ => DeviceKind::Unknown,
+    }
 }
 
 /// Get the default input device
@@ -64,3 +151,35 @@ pub fn get_input_device_by_name(name: &str) -> Result<cpal::Device, AudioError>
 
     Err(AudioError::DeviceNotAvailable(name.to_string()))
 }
+
+/// Get a specific input device by the id reported in [`list_devices`]
+pub fn get_input_device_by_id(id: &str) -> Result<cpal::Device, AudioError> {
+    let not_available = || AudioError::DeviceNotAvailable(id.to_string());
+
+    let core_audio = core_audio_devices();
+    let Some(info) = core_audio.iter().find(|info| info.uid == id) else {
+        // Devices without a UID are listed under their name
+        return get_input_device_by_name(id);
+    };
+
+    // Position among same-named Core Audio devices equals the cpal position
+    let occurrence = core_audio
+        .iter()
+        .filter(|other| other.name == info.name)
+        .position(|other| other.uid == info.uid)
+        .ok_or_else(not_available)?;
+
+    let host = cpal::default_host();
+    let device = host
+        .devices()
+        .map_err(|e| AudioError::DeviceError(e.to_string()))?
+        .filter(|d| d.name().ok().as_deref() == Some(info.name.as_str()))
+        .nth(occurrence)
+        .ok_or_else(not_available)?;
+
+    if device.default_input_config().is_err() {
+        return Err(not_available());
+    }
+
+    Ok(device)
+}
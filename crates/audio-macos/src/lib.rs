@@ -1,9 +1,13 @@
-mod conversion;
 mod device;
 mod mic;
 mod speaker;
+mod watcher;
 
-pub use heronote_audio_core::{AudioDevice, AudioError, DeviceType, AudioInput, AudioStream};
+pub use heronote_audio_core::{
+    AudioDevice, AudioError, AudioInput, AudioInputConfig, AudioStream, ChannelMode, DeviceEvent,
+    DeviceType, DeviceWatcher, StreamFormat,
+};
 pub use mic::{MicInput, MicStream};
 pub use speaker::{SpeakerInput, SpeakerStream};
 pub use device::list_devices;
+pub use watcher::watch_devices;
@@ -1,39 +1,50 @@
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 use cpal::traits::{DeviceTrait, StreamTrait};
-use cpal::{SampleFormat, Stream, StreamConfig, SupportedStreamConfig};
+use cpal::{
+    BufferSize, SampleFormat, Stream, StreamConfig, SupportedBufferSize, SupportedStreamConfig,
+    SupportedStreamConfigRange,
+};
 use futures::Stream as FuturesStream;
 use tokio::sync::mpsc as tokio_mpsc;
 
-use crate::conversion::{convert_i16_slice_to_f32, convert_i32_slice_to_f32, convert_to_mono};
-use crate::device::{get_default_input_device, get_input_device_by_name};
-use heronote_audio_core::{AudioError, AudioInput, AudioStream};
+use crate::device::{get_default_input_device, get_input_device_by_id, get_input_device_by_name};
+use heronote_audio_core::conversion::{convert_i16_slice_to_f32, convert_i32_slice_to_f32, remix_channels};
+use heronote_audio_core::{
+    capture_clock_now, negotiate, AudioChunk, AudioError, AudioInput, AudioInputConfig,
+    AudioStream, ConfigCandidate, SampleRateRange, StreamActivity, StreamErrorFilter, StreamFormat,
+};
 
 /// Microphone input handler for macOS
 pub struct MicInput {
     device: cpal::Device,
-    config: StreamConfig,
+    config: SupportedStreamConfig,
+    format: StreamFormat,
 }
 
 impl AudioInput for MicInput {
     type Stream = MicStream;
 
-    fn new() -> Result<Self, AudioError> {
-        let device = get_default_input_device()?;
-        Self::from_device(device)
+    fn with_config(config: &AudioInputConfig) -> Result<Self, AudioError> {
+        let device = match config.device_id.as_deref() {
+            Some(id) => get_input_device_by_id(id)?,
+            None => get_default_input_device()?,
+        };
+        Self::from_device(device, config)
     }
 
-    fn sample_rate(&self) -> u32 {
-        self.config.sample_rate.0
+    fn format(&self) -> StreamFormat {
+        self.format
     }
 
     fn stream(self) -> Result<MicStream, AudioError> {
-        let (tx, rx) = tokio_mpsc::unbounded_channel::<Vec<f32>>();
-        let sample_rate = self.sample_rate();
+        let (tx, rx) = tokio_mpsc::unbounded_channel::<Result<AudioChunk, AudioError>>();
+        let sample_rate = self.format.sample_rate;
+        let channels = self.format.channels;
 
-        let supported_config = self.get_supported_config()?;
-        let stream = self.build_stream(&supported_config, tx)?;
+        let stream = self.build_stream(ChunkSender::new(tx, &self.format))?;
 
         stream
             .play()
@@ -43,6 +54,8 @@ impl AudioInput for MicInput {
             _stream: stream,
             receiver: rx,
             sample_rate,
+            channels,
+            ended: false,
         })
     }
 }
@@ -51,21 +64,79 @@ impl MicInput {
     /// Create a MicInput with a specific device name
     pub fn with_device_name(name: &str) -> Result<Self, AudioError> {
         let device = get_input_device_by_name(name)?;
-        Self::from_device(device)
+        Self::from_device(device, &AudioInputConfig::default())
     }
 
-    fn from_device(device: cpal::Device) -> Result<Self, AudioError> {
-        let config = device
+    /// Create a MicInput for a device id returned by [`crate::list_devices`]
+    pub fn with_device_id(id: &str) -> Result<Self, AudioError> {
+        Self::with_config(&AudioInputConfig::new().with_device_id(id))
+    }
+
+    /// Negotiate the closest supported configuration of a cpal device
+    fn from_device(device: cpal::Device, config: &AudioInputConfig) -> Result<Self, AudioError> {
+        let default_config = device
             .default_input_config()
             .map_err(|e| AudioError::DeviceError(e.to_string()))?;
 
-        let config = StreamConfig {
-            channels: 1,
-            sample_rate: config.sample_rate(),
-            buffer_size: cpal::BufferSize::Default,
+        // Only consider formats the capture callback can convert
+        let ranges: Vec<SupportedStreamConfigRange> = device
+            .supported_input_configs()
+            .map_err(|e| AudioError::DeviceError(e.to_string()))?
+            .filter(|range| {
+                matches!(
+                    range.sample_format(),
+                    SampleFormat::F32 | SampleFormat::I16 | SampleFormat::I32
+                )
+            })
+            .collect();
+
+        let candidates: Vec<ConfigCandidate> = ranges
+            .iter()
+            .map(|range| ConfigCandidate {
+                channels: range.channels(),
+                sample_rates: SampleRateRange::new(
+                    range.min_sample_rate().0,
+                    range.max_sample_rate().0,
+                ),
+            })
+            .collect();
+
+        let (supported_config, buffer_frames) =
+            match negotiate(config, &candidates, default_config.sample_rate().0) {
+                Some(negotiated) => {
+                    let range = ranges[negotiated.index];
+                    let buffer_frames = config.buffer_frames.map(|frames| match range.buffer_size() {
+                        SupportedBufferSize::Range { min, max } => frames.clamp(*min, *max),
+                        SupportedBufferSize::Unknown => frames,
+                    });
+
+                    let supported_config =
+                        range.with_sample_rate(cpal::SampleRate(negotiated.sample_rate));
+
+                    (supported_config, buffer_frames)
+                }
+                None => (default_config, None),
+            };
+
+        let format = StreamFormat {
+            sample_rate: supported_config.sample_rate().0,
+            channels: config.channels.output_channels(supported_config.channels()),
+            buffer_frames,
         };
 
-        Ok(Self { device, config })
+        if config.sample_rate.is_some_and(|rate| rate != format.sample_rate) {
+            tracing::info!(
+                requested = config.sample_rate,
+                actual = format.sample_rate,
+                "Device does not support the requested sample rate"
+            );
+        }
+
+        Ok(Self {
+            device,
+            config: supported_config,
+            format,
+        })
     }
 
     /// Get the device name
@@ -75,39 +146,43 @@ impl MicInput {
             .map_err(|e| AudioError::DeviceError(e.to_string()))
     }
 
-    /// Get the supported stream configuration from the device
-    fn get_supported_config(&self) -> Result<SupportedStreamConfig, AudioError> {
-        self.device
-            .default_input_config()
-            .map_err(|e| AudioError::DeviceError(e.to_string()))
-    }
-
     /// Build the input stream based on the sample format
     ///
     /// This method handles the different sample formats (F32, I16, I32) and
-    /// creates the appropriate stream that converts all audio to f32 mono.
-    fn build_stream(
-        &self,
-        supported_config: &SupportedStreamConfig,
-        tx: tokio_mpsc::UnboundedSender<Vec<f32>>,
-    ) -> Result<Stream, AudioError> {
+    /// creates the appropriate stream that converts all audio to f32 with the
+    /// negotiated channel count.
+    fn build_stream(&self, mut sender: ChunkSender) -> Result<Stream, AudioError> {
+        let supported_config = &self.config;
         let channels = supported_config.channels() as usize;
+        let out_channels = self.format.channels as usize;
         let sample_format = supported_config.sample_format();
 
         let config = StreamConfig {
             channels: supported_config.channels(),
             sample_rate: supported_config.sample_rate(),
-            buffer_size: cpal::BufferSize::Default,
+            buffer_size: match self.format.buffer_frames {
+                Some(frames) => BufferSize::Fixed(frames),
+                None => BufferSize::Default,
+            },
         };
 
-        let err_fn = |err| {
-            tracing::error!("Audio stream error: {}", err);
+        let mut filter = StreamErrorFilter::new(self.device.name().unwrap_or_default());
+        sender.activity = Some(filter.activity());
+        let errors = sender.tx.clone();
+        let err_fn = move |err| {
+            let Some(error) = filter.filter(err) else {
+                return;
+            };
+            tracing::error!("Audio stream error: {}", error);
+            if let Err(e) = errors.send(Err(error)) {
+                tracing::debug!("Failed to send stream error (receiver dropped): {}", e);
+            }
         };
 
         match sample_format {
-            SampleFormat::F32 => self.build_f32_stream(&config, channels, tx, err_fn),
-            SampleFormat::I16 => self.build_i16_stream(&config, channels, tx, err_fn),
-            SampleFormat::I32 => self.build_i32_stream(&config, channels, tx, err_fn),
+            SampleFormat::F32 => self.build_f32_stream(&config, channels, out_channels, sender, err_fn),
+            SampleFormat::I16 => self.build_i16_stream(&config, channels, out_channels, sender, err_fn),
+            SampleFormat::I32 => self.build_i32_stream(&config, channels, out_channels, sender, err_fn),
             _ => Err(AudioError::UnsupportedFormat),
         }
     }
@@ -117,7 +192,8 @@ impl MicInput {
         &self,
         config: &StreamConfig,
         channels: usize,
-        tx: tokio_mpsc::UnboundedSender<Vec<f32>>,
+        out_channels: usize,
+        mut sender: ChunkSender,
         err_fn: E,
     ) -> Result<Stream, AudioError>
     where
@@ -126,9 +202,8 @@ impl MicInput {
         self.device
             .build_input_stream(
                 config,
-                move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                    let mono = convert_to_mono(data, channels);
-                    send_samples(&tx, mono);
+                move |data: &[f32], info: &cpal::InputCallbackInfo| {
+                    sender.send(remix_channels(data, channels, out_channels), capture_latency(info));
                 },
                 err_fn,
                 None,
@@ -141,7 +216,8 @@ impl MicInput {
         &self,
         config: &StreamConfig,
         channels: usize,
-        tx: tokio_mpsc::UnboundedSender<Vec<f32>>,
+        out_channels: usize,
+        mut sender: ChunkSender,
         err_fn: E,
     ) -> Result<Stream, AudioError>
     where
@@ -150,10 +226,9 @@ impl MicInput {
         self.device
             .build_input_stream(
                 config,
-                move |data: &[i16], _: &cpal::InputCallbackInfo| {
+                move |data: &[i16], info: &cpal::InputCallbackInfo| {
                     let float_data = convert_i16_slice_to_f32(data);
-                    let mono = convert_to_mono(&float_data, channels);
-                    send_samples(&tx, mono);
+                    sender.send(remix_channels(&float_data, channels, out_channels), capture_latency(info));
                 },
                 err_fn,
                 None,
@@ -166,7 +241,8 @@ impl MicInput {
         &self,
         config: &StreamConfig,
         channels: usize,
-        tx: tokio_mpsc::UnboundedSender<Vec<f32>>,
+        out_channels: usize,
+        mut sender: ChunkSender,
         err_fn: E,
     ) -> Result<Stream, AudioError>
     where
@@ -175,10 +251,9 @@ impl MicInput {
         self.device
             .build_input_stream(
                 config,
-                move |data: &[i32], _: &cpal::InputCallbackInfo| {
+                move |data: &[i32], info: &cpal::InputCallbackInfo| {
                     let float_data = convert_i32_slice_to_f32(data);
-                    let mono = convert_to_mono(&float_data, channels);
-                    send_samples(&tx, mono);
+                    sender.send(remix_channels(&float_data, channels, out_channels), capture_latency(info));
                 },
                 err_fn,
                 None,
@@ -187,15 +262,59 @@ impl MicInput {
     }
 }
 
-/// Send audio samples through the channel with proper error logging
-///
-/// In audio callbacks, we cannot block or handle errors in a complex way,
-/// so we log warnings if the receiver has been dropped (which indicates
-/// the stream is being shut down).
-fn send_samples(tx: &tokio_mpsc::UnboundedSender<Vec<f32>>, samples: Vec<f32>) {
-    if let Err(e) = tx.send(samples) {
-        // Only log at debug level since this typically happens during shutdown
-        tracing::debug!("Failed to send audio samples (receiver dropped): {}", e);
+/// Time between the capture of a cpal buffer and its callback
+fn capture_latency(info: &cpal::InputCallbackInfo) -> Duration {
+    let timestamp = info.timestamp();
+    timestamp
+        .callback
+        .duration_since(&timestamp.capture)
+        .unwrap_or_default()
+}
+
+/// Stamps captured buffers and sends them to the [`MicStream`]
+struct ChunkSender {
+    tx: tokio_mpsc::UnboundedSender<Result<AudioChunk, AudioError>>,
+    sample_rate: u32,
+    channels: u16,
+    sequence: u64,
+    /// Tells the cpal error callback that audio still arrives
+    activity: Option<StreamActivity>,
+}
+
+impl ChunkSender {
+    fn new(tx: tokio_mpsc::UnboundedSender<Result<AudioChunk, AudioError>>, format: &StreamFormat) -> Self {
+        Self {
+            tx,
+            sample_rate: format.sample_rate,
+            channels: format.channels,
+            sequence: 0,
+            activity: None,
+        }
+    }
+
+    /// Send samples captured `latency` ago through the channel
+    ///
+    /// In audio callbacks, we cannot block or handle errors in a complex way,
+    /// so we log warnings if the receiver has been dropped (which indicates
+    /// the stream is being shut down).
+    fn send(&mut self, samples: Vec<f32>, latency: Duration) {
+        if let Some(activity) = &self.activity {
+            activity.audio_received();
+        }
+
+        let chunk = AudioChunk::new(
+            samples,
+            self.sample_rate,
+            self.channels,
+            capture_clock_now().saturating_sub(latency),
+            self.sequence,
+        );
+        self.sequence += 1;
+
+        if let Err(e) = self.tx.send(Ok(chunk)) {
+            // Only log at debug level since this typically happens during shutdown
+            tracing::debug!("Failed to send audio samples (receiver dropped): {}", e);
+        }
     }
 }
 
@@ -206,20 +325,36 @@ fn send_samples(tx: &tokio_mpsc::UnboundedSender<Vec<f32>>, samples: Vec<f32>) {
 /// Stream of audio samples from the microphone
 pub struct MicStream {
     _stream: Stream,
-    receiver: tokio_mpsc::UnboundedReceiver<Vec<f32>>,
+    receiver: tokio_mpsc::UnboundedReceiver<Result<AudioChunk, AudioError>>,
     sample_rate: u32,
+    channels: u16,
+    /// Set once a fatal error has been delivered
+    ended: bool,
 }
 
 impl AudioStream for MicStream {
     fn sample_rate(&self) -> u32 {
         self.sample_rate
     }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
 }
 
 impl FuturesStream for MicStream {
-    type Item = Vec<f32>;
+    type Item = Result<AudioChunk, AudioError>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        Pin::new(&mut self.receiver).poll_recv(cx)
+        if self.ended {
+            return Poll::Ready(None);
+        }
+
+        let item = futures::ready!(Pin::new(&mut self.receiver).poll_recv(cx));
+        if let Some(Err(e)) = &item {
+            self.ended = !e.is_recoverable();
+        }
+
+        Poll::Ready(item)
     }
 }
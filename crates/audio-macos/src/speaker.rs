@@ -5,21 +5,26 @@
 //! being played to the speakers.
 
 use std::any::TypeId;
+use std::collections::VecDeque;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll, Waker};
+use std::time::Duration;
 
 use ca::aggregate_device_keys as agg_keys;
-use cidre::{arc, av, cat, cf, core_audio as ca, ns, os};
+use cidre::{arc, av, cat, cf, core_audio as ca, mach, ns, os};
 use futures::Stream as FuturesStream;
 use ringbuf::{
     traits::{Consumer, Producer, Split},
     HeapCons, HeapProd, HeapRb,
 };
 
-use crate::conversion::{f64_to_f32, i16_to_f32, i32_to_f32};
-use heronote_audio_core::{AudioError, AudioInput, AudioStream};
+use heronote_audio_core::conversion::{f64_to_f32, i16_to_f32, i32_to_f32};
+use heronote_audio_core::{
+    capture_clock_now, frames_to_duration, AudioChunk, AudioError, AudioInput, AudioInputConfig,
+    AudioStream, FrameClock, GapTracker, StreamFormat,
+};
 
 /// Device name for the audio tap aggregate device
 const TAP_DEVICE_NAME: &str = "Heronote Audio Tap";
@@ -44,6 +49,13 @@ pub struct SpeakerInput {
 struct WakerState {
     waker: Option<Waker>,
     has_data: bool,
+    /// Capture times of the frames written to the ring buffer
+    clock: FrameClock,
+    /// Audio lost before reaching the ring buffer
+    gaps: GapTracker,
+    /// Errors to report once the buffered audio has been read, with the
+    /// stream frame they follow
+    errors: VecDeque<(u64, AudioError)>,
 }
 
 /// Context passed to the Core Audio IO proc callback
@@ -52,6 +64,12 @@ struct AudioContext {
     producer: HeapProd<f32>,
     waker_state: Arc<Mutex<WakerState>>,
     current_sample_rate: Arc<AtomicU32>,
+    /// Mach host time to nanoseconds ratio as (numer, denom)
+    timebase: (u32, u32),
+    /// Capture time of the buffer being processed
+    captured_at: Duration,
+    /// Whether an unsupported tap format has already been reported
+    format_error_reported: bool,
 }
 
 impl AudioInput for SpeakerInput {
@@ -59,10 +77,22 @@ impl AudioInput for SpeakerInput {
 
     /// Create a new SpeakerInput
     ///
+    /// The process tap is a global mono mixdown running at the output
+    /// device's rate, so only the default configuration is available:
+    /// requested rates, channel modes and buffer sizes are reported back as
+    /// what the tap actually delivers. Selecting a device is not supported.
+    ///
     /// Note: On macOS 14.0+, capturing system audio requires the app to have
     /// proper entitlements and may need Screen Recording permission in
     /// System Settings > Privacy & Security > Screen Recording
-    fn new() -> Result<Self, AudioError> {
+    fn with_config(config: &AudioInputConfig) -> Result<Self, AudioError> {
+        if let Some(id) = &config.device_id {
+            return Err(AudioError::DeviceNotAvailable(format!(
+                "{} (speaker capture always taps the system mix)",
+                id
+            )));
+        }
+
         let tap_desc = ca::TapDesc::with_mono_global_tap_excluding_processes(&ns::Array::new());
         let tap = tap_desc
             .create_process_tap()
@@ -97,11 +127,18 @@ impl AudioInput for SpeakerInput {
         Ok(Self { tap, agg_desc })
     }
 
-    fn sample_rate(&self) -> u32 {
-        self.tap
+    fn format(&self) -> StreamFormat {
+        let sample_rate = self
+            .tap
             .asbd()
             .map(|asbd| asbd.sample_rate as u32)
-            .unwrap_or(DEFAULT_SAMPLE_RATE)
+            .unwrap_or(DEFAULT_SAMPLE_RATE);
+
+        StreamFormat {
+            sample_rate,
+            channels: 1,
+            buffer_frames: None,
+        }
     }
 
     /// Start capturing system audio and return a stream of samples
@@ -121,16 +158,23 @@ impl AudioInput for SpeakerInput {
         let waker_state = Arc::new(Mutex::new(WakerState {
             waker: None,
             has_data: false,
+            clock: FrameClock::default(),
+            gaps: GapTracker::new(asbd.sample_rate as u32),
+            errors: VecDeque::new(),
         }));
 
         let current_sample_rate = Arc::new(AtomicU32::new(asbd.sample_rate as u32));
         tracing::info!(sample_rate = asbd.sample_rate, "Speaker capture initialized");
 
+        let timebase = mach::TimeBaseInfo::new();
         let mut ctx = Box::new(AudioContext {
             format,
             producer,
             waker_state: waker_state.clone(),
-            current_sample_rate: current_sample_rate.clone(),
+            current_sample_rate,
+            timebase: (timebase.numer, timebase.denom),
+            captured_at: Duration::ZERO,
+            format_error_reported: false,
         });
 
         let device = self
@@ -143,8 +187,11 @@ impl AudioInput for SpeakerInput {
             _ctx: ctx,
             _tap: self.tap,
             waker_state,
-            current_sample_rate,
+            sample_rate: asbd.sample_rate as u32,
             read_buffer: vec![0.0f32; SAMPLES_PER_CHUNK],
+            frames_read: 0,
+            sequence: 0,
+            ended: false,
         })
     }
 }
@@ -159,7 +206,7 @@ impl SpeakerInput {
             device: ca::Device,
             _now: &cat::AudioTimeStamp,
             input_data: &cat::AudioBufList<1>,
-            _input_time: &cat::AudioTimeStamp,
+            input_time: &cat::AudioTimeStamp,
             _output_data: &mut cat::AudioBufList<1>,
             _output_time: &cat::AudioTimeStamp,
             ctx: Option<&mut AudioContext>,
@@ -177,9 +224,25 @@ impl SpeakerInput {
 
             if before != after {
                 ctx.current_sample_rate.store(after, Ordering::Release);
+                ctx.waker_state.lock().unwrap().gaps.set_sample_rate(after);
                 tracing::info!(before, after, "Sample rate changed");
+
+                let format = |sample_rate| StreamFormat {
+                    sample_rate,
+                    channels: 1,
+                    buffer_frames: None,
+                };
+                report_error(
+                    ctx,
+                    AudioError::FormatChanged {
+                        previous: format(before),
+                        current: format(after),
+                    },
+                );
             }
 
+            ctx.captured_at = capture_time(ctx, input_time);
+
             // Try to process using AudioPcmBuf first (preferred path)
             if let Some(view) =
                 av::AudioPcmBuf::with_buf_list_no_copy(&ctx.format, input_data, None)
@@ -210,7 +273,13 @@ impl SpeakerInput {
                 av::audio::CommonFormat::PcmI16 => {
                     process_samples(ctx, first_buffer, i16_to_f32);
                 }
-                _ => {}
+                _ => {
+                    if !ctx.format_error_reported {
+                        ctx.format_error_reported = true;
+                        tracing::error!("Unsupported process tap sample format");
+                        report_error(ctx, AudioError::UnsupportedFormat);
+                    }
+                }
             }
 
             os::Status::NO_ERR
@@ -234,6 +303,24 @@ impl SpeakerInput {
 // Audio processing utilities
 // ============================================================================
 
+/// Convert the host time of an input buffer to the capture clock
+///
+/// Falls back to the time of the callback when Core Audio does not provide
+/// a valid host time.
+fn capture_time(ctx: &AudioContext, input_time: &cat::AudioTimeStamp) -> Duration {
+    let now = capture_clock_now();
+    let host_time_valid = input_time.flags.0 & cat::AudioTimeStampFlags::HOST_TIME_VALID.0 != 0;
+    let (numer, denom) = ctx.timebase;
+
+    if !host_time_valid || denom == 0 {
+        return now;
+    }
+
+    let ticks = mach::abs_time().saturating_sub(input_time.host_time);
+    let nanos = ticks as u128 * numer as u128 / denom as u128;
+    now.saturating_sub(Duration::from_nanos(nanos as u64))
+}
+
 /// Read samples from an audio buffer as a slice of type T
 fn read_samples<T: Copy>(buffer: &cat::AudioBuf) -> Option<&[T]> {
     let byte_count = buffer.data_bytes_size as usize;
@@ -279,28 +366,47 @@ where
 }
 
 /// Push audio data to the ring buffer and wake the async consumer
+///
+/// Samples that do not fit, and time skipped between IO cycles, are
+/// recorded as gaps for the consumer to fill with silence.
 fn process_audio_data(ctx: &mut AudioContext, data: &[f32]) {
     let pushed = ctx.producer.push_slice(data);
 
-    if pushed < data.len() {
-        let dropped = data.len() - pushed;
-        tracing::warn!(dropped, "Audio samples dropped due to buffer overflow");
-    }
+    let should_wake = {
+        let mut waker_state = ctx.waker_state.lock().unwrap();
+        // The tap is mono, so every sample is one frame
+        let first_frame = waker_state
+            .gaps
+            .write(ctx.captured_at, data.len() as u64, pushed as u64);
+        waker_state.clock.anchor(first_frame, ctx.captured_at);
+
+        if pushed > 0 && !waker_state.has_data {
+            waker_state.has_data = true;
+            waker_state.waker.take()
+        } else {
+            None
+        }
+    };
 
-    if pushed > 0 {
-        let should_wake = {
-            let mut waker_state = ctx.waker_state.lock().unwrap();
-            if !waker_state.has_data {
-                waker_state.has_data = true;
-                waker_state.waker.take()
-            } else {
-                None
-            }
-        };
+    if let Some(waker) = should_wake {
+        waker.wake();
+    }
+}
 
-        if let Some(waker) = should_wake {
-            waker.wake();
-        }
+/// Queue an error for the async consumer and wake it
+///
+/// The error is delivered after the audio already written, so a format
+/// change lands between the frames of the old and the new format.
+fn report_error(ctx: &AudioContext, error: AudioError) {
+    let waker = {
+        let mut waker_state = ctx.waker_state.lock().unwrap();
+        let frame = waker_state.gaps.frames_written();
+        waker_state.errors.push_back((frame, error));
+        waker_state.waker.take()
+    };
+
+    if let Some(waker) = waker {
+        waker.wake();
     }
 }
 
@@ -315,25 +421,90 @@ pub struct SpeakerStream {
     _ctx: Box<AudioContext>,
     _tap: ca::TapGuard,
     waker_state: Arc<Mutex<WakerState>>,
-    current_sample_rate: Arc<AtomicU32>,
+    /// Rate of the frames being delivered, changed once a format change
+    /// is reached
+    sample_rate: u32,
     read_buffer: Vec<f32>,
+    /// Frames delivered so far, including gap fill
+    frames_read: u64,
+    sequence: u64,
+    /// Set once a fatal error has been delivered
+    ended: bool,
 }
 
 impl AudioStream for SpeakerStream {
     fn sample_rate(&self) -> u32 {
-        self.current_sample_rate.load(Ordering::Acquire)
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        1
     }
 }
 
 impl FuturesStream for SpeakerStream {
-    type Item = Vec<f32>;
+    type Item = Result<AudioChunk, AudioError>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.as_mut().get_mut();
-        let popped = this.consumer.pop_slice(&mut this.read_buffer);
+
+        if this.ended {
+            return Poll::Ready(None);
+        }
+
+        let (clock, readable, gap) = {
+            let mut state = this.waker_state.lock().unwrap();
+            // Errors are reported once the audio before them has been read
+            if state.errors.front().is_some_and(|(frame, _)| *frame <= this.frames_read) {
+                if let Some((_, error)) = state.errors.pop_front() {
+                    if let AudioError::FormatChanged { current, .. } = &error {
+                        this.sample_rate = current.sample_rate;
+                    }
+                    this.ended = !error.is_recoverable();
+                    return Poll::Ready(Some(Err(error)));
+                }
+            }
+            let gap = state.gaps.take_gap(this.frames_read);
+            let error = state.errors.front().map(|(frame, _)| frame - this.frames_read);
+            let readable = match (state.gaps.readable(this.frames_read), error) {
+                (Some(gap), Some(error)) => Some(gap.min(error)),
+                (gap, error) => gap.or(error),
+            };
+            (state.clock, readable, gap)
+        };
+        let sample_rate = this.sample_rate;
+
+        if let Some(frames) = gap {
+            let chunk = AudioChunk::silence(
+                frames as usize,
+                sample_rate,
+                1,
+                clock.timestamp_of(this.frames_read, sample_rate),
+                this.sequence,
+            );
+            this.frames_read += frames;
+            this.sequence += 1;
+            return Poll::Ready(Some(Ok(chunk)));
+        }
+
+        // Stop at the next gap or error so the silence or the format change
+        // lands in the right place
+        let limit = readable.map_or(this.read_buffer.len(), |frames| {
+            (frames as usize).min(this.read_buffer.len())
+        });
+        let popped = this.consumer.pop_slice(&mut this.read_buffer[..limit]);
 
         if popped > 0 {
-            return Poll::Ready(Some(this.read_buffer[..popped].to_vec()));
+            let chunk = AudioChunk::new(
+                this.read_buffer[..popped].to_vec(),
+                sample_rate,
+                1,
+                clock.timestamp_of(this.frames_read, sample_rate),
+                this.sequence,
+            );
+            this.frames_read += popped as u64;
+            this.sequence += 1;
+            return Poll::Ready(Some(Ok(chunk)));
         }
 
         {
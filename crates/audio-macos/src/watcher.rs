@@ -0,0 +1,133 @@
+//! Device hot-plug notifications from Core Audio
+//!
+//! Property listeners on the system object fire when the device list or
+//! the default input or output device changes. A dedicated thread lists
+//! the devices after every burst of notifications and lets the core
+//! [`DeviceTracker`] turn the difference into
+//! [`heronote_audio_core::DeviceEvent`]s.
+
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use cidre::{core_audio as ca, os};
+use heronote_audio_core::{AudioError, DeviceTracker, DeviceWatcher};
+
+use crate::device::list_devices;
+
+/// Quiet period after a notification before the devices are listed again
+///
+/// Connecting a headset changes the device list and both defaults within
+/// a few milliseconds; waiting lets them collapse into one listing.
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+/// Message to the watcher thread
+enum Signal {
+    Changed,
+    Stop,
+}
+
+/// System object properties that affect the device list
+fn watched_properties() -> [ca::PropAddr; 3] {
+    [
+        ca::PropSelector::HW_DEVICES.global_addr(),
+        ca::PropSelector::HW_DEFAULT_INPUT_DEVICE.global_addr(),
+        ca::PropSelector::HW_DEFAULT_OUTPUT_DEVICE.global_addr(),
+    ]
+}
+
+/// Start watching Core Audio for device changes
+///
+/// The returned [`DeviceWatcher`] yields an event whenever a device is added
+/// or removed, or the default input or output device changes.
+pub fn watch_devices() -> Result<DeviceWatcher, AudioError> {
+    let (tracker, watcher) = DeviceWatcher::channel(list_devices()?);
+    let (signal_tx, signal_rx) = mpsc::channel::<Signal>();
+
+    let thread = thread::Builder::new()
+        .name("heronote-devices".to_string())
+        .spawn(move || run(tracker, &signal_rx))
+        .map_err(|e| AudioError::DeviceError(format!("Failed to spawn device watcher thread: {}", e)))?;
+
+    // Dropping the guard on any error below unregisters what was registered
+    let guard = ListenerGuard {
+        listener_tx: Box::into_raw(Box::new(signal_tx.clone())),
+        signal_tx,
+        thread: Some(thread),
+    };
+
+    for address in watched_properties() {
+        ca::System::OBJ
+            .add_prop_listener(&address, on_property_changed, guard.listener_tx)
+            .map_err(|e| AudioError::DeviceError(format!("Failed to add device listener: {:?}", e)))?;
+    }
+
+    Ok(watcher.with_guard(guard))
+}
+
+/// Core Audio property listener forwarding notifications to the watcher thread
+extern "C-unwind" fn on_property_changed(
+    _obj: ca::Obj,
+    _number_addresses: u32,
+    _addresses: *const ca::PropAddr,
+    signal_tx: *mut mpsc::Sender<Signal>,
+) -> os::Status {
+    if let Some(signal_tx) = unsafe { signal_tx.as_ref() } {
+        let _ = signal_tx.send(Signal::Changed);
+    }
+
+    os::Status::NO_ERR
+}
+
+/// Relist devices after every settled burst of notifications until told to stop
+fn run(mut tracker: DeviceTracker, signals: &mpsc::Receiver<Signal>) {
+    while let Ok(Signal::Changed) = signals.recv() {
+        loop {
+            match signals.recv_timeout(SETTLE_TIME) {
+                Ok(Signal::Changed) => continue,
+                Ok(Signal::Stop) | Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+            }
+        }
+
+        match list_devices() {
+            Ok(devices) => {
+                if !tracker.update(devices) {
+                    return;
+                }
+            }
+            Err(e) => tracing::warn!("Failed to list devices after change: {}", e),
+        }
+    }
+}
+
+/// Listener registration and watcher thread
+///
+/// Dropping it removes the listeners, then stops and joins the thread.
+struct ListenerGuard {
+    /// Client data handed to Core Audio, freed once the listeners are gone
+    listener_tx: *mut mpsc::Sender<Signal>,
+    signal_tx: mpsc::Sender<Signal>,
+    thread: Option<JoinHandle<()>>,
+}
+
+// The raw pointer is only dereferenced by the listener, which Core Audio
+// stops calling before the pointer is freed in `drop`.
+unsafe impl Send for ListenerGuard {}
+
+impl Drop for ListenerGuard {
+    fn drop(&mut self) {
+        for address in watched_properties() {
+            let _ = ca::System::OBJ.remove_prop_listener(&address, on_property_changed, self.listener_tx);
+        }
+        drop(unsafe { Box::from_raw(self.listener_tx) });
+
+        let _ = self.signal_tx.send(Signal::Stop);
+
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("Device watcher thread panicked");
+            }
+        }
+    }
+}
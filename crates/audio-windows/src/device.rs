@@ -1,7 +1,13 @@
-use heronote_audio_core::{AudioDevice, AudioError};
+use heronote_audio_core::{AudioDevice, AudioError, DeviceWatcher};
 
 /// List all available audio devices on Windows
 pub fn list_devices() -> Result<Vec<AudioDevice>, AudioError> {
     // TODO: Implement Windows device enumeration using WASAPI
     Err(AudioError::PlatformNotSupported("Windows support coming soon".to_string()))
 }
+
+/// Watch for audio device changes on Windows
+pub fn watch_devices() -> Result<DeviceWatcher, AudioError> {
+    // TODO: Implement using IMMNotificationClient
+    Err(AudioError::PlatformNotSupported("Windows support coming soon".to_string()))
+}
@@ -7,7 +7,10 @@ mod mic;
 mod speaker;
 mod device;
 
-pub use heronote_audio_core::{AudioDevice, AudioError, DeviceType, AudioInput, AudioStream};
+pub use heronote_audio_core::{
+    AudioDevice, AudioError, AudioInput, AudioInputConfig, AudioStream, ChannelMode, DeviceEvent,
+    DeviceType, DeviceWatcher, StreamFormat,
+};
 pub use mic::{MicInput, MicStream};
 pub use speaker::{SpeakerInput, SpeakerStream};
-pub use device::list_devices;
+pub use device::{list_devices, watch_devices};
@@ -7,7 +7,9 @@ use std::pin::Pin;
 use std::task::{Context, Poll};
 
 use futures::Stream as FuturesStream;
-use heronote_audio_core::{AudioError, AudioInput, AudioStream};
+use heronote_audio_core::{
+    AudioChunk, AudioError, AudioInput, AudioInputConfig, AudioStream, StreamFormat,
+};
 
 /// Microphone input handler for Windows (stub)
 ///
@@ -21,24 +23,31 @@ pub struct MicInput {
 impl AudioInput for MicInput {
     type Stream = MicStream;
 
-    fn new() -> Result<Self, AudioError> {
+    fn with_config(_config: &AudioInputConfig) -> Result<Self, AudioError> {
         Err(AudioError::PlatformNotSupported(
             "Windows mic capture coming soon".to_string(),
         ))
     }
 
-    fn sample_rate(&self) -> u32 {
-        // This method can never be called because `new()` always returns Err,
+    fn format(&self) -> StreamFormat {
+        // This method can never be called because `with_config()` always returns Err,
         // meaning no instance of MicInput can ever exist.
         unreachable!("MicInput cannot be instantiated on Windows (stub)")
     }
 
     fn stream(self) -> Result<MicStream, AudioError> {
-        // This method can never be called because `new()` always returns Err
+        // This method can never be called because `with_config()` always returns Err
         unreachable!("MicInput cannot be instantiated on Windows (stub)")
     }
 }
 
+impl MicInput {
+    /// Create a MicInput for a device id returned by [`crate::list_devices`]
+    pub fn with_device_id(id: &str) -> Result<Self, AudioError> {
+        Self::with_config(&AudioInputConfig::new().with_device_id(id))
+    }
+}
+
 /// Stream of audio samples from the microphone (stub)
 pub struct MicStream {
     // Private field to prevent external construction
@@ -50,10 +59,15 @@ impl AudioStream for MicStream {
         // This method can never be called because MicStream cannot be created
         unreachable!("MicStream cannot be created on Windows (stub)")
     }
+
+    fn channels(&self) -> u16 {
+        // This method can never be called because MicStream cannot be created
+        unreachable!("MicStream cannot be created on Windows (stub)")
+    }
 }
 
 impl FuturesStream for MicStream {
-    type Item = Vec<f32>;
+    type Item = Result<AudioChunk, AudioError>;
 
     fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         // This method can never be called because MicStream cannot be created
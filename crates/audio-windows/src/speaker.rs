@@ -7,7 +7,9 @@ use std::pin::Pin;
 use std::task::{Context, Poll};
 
 use futures::Stream as FuturesStream;
-use heronote_audio_core::{AudioError, AudioInput, AudioStream};
+use heronote_audio_core::{
+    AudioChunk, AudioError, AudioInput, AudioInputConfig, AudioStream, StreamFormat,
+};
 
 /// Speaker input handler for Windows (stub)
 ///
@@ -21,20 +23,20 @@ pub struct SpeakerInput {
 impl AudioInput for SpeakerInput {
     type Stream = SpeakerStream;
 
-    fn new() -> Result<Self, AudioError> {
+    fn with_config(_config: &AudioInputConfig) -> Result<Self, AudioError> {
         Err(AudioError::PlatformNotSupported(
             "Windows speaker capture coming soon".to_string(),
         ))
     }
 
-    fn sample_rate(&self) -> u32 {
-        // This method can never be called because `new()` always returns Err,
+    fn format(&self) -> StreamFormat {
+        // This method can never be called because `with_config()` always returns Err,
         // meaning no instance of SpeakerInput can ever exist.
         unreachable!("SpeakerInput cannot be instantiated on Windows (stub)")
     }
 
     fn stream(self) -> Result<SpeakerStream, AudioError> {
-        // This method can never be called because `new()` always returns Err
+        // This method can never be called because `with_config()` always returns Err
         unreachable!("SpeakerInput cannot be instantiated on Windows (stub)")
     }
 }
@@ -50,10 +52,15 @@ impl AudioStream for SpeakerStream {
         // This method can never be called because SpeakerStream cannot be created
         unreachable!("SpeakerStream cannot be created on Windows (stub)")
     }
+
+    fn channels(&self) -> u16 {
+        // This method can never be called because SpeakerStream cannot be created
+        unreachable!("SpeakerStream cannot be created on Windows (stub)")
+    }
 }
 
 impl FuturesStream for SpeakerStream {
-    type Item = Vec<f32>;
+    type Item = Result<AudioChunk, AudioError>;
 
     fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         // This method can never be called because SpeakerStream cannot be created